@@ -0,0 +1,61 @@
+//! Mock callback program for integration tests
+//!
+//! Reference implementation of the Morpho Solana supply/repay callbacks.
+//! Each handler tops up the caller's token account from a `source` account
+//! so Morpho can pull the promised amount right after the callback returns.
+//!
+//! Morpho passes [caller, caller_token_account, loan_mint] followed by the
+//! transaction's remaining accounts, which this mock expects to be
+//! [source, source_authority, token_program].
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, transfer_checked, TransferChecked};
+
+declare_id!("t52UCXmhyp5sCFtDamNYu9kaRRmn2pYoe78VnbxVYfC");
+
+#[program]
+pub mod mock_callback {
+    use super::*;
+
+    pub fn on_morpho_supply(ctx: Context<FundCaller>, assets: u64, _data: Vec<u8>) -> Result<()> {
+        fund_caller(ctx, assets)
+    }
+
+    pub fn on_morpho_repay(ctx: Context<FundCaller>, assets: u64, _data: Vec<u8>) -> Result<()> {
+        fund_caller(ctx, assets)
+    }
+}
+
+#[derive(Accounts)]
+pub struct FundCaller<'info> {
+    pub caller: Signer<'info>,
+
+    #[account(mut)]
+    pub caller_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub loan_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub source: InterfaceAccount<'info, TokenAccount>,
+
+    pub source_authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Move `assets` from `source` into the caller's token account
+fn fund_caller(ctx: Context<FundCaller>, assets: u64) -> Result<()> {
+    transfer_checked(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.source.to_account_info(),
+                to: ctx.accounts.caller_token_account.to_account_info(),
+                authority: ctx.accounts.source_authority.to_account_info(),
+                mint: ctx.accounts.loan_mint.to_account_info(),
+            },
+        ),
+        assets,
+        ctx.accounts.loan_mint.decimals,
+    )
+}
@@ -50,6 +50,45 @@ pub const LIF_CURSOR: u64 = 3_000;
 /// Basis points for LIF calculations
 pub const LIF_BPS: u64 = 10_000;
 
+/// Largest LIF cursor a market may configure (50%)
+pub const MAX_LIF_CURSOR: u64 = 5_000;
+
+/// Largest max LIF a market may configure (150%)
+pub const MAX_LIF_CAP: u64 = 15_000;
+
+/// Slack on top of the LIF allowed between seized collateral and the debt
+/// actually repaid (0.1%, LIF_BPS-scaled)
+pub const OVER_SEIZURE_TOLERANCE_BPS: u64 = 10;
+
+/// Default close factor: max share of a position's debt repayable in one liquidation (50%)
+pub const DEFAULT_CLOSE_FACTOR_BPS: u64 = 5_000;
+
+/// Health factor below which the close factor no longer applies (0.95, WAD-scaled)
+pub const FULL_LIQUIDATION_HEALTH: u128 = WAD / 100 * 95;
+
+/// Longest allowed liquidation auction ramp (~1 hour at 400ms slots)
+pub const MAX_AUCTION_DURATION_SLOTS: u64 = 9_000;
+
+/// Longest allowed liquidation grace period (~1 minute at 400ms slots)
+pub const MAX_LIQUIDATION_GRACE_SLOTS: u64 = 150;
+
+/// Largest share of the liquidation bonus the protocol may take (50%), so the
+/// liquidator always keeps at least half of it
+pub const MAX_LIQUIDATION_PROTOCOL_FEE_BPS: u64 = 5_000;
+
+/// Positions per `scan_liquidatable` call. Bounded by the transaction
+/// account limit (with a lookup table) and ~4k CU per decoded position.
+pub const MAX_SCAN_POSITIONS: usize = 32;
+
+/// Positions per `liquidate_batch` call. Each one adds a position decode,
+/// quote and write on top of the shared accrual, oracle read and
+/// transfers, so a full batch stays well inside the compute budget.
+pub const MAX_LIQUIDATION_BATCH: usize = 8;
+
+/// Markets per `view_markets` page, keeping the borsh result under the
+/// 1024-byte return data limit
+pub const MAX_MARKETS_PER_PAGE: usize = 8;
+
 // === Interest Rate Constants ===
 
 /// Seconds per year for rate conversions
@@ -58,6 +97,15 @@ pub const SECONDS_PER_YEAR: u128 = 31_536_000;
 /// Maximum borrow rate per second (1000% APY cap)
 pub const MAX_BORROW_RATE_PER_SECOND: u128 = WAD * 10 / SECONDS_PER_YEAR;
 
+/// Longest period a single accrual charges interest for (one year).
+/// A market idle for longer accrues only this much; nobody was
+/// transacting, so no real interest demand is lost.
+pub const MAX_ACCRUAL_ELAPSED: u128 = SECONDS_PER_YEAR;
+
+/// Estimated slot duration (milliseconds) for markets accruing on
+/// `AccrualBasis::Slots`
+pub const SLOT_DURATION_MS: u64 = 400;
+
 // === Safe Math Constants ===
 
 /// Maximum value that fits in u64
@@ -67,3 +115,41 @@ pub const MAX_U64: u128 = u64::MAX as u128;
 
 /// Flash loan fee (0.05% = 5 basis points)
 pub const FLASH_LOAN_FEE_BPS: u64 = 5;
+
+// === Timelock Constants ===
+
+/// Minimum delay before a queued market parameter change can execute
+/// (~1 day at 400ms slots)
+pub const PARAM_CHANGE_DELAY_SLOTS: u64 = 216_000;
+
+/// Largest gap between the old and new oracle's prices that `set_oracle`
+/// accepts (5% = 500 basis points)
+pub const MAX_ORACLE_MIGRATION_BPS: u64 = 500;
+
+// === TWAP Oracle Constants ===
+
+/// Number of samples a TWAP oracle keeps
+pub const TWAP_CAPACITY: usize = 32;
+
+/// Longest TWAP averaging window (~1 hour at 400ms slots)
+pub const MAX_TWAP_WINDOW_SLOTS: u64 = 9_000;
+
+// === Median Oracle Constants ===
+
+/// Maximum number of feeds a median oracle aggregates
+pub const MAX_MEDIAN_FEEDS: usize = 8;
+
+// === Vault Constants ===
+
+/// Maximum number of markets a vault can allocate to. Deposit, withdraw and
+/// reallocate load every listed market (three accounts, a PDA check and an
+/// accrual, ~8k CU) and each market the plan touches adds a transfer CPI
+/// (~6k CU), so a full vault stays near 110k CU in the worst case.
+pub const MAX_VAULT_MARKETS: usize = 8;
+
+/// Maximum vault performance fee (50% of yield = 5000 basis points)
+pub const MAX_VAULT_FEE: u64 = 5000;
+
+/// Notice given before a vault market can be removed, for the curator to
+/// reallocate out of it (same as the market parameter timelock)
+pub const VAULT_MARKET_REMOVAL_DELAY_SLOTS: u64 = PARAM_CHANGE_DELAY_SLOTS;
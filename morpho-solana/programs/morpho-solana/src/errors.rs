@@ -15,6 +15,15 @@ pub enum MorphoError {
     #[msg("Authorization has been revoked")]
     AuthorizationRevoked = 6003,
 
+    #[msg("Signature does not match the authorization")]
+    InvalidSignature = 6004,
+
+    #[msg("Signature deadline has passed")]
+    SignatureExpired = 6005,
+
+    #[msg("Authorization nonce does not match")]
+    InvalidNonce = 6006,
+
     // === Input Validation Errors (6010-6029) ===
     #[msg("Amount must be greater than zero")]
     ZeroAmount = 6010,
@@ -25,10 +34,10 @@ pub enum MorphoError {
     #[msg("Slippage tolerance exceeded")]
     SlippageExceeded = 6012,
 
-    #[msg("Invalid LLTV value (must be 0 < lltv <= 10000)")]
+    #[msg("Invalid LLTV value (must be 0 < lltv < 10000)")]
     InvalidLltv = 6013,
 
-    #[msg("Fee exceeds maximum allowed (25%)")]
+    #[msg("Fee exceeds maximum allowed (25% market, 50% vault)")]
     FeeTooHigh = 6014,
 
     #[msg("Invalid mint address")]
@@ -43,6 +52,15 @@ pub enum MorphoError {
     #[msg("Invalid market ID")]
     InvalidMarketId = 6018,
 
+    #[msg("Mint has a Token-2022 extension the protocol cannot support")]
+    UnsupportedMintExtension = 6019,
+
+    #[msg("Native SOL handling requires a wrapped SOL mint")]
+    NativeMintRequired = 6020,
+
+    #[msg("Transaction landed after its deadline slot")]
+    DeadlineExceeded = 6021,
+
     // === Market Errors (6030-6049) ===
     #[msg("Market already exists")]
     MarketExists = 6030,
@@ -75,6 +93,12 @@ pub enum MorphoError {
     #[msg("Insufficient market liquidity")]
     InsufficientLiquidity = 6052,
 
+    #[msg("Borrow would push utilization above the market cap")]
+    UtilizationCapReached = 6053,
+
+    #[msg("Supply would push total supply above the market cap")]
+    SupplyCapReached = 6054,
+
     // === Health Errors (6070-6079) ===
     #[msg("Position would become unhealthy")]
     PositionUnhealthy = 6070,
@@ -85,6 +109,24 @@ pub enum MorphoError {
     #[msg("Position is not empty, cannot close")]
     PositionNotEmpty = 6072,
 
+    #[msg("Repay amount exceeds the close factor")]
+    LiquidationTooLarge = 6073,
+
+    #[msg("Position is past its LLTV, use liquidate")]
+    PositionLiquidatable = 6074,
+
+    #[msg("Liquidation auction has not been started for this position")]
+    AuctionNotStarted = 6075,
+
+    #[msg("Position would fall below the market's minimum size")]
+    PositionTooSmall = 6076,
+
+    #[msg("Position is still within the market's liquidation grace period")]
+    LiquidationGracePeriod = 6077,
+
+    #[msg("Liquidation would seize more collateral than the repaid debt warrants")]
+    OverSeizure = 6078,
+
     // === Pause Errors (6080-6089) ===
     #[msg("Protocol is paused")]
     ProtocolPaused = 6080,
@@ -92,6 +134,12 @@ pub enum MorphoError {
     #[msg("Market is paused")]
     MarketPaused = 6081,
 
+    #[msg("Market is not paused")]
+    MarketNotPaused = 6082,
+
+    #[msg("Positions with debt cannot use emergency withdraw")]
+    EmergencyWithdrawHasDebt = 6083,
+
     // === Oracle Errors (6090-6109) ===
     #[msg("Oracle price is stale")]
     OracleStale = 6090,
@@ -117,6 +165,42 @@ pub enum MorphoError {
     #[msg("Oracle price below minimum")]
     OraclePriceTooLow = 6097,
 
+    #[msg("Oracle confidence interval too wide")]
+    OracleConfidenceTooWide = 6098,
+
+    #[msg("Invalid oracle kind")]
+    InvalidOracleKind = 6099,
+
+    #[msg("Oracle kind already set for this market")]
+    OracleKindAlreadySet = 6100,
+
+    #[msg("Oracle staleness must be between 1 and 300 slots")]
+    InvalidOracleStaleness = 6101,
+
+    #[msg("Cannot invert a zero oracle price")]
+    OracleZeroPrice = 6102,
+
+    #[msg("Oracle price deviates too far from the last accepted price")]
+    OracleDeviationTooHigh = 6103,
+
+    #[msg("TWAP samples do not cover the averaging window")]
+    OracleTwapInsufficientCoverage = 6104,
+
+    #[msg("TWAP updated too soon after the previous sample")]
+    OracleTwapUpdateTooSoon = 6105,
+
+    #[msg("Too few oracle feeds returned a healthy price")]
+    OracleTooFewHealthyFeeds = 6106,
+
+    #[msg("Oracle price is implausible for the market's tokens")]
+    OraclePriceImplausible = 6107,
+
+    #[msg("Oracle price sits exactly on a price bound")]
+    OracleAtBoundary = 6108,
+
+    #[msg("New oracle price deviates too far from the current oracle")]
+    OracleMigrationDeviationTooHigh = 6109,
+
     // === IRM Errors (6110-6119) ===
     #[msg("IRM returned invalid rate")]
     IrmInvalidRate = 6110,
@@ -158,4 +242,65 @@ pub enum MorphoError {
 
     #[msg("Flash loan callback failed")]
     FlashLoanCallbackFailed = 6142,
+
+    // === Callback Errors (6150-6159) ===
+    #[msg("Integrator callback failed")]
+    CallbackFailed = 6150,
+
+    #[msg("Callback program must be executable and not this program")]
+    InvalidCallbackProgram = 6151,
+
+    #[msg("Vault balance change does not match the expected amount")]
+    CallbackTransferMismatch = 6152,
+
+    #[msg("Market is locked while an integrator callback runs")]
+    ReentrancyDetected = 6153,
+
+    // === Timelock Errors (6160-6169) ===
+    #[msg("Queued parameter change is not executable yet")]
+    TimelockNotElapsed = 6160,
+
+    #[msg("This parameter change must be queued")]
+    TimelockRequired = 6161,
+
+    #[msg("Invalid market parameter kind")]
+    InvalidParamKind = 6162,
+
+    // === Vault Errors (6170-6179) ===
+    #[msg("Vault already lists the maximum number of markets")]
+    VaultTooManyMarkets = 6170,
+
+    #[msg("Queue must contain each listed market exactly once")]
+    VaultQueueMismatch = 6171,
+
+    #[msg("Market is not listed in the vault")]
+    VaultMarketNotListed = 6172,
+
+    #[msg("Deposit exceeds the idle buffer and every market's supply cap")]
+    AllCapsReached = 6173,
+
+    #[msg("Vault accounts do not match its listed markets")]
+    VaultAccountsMismatch = 6174,
+
+    #[msg("Not enough idle assets to fund the allocation")]
+    VaultInsufficientIdle = 6175,
+
+    #[msg("Vault still has supply in a market dropped from the withdraw queue")]
+    VaultMarketHasSupply = 6176,
+
+    #[msg("Vault still has assets supplied to the market")]
+    MarketNotEmpty = 6177,
+
+    #[msg("Vault already has a market removal pending")]
+    VaultRemovalPending = 6178,
+
+    #[msg("No removal is pending for this market")]
+    VaultRemovalNotPending = 6179,
+
+    // === Invariant Errors (6180-6189) ===
+    #[msg("Borrowed assets plus pending bad debt exceed supplied assets")]
+    InvariantBorrowExceedsSupply = 6180,
+
+    #[msg("Supply shares are outstanding with no supply assets")]
+    InvariantSupplySharesUnbacked = 6181,
 }
@@ -1,3 +1,8 @@
+//! Program events
+//!
+//! New fields are only ever appended so existing deserializers keep working.
+//! `total_*` fields hold the market totals after the operation.
+
 use anchor_lang::prelude::*;
 
 // === Protocol Events ===
@@ -31,6 +36,11 @@ pub struct ProtocolPausedSet {
     pub paused: bool,
 }
 
+#[event]
+pub struct GuardianSet {
+    pub guardian: Pubkey,
+}
+
 #[event]
 pub struct LltvEnabled {
     pub lltv: u64,
@@ -51,6 +61,10 @@ pub struct MarketCreated {
     pub oracle: Pubkey,
     pub irm: Pubkey,
     pub lltv: u64,
+    pub oracle_kind: u8,
+    pub oracle_secondary: Pubkey,
+    pub min_borrow: u64,
+    pub min_collateral: u64,
 }
 
 #[event]
@@ -65,6 +79,162 @@ pub struct FeeSet {
     pub fee: u64,
 }
 
+#[event]
+pub struct AccrualBasisSet {
+    pub market_id: [u8; 32],
+    /// New `AccrualBasis` (0 = Timestamp, 1 = Slots)
+    pub basis: u8,
+}
+
+#[event]
+pub struct OracleKindMigrated {
+    pub market_id: [u8; 32],
+    pub oracle_kind: u8,
+}
+
+#[event]
+pub struct OracleConfidenceSet {
+    pub market_id: [u8; 32],
+    pub max_oracle_confidence_bps: u64,
+}
+
+#[event]
+pub struct OracleStdDevSet {
+    pub market_id: [u8; 32],
+    pub max_std_bps: u64,
+}
+
+#[event]
+pub struct BadDebtThresholdSet {
+    pub market_id: [u8; 32],
+    pub bad_debt_threshold: u128,
+}
+
+#[event]
+pub struct StaticPriceUpdated {
+    pub oracle: Pubkey,
+    pub admin: Pubkey,
+    pub price: u128,
+    pub last_update_slot: u64,
+}
+
+#[event]
+pub struct TwapOracleCreated {
+    pub twap_oracle: Pubkey,
+    pub source: Pubkey,
+    pub source_kind: u8,
+    pub window_slots: u64,
+    pub max_staleness_slots: u64,
+}
+
+#[event]
+pub struct TwapUpdated {
+    pub twap_oracle: Pubkey,
+    pub slot: u64,
+    pub price: u128,
+}
+
+#[event]
+pub struct MedianOracleCreated {
+    pub median_oracle: Pubkey,
+    pub aggregator_id: [u8; 32],
+    pub feed_kind: u8,
+    pub feeds: Vec<Pubkey>,
+    pub min_healthy_feeds: u8,
+}
+
+#[event]
+pub struct MedianUpdated {
+    pub median_oracle: Pubkey,
+    pub slot: u64,
+    pub price: u128,
+}
+
+#[event]
+pub struct OracleFallbackSet {
+    pub market_id: [u8; 32],
+    pub oracle_fallback: Pubkey,
+}
+
+#[event]
+pub struct CloseFactorSet {
+    pub market_id: [u8; 32],
+    pub close_factor_bps: u64,
+}
+
+#[event]
+pub struct LiquidationProtocolFeeSet {
+    pub market_id: [u8; 32],
+    pub liquidation_protocol_fee_bps: u64,
+}
+
+#[event]
+pub struct AuctionDurationSet {
+    pub market_id: [u8; 32],
+    pub auction_duration_slots: u64,
+}
+
+#[event]
+pub struct SupplyCapSet {
+    pub market_id: [u8; 32],
+    pub supply_cap: u128,
+}
+
+#[event]
+pub struct LiquidationGraceSet {
+    pub market_id: [u8; 32],
+    pub liquidation_grace_slots: u64,
+}
+
+#[event]
+pub struct MarketMigrated {
+    pub market_id: [u8; 32],
+    pub old_len: u64,
+    pub new_len: u64,
+}
+
+#[event]
+pub struct MaxDeviationSet {
+    pub market_id: [u8; 32],
+    pub max_deviation_bps: u64,
+}
+
+#[event]
+pub struct ParamChangeQueued {
+    pub market_id: [u8; 32],
+    pub param: u8,
+    pub value: u64,
+    pub address: Pubkey,
+    pub execute_after_slot: u64,
+}
+
+#[event]
+pub struct ParamChangeExecuted {
+    pub market_id: [u8; 32],
+    pub param: u8,
+    pub value: u64,
+    pub address: Pubkey,
+}
+
+#[event]
+pub struct OracleMigrated {
+    pub market_id: [u8; 32],
+    pub old_oracle: Pubkey,
+    pub new_oracle: Pubkey,
+    /// Price read from the old oracle (ORACLE_SCALE, raw token units)
+    pub old_price: u128,
+    /// Price read from the new oracle (ORACLE_SCALE, raw token units)
+    pub new_price: u128,
+}
+
+#[event]
+pub struct OracleFallbackUsed {
+    pub market_id: [u8; 32],
+    pub primary: Pubkey,
+    pub fallback: Pubkey,
+    pub slot: u64,
+}
+
 // === Position Events ===
 
 #[event]
@@ -88,6 +258,8 @@ pub struct Supply {
     pub on_behalf_of: Pubkey,
     pub assets: u128,
     pub shares: u128,
+    pub total_supply_assets: u128,
+    pub total_supply_shares: u128,
 }
 
 #[event]
@@ -98,6 +270,20 @@ pub struct Withdraw {
     pub receiver: Pubkey,
     pub assets: u128,
     pub shares: u128,
+    pub total_supply_assets: u128,
+    pub total_supply_shares: u128,
+}
+
+#[event]
+pub struct EmergencyWithdraw {
+    pub market_id: [u8; 32],
+    pub caller: Pubkey,
+    pub on_behalf_of: Pubkey,
+    pub receiver: Pubkey,
+    pub assets: u128,
+    pub shares: u128,
+    pub total_supply_assets: u128,
+    pub total_supply_shares: u128,
 }
 
 // === Collateral Events ===
@@ -129,6 +315,8 @@ pub struct Borrow {
     pub receiver: Pubkey,
     pub assets: u128,
     pub shares: u128,
+    pub total_borrow_assets: u128,
+    pub total_borrow_shares: u128,
 }
 
 #[event]
@@ -138,6 +326,19 @@ pub struct Repay {
     pub on_behalf_of: Pubkey,
     pub assets: u128,
     pub shares: u128,
+    pub total_borrow_assets: u128,
+    pub total_borrow_shares: u128,
+}
+
+/// A repay cleared the debt of a position with no collateral left, which
+/// would otherwise have been written off as bad debt
+#[event]
+pub struct DebtBackstopped {
+    pub market_id: [u8; 32],
+    pub backstop: Pubkey,
+    pub borrower: Pubkey,
+    pub assets: u128,
+    pub shares: u128,
 }
 
 // === Liquidation Events ===
@@ -150,6 +351,57 @@ pub struct Liquidation {
     pub repaid_assets: u128,
     pub repaid_shares: u128,
     pub seized_collateral: u128,
+    /// Part of `seized_collateral` sent to the fee recipient
+    pub protocol_fee_collateral: u128,
+    pub bad_debt_assets: u128,
+    pub total_borrow_assets: u128,
+    pub total_borrow_shares: u128,
+}
+
+/// Summary of a `liquidate_batch`, after one `Liquidation` per position
+#[event]
+pub struct LiquidationBatch {
+    pub market_id: [u8; 32],
+    pub liquidator: Pubkey,
+    pub liquidated: u8,
+    /// Positions no longer liquidatable when the batch landed
+    pub skipped: u8,
+    pub repaid_assets: u128,
+    pub seized_collateral: u128,
+    pub protocol_fee_collateral: u128,
+    pub bad_debt_assets: u128,
+}
+
+#[event]
+pub struct PreLiquidationSet {
+    pub market_id: [u8; 32],
+    pub borrower: Pubkey,
+    pub pre_lltv: u64,
+    pub pre_lif: u64,
+    pub liquidator: Pubkey,
+}
+
+#[event]
+pub struct PreLiquidated {
+    pub market_id: [u8; 32],
+    pub liquidator: Pubkey,
+    pub borrower: Pubkey,
+    pub repaid_assets: u128,
+    pub repaid_shares: u128,
+    pub seized_collateral: u128,
+}
+
+#[event]
+pub struct LiquidationAuctionStarted {
+    pub market_id: [u8; 32],
+    pub borrower: Pubkey,
+    pub start_slot: u64,
+}
+
+#[event]
+pub struct LiquidationAuctionReset {
+    pub market_id: [u8; 32],
+    pub borrower: Pubkey,
 }
 
 #[event]
@@ -160,6 +412,37 @@ pub struct BadDebtRealized {
     pub bad_debt_shares: u128,
 }
 
+/// Supply-side view of a bad-debt write-off: every supply share lost
+/// `share_price_before - share_price_after` (WAD-scaled loan tokens).
+/// `bad_debt_assets` is the whole pending batch, which may span several
+/// `BadDebtRealized` write-offs; `reserve_assets_used` of it was covered by
+/// the market's reserve and the rest by suppliers.
+#[event]
+pub struct BadDebtSocialized {
+    pub market_id: [u8; 32],
+    pub bad_debt_assets: u128,
+    pub reserve_assets_used: u128,
+    pub share_price_before: u128,
+    pub share_price_after: u128,
+}
+
+/// Loan tokens added to a market's bad-debt reserve
+#[event]
+pub struct ReserveFunded {
+    pub market_id: [u8; 32],
+    pub funder: Pubkey,
+    pub assets: u128,
+    pub reserve_assets: u128,
+}
+
+/// A write-off was held in `pending_bad_debt` below the market's threshold
+#[event]
+pub struct BadDebtDeferred {
+    pub market_id: [u8; 32],
+    pub bad_debt_assets: u128,
+    pub pending_bad_debt: u128,
+}
+
 // === Interest Events ===
 
 #[event]
@@ -167,8 +450,13 @@ pub struct InterestAccrued {
     pub market_id: [u8; 32],
     pub interest: u128,
     pub fee_shares: u128,
+    /// Per-second borrow rate applied (WAD-scaled)
+    pub borrow_rate: u128,
     pub total_supply_assets: u128,
+    pub total_supply_shares: u128,
     pub total_borrow_assets: u128,
+    pub total_borrow_shares: u128,
+    pub last_update: i64,
 }
 
 // === Fee Events ===
@@ -205,3 +493,103 @@ pub struct AuthorizationRevoked {
     pub authorizer: Pubkey,
     pub authorized: Pubkey,
 }
+
+// === Vault Events ===
+
+#[event]
+pub struct VaultCreated {
+    pub vault_id: [u8; 32],
+    pub curator: Pubkey,
+    pub asset_mint: Pubkey,
+    pub idle_buffer_bps: u64,
+}
+
+#[event]
+pub struct VaultMarketSet {
+    pub vault_id: [u8; 32],
+    pub market: Pubkey,
+    pub supply_cap: u128,
+}
+
+#[event]
+pub struct VaultQueuesSet {
+    pub vault_id: [u8; 32],
+}
+
+#[event]
+pub struct QueueUpdated {
+    pub vault_id: [u8; 32],
+    /// True for the withdraw queue, false for the supply queue
+    pub withdraw_queue: bool,
+    pub queue: Vec<Pubkey>,
+}
+
+#[event]
+pub struct VaultDeposit {
+    pub vault_id: [u8; 32],
+    pub depositor: Pubkey,
+    pub on_behalf_of: Pubkey,
+    pub assets: u128,
+    pub shares: u128,
+    pub total_assets: u128,
+    pub total_shares: u128,
+}
+
+#[event]
+pub struct VaultWithdraw {
+    pub vault_id: [u8; 32],
+    pub owner: Pubkey,
+    pub receiver: Pubkey,
+    pub assets: u128,
+    pub shares: u128,
+    pub total_assets: u128,
+    pub total_shares: u128,
+}
+
+#[event]
+pub struct VaultFeeSet {
+    pub vault_id: [u8; 32],
+    pub fee_bps: u64,
+    pub fee_recipient: Pubkey,
+}
+
+#[event]
+pub struct VaultFeeAccrued {
+    pub vault_id: [u8; 32],
+    pub fee_recipient: Pubkey,
+    pub interest: u128,
+    pub fee_shares: u128,
+}
+
+#[event]
+pub struct VaultFeesClaimed {
+    pub vault_id: [u8; 32],
+    pub recipient: Pubkey,
+    pub shares: u128,
+}
+
+#[event]
+pub struct VaultMarketRemovalSubmitted {
+    pub vault_id: [u8; 32],
+    pub market: Pubkey,
+    pub removal_after_slot: u64,
+}
+
+#[event]
+pub struct VaultMarketRemovalCancelled {
+    pub vault_id: [u8; 32],
+    pub market: Pubkey,
+}
+
+#[event]
+pub struct VaultMarketRemoved {
+    pub vault_id: [u8; 32],
+    pub market: Pubkey,
+}
+
+#[event]
+pub struct VaultReallocated {
+    pub vault_id: [u8; 32],
+    pub total_assets: u128,
+    pub idle_assets: u128,
+}
@@ -2,15 +2,44 @@
 //! 
 //! - Initialize protocol
 //! - Two-step ownership transfer
-//! - Pause controls
+//! - Pause controls (owner or guardian)
+//! - Set pause guardian
 //! - Enable LLTVs and IRMs
 //! - Set fees
+//! - Set oracle confidence tolerance
+//! - Set Switchboard response spread tolerance
+//! - Set bad debt socialization threshold
+//! - Backfill oracle kind for legacy markets
+//! - Set oracle deviation circuit breaker
+//! - Tighten market supply caps
+//! - Resize markets created before the account grew
+//! - Create TWAP oracles
+//! - Create median oracle aggregators
+//! - Timelocked fee / LLTV / oracle / fallback oracle / IRM / supply cap /
+//!   accrual basis changes
+//! - Timelocked liquidation close factor / protocol fee / auction duration /
+//!   grace period changes
+//! - Deviation-checked execution of queued oracle and fallback oracle changes
 
 use anchor_lang::prelude::*;
-use crate::constants::{PROGRAM_SEED_PREFIX, BPS, MAX_FEE};
+use anchor_lang::Discriminator;
+use anchor_lang::system_program::{self, Transfer};
+use crate::constants::{
+    PROGRAM_SEED_PREFIX, BPS, MAX_FEE, PARAM_CHANGE_DELAY_SLOTS, MAX_AUCTION_DURATION_SLOTS,
+    MAX_LIQUIDATION_GRACE_SLOTS, MAX_LIQUIDATION_PROTOCOL_FEE_BPS,
+    MAX_TWAP_WINDOW_SLOTS, TWAP_CAPACITY, MAX_MEDIAN_FEEDS,
+};
 use crate::errors::MorphoError;
 use crate::events::*;
-use crate::state::{ProtocolState, Market};
+use crate::state::{
+    ProtocolState, Market, LegacyMarket, LEGACY_MARKET_SPACE, MARKET_SPACE_BEFORE_FEED_IDS, OracleKind, AccrualBasis, ParamKind, PendingParamChange, TwapOracle, MedianOracle,
+    validate_median_feeds,
+};
+use crate::math::accrue_interest_on_market;
+use crate::interfaces::{
+    compute_borrow_rate, validate_max_staleness_slots, get_oracle_price_validated, check_oracle_migration,
+    validate_pyth_feed_id, validate_market_pyth_feed_ids,
+};
 
 // ============================================================================
 // Initialize
@@ -47,6 +76,7 @@ pub fn initialize(
     state.lltv_count = 0;
     state.irm_count = 0;
     state.market_count = 0;
+    state.guardian = Pubkey::default();
 
     emit!(ProtocolInitialized { owner, fee_recipient });
     Ok(())
@@ -69,6 +99,9 @@ pub struct TransferOwnership<'info> {
     pub protocol_state: Account<'info, ProtocolState>,
 }
 
+/// Nominate a new owner; control only moves once they call `accept_ownership`
+///
+/// Nominating `Pubkey::default()` cancels a pending transfer.
 pub fn transfer_ownership(ctx: Context<TransferOwnership>, new_owner: Pubkey) -> Result<()> {
     ctx.accounts.protocol_state.pending_owner = new_owner;
 
@@ -92,6 +125,7 @@ pub struct AcceptOwnership<'info> {
     pub protocol_state: Account<'info, ProtocolState>,
 }
 
+/// Complete a transfer; only the pending owner may call this
 pub fn accept_ownership(ctx: Context<AcceptOwnership>) -> Result<()> {
     let state = &mut ctx.accounts.protocol_state;
     let previous_owner = state.owner;
@@ -140,13 +174,13 @@ pub fn set_fee_recipient(ctx: Context<SetFeeRecipient>, new_recipient: Pubkey) -
 
 #[derive(Accounts)]
 pub struct SetProtocolPaused<'info> {
-    pub owner: Signer<'info>,
+    pub authority: Signer<'info>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
         bump = protocol_state.bump,
-        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+        constraint = protocol_state.is_pause_authority(&authority.key()) @ MorphoError::Unauthorized,
     )]
     pub protocol_state: Account<'info, ProtocolState>,
 }
@@ -160,21 +194,21 @@ pub fn set_protocol_paused(ctx: Context<SetProtocolPaused>, paused: bool) -> Res
 #[derive(Accounts)]
 #[instruction(market_id: [u8; 32])]
 pub struct SetMarketPaused<'info> {
-    pub owner: Signer<'info>,
+    pub authority: Signer<'info>,
 
     #[account(
         seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
         bump = protocol_state.bump,
-        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+        constraint = protocol_state.is_pause_authority(&authority.key()) @ MorphoError::Unauthorized,
     )]
     pub protocol_state: Account<'info, ProtocolState>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
     )]
-    pub market: Account<'info, Market>,
+    pub market: AccountLoader<'info, Market>,
 }
 
 pub fn set_market_paused(
@@ -182,11 +216,31 @@ pub fn set_market_paused(
     market_id: [u8; 32],
     paused: bool,
 ) -> Result<()> {
-    ctx.accounts.market.paused = paused;
+    ctx.accounts.market.load_mut()?.set_paused(paused);
     emit!(MarketPausedSet { market_id, paused });
     Ok(())
 }
 
+#[derive(Accounts)]
+pub struct SetGuardian<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+}
+
+/// Set the pause guardian (Pubkey::default() removes it)
+pub fn set_guardian(ctx: Context<SetGuardian>, guardian: Pubkey) -> Result<()> {
+    ctx.accounts.protocol_state.guardian = guardian;
+    emit!(GuardianSet { guardian });
+    Ok(())
+}
+
 // ============================================================================
 // Enable LLTV / IRM
 // ============================================================================
@@ -205,7 +259,8 @@ pub struct EnableLltv<'info> {
 }
 
 pub fn enable_lltv(ctx: Context<EnableLltv>, lltv: u64) -> Result<()> {
-    require!(lltv > 0 && lltv <= BPS, MorphoError::InvalidLltv);
+    // 100% leaves no room for a liquidation bonus before bad debt
+    require!(lltv > 0 && lltv < BPS, MorphoError::InvalidLltv);
     ctx.accounts.protocol_state.add_lltv(lltv)?;
     emit!(LltvEnabled { lltv });
     Ok(())
@@ -249,14 +304,1023 @@ pub struct SetFee<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
     )]
-    pub market: Account<'info, Market>,
+    pub market: AccountLoader<'info, Market>,
 }
 
+/// Lower the market's protocol fee immediately
+/// 
+/// Raising the fee goes through `queue_param_change`. Interest is accrued
+/// first so the old fee applies to interest earned up to now and the new
+/// fee only to interest earned afterwards. Fee shares accrue to
+/// `pending_fee_shares` and are credited to the protocol fee recipient's
+/// position by `claim_fees`.
 pub fn set_fee(ctx: Context<SetFee>, market_id: [u8; 32], fee: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market.load_mut()?;
+    require!(fee <= market.fee, MorphoError::TimelockRequired);
+    apply_fee(market, market_id, fee)
+}
+
+/// Accrue at the old fee, then switch to `fee`
+fn apply_fee(market: &mut Market, market_id: [u8; 32], fee: u64) -> Result<()> {
     require!(fee <= MAX_FEE, MorphoError::FeeTooHigh);
-    ctx.accounts.market.fee = fee;
+
+    let clock = Clock::get()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(market, elapsed)?;
+    accrue_interest_on_market(market, current_time, borrow_rate)?;
+
+    market.fee = fee;
     emit!(FeeSet { market_id, fee });
     Ok(())
 }
+
+/// Switch the clock the market accrues interest against
+///
+/// Interest is accrued on the old basis first, then `last_update` is
+/// restamped on the new one, so no period is charged twice or skipped.
+fn apply_accrual_basis(market: &mut Market, market_id: [u8; 32], basis: u64, clock: &Clock) -> Result<()> {
+    let basis = AccrualBasis::try_from(basis as u8)?;
+
+    let current_time = market.accrual_time(clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(market, elapsed)?;
+    accrue_interest_on_market(market, current_time, borrow_rate)?;
+
+    market.accrual_basis = basis as u8;
+    market.last_update = market.accrual_time(clock)?;
+    emit!(AccrualBasisSet { market_id, basis: basis as u8 });
+    Ok(())
+}
+
+// ============================================================================
+// Set Oracle Confidence
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct SetOracleConfidence<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+}
+
+/// Set the maximum oracle confidence interval (0 = protocol default)
+pub fn set_oracle_confidence(
+    ctx: Context<SetOracleConfidence>,
+    market_id: [u8; 32],
+    max_oracle_confidence_bps: u64,
+) -> Result<()> {
+    require!(max_oracle_confidence_bps <= BPS, MorphoError::InvalidInput);
+    let market = &mut ctx.accounts.market.load_mut()?;
+    market.max_oracle_confidence_bps = max_oracle_confidence_bps;
+    market.invalidate_oracle_cache();
+    emit!(OracleConfidenceSet { market_id, max_oracle_confidence_bps });
+    Ok(())
+}
+
+// ============================================================================
+// Set Oracle Std Dev
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct SetOracleStdDev<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+}
+
+/// Set the maximum Switchboard response standard deviation (0 = protocol default)
+pub fn set_oracle_std_dev(
+    ctx: Context<SetOracleStdDev>,
+    market_id: [u8; 32],
+    max_std_bps: u64,
+) -> Result<()> {
+    require!(max_std_bps <= BPS, MorphoError::InvalidInput);
+    let market = &mut ctx.accounts.market.load_mut()?;
+    market.max_std_bps = max_std_bps;
+    market.invalidate_oracle_cache();
+    emit!(OracleStdDevSet { market_id, max_std_bps });
+    Ok(())
+}
+
+// ============================================================================
+// Set Bad Debt Threshold
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct SetBadDebtThreshold<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+}
+
+/// Set the pending bad debt at which it is charged to suppliers
+/// (0 = charge every write-off immediately)
+///
+/// Lowering the threshold does not flush what is already pending; the next
+/// write-off or `socialize_bad_debt` crank does.
+pub fn set_bad_debt_threshold(
+    ctx: Context<SetBadDebtThreshold>,
+    market_id: [u8; 32],
+    bad_debt_threshold: u128,
+) -> Result<()> {
+    ctx.accounts.market.load_mut()?.bad_debt_threshold = bad_debt_threshold;
+    emit!(BadDebtThresholdSet { market_id, bad_debt_threshold });
+    Ok(())
+}
+
+// ============================================================================
+// Migrate Oracle Kind
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct MigrateOracleKind<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+}
+
+/// One-time backfill of `oracle_kind` for markets created before the field existed
+///
+/// `pyth_feed_id` pins the feed a Pyth oracle must carry (zero otherwise).
+pub fn migrate_oracle_kind(
+    ctx: Context<MigrateOracleKind>,
+    market_id: [u8; 32],
+    oracle_kind: u8,
+    pyth_feed_id: [u8; 32],
+) -> Result<()> {
+    let market = &mut ctx.accounts.market.load_mut()?;
+    require!(!market.is_oracle_kind_migrated(), MorphoError::OracleKindAlreadySet);
+
+    let oracle_kind = OracleKind::try_from(oracle_kind)? as u8;
+    market.oracle_kind = oracle_kind;
+    market.pyth_feed_id = pyth_feed_id;
+    validate_market_pyth_feed_ids(market)?;
+    market.set_oracle_kind_migrated(true);
+    market.invalidate_oracle_cache();
+
+    emit!(OracleKindMigrated { market_id, oracle_kind });
+    Ok(())
+}
+
+// ============================================================================
+// Set Max Deviation
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct SetMaxDeviation<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+}
+
+/// Set the oracle deviation circuit breaker (0 = disabled)
+pub fn set_max_deviation(
+    ctx: Context<SetMaxDeviation>,
+    market_id: [u8; 32],
+    max_deviation_bps: u64,
+) -> Result<()> {
+    require!(max_deviation_bps <= BPS, MorphoError::InvalidInput);
+    let market = &mut ctx.accounts.market.load_mut()?;
+    market.max_deviation_bps = max_deviation_bps;
+    market.invalidate_oracle_cache();
+    emit!(MaxDeviationSet { market_id, max_deviation_bps });
+    Ok(())
+}
+
+// ============================================================================
+// Set Supply Cap
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct SetSupplyCap<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+}
+
+/// Tighten the market's supply cap immediately
+///
+/// Raising or removing the cap goes through `queue_param_change`. A cap
+/// below the current supply only blocks new supply; withdrawals are
+/// unaffected.
+pub fn set_supply_cap(ctx: Context<SetSupplyCap>, market_id: [u8; 32], supply_cap: u128) -> Result<()> {
+    let market = &mut ctx.accounts.market.load_mut()?;
+    let current = market.supply_cap;
+    require!(
+        supply_cap != 0 && (current == 0 || supply_cap <= current),
+        MorphoError::TimelockRequired
+    );
+    market.supply_cap = supply_cap;
+    emit!(SupplyCapSet { market_id, supply_cap });
+    Ok(())
+}
+
+// ============================================================================
+// Migrate Market
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct MigrateMarket<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    /// CHECK: Market in the Borsh layout, which `AccountLoader<Market>`
+    /// cannot load; owner and discriminator are checked in the handler
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump,
+    )]
+    pub market: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Rewrite a market created before the current layout
+///
+/// Decodes the Borsh layout (`LegacyMarket`), zero-extending accounts
+/// from before later fields were appended, and writes it back as
+/// `Market` at `Market::space()`. Zero-copy markets from before the Pyth
+/// feed ids (`MARKET_SPACE_BEFORE_FEED_IDS`) are zero-extended in place.
+/// New fields are chosen so zero keeps the previous behaviour, except the
+/// feed ids, which a Pyth market must be given here. The owner pays the
+/// extra rent. No-op for markets already at `Market::space()`.
+pub fn migrate_market(
+    ctx: Context<MigrateMarket>,
+    market_id: [u8; 32],
+    pyth_feed_id: [u8; 32],
+    pyth_secondary_feed_id: [u8; 32],
+) -> Result<()> {
+    let market = ctx.accounts.market.to_account_info();
+    require!(*market.owner == crate::ID, MorphoError::InvalidMarketId);
+
+    let old_len = market.data_len();
+    let new_len = Market::space();
+    let migrated = {
+        let data = market.try_borrow_data()?;
+        require!(data.starts_with(Market::DISCRIMINATOR), MorphoError::InvalidMarketId);
+        if old_len >= new_len {
+            return Ok(());
+        }
+
+        let mut migrated = if old_len >= MARKET_SPACE_BEFORE_FEED_IDS {
+            let mut current: Market = bytemuck::Zeroable::zeroed();
+            bytemuck::bytes_of_mut(&mut current)[..old_len - 8].copy_from_slice(&data[8..]);
+            current
+        } else {
+            let mut legacy = data[8..].to_vec();
+            legacy.resize(LEGACY_MARKET_SPACE - 8, 0);
+            Market::from(LegacyMarket::deserialize(&mut legacy.as_slice())?)
+        };
+        require!(migrated.market_id == market_id, MorphoError::InvalidMarketId);
+        migrated.pyth_feed_id = pyth_feed_id;
+        migrated.pyth_secondary_feed_id = pyth_secondary_feed_id;
+        validate_market_pyth_feed_ids(&migrated)?;
+        migrated
+    };
+
+    let rent_due = Rent::get()?
+        .minimum_balance(new_len)
+        .saturating_sub(market.lamports());
+    if rent_due > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.owner.to_account_info(),
+                    to: market.clone(),
+                },
+            ),
+            rent_due,
+        )?;
+    }
+    market.realloc(new_len, true)?;
+    market.try_borrow_mut_data()?[8..].copy_from_slice(bytemuck::bytes_of(&migrated));
+
+    emit!(MarketMigrated {
+        market_id,
+        old_len: old_len as u64,
+        new_len: new_len as u64,
+    });
+    Ok(())
+}
+
+// ============================================================================
+// Create TWAP Oracle
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(source: Pubkey, source_kind: u8, window_slots: u64)]
+pub struct CreateTwapOracle<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = TwapOracle::space(),
+        seeds = [PROGRAM_SEED_PREFIX, TwapOracle::SEED, source.as_ref(), &window_slots.to_le_bytes()],
+        bump,
+    )]
+    pub twap_oracle: Box<Account<'info, TwapOracle>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Create a TWAP over a Switchboard or Pyth feed
+///
+/// Markets use it by setting `oracle` to the TWAP account with
+/// `OracleKind::Twap`. It reads as stale until cranked with `update_twap`
+/// for a full window.
+pub fn create_twap_oracle(
+    ctx: Context<CreateTwapOracle>,
+    source: Pubkey,
+    source_kind: u8,
+    window_slots: u64,
+    max_staleness_slots: u64,
+    pyth_feed_id: [u8; 32],
+) -> Result<()> {
+    // Static prices are in raw units and TWAPs of TWAPs would need their
+    // own staleness rules, so only live feeds can be averaged
+    let kind = OracleKind::try_from(source_kind)?;
+    require!(
+        matches!(kind, OracleKind::Switchboard | OracleKind::Pyth),
+        MorphoError::InvalidOracleKind
+    );
+    validate_pyth_feed_id(kind, &pyth_feed_id)?;
+    require!(source != Pubkey::default(), MorphoError::InvalidOracle);
+    require!(
+        (1..=MAX_TWAP_WINDOW_SLOTS).contains(&window_slots),
+        MorphoError::InvalidInput
+    );
+    validate_max_staleness_slots(max_staleness_slots)?;
+
+    let twap = &mut ctx.accounts.twap_oracle;
+    twap.bump = ctx.bumps.twap_oracle;
+    twap.source = source;
+    twap.source_kind = source_kind;
+    twap.window_slots = window_slots;
+    twap.max_staleness_slots = max_staleness_slots;
+    twap.head = 0;
+    twap.count = 0;
+    twap.sample_slots = [0; TWAP_CAPACITY];
+    twap.sample_prices = [0; TWAP_CAPACITY];
+    twap.pyth_feed_id = pyth_feed_id;
+    twap.reserved = [0; 32];
+
+    emit!(TwapOracleCreated {
+        twap_oracle: twap.key(),
+        source,
+        source_kind,
+        window_slots,
+        max_staleness_slots,
+    });
+    Ok(())
+}
+
+// ============================================================================
+// Create Median Oracle
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(aggregator_id: [u8; 32])]
+pub struct CreateMedianOracle<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = MedianOracle::space(),
+        seeds = [PROGRAM_SEED_PREFIX, MedianOracle::SEED, &aggregator_id],
+        bump,
+    )]
+    pub median_oracle: Box<Account<'info, MedianOracle>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Create an aggregator over `feeds`, all of kind `feed_kind`
+///
+/// Markets use it by setting `oracle` to the aggregator with
+/// `OracleKind::Median`. It reads as stale until the first `update_median`.
+pub fn create_median_oracle(
+    ctx: Context<CreateMedianOracle>,
+    aggregator_id: [u8; 32],
+    feed_kind: u8,
+    feeds: Vec<Pubkey>,
+    min_healthy_feeds: u8,
+    max_staleness_slots: u64,
+    pyth_feed_id: [u8; 32],
+) -> Result<()> {
+    // Static prices are in raw units and nested medians add nothing
+    let kind = OracleKind::try_from(feed_kind)?;
+    require!(
+        matches!(kind, OracleKind::Switchboard | OracleKind::Pyth | OracleKind::Twap),
+        MorphoError::InvalidOracleKind
+    );
+    validate_pyth_feed_id(kind, &pyth_feed_id)?;
+    validate_median_feeds(&feeds, min_healthy_feeds)?;
+    validate_max_staleness_slots(max_staleness_slots)?;
+
+    let median = &mut ctx.accounts.median_oracle;
+    median.bump = ctx.bumps.median_oracle;
+    median.aggregator_id = aggregator_id;
+    median.feed_kind = feed_kind;
+    median.feed_count = feeds.len() as u8;
+    median.feeds = [Pubkey::default(); MAX_MEDIAN_FEEDS];
+    median.feeds[..feeds.len()].copy_from_slice(&feeds);
+    median.min_healthy_feeds = min_healthy_feeds;
+    median.max_staleness_slots = max_staleness_slots;
+    median.price = 0;
+    median.last_update_slot = 0;
+    median.pyth_feed_id = pyth_feed_id;
+    median.reserved = [0; 32];
+
+    emit!(MedianOracleCreated {
+        median_oracle: median.key(),
+        aggregator_id,
+        feed_kind,
+        feeds,
+        min_healthy_feeds,
+    });
+    Ok(())
+}
+
+// ============================================================================
+// Timelocked Parameter Changes
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32], param: u8)]
+pub struct QueueParamChange<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = PendingParamChange::space(),
+        seeds = [PROGRAM_SEED_PREFIX, PendingParamChange::SEED, &market_id, &[param]],
+        bump,
+    )]
+    pub pending_change: Account<'info, PendingParamChange>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Queue a change to a market parameter (see `ParamKind`)
+///
+/// `address` is used for the oracles and IRM, `value` for everything else.
+/// Queuing again for the same parameter replaces the pending value and
+/// restarts the delay. Oracle changes are executed with `set_oracle`,
+/// fallback changes with `set_oracle_fallback`, everything else with
+/// `execute_param_change`.
+pub fn queue_param_change(
+    ctx: Context<QueueParamChange>,
+    market_id: [u8; 32],
+    param: u8,
+    value: u64,
+    address: Pubkey,
+) -> Result<()> {
+    let kind = ParamKind::try_from(param)?;
+    validate_param_change(&ctx.accounts.protocol_state, kind, value, &address)?;
+
+    let slot = Clock::get()?.slot;
+    let execute_after_slot = slot
+        .checked_add(PARAM_CHANGE_DELAY_SLOTS)
+        .ok_or(MorphoError::MathOverflow)?;
+
+    let pending = &mut ctx.accounts.pending_change;
+    pending.bump = ctx.bumps.pending_change;
+    pending.market_id = market_id;
+    pending.param = param;
+    pending.value = value;
+    pending.address = address;
+    pending.queued_at_slot = slot;
+    pending.execute_after_slot = execute_after_slot;
+
+    emit!(ParamChangeQueued {
+        market_id,
+        param,
+        value,
+        address,
+        execute_after_slot,
+    });
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32], param: u8)]
+pub struct ExecuteParamChange<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [PROGRAM_SEED_PREFIX, PendingParamChange::SEED, &market_id, &[param]],
+        bump = pending_change.bump,
+    )]
+    pub pending_change: Account<'info, PendingParamChange>,
+}
+
+/// Apply a queued change once its delay has elapsed
+///
+/// Bounds are checked again since the whitelist may have changed while the
+/// change was pending. `market_id` stays the hash of the creation parameters.
+/// Oracle and fallback changes must read both feeds, so they execute
+/// through `set_oracle` and `set_oracle_fallback` instead.
+pub fn execute_param_change(
+    ctx: Context<ExecuteParamChange>,
+    market_id: [u8; 32],
+    param: u8,
+) -> Result<()> {
+    let pending = &ctx.accounts.pending_change;
+    require!(
+        pending.is_executable(Clock::get()?.slot),
+        MorphoError::TimelockNotElapsed
+    );
+
+    let kind = ParamKind::try_from(param)?;
+    let (value, address) = (pending.value, pending.address);
+    validate_param_change(&ctx.accounts.protocol_state, kind, value, &address)?;
+
+    let market = &mut ctx.accounts.market.load_mut()?;
+    match kind {
+        ParamKind::Fee => apply_fee(market, market_id, value)?,
+        ParamKind::Lltv => market.lltv = value,
+        ParamKind::Oracle | ParamKind::OracleFallback => return err!(MorphoError::InvalidParamKind),
+        ParamKind::Irm => market.irm = address,
+        ParamKind::CloseFactor => {
+            market.close_factor_bps = value;
+            emit!(CloseFactorSet { market_id, close_factor_bps: value });
+        }
+        ParamKind::AuctionDuration => {
+            market.auction_duration_slots = value;
+            emit!(AuctionDurationSet { market_id, auction_duration_slots: value });
+        }
+        ParamKind::LiquidationProtocolFee => {
+            market.liquidation_protocol_fee_bps = value;
+            emit!(LiquidationProtocolFeeSet { market_id, liquidation_protocol_fee_bps: value });
+        }
+        ParamKind::LiquidationGrace => {
+            market.liquidation_grace_slots = value;
+            emit!(LiquidationGraceSet { market_id, liquidation_grace_slots: value });
+        }
+        ParamKind::AccrualBasis => apply_accrual_basis(market, market_id, value, &Clock::get()?)?,
+        ParamKind::SupplyCap => market.supply_cap = value as u128,
+    }
+
+    emit!(ParamChangeExecuted {
+        market_id,
+        param,
+        value,
+        address,
+    });
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct SetOracle<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [PROGRAM_SEED_PREFIX, PendingParamChange::SEED, &market_id, &[ParamKind::Oracle as u8]],
+        bump = pending_change.bump,
+    )]
+    pub pending_change: Account<'info, PendingParamChange>,
+
+    /// CHECK: Current oracle - checked against the market in the handler
+    pub old_oracle: UncheckedAccount<'info>,
+
+    /// CHECK: Queued oracle - checked against the pending change in the handler
+    pub new_oracle: UncheckedAccount<'info>,
+
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback for the old oracle read, if the market has one
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+}
+
+/// Execute a queued oracle change once both feeds agree
+///
+/// Reads the old and new oracle in the same slot, priced exactly as the
+/// market would price them, and rejects the switch if they are more than
+/// MAX_ORACLE_MIGRATION_BPS apart, so a migration cannot make healthy
+/// positions liquidatable at once. The old read may go through the
+/// market's fallback; the new feed must read on its own.
+pub fn set_oracle(ctx: Context<SetOracle>, market_id: [u8; 32]) -> Result<()> {
+    let pending = &ctx.accounts.pending_change;
+    require!(
+        pending.is_executable(Clock::get()?.slot),
+        MorphoError::TimelockNotElapsed
+    );
+    let (value, new_oracle) = (pending.value, pending.address);
+    validate_param_change(&ctx.accounts.protocol_state, ParamKind::Oracle, value, &new_oracle)?;
+    require!(ctx.accounts.new_oracle.key() == new_oracle, MorphoError::InvalidOracle);
+
+    let secondary = ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref());
+    let fallback = ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref());
+    let market = &mut ctx.accounts.market.load_mut()?;
+    let old_oracle = market.oracle;
+    let old_price = get_oracle_price_validated(
+        &ctx.accounts.old_oracle.to_account_info(),
+        secondary,
+        fallback,
+        market,
+    )?;
+
+    let mut candidate = **market;
+    switch_oracle(&mut candidate, new_oracle);
+    let new_price = get_oracle_price_validated(
+        &ctx.accounts.new_oracle.to_account_info(),
+        secondary,
+        None,
+        &mut candidate,
+    )?;
+    check_oracle_migration(old_price, new_price)?;
+
+    switch_oracle(market, new_oracle);
+
+    emit!(ParamChangeExecuted {
+        market_id,
+        param: ParamKind::Oracle as u8,
+        value,
+        address: new_oracle,
+    });
+    emit!(OracleMigrated {
+        market_id,
+        old_oracle,
+        new_oracle,
+        old_price,
+        new_price,
+    });
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct SetOracleFallback<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+        constraint = protocol_state.owner == owner.key() @ MorphoError::Unauthorized,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [PROGRAM_SEED_PREFIX, PendingParamChange::SEED, &market_id, &[ParamKind::OracleFallback as u8]],
+        bump = pending_change.bump,
+    )]
+    pub pending_change: Account<'info, PendingParamChange>,
+
+    /// CHECK: Current oracle - checked against the market in the handler
+    pub oracle: UncheckedAccount<'info>,
+
+    /// CHECK: Queued fallback - checked against the pending change in the
+    /// handler; not needed when the fallback is being removed
+    pub new_fallback: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Current fallback for the oracle read, if the market has one
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+}
+
+/// Execute a queued fallback oracle change once it agrees with the oracle
+///
+/// The new fallback is read on its own, priced exactly as the market would
+/// price it, and rejected if it is more than MAX_ORACLE_MIGRATION_BPS from
+/// the market's current price in the same slot (see `set_oracle`).
+/// Removing the fallback (Pubkey::default()) reads nothing.
+pub fn set_oracle_fallback(ctx: Context<SetOracleFallback>, market_id: [u8; 32]) -> Result<()> {
+    let pending = &ctx.accounts.pending_change;
+    require!(
+        pending.is_executable(Clock::get()?.slot),
+        MorphoError::TimelockNotElapsed
+    );
+    let (value, oracle_fallback) = (pending.value, pending.address);
+    validate_param_change(&ctx.accounts.protocol_state, ParamKind::OracleFallback, value, &oracle_fallback)?;
+
+    let market = &mut ctx.accounts.market.load_mut()?;
+    require!(oracle_fallback != market.oracle, MorphoError::InvalidOracle);
+
+    if oracle_fallback != Pubkey::default() {
+        let new_fallback = ctx.accounts.new_fallback.as_ref().ok_or(MorphoError::InvalidOracle)?;
+        require!(new_fallback.key() == oracle_fallback, MorphoError::InvalidOracle);
+
+        let secondary = ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref());
+        let fallback = ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref());
+        let current_price = get_oracle_price_validated(
+            &ctx.accounts.oracle.to_account_info(),
+            secondary,
+            fallback,
+            market,
+        )?;
+
+        // Price the fallback as if it were the market's only oracle
+        let mut candidate = **market;
+        switch_oracle(&mut candidate, oracle_fallback);
+        candidate.oracle_fallback = Pubkey::default();
+        let fallback_price = get_oracle_price_validated(
+            &new_fallback.to_account_info(),
+            secondary,
+            None,
+            &mut candidate,
+        )?;
+        check_oracle_migration(current_price, fallback_price)?;
+    }
+
+    market.oracle_fallback = oracle_fallback;
+    market.invalidate_oracle_cache();
+
+    emit!(ParamChangeExecuted {
+        market_id,
+        param: ParamKind::OracleFallback as u8,
+        value,
+        address: oracle_fallback,
+    });
+    emit!(OracleFallbackSet { market_id, oracle_fallback });
+    Ok(())
+}
+
+/// Point the market at `oracle` and clear state tied to the old feed
+fn switch_oracle(market: &mut Market, oracle: Pubkey) {
+    market.oracle = oracle;
+    // The deviation breaker must not compare against the old feed
+    market.last_oracle_price = 0;
+    market.last_oracle_slot = 0;
+    market.invalidate_oracle_cache();
+}
+
+/// Bounds shared by queue and execute
+fn validate_param_change(
+    protocol_state: &ProtocolState,
+    kind: ParamKind,
+    value: u64,
+    address: &Pubkey,
+) -> Result<()> {
+    match kind {
+        ParamKind::Fee => require!(value <= MAX_FEE, MorphoError::FeeTooHigh),
+        ParamKind::Lltv => require!(protocol_state.is_lltv_enabled(value), MorphoError::LltvNotEnabled),
+        ParamKind::Oracle => require!(*address != Pubkey::default(), MorphoError::InvalidOracle),
+        ParamKind::Irm => require!(protocol_state.is_irm_enabled(address), MorphoError::IrmNotEnabled),
+        ParamKind::CloseFactor => require!(value <= BPS, MorphoError::InvalidInput),
+        ParamKind::AuctionDuration => require!(value <= MAX_AUCTION_DURATION_SLOTS, MorphoError::InvalidInput),
+        ParamKind::LiquidationProtocolFee => require!(
+            value <= MAX_LIQUIDATION_PROTOCOL_FEE_BPS,
+            MorphoError::FeeTooHigh
+        ),
+        ParamKind::LiquidationGrace => require!(value <= MAX_LIQUIDATION_GRACE_SLOTS, MorphoError::InvalidInput),
+        ParamKind::AccrualBasis => {
+            AccrualBasis::try_from(u8::try_from(value).map_err(|_| MorphoError::InvalidInput)?)?;
+        }
+        ParamKind::SupplyCap | ParamKind::OracleFallback => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{MAX_IRMS, MAX_LLTVS};
+    use crate::interfaces::INITIAL_RATE_AT_TARGET;
+
+    fn test_protocol_state() -> ProtocolState {
+        ProtocolState {
+            bump: 0,
+            owner: Pubkey::default(),
+            pending_owner: Pubkey::default(),
+            fee_recipient: Pubkey::default(),
+            paused: false,
+            lltv_count: 0,
+            enabled_lltvs: [0; MAX_LLTVS],
+            irm_count: 0,
+            enabled_irms: [Pubkey::default(); MAX_IRMS],
+            market_count: 0,
+            guardian: Pubkey::default(),
+            reserved: [0; 224],
+        }
+    }
+
+    #[test]
+    fn test_validate_param_change_rejects_out_of_range_values() {
+        let state = test_protocol_state();
+        let validate = |kind, value| validate_param_change(&state, kind, value, &Pubkey::default());
+
+        let bounds = [
+            (ParamKind::CloseFactor, BPS, MorphoError::InvalidInput),
+            (ParamKind::AuctionDuration, MAX_AUCTION_DURATION_SLOTS, MorphoError::InvalidInput),
+            (ParamKind::LiquidationProtocolFee, MAX_LIQUIDATION_PROTOCOL_FEE_BPS, MorphoError::FeeTooHigh),
+            (ParamKind::LiquidationGrace, MAX_LIQUIDATION_GRACE_SLOTS, MorphoError::InvalidInput),
+        ];
+        for (kind, max, error) in bounds {
+            assert!(validate(kind, max).is_ok(), "{kind:?} accepts its maximum");
+            assert_eq!(validate(kind, max + 1).unwrap_err(), error.into(), "{kind:?} rejects its maximum + 1");
+        }
+
+        assert!(validate(ParamKind::AccrualBasis, AccrualBasis::Slots as u64).is_ok());
+        assert_eq!(validate(ParamKind::AccrualBasis, 2).unwrap_err(), MorphoError::InvalidInput.into());
+        // Must not wrap to a valid basis when truncated to u8
+        assert_eq!(validate(ParamKind::AccrualBasis, 257).unwrap_err(), MorphoError::InvalidInput.into());
+    }
+
+    #[test]
+    fn test_accrual_basis_switch_accrues_then_reanchors() {
+        let start = Clock { slot: 250_000_000, unix_timestamp: 1_700_000_000, ..Clock::default() };
+        let end = Clock { slot: start.slot + 216_000, unix_timestamp: start.unix_timestamp + 86_400, ..Clock::default() };
+        let mut market = Market {
+            total_supply_assets: 1_000_000_000_000,
+            total_supply_shares: 1_000_000_000_000_000_000,
+            total_borrow_assets: 500_000_000_000,
+            total_borrow_shares: 500_000_000_000_000_000,
+            last_update: start.unix_timestamp,
+            rate_at_target: INITIAL_RATE_AT_TARGET as u128,
+            ..bytemuck::Zeroable::zeroed()
+        };
+
+        // The day before the switch is charged on the timestamp clock
+        let mut expected = market;
+        let rate = compute_borrow_rate(&mut expected, 86_400).unwrap();
+        accrue_interest_on_market(&mut expected, end.unix_timestamp, rate).unwrap();
+        assert!(expected.total_borrow_assets > market.total_borrow_assets);
+
+        apply_accrual_basis(&mut market, [0u8; 32], AccrualBasis::Slots as u64, &end).unwrap();
+        assert_eq!(market.total_borrow_assets, expected.total_borrow_assets);
+        assert_eq!(market.total_supply_assets, expected.total_supply_assets);
+        assert_eq!(market.accrual_basis().unwrap(), AccrualBasis::Slots);
+
+        // last_update now reads the slot clock, so the next accrual starts
+        // from the switch rather than from a timestamp-scale value
+        assert_eq!(market.last_update, market.accrual_time(&end).unwrap());
+        assert_eq!(market.last_update, 100_086_400);
+        let borrowed = market.total_borrow_assets;
+        let now = market.accrual_time(&end).unwrap();
+        let rate = compute_borrow_rate(&mut market, 0).unwrap();
+        accrue_interest_on_market(&mut market, now, rate).unwrap();
+        assert_eq!(market.total_borrow_assets, borrowed);
+    }
+}
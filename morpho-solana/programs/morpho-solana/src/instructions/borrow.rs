@@ -10,10 +10,16 @@ use crate::events;
 use crate::state::{ProtocolState, Market, Position, Authorization};
 use crate::math::{
     checked_add, checked_sub, safe_u128_to_u64,
-    to_shares_up, to_shares_down, to_assets_up,
+    to_shares_up, to_assets_up, repay_amounts,
     accrue_interest_on_market,
 };
-use crate::interfaces::{get_borrow_rate_internal, get_oracle_price_validated, is_liquidatable};
+use super::utils::{validate_authorization, check_deadline};
+use crate::interfaces::{
+    compute_borrow_rate, get_oracle_price_validated, is_liquidatable,
+    amount_received, amount_to_send, wrap_native, unwrap_native,
+    invoke_callback, validate_callback_program, verify_vault_delta, ON_MORPHO_REPAY,
+    repay_backstops_position,
+};
 
 // ============================================================================
 // Supply Collateral
@@ -32,10 +38,12 @@ pub struct SupplyCollateral<'info> {
     pub protocol_state: Box<Account<'info, ProtocolState>>,
 
     #[account(
+        mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
@@ -47,39 +55,79 @@ pub struct SupplyCollateral<'info> {
     /// CHECK: Position owner
     pub on_behalf_of: UncheckedAccount<'info>,
 
+    /// CHECK: Oracle account, pass to clear a liquidation auction or grace
+    /// period the deposit ends, see `clear_stamps_if_healthy`
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+
     #[account(
         mut,
-        constraint = depositor_token_account.mint == market.collateral_mint,
+        constraint = depositor_token_account.mint == market.load()?.collateral_mint,
     )]
     pub depositor_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::COLLATERAL_VAULT_SEED, &market_id],
-        bump = market.collateral_vault_bump,
+        bump = market.load()?.collateral_vault_bump,
+        token::mint = collateral_mint,
+        token::authority = market,
     )]
     pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(constraint = collateral_mint.key() == market.load()?.collateral_mint @ MorphoError::InvalidMint)]
     pub collateral_mint: InterfaceAccount<'info, Mint>,
 
     pub token_program: Interface<'info, TokenInterface>,
 }
 
+/// Deposit collateral, crediting the amount the vault actually receives
+///
+/// Collateral only backs borrows: it mints no supply shares and earns no
+/// interest, so market totals are untouched. With `oracle` passed, a
+/// position the deposit brings back to health has its liquidation auction
+/// and grace period cleared.
 pub fn supply_collateral(
     ctx: Context<SupplyCollateral>,
     market_id: [u8; 32],
     amount: u128,
 ) -> Result<()> {
     // ===== CHECKS =====
-    require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
-    require!(!ctx.accounts.market.paused, MorphoError::MarketPaused);
+    // Note: Collateral deposits allowed even when paused (helps users de-risk)
     require!(amount > 0, MorphoError::ZeroAmount);
 
+    let amount_u64 = safe_u128_to_u64(amount)?;
+    let received_u64 = amount_received(&ctx.accounts.collateral_mint.to_account_info(), amount_u64)?;
+    let received = received_u64 as u128;
+    require!(received > 0, MorphoError::ZeroAmount);
+
     // ===== EFFECTS =====
-    ctx.accounts.position.collateral = checked_add(ctx.accounts.position.collateral, amount)?;
+    ctx.accounts.position.collateral = checked_add(ctx.accounts.position.collateral, received)?;
+    ctx.accounts.position.touch()?;
+    if ctx.accounts.oracle.is_some() && ctx.accounts.position.has_liquidation_stamp() {
+        // Accrue interest so health is read against current debt
+        let clock = Clock::get()?;
+        let mut market = ctx.accounts.market.load_mut()?;
+        let current_time = market.accrual_time(&clock)?;
+        let elapsed = current_time - market.last_update;
+        let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+        accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
+        clear_stamps_if_healthy(
+            &mut ctx.accounts.position,
+            &mut market,
+            ctx.accounts.oracle.as_ref().map(|a| a.as_ref()),
+            ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref()),
+            ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref()),
+        )?;
+    }
 
     // ===== INTERACTIONS =====
-    let amount_u64 = safe_u128_to_u64(amount)?;
+    let vault_before = ctx.accounts.collateral_vault.amount;
     transfer_checked(
         CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
@@ -93,12 +141,14 @@ pub fn supply_collateral(
         amount_u64,
         ctx.accounts.collateral_mint.decimals,
     )?;
+    ctx.accounts.collateral_vault.reload()?;
+    verify_vault_delta(vault_before, ctx.accounts.collateral_vault.amount, received_u64)?;
 
     emit!(events::SupplyCollateral {
         market_id,
         depositor: ctx.accounts.depositor.key(),
         on_behalf_of: ctx.accounts.on_behalf_of.key(),
-        amount,
+        amount: received,
     });
 
     Ok(())
@@ -123,9 +173,10 @@ pub struct WithdrawCollateral<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
@@ -139,24 +190,34 @@ pub struct WithdrawCollateral<'info> {
     /// CHECK: Oracle account for health check
     pub oracle: UncheckedAccount<'info>,
 
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+
     #[account(
         mut,
-        constraint = receiver_token_account.mint == market.collateral_mint,
+        constraint = receiver_token_account.mint == market.load()?.collateral_mint,
     )]
     pub receiver_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::COLLATERAL_VAULT_SEED, &market_id],
-        bump = market.collateral_vault_bump,
+        bump = market.load()?.collateral_vault_bump,
+        token::mint = collateral_mint,
+        token::authority = market,
     )]
     pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(constraint = collateral_mint.key() == market.load()?.collateral_mint @ MorphoError::InvalidMint)]
     pub collateral_mint: InterfaceAccount<'info, Mint>,
 
     pub token_program: Interface<'info, TokenInterface>,
 }
 
+/// Withdraw collateral to any receiver, then require the position stays healthy
 pub fn withdraw_collateral(
     ctx: Context<WithdrawCollateral>,
     market_id: [u8; 32],
@@ -164,7 +225,7 @@ pub fn withdraw_collateral(
 ) -> Result<()> {
     // ===== CHECKS =====
     require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
-    require!(!ctx.accounts.market.paused, MorphoError::MarketPaused);
+    require!(!ctx.accounts.market.load()?.is_paused(), MorphoError::MarketPaused);
     require!(amount > 0, MorphoError::ZeroAmount);
 
     validate_authorization(
@@ -174,14 +235,12 @@ pub fn withdraw_collateral(
     )?;
 
     // Accrue interest
-    let borrow_rate = get_borrow_rate_internal(
-        ctx.accounts.market.total_supply_assets,
-        ctx.accounts.market.total_borrow_assets,
-    )?;
-    let current_time = Clock::get()?.unix_timestamp;
-    
-    let market = &mut ctx.accounts.market;
-    accrue_interest_on_market(market, current_time, borrow_rate)?;
+    let clock = Clock::get()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
 
     require!(
         ctx.accounts.position.collateral >= amount,
@@ -190,12 +249,17 @@ pub fn withdraw_collateral(
 
     // ===== EFFECTS =====
     ctx.accounts.position.collateral = checked_sub(ctx.accounts.position.collateral, amount)?;
+    ctx.accounts.position.touch()?;
+
+    ctx.accounts.position.ensure_debt_collateralized()?;
 
     // Health check AFTER effect, BEFORE interaction
     if ctx.accounts.position.borrow_shares > 0 {
         let oracle_price = get_oracle_price_validated(
             &ctx.accounts.oracle.to_account_info(),
-            market,
+            ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref()),
+            ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref()),
+            &mut market,
         )?;
         require!(
             !is_liquidatable(
@@ -206,13 +270,23 @@ pub fn withdraw_collateral(
                 oracle_price,
                 market.lltv,
             )?,
-            MorphoError::PositionUnhealthy
+            MorphoError::InsufficientCollateral
         );
+        let borrowed = to_assets_up(
+            ctx.accounts.position.borrow_shares,
+            market.total_borrow_assets,
+            market.total_borrow_shares,
+        )?;
+        market.ensure_position_size(ctx.accounts.position.collateral, borrowed, oracle_price)?;
     }
+    // Healthy afterwards, so any liquidation auction or grace period is over
+    ctx.accounts.position.auction_start_slot = 0;
+    ctx.accounts.position.became_unhealthy_slot = 0;
 
     // ===== INTERACTIONS =====
     let amount_u64 = safe_u128_to_u64(amount)?;
     let bump = market.bump;
+    drop(market);
     let seeds = &[
         PROGRAM_SEED_PREFIX,
         Market::SEED,
@@ -235,6 +309,9 @@ pub fn withdraw_collateral(
         ctx.accounts.collateral_mint.decimals,
     )?;
 
+    #[cfg(feature = "invariant-checks")]
+    crate::state::assert_invariants(&*ctx.accounts.market.load()?)?;
+
     emit!(events::WithdrawCollateral {
         market_id,
         caller: ctx.accounts.caller.key(),
@@ -265,9 +342,10 @@ pub struct Borrow<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
@@ -281,33 +359,56 @@ pub struct Borrow<'info> {
     /// CHECK: Oracle account for health check
     pub oracle: UncheckedAccount<'info>,
 
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+
     #[account(
         mut,
-        constraint = receiver_token_account.mint == market.loan_mint,
+        constraint = receiver_token_account.mint == market.load()?.loan_mint,
     )]
     pub receiver_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
-        bump = market.loan_vault_bump,
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
     )]
     pub loan_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
     pub loan_mint: InterfaceAccount<'info, Mint>,
 
     pub token_program: Interface<'info, TokenInterface>,
+
+    /// Pass in WSOL markets to close the caller-owned `receiver_token_account`
+    /// and receive the borrowed assets as native SOL
+    #[account(mut)]
+    pub native_receiver: Option<SystemAccount<'info>>,
 }
 
+/// Borrow `assets` against the position's collateral
+///
+/// A non-zero `max_shares` caps the debt shares minted. A non-zero
+/// `min_assets_out` is the fewest tokens the receiver must get after
+/// Token-2022 transfer fees. A non-zero `deadline_slot` rejects the
+/// transaction if it lands later than that slot.
 pub fn borrow(
     ctx: Context<Borrow>,
     market_id: [u8; 32],
     assets: u128,
     max_shares: u128,
+    min_assets_out: u128,
+    deadline_slot: u64,
 ) -> Result<()> {
     // ===== CHECKS =====
+    check_deadline(deadline_slot, Clock::get()?.slot)?;
     require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
-    require!(!ctx.accounts.market.paused, MorphoError::MarketPaused);
+    require!(!ctx.accounts.market.load()?.is_paused(), MorphoError::MarketPaused);
     require!(assets > 0, MorphoError::ZeroAmount);
 
     validate_authorization(
@@ -317,35 +418,41 @@ pub fn borrow(
     )?;
 
     // Accrue interest
-    let borrow_rate = get_borrow_rate_internal(
-        ctx.accounts.market.total_supply_assets,
-        ctx.accounts.market.total_borrow_assets,
-    )?;
-    let current_time = Clock::get()?.unix_timestamp;
-    
-    let market = &mut ctx.accounts.market;
-    accrue_interest_on_market(market, current_time, borrow_rate)?;
+    let clock = Clock::get()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
 
-    require!(
-        assets <= market.available_liquidity(),
-        MorphoError::InsufficientLiquidity
-    );
+    market.ensure_liquidity(assets)?;
+    market.ensure_utilization_cap(assets)?;
 
     // Calculate shares (round UP - user owes more)
     let shares = to_shares_up(assets, market.total_borrow_assets, market.total_borrow_shares)?;
     if max_shares > 0 {
         require!(shares <= max_shares, MorphoError::SlippageExceeded);
     }
+    let amount_u64 = safe_u128_to_u64(assets)?;
+    if min_assets_out > 0 {
+        let received = amount_received(&ctx.accounts.loan_mint.to_account_info(), amount_u64)?;
+        require!(received as u128 >= min_assets_out, MorphoError::SlippageExceeded);
+    }
 
     // ===== EFFECTS =====
-    ctx.accounts.position.borrow_shares = checked_add(ctx.accounts.position.borrow_shares, shares)?;
+    ctx.accounts.position.add_borrow_shares(shares, market.borrow_index())?;
+    ctx.accounts.position.touch()?;
     market.total_borrow_assets = checked_add(market.total_borrow_assets, assets)?;
     market.total_borrow_shares = checked_add(market.total_borrow_shares, shares)?;
 
+    ctx.accounts.position.ensure_debt_collateralized()?;
+
     // Health check AFTER effect
     let oracle_price = get_oracle_price_validated(
         &ctx.accounts.oracle.to_account_info(),
-        market,
+        ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref()),
+        ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref()),
+        &mut market,
     )?;
     require!(
         !is_liquidatable(
@@ -356,12 +463,21 @@ pub fn borrow(
             oracle_price,
             market.lltv,
         )?,
-        MorphoError::PositionUnhealthy
+        MorphoError::InsufficientCollateral
     );
+    let borrowed = to_assets_up(
+        ctx.accounts.position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+    )?;
+    market.ensure_position_size(ctx.accounts.position.collateral, borrowed, oracle_price)?;
+    // Healthy afterwards, so any liquidation auction or grace period is over
+    ctx.accounts.position.auction_start_slot = 0;
+    ctx.accounts.position.became_unhealthy_slot = 0;
 
     // ===== INTERACTIONS =====
-    let amount_u64 = safe_u128_to_u64(assets)?;
     let bump = market.bump;
+    drop(market);
     let seeds = &[
         PROGRAM_SEED_PREFIX,
         Market::SEED,
@@ -384,6 +500,19 @@ pub fn borrow(
         ctx.accounts.loan_mint.decimals,
     )?;
 
+    if let Some(native_receiver) = &ctx.accounts.native_receiver {
+        unwrap_native(
+            &ctx.accounts.token_program,
+            &ctx.accounts.caller,
+            &ctx.accounts.receiver_token_account,
+            &native_receiver.to_account_info(),
+        )?;
+    }
+
+    let market = ctx.accounts.market.load()?;
+    #[cfg(feature = "invariant-checks")]
+    crate::state::assert_invariants(&market)?;
+
     emit!(events::Borrow {
         market_id,
         caller: ctx.accounts.caller.key(),
@@ -391,6 +520,8 @@ pub fn borrow(
         receiver: ctx.accounts.receiver_token_account.key(),
         assets,
         shares,
+        total_borrow_assets: market.total_borrow_assets,
+        total_borrow_shares: market.total_borrow_shares,
     });
 
     Ok(())
@@ -409,9 +540,10 @@ pub struct Repay<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
@@ -425,66 +557,147 @@ pub struct Repay<'info> {
 
     #[account(
         mut,
-        constraint = repayer_token_account.mint == market.loan_mint,
+        constraint = repayer_token_account.mint == market.load()?.loan_mint,
     )]
     pub repayer_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
-        bump = market.loan_vault_bump,
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
     )]
     pub loan_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
     pub loan_mint: InterfaceAccount<'info, Mint>,
 
     pub token_program: Interface<'info, TokenInterface>,
+
+    /// CHECK: Optional program invoked before tokens are pulled, see `validate_callback_program`
+    pub callback_program: Option<UncheckedAccount<'info>>,
+
+    /// Pass in WSOL markets to wrap the repaid lamports from the repayer
+    /// into `repayer_token_account` and close it afterwards
+    pub system_program: Option<Program<'info, System>>,
+
+    /// CHECK: Oracle account, pass to clear a liquidation auction or grace
+    /// period the repayment ends, see `clear_stamps_if_healthy`
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
 }
 
-pub fn repay(
-    ctx: Context<Repay>,
+/// Repay debt by assets or shares
+///
+/// With a `callback_program`, `on_morpho_repay(assets: u64, data: Vec<u8>)`
+/// is invoked after the debt is reduced and before tokens are pulled. It
+/// receives the repayer (signer), repayer_token_account, loan_mint, then
+/// `remaining_accounts`.
+///
+/// For Token-2022 mints with a transfer fee the repayer sends enough extra
+/// to cover the fee, so the vault receives exactly the debt being cleared.
+/// A non-zero `max_assets_in` caps that amount, so repaying by shares cannot
+/// cost more than quoted once interest has accrued.
+///
+/// Anyone can repay on behalf of any borrower. Clearing the residual debt
+/// of a position with no collateral left (e.g. after a pre-liquidation
+/// seized all of it) leaves nothing for a write-off, so suppliers take no
+/// loss; this emits `DebtBackstopped`.
+///
+/// A position left healthy has its liquidation auction and grace period
+/// cleared: always once its debt is gone, otherwise when `oracle` is passed.
+pub fn repay<'info>(
+    ctx: Context<'_, '_, '_, 'info, Repay<'info>>,
     market_id: [u8; 32],
     assets: u128,
     shares: u128,
+    max_assets_in: u128,
+    data: Vec<u8>,
 ) -> Result<()> {
     // ===== CHECKS =====
     // Note: Repay allowed even when paused (helps users exit)
     require!(assets > 0 || shares > 0, MorphoError::ZeroAmount);
     require!(!(assets > 0 && shares > 0), MorphoError::InvalidInput);
+    if let Some(callback_program) = &ctx.accounts.callback_program {
+        validate_callback_program(callback_program)?;
+    }
 
     // Accrue interest
-    let borrow_rate = get_borrow_rate_internal(
-        ctx.accounts.market.total_supply_assets,
-        ctx.accounts.market.total_borrow_assets,
-    )?;
-    let current_time = Clock::get()?.unix_timestamp;
-    
-    let market = &mut ctx.accounts.market;
-    accrue_interest_on_market(market, current_time, borrow_rate)?;
+    let clock = Clock::get()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
 
     let position = &ctx.accounts.position;
 
-    // Calculate amounts
-    let (repay_assets, burn_shares) = if assets > 0 {
-        let s = to_shares_down(assets, market.total_borrow_assets, market.total_borrow_shares)?;
-        let s = std::cmp::min(s, position.borrow_shares);
-        let a = to_assets_up(s, market.total_borrow_assets, market.total_borrow_shares)?;
-        (a, s)
-    } else {
-        let s = std::cmp::min(shares, position.borrow_shares);
-        let a = to_assets_up(s, market.total_borrow_assets, market.total_borrow_shares)?;
-        (a, s)
-    };
+    // Calculate amounts (pass `shares = borrow_shares` to clear the debt exactly)
+    let (repay_assets, burn_shares) = repay_amounts(
+        assets,
+        shares,
+        position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+    )?;
 
     require!(burn_shares > 0, MorphoError::ZeroAmount);
+    let backstopped = repay_backstops_position(position, burn_shares);
+
+    let owed_u64 = safe_u128_to_u64(repay_assets)?;
+    let amount_u64 = amount_to_send(&ctx.accounts.loan_mint.to_account_info(), owed_u64)?;
+    if max_assets_in > 0 {
+        require!(amount_u64 as u128 <= max_assets_in, MorphoError::SlippageExceeded);
+    }
 
     // ===== EFFECTS =====
     ctx.accounts.position.borrow_shares = checked_sub(ctx.accounts.position.borrow_shares, burn_shares)?;
-    market.total_borrow_assets = checked_sub(market.total_borrow_assets, repay_assets)?;
+    ctx.accounts.position.touch()?;
+    // Rounding up can put the last repay a unit above the market total
+    market.total_borrow_assets = market.total_borrow_assets.saturating_sub(repay_assets);
     market.total_borrow_shares = checked_sub(market.total_borrow_shares, burn_shares)?;
+    clear_stamps_if_healthy(
+        &mut ctx.accounts.position,
+        &mut market,
+        ctx.accounts.oracle.as_ref().map(|a| a.as_ref()),
+        ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref()),
+        ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref()),
+    )?;
+    drop(market);
 
     // ===== INTERACTIONS =====
-    let amount_u64 = safe_u128_to_u64(repay_assets)?;
+    // Let the integrator source tokens just-in-time
+    if let Some(callback_program) = &ctx.accounts.callback_program {
+        ctx.accounts.market.load_mut()?.enter_callback()?;
+        ctx.accounts.position.exit(&crate::ID)?;
+
+        let mut callback_accounts = vec![
+            ctx.accounts.repayer.to_account_info(),
+            ctx.accounts.repayer_token_account.to_account_info(),
+            ctx.accounts.loan_mint.to_account_info(),
+        ];
+        callback_accounts.extend(ctx.remaining_accounts.iter().cloned());
+        invoke_callback(callback_program, ON_MORPHO_REPAY, (amount_u64, data), &callback_accounts)?;
+        ctx.accounts.market.load_mut()?.exit_callback();
+    }
+
+    if let Some(system_program) = &ctx.accounts.system_program {
+        wrap_native(
+            &ctx.accounts.token_program,
+            system_program,
+            &ctx.accounts.repayer,
+            &ctx.accounts.repayer_token_account,
+            amount_u64,
+        )?;
+    }
+
+    let vault_before = ctx.accounts.loan_vault.amount;
     transfer_checked(
         CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
@@ -498,6 +711,21 @@ pub fn repay(
         amount_u64,
         ctx.accounts.loan_mint.decimals,
     )?;
+    ctx.accounts.loan_vault.reload()?;
+    verify_vault_delta(vault_before, ctx.accounts.loan_vault.amount, owed_u64)?;
+
+    if ctx.accounts.system_program.is_some() {
+        unwrap_native(
+            &ctx.accounts.token_program,
+            &ctx.accounts.repayer,
+            &ctx.accounts.repayer_token_account,
+            &ctx.accounts.repayer.to_account_info(),
+        )?;
+    }
+
+    let market = ctx.accounts.market.load()?;
+    #[cfg(feature = "invariant-checks")]
+    crate::state::assert_invariants(&market)?;
 
     emit!(events::Repay {
         market_id,
@@ -505,31 +733,60 @@ pub fn repay(
         on_behalf_of: ctx.accounts.on_behalf_of.key(),
         assets: repay_assets,
         shares: burn_shares,
+        total_borrow_assets: market.total_borrow_assets,
+        total_borrow_shares: market.total_borrow_shares,
     });
+    if backstopped {
+        emit!(events::DebtBackstopped {
+            market_id,
+            backstop: ctx.accounts.repayer.key(),
+            borrower: ctx.accounts.on_behalf_of.key(),
+            assets: repay_assets,
+            shares: burn_shares,
+        });
+    }
 
     Ok(())
 }
 
-/// Validate authorization for delegated operations
-fn validate_authorization(
-    caller: &Signer,
-    owner: &Pubkey,
-    authorization: Option<&Account<Authorization>>,
+// ============================================================================
+// Helpers
+// ============================================================================
+
+/// Clear a stamped position's liquidation auction and grace period once it
+/// is healthy, so the next breach starts both afresh
+///
+/// A position without debt is healthy whatever the price; otherwise health
+/// needs the oracle, and without it the stamps stay until
+/// `start_liquidation_auction` or a borrow resets them.
+fn clear_stamps_if_healthy(
+    position: &mut Position,
+    market: &mut Market,
+    oracle: Option<&AccountInfo>,
+    oracle_secondary: Option<&AccountInfo>,
+    oracle_fallback: Option<&AccountInfo>,
 ) -> Result<()> {
-    if caller.key() == *owner {
+    if !position.has_liquidation_stamp() {
         return Ok(());
     }
-
-    let current_time = Clock::get()?.unix_timestamp;
-
-    if let Some(auth) = authorization {
-        if auth.authorizer == *owner
-            && auth.authorized == caller.key()
-            && auth.is_valid(current_time)
-        {
-            return Ok(());
-        }
+    let healthy = if !position.has_debt() {
+        true
+    } else if let Some(oracle) = oracle {
+        let oracle_price = get_oracle_price_validated(oracle, oracle_secondary, oracle_fallback, market)?;
+        !is_liquidatable(
+            position.collateral,
+            position.borrow_shares,
+            market.total_borrow_assets,
+            market.total_borrow_shares,
+            oracle_price,
+            market.lltv,
+        )?
+    } else {
+        false
+    };
+    if healthy {
+        position.auction_start_slot = 0;
+        position.became_unhealthy_slot = 0;
     }
-
-    Err(MorphoError::Unauthorized.into())
+    Ok(())
 }
@@ -1,4 +1,7 @@
-//! Flash loan instruction with lock mechanism
+//! Flash loan instructions with lock mechanism
+//!
+//! - `flash_loan`: single instruction, funds are returned from a CPI callback
+//! - `flash_loan_start` / `flash_loan_end`: two-instruction variant
 
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, transfer_checked, TransferChecked};
@@ -7,6 +10,7 @@ use crate::errors::MorphoError;
 use crate::events::FlashLoan;
 use crate::state::{ProtocolState, Market};
 use crate::math::{checked_add, safe_u128_to_u64, mul_div_up};
+use crate::interfaces::{invoke_callback, validate_callback_program, ON_FLASH_LOAN};
 
 #[derive(Accounts)]
 #[instruction(market_id: [u8; 32])]
@@ -23,23 +27,27 @@ pub struct FlashLoanStart<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
-        constraint = borrower_token_account.mint == market.loan_mint,
+        constraint = borrower_token_account.mint == market.load()?.loan_mint,
     )]
     pub borrower_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
-        bump = market.loan_vault_bump,
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
     )]
     pub loan_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
     pub loan_mint: InterfaceAccount<'info, Mint>,
 
     pub token_program: Interface<'info, TokenInterface>,
@@ -53,18 +61,11 @@ pub fn flash_loan_start(
 ) -> Result<()> {
     // ===== CHECKS =====
     require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
-    require!(!ctx.accounts.market.paused, MorphoError::MarketPaused);
+    let mut market = ctx.accounts.market.load_mut()?;
+    require!(!market.is_paused(), MorphoError::MarketPaused);
     require!(amount > 0, MorphoError::ZeroAmount);
-    require!(
-        amount <= ctx.accounts.market.available_liquidity(),
-        MorphoError::InsufficientLiquidity
-    );
-    require!(
-        !ctx.accounts.market.is_flash_loan_active(),
-        MorphoError::FlashLoanInProgress
-    );
-
-    let market = &mut ctx.accounts.market;
+    market.ensure_liquidity(amount)?;
+    require!(!market.is_flash_loan_active(), MorphoError::FlashLoanInProgress);
     
     // Set flash loan lock
     market.flash_loan_lock = 1;
@@ -72,6 +73,7 @@ pub fn flash_loan_start(
     // ===== INTERACTIONS =====
     let amount_u64 = safe_u128_to_u64(amount)?;
     let bump = market.bump;
+    drop(market);
     let seeds = &[
         PROGRAM_SEED_PREFIX,
         Market::SEED,
@@ -106,23 +108,27 @@ pub struct FlashLoanEnd<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
-        constraint = borrower_token_account.mint == market.loan_mint,
+        constraint = borrower_token_account.mint == market.load()?.loan_mint,
     )]
     pub borrower_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
-        bump = market.loan_vault_bump,
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
     )]
     pub loan_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
     pub loan_mint: InterfaceAccount<'info, Mint>,
 
     pub token_program: Interface<'info, TokenInterface>,
@@ -136,7 +142,7 @@ pub fn flash_loan_end(
 ) -> Result<()> {
     // ===== CHECKS =====
     require!(
-        ctx.accounts.market.is_flash_loan_active(),
+        ctx.accounts.market.load()?.is_flash_loan_active(),
         MorphoError::FlashLoanCallbackFailed
     );
 
@@ -162,7 +168,7 @@ pub fn flash_loan_end(
     )?;
 
     // ===== EFFECTS (after successful repayment) =====
-    let market = &mut ctx.accounts.market;
+    let mut market = ctx.accounts.market.load_mut()?;
     
     // Fee goes to suppliers
     market.total_supply_assets = checked_add(market.total_supply_assets, fee)?;
@@ -180,28 +186,85 @@ pub fn flash_loan_end(
     Ok(())
 }
 
-/// Single-instruction flash loan (for composable transactions)
-/// The token repayment must happen atomically within the same transaction
-pub fn flash_loan(
-    ctx: Context<FlashLoanStart>,
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct FlashLoanWithCallback<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+    )]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        mut,
+        constraint = borrower_token_account.mint == market.load()?.loan_mint,
+    )]
+    pub borrower_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
+    )]
+    pub loan_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
+    pub loan_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: Program invoked with the borrowed funds, see `validate_callback_program`
+    pub callback_program: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Single-instruction flash loan with a CPI callback
+///
+/// Sends `amount` to the borrower, then invokes `callback_program` with the
+/// `on_flash_loan(amount: u64, fee: u64, data: Vec<u8>)` Anchor instruction.
+/// Accounts passed to the callback are the borrower, the borrower's token
+/// account, the loan mint and the loan vault, followed by `remaining_accounts`.
+/// The vault must hold principal + fee once the callback returns.
+pub fn flash_loan<'info>(
+    ctx: Context<'_, '_, '_, 'info, FlashLoanWithCallback<'info>>,
     market_id: [u8; 32],
     amount: u128,
+    data: Vec<u8>,
 ) -> Result<()> {
+    // ===== CHECKS =====
     require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
-    require!(!ctx.accounts.market.paused, MorphoError::MarketPaused);
+    let mut market = ctx.accounts.market.load_mut()?;
+    require!(!market.is_paused(), MorphoError::MarketPaused);
     require!(amount > 0, MorphoError::ZeroAmount);
-    require!(
-        amount <= ctx.accounts.market.available_liquidity(),
-        MorphoError::InsufficientLiquidity
-    );
+    market.ensure_liquidity(amount)?;
+    require!(!market.is_flash_loan_active(), MorphoError::FlashLoanInProgress);
+    validate_callback_program(&ctx.accounts.callback_program)?;
 
     let fee = mul_div_up(amount, FLASH_LOAN_FEE_BPS as u128, BPS as u128)?;
     let vault_before = ctx.accounts.loan_vault.amount;
 
+    // Lock the market; the flags are written in place, so a reentrant
+    // call sees them once the borrow is dropped
+    market.flash_loan_lock = 1;
+    market.enter_callback()?;
+
+    // ===== INTERACTIONS =====
     // Transfer out
     let amount_u64 = safe_u128_to_u64(amount)?;
-    let market = &ctx.accounts.market;
     let bump = market.bump;
+    drop(market);
     let seeds = &[
         PROGRAM_SEED_PREFIX,
         Market::SEED,
@@ -224,6 +287,21 @@ pub fn flash_loan(
         ctx.accounts.loan_mint.decimals,
     )?;
 
+    // Hand control to the borrower's program
+    let mut callback_accounts = vec![
+        ctx.accounts.borrower.to_account_info(),
+        ctx.accounts.borrower_token_account.to_account_info(),
+        ctx.accounts.loan_mint.to_account_info(),
+        ctx.accounts.loan_vault.to_account_info(),
+    ];
+    callback_accounts.extend(ctx.remaining_accounts.iter().cloned());
+    invoke_callback(
+        &ctx.accounts.callback_program,
+        ON_FLASH_LOAN,
+        (amount_u64, safe_u128_to_u64(fee)?, data),
+        &callback_accounts,
+    )?;
+
     // Reload vault and verify repayment
     ctx.accounts.loan_vault.reload()?;
     let required = checked_add(vault_before as u128, fee)?;
@@ -232,10 +310,16 @@ pub fn flash_loan(
         MorphoError::FlashLoanNotRepaid
     );
 
+    // ===== EFFECTS (after successful repayment) =====
+    let mut market = ctx.accounts.market.load_mut()?;
+
     // Fee to suppliers
-    let market = &mut ctx.accounts.market;
     market.total_supply_assets = checked_add(market.total_supply_assets, fee)?;
 
+    // Unlock flash loan
+    market.flash_loan_lock = 0;
+    market.exit_callback();
+
     emit!(FlashLoan {
         market_id,
         borrower: ctx.accounts.borrower.key(),
@@ -1,20 +1,102 @@
 //! Liquidation instruction
+//!
+//! The liquidator fixes one side: the loan tokens to repay (`seized_assets`,
+//! collateral derived through `calculate_seized_collateral`) or the exact
+//! collateral to seize (`seized_collateral`, repay derived through
+//! `calculate_repaid_assets` and rounded up).
+//!
+//! When repaying by `seized_assets`, `bonus_mode` picks how the incentive is
+//! paid (see `LiquidationBonus`): as extra collateral on top of the repaid
+//! debt's value (the default), or as a discount on the repay for collateral
+//! worth `seized_assets` at the oracle price. At a fixed price both trade
+//! collateral for debt at the same rate.
+//!
+//! Whichever side is fixed, the final quote must not seize more than the
+//! debt actually repaid is worth at the LIF (`check_seizure`); share
+//! rounding that would hand out collateral for less debt reverts with
+//! `OverSeizure`.
+//!
+//! Seized collateral is sent before the debt is pulled, so a liquidator can
+//! pass a `callback_program` and swap the collateral for loan tokens inside
+//! `on_morpho_liquidate(repaid_assets: u64, seized_collateral: u64, data: Vec<u8>)`.
+//! The callback receives, in order:
+//!
+//! 0. liquidator (signer)
+//! 1. liquidator_collateral_account (writable, already holds the seized collateral)
+//! 2. liquidator_loan_account (writable, must hold `repaid_assets` on return)
+//! 3. collateral_mint
+//! 4. loan_mint
+//! 5. ..remaining_accounts
+//!
+//! Markets with `auction_duration_slots > 0` liquidate by Dutch auction:
+//! anyone calls `start_liquidation_auction` once the position breaches its
+//! LLTV, and the incentive then ramps from no bonus up to the market's max
+//! LIF (see `auction_lif`). The auction is cleared once the position is healthy again.
+//!
+//! Markets with `liquidation_grace_slots > 0` only liquidate positions that
+//! have stayed liquidatable that long, so a momentary oracle blip cannot be
+//! liquidated into. The same permissionless call stamps the position's
+//! `became_unhealthy_slot` on the first unhealthy reading and clears it on a
+//! healthy one.
+//!
+//! Leftover debt of a position with no collateral is written off into
+//! `pending_bad_debt` and charged to suppliers once that reaches the
+//! market's `bad_debt_threshold` (immediately when the threshold is 0).
+//! `liquidate` returns the bad debt it socialized (u128 little-endian, 0 if
+//! none or deferred) via `set_return_data`.
+//!
+//! `liquidate_batch` liquidates several positions of one market against a
+//! single accrual and oracle read, skipping those no longer liquidatable.
 
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program::set_return_data;
 use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, transfer_checked, TransferChecked};
-use crate::constants::PROGRAM_SEED_PREFIX;
+use crate::constants::{PROGRAM_SEED_PREFIX, MAX_LIQUIDATION_BATCH, LIF_BPS};
 use crate::errors::MorphoError;
-use crate::events::{Liquidation, BadDebtRealized};
-use crate::state::{Market, Position};
+use crate::events::{
+    Liquidation, LiquidationBatch, BadDebtRealized, BadDebtDeferred, LiquidationAuctionStarted, LiquidationAuctionReset,
+};
+use crate::state::{ProtocolState, Market, Position};
 use crate::math::{
-    checked_sub, safe_u128_to_u64,
-    to_shares_down, to_assets_up,
+    checked_add, checked_sub, safe_u128_to_u64,
+    to_shares_down, to_shares_up, to_assets_up,
     accrue_interest_on_market,
 };
 use crate::interfaces::{
-    get_borrow_rate_internal, get_oracle_price_validated, 
-    is_liquidatable, calculate_lif, calculate_seized_collateral, socialize_bad_debt,
+    compute_borrow_rate, get_oracle_price_validated, 
+    is_liquidatable, ensure_liquidatable, health_factor, max_liquidation_repay,
+    calculate_lif, market_lif_curve, auction_lif, calculate_seized_collateral, calculate_repaid_assets,
+    check_seizure, liquidation_protocol_fee,
+    write_off_bad_debt, socialize_pending_bad_debt,
+    invoke_callback, validate_callback_program, ON_MORPHO_LIQUIDATE,
+    amount_to_send,
 };
+use super::utils::check_deadline;
+use crate::profiling::cu_checkpoint;
+
+/// How `liquidate` pays the incentive when repaying by `seized_assets`
+///
+/// Passed to the instruction as a `u8`. With `seized_collateral` fixed the
+/// incentive is always a discount on the repay, so the mode is ignored.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiquidationBonus {
+    /// Repay `seized_assets` and seize its value times the LIF in collateral
+    Collateral = 0,
+    /// Seize collateral worth `seized_assets` and repay that divided by the LIF
+    DebtDiscount = 1,
+}
+
+impl TryFrom<u8> for LiquidationBonus {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(LiquidationBonus::Collateral),
+            1 => Ok(LiquidationBonus::DebtDiscount),
+            _ => Err(MorphoError::InvalidInput.into()),
+        }
+    }
+}
 
 #[derive(Accounts)]
 #[instruction(market_id: [u8; 32])]
@@ -22,12 +104,19 @@ pub struct Liquidate<'info> {
     #[account(mut)]
     pub liquidator: Signer<'info>,
 
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+    )]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
@@ -42,113 +131,216 @@ pub struct Liquidate<'info> {
     /// CHECK: Oracle for price
     pub oracle: UncheckedAccount<'info>,
 
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+
     #[account(
         mut,
-        constraint = liquidator_loan_account.mint == market.loan_mint,
+        constraint = liquidator_loan_account.mint == market.load()?.loan_mint,
     )]
     pub liquidator_loan_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
-        constraint = liquidator_collateral_account.mint == market.collateral_mint,
+        constraint = liquidator_collateral_account.mint == market.load()?.collateral_mint,
     )]
     pub liquidator_collateral_account: InterfaceAccount<'info, TokenAccount>,
 
+    /// Fee recipient's collateral account, required when the market takes a
+    /// liquidation protocol fee
+    #[account(
+        mut,
+        constraint = fee_recipient_collateral_account.mint == market.load()?.collateral_mint,
+        constraint = fee_recipient_collateral_account.owner == protocol_state.fee_recipient @ MorphoError::InvalidOwner,
+    )]
+    pub fee_recipient_collateral_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
-        bump = market.loan_vault_bump,
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
     )]
     pub loan_vault: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::COLLATERAL_VAULT_SEED, &market_id],
-        bump = market.collateral_vault_bump,
+        bump = market.load()?.collateral_vault_bump,
+        token::mint = collateral_mint,
+        token::authority = market,
     )]
     pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
     pub loan_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = collateral_mint.key() == market.load()?.collateral_mint @ MorphoError::InvalidMint)]
     pub collateral_mint: InterfaceAccount<'info, Mint>,
 
+    /// Token program owning the loan mint
     pub token_program: Interface<'info, TokenInterface>,
+
+    /// Token program owning the collateral mint
+    pub collateral_token_program: Interface<'info, TokenInterface>,
+
+    /// CHECK: Optional program invoked between seizing and repaying, see `validate_callback_program`
+    pub callback_program: Option<UncheckedAccount<'info>>,
 }
 
-pub fn liquidate(
-    ctx: Context<Liquidate>,
+pub fn liquidate<'info>(
+    ctx: Context<'_, '_, '_, 'info, Liquidate<'info>>,
     market_id: [u8; 32],
-    seized_assets: u128,  // Amount of loan tokens the liquidator wants to repay
+    seized_assets: u128,      // Amount of loan tokens the liquidator wants to repay
+    seized_collateral: u128,  // Or: exact collateral to seize (the other must be 0)
+    bonus_mode: u8,           // `LiquidationBonus` applied to `seized_assets`
+    deadline_slot: u64,       // Last slot the liquidation may land in (0 = none)
+    data: Vec<u8>,            // Forwarded to the callback, ignored without one
 ) -> Result<()> {
     // ===== CHECKS =====
-    // Note: Liquidation allowed even when paused (maintains protocol health)
-    require!(seized_assets > 0, MorphoError::ZeroAmount);
+    check_deadline(deadline_slot, Clock::get()?.slot)?;
+    require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
+    require!(!ctx.accounts.market.load()?.is_paused(), MorphoError::MarketPaused);
+    require!(seized_assets > 0 || seized_collateral > 0, MorphoError::ZeroAmount);
+    require!(!(seized_assets > 0 && seized_collateral > 0), MorphoError::InvalidInput);
+    let bonus = LiquidationBonus::try_from(bonus_mode)?;
+    // A debt-free position can never be liquidatable; fail before accrual
+    // and the oracle read
+    require!(ctx.accounts.borrower_position.borrow_shares > 0, MorphoError::PositionHealthy);
+    if let Some(callback_program) = &ctx.accounts.callback_program {
+        validate_callback_program(callback_program)?;
+    }
 
     // Accrue interest
-    let borrow_rate = get_borrow_rate_internal(
-        ctx.accounts.market.total_supply_assets,
-        ctx.accounts.market.total_borrow_assets,
-    )?;
-    let current_time = Clock::get()?.unix_timestamp;
-    
-    let market = &mut ctx.accounts.market;
-    accrue_interest_on_market(market, current_time, borrow_rate)?;
+    cu_checkpoint("liquidate: accrual start");
+    let clock = Clock::get()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
+    cu_checkpoint("liquidate: accrual end");
 
     let position = &ctx.accounts.borrower_position;
 
     // Get validated oracle price
+    cu_checkpoint("liquidate: oracle start");
     let oracle_price = get_oracle_price_validated(
         &ctx.accounts.oracle.to_account_info(),
-        market,
+        ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref()),
+        ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref()),
+        &mut market,
     )?;
+    cu_checkpoint("liquidate: oracle end");
 
-    // Verify position is liquidatable
+    // Verify position is liquidatable (before any token movement)
+    ensure_liquidatable(
+        position.collateral,
+        position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+        oracle_price,
+        market.lltv,
+    )?;
+    let slot = Clock::get()?.slot;
     require!(
-        is_liquidatable(
-            position.collateral,
-            position.borrow_shares,
-            market.total_borrow_assets,
-            market.total_borrow_shares,
-            oracle_price,
-            market.lltv,
-        )?,
-        MorphoError::PositionHealthy
+        market.liquidation_grace_elapsed(position.became_unhealthy_slot, slot),
+        MorphoError::LiquidationGracePeriod
     );
 
-    // Calculate liquidation incentive and seized collateral
-    let lif = calculate_lif(market.lltv);
-    let seized_collateral = calculate_seized_collateral(seized_assets, oracle_price, lif)?;
-    let seized_collateral = std::cmp::min(seized_collateral, position.collateral);
-
-    // Calculate repaid shares
-    let repaid_shares = to_shares_down(seized_assets, market.total_borrow_assets, market.total_borrow_shares)?;
-    let repaid_shares = std::cmp::min(repaid_shares, position.borrow_shares);
-    let actual_seized_assets = to_assets_up(repaid_shares, market.total_borrow_assets, market.total_borrow_shares)?;
+    let lif = liquidation_lif(&market, position, slot)?;
+    let quote = quote_liquidation(&market, position, oracle_price, lif, seized_assets, seized_collateral, bonus)?;
+    let LiquidationQuote { repaid_shares, repaid_assets: actual_seized_assets, seized_collateral, protocol_fee_collateral } = quote;
 
     // ===== EFFECTS =====
     let position = &mut ctx.accounts.borrower_position;
-    position.borrow_shares = checked_sub(position.borrow_shares, repaid_shares)?;
-    position.collateral = checked_sub(position.collateral, seized_collateral)?;
+    apply_liquidation(&mut market, position, &quote)?;
+    let bad_debt_assets = settle_bad_debt(&mut market, position, market_id, ctx.accounts.borrower.key())?;
+
+    // ===== INTERACTIONS =====
+    cu_checkpoint("liquidate: transfers start");
+    // Liquidator receives collateral, less the protocol's cut of the bonus
+    let collateral_amount = safe_u128_to_u64(checked_sub(seized_collateral, protocol_fee_collateral)?)?;
+    let protocol_fee_amount = safe_u128_to_u64(protocol_fee_collateral)?;
+    // Grossed up so the vault nets the repaid debt under a transfer fee
+    let repay_amount = amount_to_send(
+        &ctx.accounts.loan_mint.to_account_info(),
+        safe_u128_to_u64(actual_seized_assets)?,
+    )?;
+    let bump = market.bump;
+    drop(market);
+    let seeds = &[
+        PROGRAM_SEED_PREFIX,
+        Market::SEED,
+        market_id.as_ref(),
+        &[bump],
+    ];
 
-    market.total_borrow_shares = checked_sub(market.total_borrow_shares, repaid_shares)?;
-    market.total_borrow_assets = checked_sub(market.total_borrow_assets, actual_seized_assets)?;
+    transfer_checked(
+        CpiContext::new_with_signer(
+            ctx.accounts.collateral_token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.collateral_vault.to_account_info(),
+                to: ctx.accounts.liquidator_collateral_account.to_account_info(),
+                authority: ctx.accounts.market.to_account_info(),
+                mint: ctx.accounts.collateral_mint.to_account_info(),
+            },
+            &[seeds],
+        ),
+        collateral_amount,
+        ctx.accounts.collateral_mint.decimals,
+    )?;
 
-    // Bad debt handling: if no collateral left but still has debt
-    if position.collateral == 0 && position.borrow_shares > 0 {
-        let remaining_shares = position.borrow_shares;
-        let bad_debt = socialize_bad_debt(market, remaining_shares)?;
-        position.borrow_shares = 0;
+    if protocol_fee_amount > 0 {
+        let fee_recipient_account = ctx.accounts.fee_recipient_collateral_account
+            .as_ref()
+            .ok_or(MorphoError::InvalidInput)?;
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.collateral_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.collateral_vault.to_account_info(),
+                    to: fee_recipient_account.to_account_info(),
+                    authority: ctx.accounts.market.to_account_info(),
+                    mint: ctx.accounts.collateral_mint.to_account_info(),
+                },
+                &[seeds],
+            ),
+            protocol_fee_amount,
+            ctx.accounts.collateral_mint.decimals,
+        )?;
+    }
 
-        emit!(BadDebtRealized {
-            market_id,
-            borrower: ctx.accounts.borrower.key(),
-            bad_debt_assets: bad_debt,
-            bad_debt_shares: remaining_shares,
-        });
+    // Let the liquidator source loan tokens from the seized collateral
+    if let Some(callback_program) = &ctx.accounts.callback_program {
+        cu_checkpoint("liquidate: callback start");
+        // Persist state so the callback observes the post-liquidation position
+        // (and the reentrancy lock)
+        ctx.accounts.market.load_mut()?.enter_callback()?;
+        ctx.accounts.borrower_position.exit(&crate::ID)?;
+
+        let mut callback_accounts = vec![
+            ctx.accounts.liquidator.to_account_info(),
+            ctx.accounts.liquidator_collateral_account.to_account_info(),
+            ctx.accounts.liquidator_loan_account.to_account_info(),
+            ctx.accounts.collateral_mint.to_account_info(),
+            ctx.accounts.loan_mint.to_account_info(),
+        ];
+        callback_accounts.extend(ctx.remaining_accounts.iter().cloned());
+        invoke_callback(
+            callback_program,
+            ON_MORPHO_LIQUIDATE,
+            (repay_amount, collateral_amount, data),
+            &callback_accounts,
+        )?;
+        ctx.accounts.market.load_mut()?.exit_callback();
+        cu_checkpoint("liquidate: callback end");
     }
 
-    // ===== INTERACTIONS =====
-    // Liquidator repays loan tokens
-    let repay_amount = safe_u128_to_u64(actual_seized_assets)?;
+    // Liquidator repays loan tokens (reverts the whole liquidation if short)
     transfer_checked(
         CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
@@ -162,10 +354,270 @@ pub fn liquidate(
         repay_amount,
         ctx.accounts.loan_mint.decimals,
     )?;
+    cu_checkpoint("liquidate: transfers end");
+
+    // A position brought back to health starts a fresh auction and grace
+    // period next breach
+    let market = ctx.accounts.market.load()?;
+    let position = &mut ctx.accounts.borrower_position;
+    if (position.auction_start_slot > 0 || position.became_unhealthy_slot > 0) && !is_liquidatable(
+        position.collateral,
+        position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+        oracle_price,
+        market.lltv,
+    )? {
+        position.auction_start_slot = 0;
+        position.became_unhealthy_slot = 0;
+    }
+
+    #[cfg(feature = "invariant-checks")]
+    crate::state::assert_invariants(&market)?;
+
+    emit!(Liquidation {
+        market_id,
+        liquidator: ctx.accounts.liquidator.key(),
+        borrower: ctx.accounts.borrower.key(),
+        repaid_assets: actual_seized_assets,
+        repaid_shares,
+        seized_collateral,
+        protocol_fee_collateral,
+        bad_debt_assets,
+        total_borrow_assets: market.total_borrow_assets,
+        total_borrow_shares: market.total_borrow_shares,
+    });
+
+    // Set last so the liquidation callback cannot overwrite it
+    set_return_data(&bad_debt_assets.to_le_bytes());
+    Ok(())
+}
+
+// ============================================================================
+// Liquidate Batch
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct LiquidateBatch<'info> {
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+    )]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    /// CHECK: Oracle for price
+    pub oracle: UncheckedAccount<'info>,
+
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        constraint = liquidator_loan_account.mint == market.load()?.loan_mint,
+    )]
+    pub liquidator_loan_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = liquidator_collateral_account.mint == market.load()?.collateral_mint,
+    )]
+    pub liquidator_collateral_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Fee recipient's collateral account, required when the market takes a
+    /// liquidation protocol fee
+    #[account(
+        mut,
+        constraint = fee_recipient_collateral_account.mint == market.load()?.collateral_mint,
+        constraint = fee_recipient_collateral_account.owner == protocol_state.fee_recipient @ MorphoError::InvalidOwner,
+    )]
+    pub fee_recipient_collateral_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
+    )]
+    pub loan_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::COLLATERAL_VAULT_SEED, &market_id],
+        bump = market.load()?.collateral_vault_bump,
+        token::mint = collateral_mint,
+        token::authority = market,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
+    pub loan_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = collateral_mint.key() == market.load()?.collateral_mint @ MorphoError::InvalidMint)]
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    /// Token program owning the loan mint
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Token program owning the collateral mint
+    pub collateral_token_program: Interface<'info, TokenInterface>,
+}
+
+/// Liquidate up to MAX_LIQUIDATION_BATCH positions of one market
+///
+/// Positions are passed as writable remaining accounts, `repaid_assets[i]`
+/// being the loan tokens to repay on position `i`. Interest is accrued and
+/// the oracle read once. Positions that are no longer liquidatable (or
+/// whose auction has not started, or still in their grace period, which
+/// the batch stamps) are skipped instead of failing the batch, since
+/// another keeper may have got there first; the batch fails only if
+/// nothing was liquidated. Collateral and repayment move in one transfer
+/// each, and there is no callback, so the liquidator must hold the total
+/// repay up front.
+///
+/// Returns the loan tokens repaid on each position (borsh `Vec<u128>`,
+/// 0 = skipped) via `set_return_data`.
+pub fn liquidate_batch<'info>(
+    ctx: Context<'_, '_, 'info, 'info, LiquidateBatch<'info>>,
+    market_id: [u8; 32],
+    repaid_assets: Vec<u128>,
+    deadline_slot: u64,
+) -> Result<()> {
+    // ===== CHECKS =====
+    let clock = Clock::get()?;
+    check_deadline(deadline_slot, clock.slot)?;
+    require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
+    require!(!ctx.accounts.market.load()?.is_paused(), MorphoError::MarketPaused);
+    let positions = ctx.remaining_accounts;
+    require!(!positions.is_empty(), MorphoError::InvalidInput);
+    require!(positions.len() <= MAX_LIQUIDATION_BATCH, MorphoError::InvalidInput);
+    require!(positions.len() == repaid_assets.len(), MorphoError::InvalidInput);
+
+    // Accrue interest
+    cu_checkpoint("liquidate_batch: accrual start");
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
+    cu_checkpoint("liquidate_batch: accrual end");
+
+    cu_checkpoint("liquidate_batch: oracle start");
+    let oracle_price = get_oracle_price_validated(
+        &ctx.accounts.oracle.to_account_info(),
+        ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref()),
+        ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref()),
+        &mut market,
+    )?;
+    cu_checkpoint("liquidate_batch: oracle end");
+
+    // ===== EFFECTS =====
+    let mut results = Vec::with_capacity(positions.len());
+    let mut liquidated = 0u8;
+    let mut total_repaid_assets = 0u128;
+    let mut total_seized_collateral = 0u128;
+    let mut total_protocol_fee_collateral = 0u128;
+    let mut total_bad_debt_assets = 0u128;
+
+    for (i, (info, &assets)) in positions.iter().zip(&repaid_assets).enumerate() {
+        require!(
+            !positions[..i].iter().any(|other| other.key == info.key),
+            MorphoError::InvalidInput
+        );
+        let mut position = Account::<Position>::try_from(info)?;
+        require!(position.market_id == market_id, MorphoError::InvalidMarketId);
 
-    // Liquidator receives collateral
-    let collateral_amount = safe_u128_to_u64(seized_collateral)?;
+        let liquidatable = is_liquidatable(
+            position.collateral,
+            position.borrow_shares,
+            market.total_borrow_assets,
+            market.total_borrow_shares,
+            oracle_price,
+            market.lltv,
+        )?;
+        // Start or clear the grace period of every position read in passing
+        if market.liquidation_grace_slots > 0 && liquidatable != (position.became_unhealthy_slot > 0) {
+            position.record_health(liquidatable, clock.slot);
+            position.exit(&crate::ID)?;
+        }
+
+        let auction_pending = market.auction_duration_slots > 0 && position.auction_start_slot == 0;
+        if assets == 0
+            || !liquidatable
+            || auction_pending
+            || !market.liquidation_grace_elapsed(position.became_unhealthy_slot, clock.slot)
+        {
+            results.push(0);
+            continue;
+        }
+
+        let lif = liquidation_lif(&market, &position, clock.slot)?;
+        let quote = quote_liquidation(&market, &position, oracle_price, lif, assets, 0, LiquidationBonus::Collateral)?;
+        apply_liquidation(&mut market, &mut position, &quote)?;
+        let borrower = position.owner;
+        let bad_debt_assets = settle_bad_debt(&mut market, &mut position, market_id, borrower)?;
+
+        // A position brought back to health starts a fresh auction and
+        // grace period next breach
+        if (position.auction_start_slot > 0 || position.became_unhealthy_slot > 0) && !is_liquidatable(
+            position.collateral,
+            position.borrow_shares,
+            market.total_borrow_assets,
+            market.total_borrow_shares,
+            oracle_price,
+            market.lltv,
+        )? {
+            position.auction_start_slot = 0;
+            position.became_unhealthy_slot = 0;
+        }
+        position.exit(&crate::ID)?;
+
+        emit!(Liquidation {
+            market_id,
+            liquidator: ctx.accounts.liquidator.key(),
+            borrower,
+            repaid_assets: quote.repaid_assets,
+            repaid_shares: quote.repaid_shares,
+            seized_collateral: quote.seized_collateral,
+            protocol_fee_collateral: quote.protocol_fee_collateral,
+            bad_debt_assets,
+            total_borrow_assets: market.total_borrow_assets,
+            total_borrow_shares: market.total_borrow_shares,
+        });
+
+        liquidated += 1;
+        total_repaid_assets = checked_add(total_repaid_assets, quote.repaid_assets)?;
+        total_seized_collateral = checked_add(total_seized_collateral, quote.seized_collateral)?;
+        total_protocol_fee_collateral = checked_add(total_protocol_fee_collateral, quote.protocol_fee_collateral)?;
+        total_bad_debt_assets = checked_add(total_bad_debt_assets, bad_debt_assets)?;
+        results.push(quote.repaid_assets);
+    }
+    require!(liquidated > 0, MorphoError::PositionHealthy);
+
+    // ===== INTERACTIONS =====
+    cu_checkpoint("liquidate_batch: transfers start");
+    let collateral_amount = safe_u128_to_u64(checked_sub(total_seized_collateral, total_protocol_fee_collateral)?)?;
+    let protocol_fee_amount = safe_u128_to_u64(total_protocol_fee_collateral)?;
+    let repay_amount = amount_to_send(
+        &ctx.accounts.loan_mint.to_account_info(),
+        safe_u128_to_u64(total_repaid_assets)?,
+    )?;
     let bump = market.bump;
+    drop(market);
     let seeds = &[
         PROGRAM_SEED_PREFIX,
         Market::SEED,
@@ -175,7 +627,7 @@ pub fn liquidate(
 
     transfer_checked(
         CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.collateral_token_program.to_account_info(),
             TransferChecked {
                 from: ctx.accounts.collateral_vault.to_account_info(),
                 to: ctx.accounts.liquidator_collateral_account.to_account_info(),
@@ -188,14 +640,439 @@ pub fn liquidate(
         ctx.accounts.collateral_mint.decimals,
     )?;
 
-    emit!(Liquidation {
+    if protocol_fee_amount > 0 {
+        let fee_recipient_account = ctx.accounts.fee_recipient_collateral_account
+            .as_ref()
+            .ok_or(MorphoError::InvalidInput)?;
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.collateral_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.collateral_vault.to_account_info(),
+                    to: fee_recipient_account.to_account_info(),
+                    authority: ctx.accounts.market.to_account_info(),
+                    mint: ctx.accounts.collateral_mint.to_account_info(),
+                },
+                &[seeds],
+            ),
+            protocol_fee_amount,
+            ctx.accounts.collateral_mint.decimals,
+        )?;
+    }
+
+    transfer_checked(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.liquidator_loan_account.to_account_info(),
+                to: ctx.accounts.loan_vault.to_account_info(),
+                authority: ctx.accounts.liquidator.to_account_info(),
+                mint: ctx.accounts.loan_mint.to_account_info(),
+            },
+        ),
+        repay_amount,
+        ctx.accounts.loan_mint.decimals,
+    )?;
+    cu_checkpoint("liquidate_batch: transfers end");
+
+    #[cfg(feature = "invariant-checks")]
+    crate::state::assert_invariants(&*ctx.accounts.market.load()?)?;
+
+    emit!(LiquidationBatch {
         market_id,
         liquidator: ctx.accounts.liquidator.key(),
-        borrower: ctx.accounts.borrower.key(),
-        repaid_assets: actual_seized_assets,
-        repaid_shares,
+        liquidated,
+        skipped: positions.len() as u8 - liquidated,
+        repaid_assets: total_repaid_assets,
+        seized_collateral: total_seized_collateral,
+        protocol_fee_collateral: total_protocol_fee_collateral,
+        bad_debt_assets: total_bad_debt_assets,
+    });
+
+    set_return_data(&results.try_to_vec()?);
+    Ok(())
+}
+
+// ============================================================================
+// Shared Liquidation Steps
+// ============================================================================
+
+/// Amounts one liquidation moves
+#[derive(Clone, Copy, Debug)]
+struct LiquidationQuote {
+    repaid_shares: u128,
+    /// Debt cleared by `repaid_shares` (rounded up)
+    repaid_assets: u128,
+    seized_collateral: u128,
+    /// Part of `seized_collateral` sent to the fee recipient
+    protocol_fee_collateral: u128,
+}
+
+/// Incentive for liquidating `position`: the auction ramp in auction
+/// markets (which requires a started auction), the LLTV curve otherwise
+fn liquidation_lif(market: &Market, position: &Position, slot: u64) -> Result<u64> {
+    let (lif_cursor, max_lif) = market_lif_curve(market);
+    if market.auction_duration_slots > 0 {
+        require!(position.auction_start_slot > 0, MorphoError::AuctionNotStarted);
+        let elapsed_slots = slot.saturating_sub(position.auction_start_slot);
+        Ok(auction_lif(elapsed_slots, market.auction_duration_slots, max_lif))
+    } else {
+        calculate_lif(market.lltv, lif_cursor, max_lif)
+    }
+}
+
+/// Derive the side the liquidator did not fix (exactly one of
+/// `seized_assets` / `seized_collateral` is non-zero) and enforce the
+/// close factor
+///
+/// A `DebtDiscount` quote converts `seized_assets` into collateral at par
+/// (capped at the position's) and prices it like a fixed-collateral one.
+fn quote_liquidation(
+    market: &Market,
+    position: &Position,
+    oracle_price: u128,
+    lif: u64,
+    seized_assets: u128,
+    seized_collateral: u128,
+    bonus: LiquidationBonus,
+) -> Result<LiquidationQuote> {
+    let borrowed = to_assets_up(position.borrow_shares, market.total_borrow_assets, market.total_borrow_shares)?;
+    let seized_collateral = if seized_collateral > 0 {
+        require!(seized_collateral <= position.collateral, MorphoError::InsufficientCollateral);
+        seized_collateral
+    } else if bonus == LiquidationBonus::DebtDiscount {
+        let at_par = calculate_seized_collateral(seized_assets, oracle_price, LIF_BPS)?;
+        std::cmp::min(at_par, position.collateral)
+    } else {
+        0
+    };
+    let exact_collateral = seized_collateral > 0;
+    let (seized_assets, seized_collateral) = if exact_collateral {
+        let repaid = calculate_repaid_assets(seized_collateral, oracle_price, lif)?;
+        require!(repaid <= borrowed, MorphoError::LiquidationTooLarge);
+        (repaid, seized_collateral)
+    } else {
+        let seized = calculate_seized_collateral(seized_assets, oracle_price, lif)?;
+        (seized_assets, std::cmp::min(seized, position.collateral))
+    };
+
+    // Enforce close factor unless deeply underwater
+    let health = health_factor(position.collateral, borrowed, oracle_price, market.lltv)?;
+    require!(
+        seized_assets <= max_liquidation_repay(borrowed, health, market.close_factor_bps)?,
+        MorphoError::LiquidationTooLarge
+    );
+    let protocol_fee_collateral = liquidation_protocol_fee(
         seized_collateral,
+        seized_assets,
+        oracle_price,
+        market.liquidation_protocol_fee_bps,
+    )?;
+
+    // Calculate repaid shares (rounded up when the collateral was fixed, so
+    // the repay covers it)
+    let repaid_shares = if exact_collateral {
+        to_shares_up(seized_assets, market.total_borrow_assets, market.total_borrow_shares)?
+    } else {
+        to_shares_down(seized_assets, market.total_borrow_assets, market.total_borrow_shares)?
+    };
+    let repaid_shares = std::cmp::min(repaid_shares, position.borrow_shares);
+    let repaid_assets = to_assets_up(repaid_shares, market.total_borrow_assets, market.total_borrow_shares)?;
+    check_seizure(seized_collateral, repaid_assets, oracle_price, lif)?;
+
+    Ok(LiquidationQuote { repaid_shares, repaid_assets, seized_collateral, protocol_fee_collateral })
+}
+
+/// Move the quoted debt and collateral out of the position and market
+fn apply_liquidation(market: &mut Market, position: &mut Position, quote: &LiquidationQuote) -> Result<()> {
+    position.borrow_shares = checked_sub(position.borrow_shares, quote.repaid_shares)?;
+    position.collateral = checked_sub(position.collateral, quote.seized_collateral)?;
+    position.touch()?;
+
+    market.total_borrow_shares = checked_sub(market.total_borrow_shares, quote.repaid_shares)?;
+    market.total_borrow_assets = checked_sub(market.total_borrow_assets, quote.repaid_assets)?;
+    Ok(())
+}
+
+/// Bad debt handling: if no collateral is left but debt is, write it off
+/// and charge suppliers once the pending batch reaches the threshold
+///
+/// Returns the bad debt socialized (0 if none or deferred).
+fn settle_bad_debt(
+    market: &mut Market,
+    position: &mut Position,
+    market_id: [u8; 32],
+    borrower: Pubkey,
+) -> Result<u128> {
+    let Some((written_off_assets, written_off_shares)) = write_off_bad_debt(market, position)? else {
+        return Ok(0);
+    };
+    emit!(BadDebtRealized {
+        market_id,
+        borrower,
+        bad_debt_assets: written_off_assets,
+        bad_debt_shares: written_off_shares,
     });
 
+    match socialize_pending_bad_debt(market, false)? {
+        Some(socialized) => {
+            let bad_debt_assets = socialized.bad_debt_assets;
+            emit!(socialized);
+            Ok(bad_debt_assets)
+        }
+        None => {
+            emit!(BadDebtDeferred {
+                market_id,
+                bad_debt_assets: written_off_assets,
+                pending_bad_debt: market.pending_bad_debt,
+            });
+            Ok(0)
+        }
+    }
+}
+
+// ============================================================================
+// Start Liquidation Auction
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct StartLiquidationAuction<'info> {
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Position::SEED, &market_id, borrower.key().as_ref()],
+        bump = borrower_position.bump,
+    )]
+    pub borrower_position: Box<Account<'info, Position>>,
+
+    /// CHECK: Borrower whose auction is started or reset
+    pub borrower: UncheckedAccount<'info>,
+
+    /// CHECK: Oracle for price
+    pub oracle: UncheckedAccount<'info>,
+
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+}
+
+/// Stamp the auction start on a liquidatable position, or clear it on a healthy one
+///
+/// Permissionless: keepers start auctions, and borrowers who repaid or added
+/// collateral can reset a stale one. In markets with a liquidation grace
+/// period it stamps or clears `became_unhealthy_slot` the same way. Only
+/// meaningful in auction or grace-period markets.
+pub fn start_liquidation_auction(
+    ctx: Context<StartLiquidationAuction>,
+    market_id: [u8; 32],
+) -> Result<()> {
+    let mut market = ctx.accounts.market.load_mut()?;
+    require!(
+        market.auction_duration_slots > 0 || market.liquidation_grace_slots > 0,
+        MorphoError::InvalidInput
+    );
+
+    // Accrue interest
+    let clock = Clock::get()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
+
+    let oracle_price = get_oracle_price_validated(
+        &ctx.accounts.oracle.to_account_info(),
+        ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref()),
+        ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref()),
+        &mut market,
+    )?;
+
+    let position = &mut ctx.accounts.borrower_position;
+    let liquidatable = is_liquidatable(
+        position.collateral,
+        position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+        oracle_price,
+        market.lltv,
+    )?;
+    let slot = Clock::get()?.slot;
+
+    if market.liquidation_grace_slots > 0 {
+        position.record_health(liquidatable, slot);
+    }
+
+    if market.auction_duration_slots > 0 {
+        if liquidatable && position.auction_start_slot == 0 {
+            position.auction_start_slot = slot.max(1);
+            emit!(LiquidationAuctionStarted {
+                market_id,
+                borrower: ctx.accounts.borrower.key(),
+                start_slot: position.auction_start_slot,
+            });
+        } else if !liquidatable && position.auction_start_slot > 0 {
+            position.auction_start_slot = 0;
+            emit!(LiquidationAuctionReset {
+                market_id,
+                borrower: ctx.accounts.borrower.key(),
+            });
+        }
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{ORACLE_SCALE, LIF_CURSOR, MAX_LIF};
+    use crate::math::mul_div_down;
+
+    /// Two 1_000-token debts at 80% LLTV and a 1:1 price
+    fn test_market() -> Market {
+        Market {
+            collateral_decimals: 9,
+            loan_decimals: 6,
+            lltv: 8000,
+            total_supply_assets: 10_000,
+            total_supply_shares: 10_000_000_000,
+            total_borrow_assets: 2_000,
+            total_borrow_shares: 2_000_000_000,
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+
+    fn test_position(collateral: u128) -> Position {
+        Position {
+            owner: Pubkey::new_unique(),
+            borrow_shares: 1_000_000_000,
+            collateral,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_quote_liquidation_applies_close_factor_per_position() {
+        let market = test_market();
+        let lif = liquidation_lif(&market, &test_position(0), 0).unwrap();
+
+        // Health 0.96: only half the debt may be repaid
+        let mild = test_position(1_200);
+        let quote = quote_liquidation(&market, &mild, ORACLE_SCALE, lif, 500, 0, LiquidationBonus::Collateral).unwrap();
+        assert!(quote.repaid_assets <= 500);
+        assert_eq!(quote.seized_collateral, calculate_seized_collateral(500, ORACLE_SCALE, lif).unwrap());
+        assert_eq!(
+            quote_liquidation(&market, &mild, ORACLE_SCALE, lif, 501, 0, LiquidationBonus::Collateral).unwrap_err(),
+            MorphoError::LiquidationTooLarge.into()
+        );
+
+        // Health 0.8: the whole debt goes, seizure capped at the collateral
+        let deep = test_position(1_000);
+        let quote = quote_liquidation(&market, &deep, ORACLE_SCALE, lif, 1_000, 0, LiquidationBonus::Collateral).unwrap();
+        assert_eq!(quote.repaid_shares, deep.borrow_shares);
+        assert_eq!(quote.repaid_assets, 1_000);
+        assert_eq!(quote.seized_collateral, 1_000);
+    }
+
+    #[test]
+    fn test_quote_liquidation_exact_collateral() {
+        let market = test_market();
+        let lif = liquidation_lif(&market, &test_position(0), 0).unwrap();
+        let deep = test_position(1_000);
+
+        let quote = quote_liquidation(&market, &deep, ORACLE_SCALE, lif, 0, 100, LiquidationBonus::Collateral).unwrap();
+        assert_eq!(quote.seized_collateral, 100);
+        // Shares round up, so the repay covers the fixed collateral
+        assert!(quote.repaid_assets >= calculate_repaid_assets(100, ORACLE_SCALE, lif).unwrap());
+        assert_eq!(
+            quote_liquidation(&market, &deep, ORACLE_SCALE, lif, 0, 1_001, LiquidationBonus::Collateral).unwrap_err(),
+            MorphoError::InsufficientCollateral.into()
+        );
+    }
+
+    #[test]
+    fn test_quote_liquidation_rejects_over_seizure() {
+        // Borrow shares worth ~500k loan units each, and a collateral price
+        // pushed 10x by a lagging feed
+        let mut market = test_market();
+        market.total_borrow_assets = 1_000_000_000_000;
+        market.total_borrow_shares = 1_000_000;
+        let price = 10 * ORACLE_SCALE;
+        let lif = liquidation_lif(&market, &test_position(0), 0).unwrap();
+        let mut position = test_position(100_000_000);
+        position.borrow_shares = 2;
+
+        // Under one share's worth rounds to zero repaid shares, yet would
+        // seize collateral priced off the requested assets
+        assert_eq!(
+            quote_liquidation(&market, &position, price, lif, 400_000, 0, LiquidationBonus::Collateral).unwrap_err(),
+            MorphoError::OverSeizure.into()
+        );
+
+        // One whole share repays what it seizes
+        let quote = quote_liquidation(&market, &position, price, lif, 500_001, 0, LiquidationBonus::Collateral).unwrap();
+        assert_eq!(quote.repaid_shares, 1);
+        assert_eq!(quote.seized_collateral, calculate_seized_collateral(quote.repaid_assets, price, lif).unwrap());
+    }
+
+    #[test]
+    fn test_debt_discount_matches_collateral_bonus() {
+        let market = test_market();
+        let lif = liquidation_lif(&market, &test_position(0), 0).unwrap();
+
+        // Health 0.8 at either price, so the whole debt is liquidatable;
+        // repays stay below the collateral cap
+        for (price, collateral, repays) in [
+            (ORACLE_SCALE, 1_000, [1, 250, 500, 900]),
+            (2 * ORACLE_SCALE, 500, [1, 50, 125, 200]),
+        ] {
+            let deep = test_position(collateral);
+            for repaid in repays {
+                let bonus = quote_liquidation(&market, &deep, price, lif, repaid, 0, LiquidationBonus::Collateral).unwrap();
+                // Ask for the same collateral, named by its loan-token value
+                let value = mul_div_down(bonus.seized_collateral, ORACLE_SCALE, price).unwrap();
+                let discount = quote_liquidation(&market, &deep, price, lif, value, 0, LiquidationBonus::DebtDiscount).unwrap();
+
+                // Same exchange rate up to rounding, which favors the borrower
+                assert!(discount.seized_collateral.abs_diff(bonus.seized_collateral) <= 1);
+                assert!(discount.repaid_assets.abs_diff(bonus.repaid_assets) <= 1);
+                assert!(discount.repaid_assets >= calculate_repaid_assets(discount.seized_collateral, price, lif).unwrap());
+            }
+        }
+
+        // The discount mode seizes at par and caps at the position's collateral
+        let deep = test_position(1_000);
+        let quote = quote_liquidation(&market, &deep, ORACLE_SCALE, lif, 400, 0, LiquidationBonus::DebtDiscount).unwrap();
+        assert_eq!(quote.seized_collateral, 400);
+        assert!(quote.repaid_assets < 400);
+        let quote = quote_liquidation(&market, &deep, ORACLE_SCALE, lif, 5_000, 0, LiquidationBonus::DebtDiscount).unwrap();
+        assert_eq!(quote.seized_collateral, 1_000);
+
+        assert!(LiquidationBonus::try_from(2).is_err());
+    }
+
+    #[test]
+    fn test_liquidation_lif_requires_started_auction() {
+        let mut market = test_market();
+        let position = test_position(1_000);
+        assert_eq!(
+            liquidation_lif(&market, &position, 0).unwrap(),
+            calculate_lif(8000, LIF_CURSOR, MAX_LIF).unwrap()
+        );
+
+        market.auction_duration_slots = 100;
+        assert_eq!(
+            liquidation_lif(&market, &position, 50).unwrap_err(),
+            MorphoError::AuctionNotStarted.into()
+        );
+    }
+}
@@ -2,10 +2,16 @@
 
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
-use crate::constants::PROGRAM_SEED_PREFIX;
+use crate::constants::{PROGRAM_SEED_PREFIX, BPS, WAD};
 use crate::errors::MorphoError;
 use crate::events::MarketCreated;
-use crate::state::{ProtocolState, Market, calculate_market_id};
+use crate::interfaces::{
+    validate_max_staleness_slots, validate_min_oracle_samples, validate_max_price,
+    validate_lif_curve, validate_mint_extensions, get_oracle_price_validated, check_price_plausible,
+    check_price_off_boundary,
+    validate_rate_bounds, validate_market_pyth_feed_ids, StaticOracle,
+};
+use crate::state::{ProtocolState, Market, MarketConfig, MarketIndex, OracleKind, AccrualBasis, calculate_market_id};
 
 #[derive(Accounts)]
 #[instruction(
@@ -14,6 +20,7 @@ use crate::state::{ProtocolState, Market, calculate_market_id};
     oracle_key: Pubkey,
     irm_key: Pubkey,
     lltv: u64,
+    config: MarketConfig,
 )]
 pub struct CreateMarket<'info> {
     #[account(mut)]
@@ -33,11 +40,11 @@ pub struct CreateMarket<'info> {
         seeds = [
             PROGRAM_SEED_PREFIX,
             Market::SEED,
-            &calculate_market_id(&collateral_mint_key, &loan_mint_key, &oracle_key, &irm_key, lltv),
+            &calculate_market_id(&collateral_mint_key, &loan_mint_key, &oracle_key, &irm_key, lltv, &config),
         ],
         bump,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(constraint = collateral_mint.key() == collateral_mint_key)]
     pub collateral_mint: InterfaceAccount<'info, Mint>,
@@ -50,10 +57,11 @@ pub struct CreateMarket<'info> {
         payer = creator,
         token::mint = collateral_mint,
         token::authority = market,
+        token::token_program = collateral_token_program,
         seeds = [
             PROGRAM_SEED_PREFIX,
             Market::COLLATERAL_VAULT_SEED,
-            &calculate_market_id(&collateral_mint_key, &loan_mint_key, &oracle_key, &irm_key, lltv),
+            &calculate_market_id(&collateral_mint_key, &loan_mint_key, &oracle_key, &irm_key, lltv, &config),
         ],
         bump,
     )]
@@ -64,27 +72,50 @@ pub struct CreateMarket<'info> {
         payer = creator,
         token::mint = loan_mint,
         token::authority = market,
+        token::token_program = token_program,
         seeds = [
             PROGRAM_SEED_PREFIX,
             Market::LOAN_VAULT_SEED,
-            &calculate_market_id(&collateral_mint_key, &loan_mint_key, &oracle_key, &irm_key, lltv),
+            &calculate_market_id(&collateral_mint_key, &loan_mint_key, &oracle_key, &irm_key, lltv, &config),
         ],
         bump,
     )]
     pub loan_vault: InterfaceAccount<'info, TokenAccount>,
 
+    /// Registry entry at the next market index
+    #[account(
+        init,
+        payer = creator,
+        space = MarketIndex::space(),
+        seeds = [
+            PROGRAM_SEED_PREFIX,
+            MarketIndex::SEED,
+            &protocol_state.market_count.to_le_bytes(),
+        ],
+        bump,
+    )]
+    pub market_index: Box<Account<'info, MarketIndex>>,
+
     /// CHECK: Oracle - validated by creator, will be used for price feeds
     #[account(constraint = oracle.key() == oracle_key)]
     pub oracle: UncheckedAccount<'info>,
 
+    /// CHECK: Loan/USD feed, required only for dual-feed markets (checked
+    /// against `oracle_secondary` in the handler)
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
     /// CHECK: IRM - must be whitelisted in protocol_state
     #[account(constraint = irm.key() == irm_key)]
     pub irm: UncheckedAccount<'info>,
 
+    /// Token program owning the loan mint
     pub token_program: Interface<'info, TokenInterface>,
+    /// Token program owning the collateral mint (may differ from the loan side)
+    pub collateral_token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_market(
     ctx: Context<CreateMarket>,
     collateral_mint_key: Pubkey,
@@ -92,8 +123,44 @@ pub fn create_market(
     oracle_key: Pubkey,
     irm_key: Pubkey,
     lltv: u64,
+    config: MarketConfig,
+    allow_boundary_price: bool,
 ) -> Result<()> {
+    let MarketConfig {
+        oracle_kind,
+        oracle_secondary,
+        pyth_feed_id,
+        pyth_secondary_feed_id,
+        max_staleness_slots,
+        invert_oracle,
+        min_borrow,
+        min_collateral,
+        min_oracle_samples,
+        max_price,
+        max_utilization_bps,
+        lif_cursor,
+        max_lif,
+        supply_cap,
+        min_rate,
+        max_rate,
+    } = config;
     let state = &ctx.accounts.protocol_state;
+    // Only the owner may list a market whose oracle sits on a price bound
+    require!(
+        !allow_boundary_price || ctx.accounts.creator.key() == state.owner,
+        MorphoError::Unauthorized
+    );
+    // A market lending a token against itself has no price risk to manage
+    require!(collateral_mint_key != loan_mint_key, MorphoError::InvalidMint);
+    let oracle_kind = OracleKind::try_from(oracle_kind)?;
+    validate_max_staleness_slots(max_staleness_slots)?;
+    validate_min_oracle_samples(min_oracle_samples)?;
+    validate_max_price(max_price)?;
+    require!(max_utilization_bps <= BPS, MorphoError::InvalidInput);
+    validate_lif_curve(lif_cursor, max_lif)?;
+    validate_rate_bounds(min_rate, max_rate)?;
+    validate_mint_extensions(&ctx.accounts.collateral_mint.to_account_info())?;
+    validate_mint_extensions(&ctx.accounts.loan_mint.to_account_info())?;
 
     // Validate LLTV and IRM are whitelisted
     require!(state.is_lltv_enabled(lltv), MorphoError::LltvNotEnabled);
@@ -105,9 +172,10 @@ pub fn create_market(
         &oracle_key,
         &irm_key,
         lltv,
+        &config,
     );
 
-    let market = &mut ctx.accounts.market;
+    let market = &mut ctx.accounts.market.load_init()?;
     market.bump = ctx.bumps.market;
     market.market_id = market_id;
     market.collateral_mint = collateral_mint_key;
@@ -117,7 +185,7 @@ pub fn create_market(
     market.oracle = oracle_key;
     market.irm = irm_key;
     market.lltv = lltv;
-    market.paused = false;
+    market.set_paused(false);
     market.fee = 0;
     market.total_supply_assets = 0;
     market.total_supply_shares = 0;
@@ -128,6 +196,77 @@ pub fn create_market(
     market.collateral_vault_bump = ctx.bumps.collateral_vault;
     market.loan_vault_bump = ctx.bumps.loan_vault;
     market.flash_loan_lock = 0;
+    market.callback_lock = 0;
+    market.bad_debt_threshold = 0;
+    market.pending_bad_debt = 0;
+    market.reserve_assets = 0;
+    market.accrual_basis = AccrualBasis::Timestamp as u8;
+    market.supply_index = WAD;
+    market.borrow_index = WAD;
+    market.max_oracle_confidence_bps = 0;
+    market.max_std_bps = 0;
+    market.oracle_kind = oracle_kind as u8;
+    market.set_oracle_kind_migrated(true);
+    market.oracle_secondary = oracle_secondary;
+    market.pyth_feed_id = pyth_feed_id;
+    market.pyth_secondary_feed_id = pyth_secondary_feed_id;
+    validate_market_pyth_feed_ids(market)?;
+    market.max_staleness_slots = max_staleness_slots;
+    market.set_oracle_inverted(invert_oracle);
+    market.auction_duration_slots = 0;
+    market.min_borrow = min_borrow;
+    market.min_collateral = min_collateral;
+    market.min_oracle_samples = min_oracle_samples;
+    market.max_price = max_price;
+    market.max_utilization_bps = max_utilization_bps;
+    market.liquidation_protocol_fee_bps = 0;
+    market.lif_cursor = lif_cursor;
+    market.max_lif = max_lif;
+    market.oracle_cache_published_slot = 0;
+    market.oracle_cache_confidence_bps = 0;
+    market.liquidation_grace_slots = 0;
+    market.supply_cap = supply_cap;
+    market.min_rate = min_rate;
+    market.max_rate = max_rate;
+
+    // Read the oracle once so a broken feed never gets a market. Static
+    // oracles are for tests and may not have a price yet, so only the
+    // account is checked. A read exactly on a price bound is refused unless
+    // the owner opts in for a test market.
+    if oracle_kind == OracleKind::Static {
+        let oracle = ctx.accounts.oracle.to_account_info();
+        require!(
+            *oracle.owner == crate::ID
+                && oracle.try_borrow_data()?.starts_with(StaticOracle::DISCRIMINATOR),
+            MorphoError::InvalidOracle
+        );
+    } else {
+        if let Some(secondary) = &ctx.accounts.oracle_secondary {
+            require!(secondary.key() == oracle_secondary, MorphoError::InvalidOracle);
+        }
+        let price = get_oracle_price_validated(
+            &ctx.accounts.oracle.to_account_info(),
+            ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref()),
+            None,
+            market,
+        )?;
+        check_price_plausible(price, market.collateral_decimals, market.loan_decimals)?;
+        if !allow_boundary_price {
+            check_price_off_boundary(price, market.collateral_decimals, market.loan_decimals)?;
+        }
+    }
+
+    let market_index = &mut ctx.accounts.market_index;
+    market_index.bump = ctx.bumps.market_index;
+    market_index.index = ctx.accounts.protocol_state.market_count;
+    market_index.market = ctx.accounts.market.key();
+    market_index.market_id = market_id;
+    market_index.collateral_mint = collateral_mint_key;
+    market_index.loan_mint = loan_mint_key;
+    market_index.oracle = oracle_key;
+    market_index.irm = irm_key;
+    market_index.lltv = lltv;
+    market_index.created_slot = Clock::get()?.slot;
 
     ctx.accounts.protocol_state.market_count += 1;
 
@@ -138,6 +277,10 @@ pub fn create_market(
         oracle: market.oracle,
         irm: market.irm,
         lltv: market.lltv,
+        oracle_kind: market.oracle_kind,
+        oracle_secondary: market.oracle_secondary,
+        min_borrow,
+        min_collateral,
     });
 
     Ok(())
@@ -6,8 +6,11 @@ pub mod position;
 pub mod supply;
 pub mod borrow;
 pub mod liquidate;
+pub mod pre_liquidation;
 pub mod flash_loan;
 pub mod utils;
+pub mod view;
+pub mod vault;
 
 pub use admin::*;
 pub use market::*;
@@ -15,5 +18,8 @@ pub use position::*;
 pub use supply::*;
 pub use borrow::*;
 pub use liquidate::*;
+pub use pre_liquidation::*;
 pub use flash_loan::*;
 pub use utils::*;
+pub use view::*;
+pub use vault::*;
@@ -1,10 +1,16 @@
 //! Position management instructions (create, close)
+//!
+//! Closing settles the position in one go: debt is repaid and collateral
+//! released before the account is closed.
 
 use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, transfer_checked, TransferChecked};
 use crate::constants::PROGRAM_SEED_PREFIX;
 use crate::errors::MorphoError;
-use crate::events::{PositionCreated, PositionClosed};
-use crate::state::{Market, Position};
+use crate::events::{PositionCreated, PositionClosed, Repay, WithdrawCollateral};
+use crate::state::{ProtocolState, Market, Position};
+use crate::math::{checked_sub, safe_u128_to_u64, to_assets_up, accrue_interest_on_market};
+use crate::interfaces::{compute_borrow_rate, amount_to_send, verify_vault_delta};
 
 // ============================================================================
 // Create Position
@@ -21,9 +27,9 @@ pub struct CreatePosition<'info> {
 
     #[account(
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
     )]
-    pub market: Account<'info, Market>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         init,
@@ -45,6 +51,9 @@ pub fn create_position(ctx: Context<CreatePosition>, market_id: [u8; 32]) -> Res
     position.supply_shares = 0;
     position.borrow_shares = 0;
     position.collateral = 0;
+    position.supply_entry_index = 0;
+    position.borrow_entry_index = 0;
+    position.touch()?;
 
     emit!(PositionCreated {
         market_id,
@@ -67,21 +76,180 @@ pub struct ClosePosition<'info> {
     #[account(mut)]
     pub rent_receiver: UncheckedAccount<'info>,
 
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+    )]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
     #[account(
         mut,
         close = rent_receiver,
         seeds = [PROGRAM_SEED_PREFIX, Position::SEED, &market_id, owner.key().as_ref()],
         bump = position.bump,
         constraint = position.owner == owner.key() @ MorphoError::Unauthorized,
-        constraint = position.can_close() @ MorphoError::PositionNotEmpty,
+        // Supply must be withdrawn first: it depends on market liquidity
+        constraint = position.supply_shares == 0 @ MorphoError::PositionNotEmpty,
     )]
-    pub position: Account<'info, Position>,
+    pub position: Box<Account<'info, Position>>,
+
+    /// Pays the outstanding debt
+    #[account(
+        mut,
+        constraint = owner_loan_account.mint == market.load()?.loan_mint,
+    )]
+    pub owner_loan_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Receives the released collateral
+    #[account(
+        mut,
+        constraint = owner_collateral_account.mint == market.load()?.collateral_mint,
+    )]
+    pub owner_collateral_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
+    )]
+    pub loan_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::COLLATERAL_VAULT_SEED, &market_id],
+        bump = market.load()?.collateral_vault_bump,
+        token::mint = collateral_mint,
+        token::authority = market,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
+    pub loan_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(constraint = collateral_mint.key() == market.load()?.collateral_mint @ MorphoError::InvalidMint)]
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Interface<'info, TokenInterface>,
 }
 
+/// Repay all debt, withdraw all collateral and close the position
+///
+/// Interest is accrued first so the whole debt, to the last share, is
+/// cleared. Reverts with InsufficientBalance if the owner cannot pay it.
+/// An already-empty position is simply closed.
 pub fn close_position(ctx: Context<ClosePosition>, market_id: [u8; 32]) -> Result<()> {
+    // ===== CHECKS =====
+    let collateral = ctx.accounts.position.collateral;
+    let borrow_shares = ctx.accounts.position.borrow_shares;
+    if collateral > 0 {
+        // Matches withdraw_collateral; repay alone stays open while paused
+        require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
+        require!(!ctx.accounts.market.load()?.is_paused(), MorphoError::MarketPaused);
+    }
+
+    // Accrue interest
+    let clock = Clock::get()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
+
+    let repay_assets = to_assets_up(borrow_shares, market.total_borrow_assets, market.total_borrow_shares)?;
+    let owed_u64 = safe_u128_to_u64(repay_assets)?;
+    let repay_u64 = amount_to_send(&ctx.accounts.loan_mint.to_account_info(), owed_u64)?;
+    require!(
+        ctx.accounts.owner_loan_account.amount >= repay_u64,
+        MorphoError::InsufficientBalance
+    );
+
+    // ===== EFFECTS =====
+    market.total_borrow_assets = market.total_borrow_assets.saturating_sub(repay_assets);
+    market.total_borrow_shares = checked_sub(market.total_borrow_shares, borrow_shares)?;
+    let position = &mut ctx.accounts.position;
+    position.borrow_shares = 0;
+    position.collateral = 0;
+    let (total_borrow_assets, total_borrow_shares) =
+        (market.total_borrow_assets, market.total_borrow_shares);
+    let bump = market.bump;
+    drop(market);
+
+    // ===== INTERACTIONS =====
+    let owner = ctx.accounts.owner.key();
+    if borrow_shares > 0 {
+        let vault_before = ctx.accounts.loan_vault.amount;
+        transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.owner_loan_account.to_account_info(),
+                    to: ctx.accounts.loan_vault.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                    mint: ctx.accounts.loan_mint.to_account_info(),
+                },
+            ),
+            repay_u64,
+            ctx.accounts.loan_mint.decimals,
+        )?;
+        ctx.accounts.loan_vault.reload()?;
+        verify_vault_delta(vault_before, ctx.accounts.loan_vault.amount, owed_u64)?;
+
+        emit!(Repay {
+            market_id,
+            repayer: owner,
+            on_behalf_of: owner,
+            assets: repay_assets,
+            shares: borrow_shares,
+            total_borrow_assets,
+            total_borrow_shares,
+        });
+    }
+
+    if collateral > 0 {
+        let seeds = &[
+            PROGRAM_SEED_PREFIX,
+            Market::SEED,
+            market_id.as_ref(),
+            &[bump],
+        ];
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.collateral_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.collateral_vault.to_account_info(),
+                    to: ctx.accounts.owner_collateral_account.to_account_info(),
+                    authority: ctx.accounts.market.to_account_info(),
+                    mint: ctx.accounts.collateral_mint.to_account_info(),
+                },
+                &[seeds],
+            ),
+            safe_u128_to_u64(collateral)?,
+            ctx.accounts.collateral_mint.decimals,
+        )?;
+
+        emit!(WithdrawCollateral {
+            market_id,
+            caller: owner,
+            on_behalf_of: owner,
+            receiver: ctx.accounts.owner_collateral_account.key(),
+            amount: collateral,
+        });
+    }
+
     emit!(PositionClosed {
         market_id,
-        owner: ctx.accounts.owner.key(),
+        owner,
     });
     Ok(())
 }
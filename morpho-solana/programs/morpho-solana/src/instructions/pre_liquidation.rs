@@ -0,0 +1,316 @@
+//! Pre-liquidation instructions
+//!
+//! - Borrower opts in with soft terms (pre_lltv, pre_lif, liquidator)
+//! - Partial liquidation between pre_lltv and the market's hard LLTV
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, transfer_checked, TransferChecked};
+use crate::constants::{PROGRAM_SEED_PREFIX, LIF_BPS};
+use crate::errors::MorphoError;
+use crate::events::{PreLiquidationSet, PreLiquidated};
+use crate::state::{ProtocolState, Market, Position, PreLiquidation};
+use crate::math::{
+    checked_sub, safe_u128_to_u64,
+    to_shares_down, to_assets_up,
+    accrue_interest_on_market,
+};
+use crate::interfaces::{
+    compute_borrow_rate, get_oracle_price_validated,
+    is_liquidatable, ensure_liquidatable, health_factor, max_liquidation_repay,
+    calculate_lif, market_lif_curve, calculate_seized_collateral, check_seizure, amount_to_send,
+};
+
+// ============================================================================
+// Set Pre-Liquidation
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct SetPreLiquidation<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        init_if_needed,
+        payer = borrower,
+        space = PreLiquidation::space(),
+        seeds = [PROGRAM_SEED_PREFIX, PreLiquidation::SEED, &market_id, borrower.key().as_ref()],
+        bump,
+    )]
+    pub pre_liquidation: Account<'info, PreLiquidation>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Opt in to (or update) pre-liquidation terms
+///
+/// `pre_lltv` must be below the market's LLTV and `pre_lif` must not exceed
+/// the hard liquidation incentive, so pre-liquidation is always the gentler path.
+pub fn set_pre_liquidation(
+    ctx: Context<SetPreLiquidation>,
+    market_id: [u8; 32],
+    pre_lltv: u64,
+    pre_lif: u64,
+    liquidator: Pubkey,
+) -> Result<()> {
+    let market = &ctx.accounts.market.load()?;
+    require!(pre_lltv > 0 && pre_lltv < market.lltv, MorphoError::InvalidLltv);
+    let (lif_cursor, max_lif) = market_lif_curve(market);
+    require!(
+        pre_lif >= LIF_BPS && pre_lif <= calculate_lif(market.lltv, lif_cursor, max_lif)?,
+        MorphoError::InvalidInput
+    );
+
+    let pre_liquidation = &mut ctx.accounts.pre_liquidation;
+    pre_liquidation.bump = ctx.bumps.pre_liquidation;
+    pre_liquidation.market_id = market_id;
+    pre_liquidation.borrower = ctx.accounts.borrower.key();
+    pre_liquidation.pre_lltv = pre_lltv;
+    pre_liquidation.pre_lif = pre_lif;
+    pre_liquidation.liquidator = liquidator;
+
+    emit!(PreLiquidationSet {
+        market_id,
+        borrower: pre_liquidation.borrower,
+        pre_lltv,
+        pre_lif,
+        liquidator,
+    });
+
+    Ok(())
+}
+
+// ============================================================================
+// Pre-Liquidate
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct PreLiquidate<'info> {
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+    )]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Position::SEED, &market_id, borrower.key().as_ref()],
+        bump = borrower_position.bump,
+    )]
+    pub borrower_position: Box<Account<'info, Position>>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, PreLiquidation::SEED, &market_id, borrower.key().as_ref()],
+        bump = pre_liquidation.bump,
+        constraint = pre_liquidation.is_liquidator_allowed(&liquidator.key()) @ MorphoError::Unauthorized,
+    )]
+    pub pre_liquidation: Box<Account<'info, PreLiquidation>>,
+
+    /// CHECK: Borrower being pre-liquidated
+    pub borrower: UncheckedAccount<'info>,
+
+    /// CHECK: Oracle for price
+    pub oracle: UncheckedAccount<'info>,
+
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        constraint = liquidator_loan_account.mint == market.load()?.loan_mint,
+    )]
+    pub liquidator_loan_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = liquidator_collateral_account.mint == market.load()?.collateral_mint,
+    )]
+    pub liquidator_collateral_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
+    )]
+    pub loan_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::COLLATERAL_VAULT_SEED, &market_id],
+        bump = market.load()?.collateral_vault_bump,
+        token::mint = collateral_mint,
+        token::authority = market,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
+    pub loan_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = collateral_mint.key() == market.load()?.collateral_mint @ MorphoError::InvalidMint)]
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    /// Token program owning the loan mint
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Token program owning the collateral mint
+    pub collateral_token_program: Interface<'info, TokenInterface>,
+}
+
+pub fn pre_liquidate(
+    ctx: Context<PreLiquidate>,
+    market_id: [u8; 32],
+    repaid_assets: u128,  // Amount of loan tokens the liquidator wants to repay
+) -> Result<()> {
+    // ===== CHECKS =====
+    require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
+    require!(!ctx.accounts.market.load()?.is_paused(), MorphoError::MarketPaused);
+    require!(repaid_assets > 0, MorphoError::ZeroAmount);
+
+    // Accrue interest
+    let clock = Clock::get()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
+
+    let position = &ctx.accounts.borrower_position;
+    let terms = &ctx.accounts.pre_liquidation;
+
+    // Get validated oracle price
+    let oracle_price = get_oracle_price_validated(
+        &ctx.accounts.oracle.to_account_info(),
+        ctx.accounts.oracle_secondary.as_ref().map(|a| a.as_ref()),
+        ctx.accounts.oracle_fallback.as_ref().map(|a| a.as_ref()),
+        &mut market,
+    )?;
+
+    // Past the hard LLTV the regular liquidation path takes over
+    require!(
+        !is_liquidatable(
+            position.collateral,
+            position.borrow_shares,
+            market.total_borrow_assets,
+            market.total_borrow_shares,
+            oracle_price,
+            market.lltv,
+        )?,
+        MorphoError::PositionLiquidatable
+    );
+
+    // Must be past the soft threshold
+    ensure_liquidatable(
+        position.collateral,
+        position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+        oracle_price,
+        terms.pre_lltv,
+    )?;
+
+    // Enforce close factor, measured against the soft threshold
+    let borrowed = to_assets_up(position.borrow_shares, market.total_borrow_assets, market.total_borrow_shares)?;
+    let health = health_factor(position.collateral, borrowed, oracle_price, terms.pre_lltv)?;
+    require!(
+        repaid_assets <= max_liquidation_repay(borrowed, health, market.close_factor_bps)?,
+        MorphoError::LiquidationTooLarge
+    );
+
+    // Calculate seized collateral with the pre-liquidation incentive
+    let seized_collateral = calculate_seized_collateral(repaid_assets, oracle_price, terms.pre_lif)?;
+    let seized_collateral = std::cmp::min(seized_collateral, position.collateral);
+
+    // Calculate repaid shares
+    let repaid_shares = to_shares_down(repaid_assets, market.total_borrow_assets, market.total_borrow_shares)?;
+    let repaid_shares = std::cmp::min(repaid_shares, position.borrow_shares);
+    let actual_repaid_assets = to_assets_up(repaid_shares, market.total_borrow_assets, market.total_borrow_shares)?;
+    check_seizure(seized_collateral, actual_repaid_assets, oracle_price, terms.pre_lif)?;
+
+    // ===== EFFECTS =====
+    let position = &mut ctx.accounts.borrower_position;
+    position.borrow_shares = checked_sub(position.borrow_shares, repaid_shares)?;
+    position.collateral = checked_sub(position.collateral, seized_collateral)?;
+    position.touch()?;
+
+    market.total_borrow_shares = checked_sub(market.total_borrow_shares, repaid_shares)?;
+    market.total_borrow_assets = checked_sub(market.total_borrow_assets, actual_repaid_assets)?;
+
+    // ===== INTERACTIONS =====
+    // Liquidator repays loan tokens
+    let repay_amount = amount_to_send(
+        &ctx.accounts.loan_mint.to_account_info(),
+        safe_u128_to_u64(actual_repaid_assets)?,
+    )?;
+    transfer_checked(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.liquidator_loan_account.to_account_info(),
+                to: ctx.accounts.loan_vault.to_account_info(),
+                authority: ctx.accounts.liquidator.to_account_info(),
+                mint: ctx.accounts.loan_mint.to_account_info(),
+            },
+        ),
+        repay_amount,
+        ctx.accounts.loan_mint.decimals,
+    )?;
+
+    // Liquidator receives collateral
+    let collateral_amount = safe_u128_to_u64(seized_collateral)?;
+    let bump = market.bump;
+    drop(market);
+    let seeds = &[
+        PROGRAM_SEED_PREFIX,
+        Market::SEED,
+        market_id.as_ref(),
+        &[bump],
+    ];
+
+    transfer_checked(
+        CpiContext::new_with_signer(
+            ctx.accounts.collateral_token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.collateral_vault.to_account_info(),
+                to: ctx.accounts.liquidator_collateral_account.to_account_info(),
+                authority: ctx.accounts.market.to_account_info(),
+                mint: ctx.accounts.collateral_mint.to_account_info(),
+            },
+            &[seeds],
+        ),
+        collateral_amount,
+        ctx.accounts.collateral_mint.decimals,
+    )?;
+
+    emit!(PreLiquidated {
+        market_id,
+        liquidator: ctx.accounts.liquidator.key(),
+        borrower: ctx.accounts.borrower.key(),
+        repaid_assets: actual_repaid_assets,
+        repaid_shares,
+        seized_collateral,
+    });
+
+    Ok(())
+}
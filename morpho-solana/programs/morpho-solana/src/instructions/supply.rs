@@ -10,10 +10,14 @@ use crate::events;
 use crate::state::{ProtocolState, Market, Position, Authorization};
 use crate::math::{
     checked_add, checked_sub, safe_u128_to_u64,
-    to_shares_down, to_shares_up, to_assets_down,
+    to_shares_down, to_shares_up, to_assets_down, to_assets_up,
     accrue_interest_on_market,
 };
-use crate::interfaces::get_borrow_rate_internal;
+use super::utils::{validate_authorization, check_deadline};
+use crate::interfaces::{
+    compute_borrow_rate, amount_received, amount_to_send, wrap_native, unwrap_native,
+    invoke_callback, validate_callback_program, verify_vault_delta, ON_MORPHO_SUPPLY,
+};
 
 // ============================================================================
 // Supply
@@ -34,9 +38,10 @@ pub struct Supply<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
@@ -50,58 +55,167 @@ pub struct Supply<'info> {
 
     #[account(
         mut,
-        constraint = supplier_token_account.mint == market.loan_mint,
+        constraint = supplier_token_account.mint == market.load()?.loan_mint,
     )]
     pub supplier_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
-        bump = market.loan_vault_bump,
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
     )]
     pub loan_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
     pub loan_mint: InterfaceAccount<'info, Mint>,
 
     pub token_program: Interface<'info, TokenInterface>,
+
+    /// CHECK: Optional program invoked before tokens are pulled, see `validate_callback_program`
+    pub callback_program: Option<UncheckedAccount<'info>>,
+
+    /// Pass in WSOL markets to wrap `assets` lamports from the supplier
+    /// into `supplier_token_account` and close it afterwards
+    pub system_program: Option<Program<'info, System>>,
 }
 
-pub fn supply(
-    ctx: Context<Supply>,
+/// Supply loan tokens for shares
+///
+/// Shares are minted on the amount the vault receives, which is below
+/// `assets` for Token-2022 mints with a transfer fee.
+///
+/// With a `callback_program`, `on_morpho_supply(assets: u64, data: Vec<u8>)`
+/// is invoked after shares are credited and before tokens are pulled. It
+/// receives the supplier (signer), supplier_token_account, loan_mint, then
+/// `remaining_accounts`.
+pub fn supply<'info>(
+    ctx: Context<'_, '_, '_, 'info, Supply<'info>>,
     market_id: [u8; 32],
     assets: u128,
     min_shares: u128,
+    data: Vec<u8>,
 ) -> Result<()> {
     // ===== CHECKS =====
-    require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
-    require!(!ctx.accounts.market.paused, MorphoError::MarketPaused);
+    // Note: Supply allowed even when paused (adds liquidity for exits)
     require!(assets > 0, MorphoError::ZeroAmount);
+    if let Some(callback_program) = &ctx.accounts.callback_program {
+        validate_callback_program(callback_program)?;
+    }
 
     // Accrue interest
-    let borrow_rate = get_borrow_rate_internal(
-        ctx.accounts.market.total_supply_assets,
-        ctx.accounts.market.total_borrow_assets,
-    )?;
-    let current_time = Clock::get()?.unix_timestamp;
-    
-    let market = &mut ctx.accounts.market;
-    accrue_interest_on_market(market, current_time, borrow_rate)?;
+    let clock = Clock::get()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
+
+    // Credit only what reaches the vault
+    let amount_u64 = safe_u128_to_u64(assets)?;
+    let received_u64 = amount_received(&ctx.accounts.loan_mint.to_account_info(), amount_u64)?;
+    let received = received_u64 as u128;
+    require!(received > 0, MorphoError::ZeroAmount);
 
     // Calculate shares (round DOWN - user gets fewer shares)
     let shares = to_shares_down(
-        assets,
+        received,
         market.total_supply_assets,
         market.total_supply_shares,
     )?;
     require!(shares >= min_shares, MorphoError::SlippageExceeded);
+    drop(market);
+
+    settle_supply(ctx, market_id, amount_u64, received_u64, shares, data)
+}
+
+/// Supply exactly `shares`, pulling at most `max_assets` tokens
+///
+/// The vault must receive `to_assets_up(shares)` (grossed up for Token-2022
+/// transfer fees), so the new shares are never worth more than was paid.
+/// Callback and native wrapping work as in `supply`.
+pub fn supply_shares<'info>(
+    ctx: Context<'_, '_, '_, 'info, Supply<'info>>,
+    market_id: [u8; 32],
+    shares: u128,
+    max_assets: u128,
+    data: Vec<u8>,
+) -> Result<()> {
+    // ===== CHECKS =====
+    require!(shares > 0, MorphoError::ZeroAmount);
+    if let Some(callback_program) = &ctx.accounts.callback_program {
+        validate_callback_program(callback_program)?;
+    }
+
+    // Accrue interest
+    let clock = Clock::get()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
+
+    // Calculate assets (round UP - user pays more)
+    let required = to_assets_up(shares, market.total_supply_assets, market.total_supply_shares)?;
+    require!(required > 0, MorphoError::ZeroAmount);
+    let mint = ctx.accounts.loan_mint.to_account_info();
+    let amount_u64 = amount_to_send(&mint, safe_u128_to_u64(required)?)?;
+    require!(amount_u64 as u128 <= max_assets, MorphoError::SlippageExceeded);
+    let received_u64 = amount_received(&mint, amount_u64)?;
+    drop(market);
+
+    settle_supply(ctx, market_id, amount_u64, received_u64, shares, data)
+}
+
+/// Credit `shares` for `received_u64`, then pull `amount_u64` from the supplier
+fn settle_supply<'info>(
+    ctx: Context<'_, '_, '_, 'info, Supply<'info>>,
+    market_id: [u8; 32],
+    amount_u64: u64,
+    received_u64: u64,
+    shares: u128,
+    data: Vec<u8>,
+) -> Result<()> {
+    let received = received_u64 as u128;
+    let mut market = ctx.accounts.market.load_mut()?;
+    market.ensure_supply_cap(received)?;
 
     // ===== EFFECTS =====
-    market.total_supply_assets = checked_add(market.total_supply_assets, assets)?;
+    market.total_supply_assets = checked_add(market.total_supply_assets, received)?;
     market.total_supply_shares = checked_add(market.total_supply_shares, shares)?;
-    ctx.accounts.position.supply_shares = checked_add(ctx.accounts.position.supply_shares, shares)?;
+    ctx.accounts.position.add_supply_shares(shares, market.supply_index())?;
+    ctx.accounts.position.touch()?;
+    drop(market);
 
     // ===== INTERACTIONS =====
-    let amount_u64 = safe_u128_to_u64(assets)?;
+
+    // Let the integrator source tokens just-in-time
+    if let Some(callback_program) = &ctx.accounts.callback_program {
+        ctx.accounts.market.load_mut()?.enter_callback()?;
+        ctx.accounts.position.exit(&crate::ID)?;
+
+        let mut callback_accounts = vec![
+            ctx.accounts.supplier.to_account_info(),
+            ctx.accounts.supplier_token_account.to_account_info(),
+            ctx.accounts.loan_mint.to_account_info(),
+        ];
+        callback_accounts.extend(ctx.remaining_accounts.iter().cloned());
+        invoke_callback(callback_program, ON_MORPHO_SUPPLY, (amount_u64, data), &callback_accounts)?;
+        ctx.accounts.market.load_mut()?.exit_callback();
+    }
+
+    if let Some(system_program) = &ctx.accounts.system_program {
+        wrap_native(
+            &ctx.accounts.token_program,
+            system_program,
+            &ctx.accounts.supplier,
+            &ctx.accounts.supplier_token_account,
+            amount_u64,
+        )?;
+    }
+
+    let vault_before = ctx.accounts.loan_vault.amount;
     transfer_checked(
         CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
@@ -115,13 +229,30 @@ pub fn supply(
         amount_u64,
         ctx.accounts.loan_mint.decimals,
     )?;
+    ctx.accounts.loan_vault.reload()?;
+    verify_vault_delta(vault_before, ctx.accounts.loan_vault.amount, received_u64)?;
+
+    if ctx.accounts.system_program.is_some() {
+        unwrap_native(
+            &ctx.accounts.token_program,
+            &ctx.accounts.supplier,
+            &ctx.accounts.supplier_token_account,
+            &ctx.accounts.supplier.to_account_info(),
+        )?;
+    }
+
+    let market = ctx.accounts.market.load()?;
+    #[cfg(feature = "invariant-checks")]
+    crate::state::assert_invariants(&market)?;
 
     emit!(events::Supply {
         market_id,
         supplier: ctx.accounts.supplier.key(),
         on_behalf_of: ctx.accounts.on_behalf_of.key(),
-        assets,
+        assets: received,
         shares,
+        total_supply_assets: market.total_supply_assets,
+        total_supply_shares: market.total_supply_shares,
     });
 
     Ok(())
@@ -146,9 +277,10 @@ pub struct Withdraw<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Box<Account<'info, Market>>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
@@ -162,31 +294,48 @@ pub struct Withdraw<'info> {
 
     #[account(
         mut,
-        constraint = receiver_token_account.mint == market.loan_mint,
+        constraint = receiver_token_account.mint == market.load()?.loan_mint,
     )]
     pub receiver_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
-        bump = market.loan_vault_bump,
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
     )]
     pub loan_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
     pub loan_mint: InterfaceAccount<'info, Mint>,
 
     pub token_program: Interface<'info, TokenInterface>,
+
+    /// Pass in WSOL markets to close the caller-owned `receiver_token_account`
+    /// and receive the withdrawn assets as native SOL
+    #[account(mut)]
+    pub native_receiver: Option<SystemAccount<'info>>,
 }
 
+/// Withdraw supply by assets or shares
+///
+/// A non-zero `min_assets_out` is the fewest tokens the receiver must get
+/// after transfer fees; withdrawing by shares otherwise pays whatever those
+/// shares are worth once interest has accrued. A non-zero `deadline_slot`
+/// rejects the transaction if it lands later than that slot.
 pub fn withdraw(
     ctx: Context<Withdraw>,
     market_id: [u8; 32],
     assets: u128,
     shares: u128,
+    min_assets_out: u128,
+    deadline_slot: u64,
 ) -> Result<()> {
     // ===== CHECKS =====
+    check_deadline(deadline_slot, Clock::get()?.slot)?;
     require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
-    require!(!ctx.accounts.market.paused, MorphoError::MarketPaused);
+    require!(!ctx.accounts.market.load()?.is_paused(), MorphoError::MarketPaused);
     require!(assets > 0 || shares > 0, MorphoError::ZeroAmount);
     require!(!(assets > 0 && shares > 0), MorphoError::InvalidInput);
 
@@ -198,14 +347,12 @@ pub fn withdraw(
     )?;
 
     // Accrue interest
-    let borrow_rate = get_borrow_rate_internal(
-        ctx.accounts.market.total_supply_assets,
-        ctx.accounts.market.total_borrow_assets,
-    )?;
-    let current_time = Clock::get()?.unix_timestamp;
-    
-    let market = &mut ctx.accounts.market;
-    accrue_interest_on_market(market, current_time, borrow_rate)?;
+    let clock = Clock::get()?;
+    let mut market = ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
 
     // Calculate amounts
     let (withdraw_assets, burn_shares) = if assets > 0 {
@@ -220,20 +367,24 @@ pub fn withdraw(
         ctx.accounts.position.supply_shares >= burn_shares,
         MorphoError::InsufficientBalance
     );
-    require!(
-        withdraw_assets <= market.available_liquidity(),
-        MorphoError::InsufficientLiquidity
-    );
+    market.ensure_liquidity(withdraw_assets)?;
+
+    let amount_u64 = safe_u128_to_u64(withdraw_assets)?;
+    if min_assets_out > 0 {
+        let received = amount_received(&ctx.accounts.loan_mint.to_account_info(), amount_u64)?;
+        require!(received as u128 >= min_assets_out, MorphoError::SlippageExceeded);
+    }
 
     // ===== EFFECTS =====
     ctx.accounts.position.supply_shares = checked_sub(ctx.accounts.position.supply_shares, burn_shares)?;
+    ctx.accounts.position.touch()?;
     market.total_supply_assets = checked_sub(market.total_supply_assets, withdraw_assets)?;
     market.total_supply_shares = checked_sub(market.total_supply_shares, burn_shares)?;
 
     // ===== INTERACTIONS =====
-    let amount_u64 = safe_u128_to_u64(withdraw_assets)?;
     let market_id_ref = market_id;
     let bump = market.bump;
+    drop(market);
     let seeds = &[
         PROGRAM_SEED_PREFIX,
         Market::SEED,
@@ -256,6 +407,19 @@ pub fn withdraw(
         ctx.accounts.loan_mint.decimals,
     )?;
 
+    if let Some(native_receiver) = &ctx.accounts.native_receiver {
+        unwrap_native(
+            &ctx.accounts.token_program,
+            &ctx.accounts.caller,
+            &ctx.accounts.receiver_token_account,
+            &native_receiver.to_account_info(),
+        )?;
+    }
+
+    let market = ctx.accounts.market.load()?;
+    #[cfg(feature = "invariant-checks")]
+    crate::state::assert_invariants(&market)?;
+
     emit!(events::Withdraw {
         market_id,
         caller: ctx.accounts.caller.key(),
@@ -263,31 +427,201 @@ pub fn withdraw(
         receiver: ctx.accounts.receiver_token_account.key(),
         assets: withdraw_assets,
         shares: burn_shares,
+        total_supply_assets: market.total_supply_assets,
+        total_supply_shares: market.total_supply_shares,
     });
 
     Ok(())
 }
 
-/// Validate authorization for delegated operations
-fn validate_authorization(
-    caller: &Signer,
-    owner: &Pubkey,
-    authorization: Option<&Account<Authorization>>,
+// ============================================================================
+// Emergency Withdraw
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct EmergencyWithdraw<'info> {
+    pub caller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Position::SEED, &market_id, position.owner.as_ref()],
+        bump = position.bump,
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    /// Optional authorization account
+    pub authorization: Option<Account<'info, Authorization>>,
+
+    #[account(
+        mut,
+        constraint = receiver_token_account.mint == market.load()?.loan_mint,
+    )]
+    pub receiver_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
+    )]
+    pub loan_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
+    pub loan_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Loan tokens paid for redeeming `shares` through `emergency_withdraw`
+///
+/// Shares are priced against the stored `total_supply_assets`, without
+/// accruing interest; interest owed since `last_update` goes to the
+/// suppliers still in the market once it is unpaused.
+fn quote_emergency_withdraw(market: &Market, position: &Position, shares: u128) -> Result<u128> {
+    require!(market.is_paused(), MorphoError::MarketNotPaused);
+    require!(shares > 0, MorphoError::ZeroAmount);
+    require!(position.borrow_shares == 0, MorphoError::EmergencyWithdrawHasDebt);
+    require!(position.supply_shares >= shares, MorphoError::InsufficientBalance);
+
+    let assets = to_assets_down(shares, market.total_supply_assets, market.total_supply_shares)?;
+    require!(assets > 0, MorphoError::ZeroAmount);
+    market.ensure_liquidity(assets)?;
+    Ok(assets)
+}
+
+/// Redeem supply shares from a paused market without touching the IRM or oracle
+///
+/// For markets whose oracle or IRM is permanently broken, where every
+/// accruing handler would fail. Works whether or not the protocol itself is
+/// paused. Positions with debt are refused, since releasing anything of
+/// theirs needs a price.
+pub fn emergency_withdraw(
+    ctx: Context<EmergencyWithdraw>,
+    market_id: [u8; 32],
+    shares: u128,
 ) -> Result<()> {
-    if caller.key() == *owner {
-        return Ok(());
-    }
+    // ===== CHECKS =====
+    validate_authorization(
+        &ctx.accounts.caller,
+        &ctx.accounts.position.owner,
+        ctx.accounts.authorization.as_ref(),
+    )?;
+    let assets = quote_emergency_withdraw(&*ctx.accounts.market.load()?, &ctx.accounts.position, shares)?;
+    let amount_u64 = safe_u128_to_u64(assets)?;
 
-    let current_time = Clock::get()?.unix_timestamp;
+    // ===== EFFECTS =====
+    let mut market = ctx.accounts.market.load_mut()?;
+    ctx.accounts.position.supply_shares = checked_sub(ctx.accounts.position.supply_shares, shares)?;
+    ctx.accounts.position.touch()?;
+    market.total_supply_assets = checked_sub(market.total_supply_assets, assets)?;
+    market.total_supply_shares = checked_sub(market.total_supply_shares, shares)?;
 
-    if let Some(auth) = authorization {
-        if auth.authorizer == *owner
-            && auth.authorized == caller.key()
-            && auth.is_valid(current_time)
-        {
-            return Ok(());
-        }
-    }
+    // ===== INTERACTIONS =====
+    let bump = market.bump;
+    drop(market);
+    let seeds = &[
+        PROGRAM_SEED_PREFIX,
+        Market::SEED,
+        market_id.as_ref(),
+        &[bump],
+    ];
 
-    Err(MorphoError::Unauthorized.into())
+    transfer_checked(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.loan_vault.to_account_info(),
+                to: ctx.accounts.receiver_token_account.to_account_info(),
+                authority: ctx.accounts.market.to_account_info(),
+                mint: ctx.accounts.loan_mint.to_account_info(),
+            },
+            &[seeds],
+        ),
+        amount_u64,
+        ctx.accounts.loan_mint.decimals,
+    )?;
+
+    let market = ctx.accounts.market.load()?;
+    #[cfg(feature = "invariant-checks")]
+    crate::state::assert_invariants(&market)?;
+
+    emit!(events::EmergencyWithdraw {
+        market_id,
+        caller: ctx.accounts.caller.key(),
+        on_behalf_of: ctx.accounts.position.owner,
+        receiver: ctx.accounts.receiver_token_account.key(),
+        assets,
+        shares,
+        total_supply_assets: market.total_supply_assets,
+        total_supply_shares: market.total_supply_shares,
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_emergency_withdraw_ignores_broken_oracle() {
+        let mut market = Market {
+            // Garbage oracle and IRM: nothing here may read them
+            oracle: Pubkey::new_unique(),
+            irm: Pubkey::new_unique(),
+            paused: 1,
+            total_supply_assets: 10_000,
+            total_supply_shares: 10_000_000_000,
+            total_borrow_assets: 2_000,
+            total_borrow_shares: 2_000_000_000,
+            last_update: 1_000,
+            oracle_kind: u8::MAX,
+            ..bytemuck::Zeroable::zeroed()
+        };
+
+        let supplier = Position {
+            owner: Pubkey::new_unique(),
+            supply_shares: 4_000_000_000,
+            ..Default::default()
+        };
+
+        // 40% of the shares take 40% of the stored supply, with no accrual
+        assert_eq!(quote_emergency_withdraw(&market, &supplier, 4_000_000_000).unwrap(), 4_000);
+        assert_eq!(
+            quote_emergency_withdraw(&market, &supplier, 4_000_000_001).unwrap_err(),
+            MorphoError::InsufficientBalance.into()
+        );
+
+        // Borrowers are refused
+        let mut borrower = supplier.clone();
+        borrower.borrow_shares = 1;
+        assert_eq!(
+            quote_emergency_withdraw(&market, &borrower, 1_000_000).unwrap_err(),
+            MorphoError::EmergencyWithdrawHasDebt.into()
+        );
+
+        // Lent-out supply stays put
+        let mut whale = supplier.clone();
+        whale.supply_shares = market.total_supply_shares;
+        assert_eq!(
+            quote_emergency_withdraw(&market, &whale, market.total_supply_shares).unwrap_err(),
+            MorphoError::InsufficientLiquidity.into()
+        );
+
+        market.set_paused(false);
+        assert_eq!(
+            quote_emergency_withdraw(&market, &supplier, 1_000_000).unwrap_err(),
+            MorphoError::MarketNotPaused.into()
+        );
+    }
 }
@@ -1,12 +1,33 @@
-//! Utility instructions (accrue interest, set authorization, claim fees)
+//! Utility instructions (accrue interest, socialize pending bad debt, fund
+//! the bad-debt reserve, set authorization, claim fees, set static oracle
+//! price, crank TWAP and median oracles)
+//!
+//! Authorizations can also be granted by relayers with an Ed25519 signature
+//! from the authorizer, verified through the instructions sysvar.
 
 use anchor_lang::prelude::*;
-use crate::constants::PROGRAM_SEED_PREFIX;
+use anchor_lang::solana_program::{
+    ed25519_program,
+    instruction::Instruction,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, transfer_checked, TransferChecked};
+use crate::constants::{PROGRAM_SEED_PREFIX, MIN_ORACLE_PRICE};
 use crate::errors::MorphoError;
-use crate::events::{InterestAccrued, AuthorizationSet, AuthorizationRevoked, FeesClaimed};
-use crate::state::{ProtocolState, Market, Position, Authorization};
-use crate::math::{checked_add, accrue_interest_on_market};
-use crate::interfaces::get_borrow_rate_internal;
+use crate::events::{
+    InterestAccrued, AuthorizationSet, AuthorizationRevoked, FeesClaimed, StaticPriceUpdated,
+    TwapUpdated, MedianUpdated, ReserveFunded,
+};
+use crate::state::{
+    ProtocolState, Market, Position, Authorization, AuthorizationNonce, OracleKind, TwapOracle,
+    MedianOracle, authorization_message,
+};
+use crate::math::{checked_add, safe_u128_to_u64, accrue_interest_on_market};
+use crate::interfaces::{
+    compute_borrow_rate, max_oracle_price, read_feed, get_median_price, socialize_pending_bad_debt,
+    amount_received, verify_vault_delta, FeedLimits, StaticOracle,
+    MAX_ORACLE_CONFIDENCE_BPS, MAX_ORACLE_STD_BPS, MIN_ORACLE_SAMPLES,
+};
 
 // ============================================================================
 // Accrue Interest (Public)
@@ -18,27 +39,146 @@ pub struct AccrueInterest<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
     )]
-    pub market: Account<'info, Market>,
+    pub market: AccountLoader<'info, Market>,
 }
 
+/// Accrue interest without any user action (permissionless keeper crank)
+///
+/// Keeps on-chain totals fresh for view consumers between user operations.
 pub fn accrue_interest_ix(ctx: Context<AccrueInterest>, market_id: [u8; 32]) -> Result<()> {
-    let borrow_rate = get_borrow_rate_internal(
-        ctx.accounts.market.total_supply_assets,
-        ctx.accounts.market.total_borrow_assets,
-    )?;
-    let current_time = Clock::get()?.unix_timestamp;
-
-    let market = &mut ctx.accounts.market;
+    let clock = Clock::get()?;
+    let market = &mut ctx.accounts.market.load_mut()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(market, elapsed)?;
     let result = accrue_interest_on_market(market, current_time, borrow_rate)?;
+    #[cfg(feature = "invariant-checks")]
+    crate::state::assert_invariants(market)?;
 
     emit!(InterestAccrued {
         market_id,
         interest: result.interest,
         fee_shares: result.fee_shares,
+        borrow_rate,
         total_supply_assets: market.total_supply_assets,
+        total_supply_shares: market.total_supply_shares,
         total_borrow_assets: market.total_borrow_assets,
+        total_borrow_shares: market.total_borrow_shares,
+        last_update: market.last_update,
+    });
+
+    Ok(())
+}
+
+// ============================================================================
+// Socialize Bad Debt (Public)
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct SocializeBadDebt<'info> {
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
+    )]
+    pub market: AccountLoader<'info, Market>,
+}
+
+/// Charge the market's pending bad debt to suppliers regardless of the
+/// threshold, so a batch below it does not sit in `total_supply_assets`
+/// indefinitely
+pub fn socialize_bad_debt_ix(ctx: Context<SocializeBadDebt>, _market_id: [u8; 32]) -> Result<()> {
+    if let Some(socialized) = socialize_pending_bad_debt(&mut *ctx.accounts.market.load_mut()?, true)? {
+        emit!(socialized);
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Fund Reserve (Public)
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct FundReserve<'info> {
+    pub funder: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+        constraint = !market.load()?.is_callback_active() @ MorphoError::ReentrancyDetected,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        mut,
+        constraint = funder_token_account.mint == market.load()?.loan_mint,
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_id],
+        bump = market.load()?.loan_vault_bump,
+        token::mint = loan_mint,
+        token::authority = market,
+    )]
+    pub loan_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(constraint = loan_mint.key() == market.load()?.loan_mint @ MorphoError::InvalidMint)]
+    pub loan_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Deposit loan tokens into the market's bad-debt reserve
+///
+/// The tokens stay in the loan vault but outside `total_supply_assets`, so
+/// they earn nothing and cannot be borrowed or withdrawn; socialization
+/// draws on them before charging suppliers. Only what reaches the vault is
+/// credited (Token-2022 transfer fees are the funder's).
+pub fn fund_reserve(ctx: Context<FundReserve>, market_id: [u8; 32], assets: u128) -> Result<()> {
+    // ===== CHECKS =====
+    require!(assets > 0, MorphoError::ZeroAmount);
+    let amount_u64 = safe_u128_to_u64(assets)?;
+    let received_u64 = amount_received(&ctx.accounts.loan_mint.to_account_info(), amount_u64)?;
+    require!(received_u64 > 0, MorphoError::ZeroAmount);
+
+    // ===== EFFECTS =====
+    let received = received_u64 as u128;
+    let mut market = ctx.accounts.market.load_mut()?;
+    market.reserve_assets = checked_add(market.reserve_assets, received)?;
+    drop(market);
+
+    // ===== INTERACTIONS =====
+    let vault_before = ctx.accounts.loan_vault.amount;
+    transfer_checked(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.funder_token_account.to_account_info(),
+                to: ctx.accounts.loan_vault.to_account_info(),
+                authority: ctx.accounts.funder.to_account_info(),
+                mint: ctx.accounts.loan_mint.to_account_info(),
+            },
+        ),
+        amount_u64,
+        ctx.accounts.loan_mint.decimals,
+    )?;
+    ctx.accounts.loan_vault.reload()?;
+    verify_vault_delta(vault_before, ctx.accounts.loan_vault.amount, received_u64)?;
+
+    emit!(ReserveFunded {
+        market_id,
+        funder: ctx.accounts.funder.key(),
+        assets: received,
+        reserve_assets: ctx.accounts.market.load()?.reserve_assets,
     });
 
     Ok(())
@@ -99,6 +239,156 @@ pub fn set_authorization(
     Ok(())
 }
 
+// ============================================================================
+// Set Authorization With Signature
+// ============================================================================
+
+#[derive(Accounts)]
+pub struct SetAuthorizationWithSig<'info> {
+    /// Relayer submitting the signed grant
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Verified against the Ed25519 signature
+    pub authorizer: UncheckedAccount<'info>,
+
+    /// CHECK: Account to authorize
+    pub authorized: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = Authorization::space(),
+        seeds = [
+            PROGRAM_SEED_PREFIX,
+            Authorization::SEED,
+            authorizer.key().as_ref(),
+            authorized.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub authorization: Account<'info, Authorization>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = AuthorizationNonce::space(),
+        seeds = [PROGRAM_SEED_PREFIX, AuthorizationNonce::SEED, authorizer.key().as_ref()],
+        bump,
+    )]
+    pub authorization_nonce: Account<'info, AuthorizationNonce>,
+
+    /// CHECK: Instructions sysvar, holds the Ed25519 verify instruction
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Set authorization from an off-chain signature
+///
+/// The transaction must include an Ed25519 program instruction, directly
+/// before this one, verifying the authorizer's signature over
+/// `authorization_message(...)`. The nonce is consumed on success.
+pub fn set_authorization_with_sig(
+    ctx: Context<SetAuthorizationWithSig>,
+    is_authorized: bool,
+    expires_at: i64,
+    nonce: u64,
+    deadline: i64,
+) -> Result<()> {
+    // ===== CHECKS =====
+    let current_time = Clock::get()?.unix_timestamp;
+    validate_signature_terms(current_time, deadline, ctx.accounts.authorization_nonce.nonce, nonce)?;
+    require!(!ctx.accounts.authorization.is_revoked, MorphoError::AuthorizationRevoked);
+
+    let authorizer = ctx.accounts.authorizer.key();
+    let authorized = ctx.accounts.authorized.key();
+    let message = authorization_message(
+        &crate::ID,
+        &authorizer,
+        &authorized,
+        is_authorized,
+        expires_at,
+        nonce,
+        deadline,
+    );
+
+    let sysvar = ctx.accounts.instructions_sysvar.to_account_info();
+    let current_index = load_current_index_checked(&sysvar)?;
+    require!(current_index > 0, MorphoError::InvalidSignature);
+    let ed25519_ix = load_instruction_at_checked(current_index as usize - 1, &sysvar)?;
+    verify_ed25519_instruction(&ed25519_ix, &authorizer, &message)?;
+
+    // ===== EFFECTS =====
+    let nonce_account = &mut ctx.accounts.authorization_nonce;
+    nonce_account.bump = ctx.bumps.authorization_nonce;
+    nonce_account.authorizer = authorizer;
+    nonce_account.nonce = nonce_account.nonce.checked_add(1).ok_or(MorphoError::MathOverflow)?;
+
+    let auth = &mut ctx.accounts.authorization;
+    auth.bump = ctx.bumps.authorization;
+    auth.authorizer = authorizer;
+    auth.authorized = authorized;
+    auth.is_authorized = is_authorized;
+    auth.expires_at = expires_at;
+
+    emit!(AuthorizationSet {
+        authorizer,
+        authorized,
+        is_authorized,
+        expires_at,
+    });
+
+    Ok(())
+}
+
+/// Reject expired or replayed signatures
+fn validate_signature_terms(
+    current_time: i64,
+    deadline: i64,
+    expected_nonce: u64,
+    nonce: u64,
+) -> Result<()> {
+    require!(current_time <= deadline, MorphoError::SignatureExpired);
+    require!(nonce == expected_nonce, MorphoError::InvalidNonce);
+    Ok(())
+}
+
+/// Check that an Ed25519 program instruction verified `message` signed by `signer`
+///
+/// The precompile has already checked the signature itself; this confirms it
+/// covered the expected key and bytes. Only a single signature whose data is
+/// embedded in the same instruction is accepted.
+fn verify_ed25519_instruction(ix: &Instruction, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    require!(ix.program_id == ed25519_program::ID, MorphoError::InvalidSignature);
+
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, MorphoError::InvalidSignature);
+
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let public_key_offset = read_u16(6) as usize;
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+
+    // Signature, key and message must all live in this instruction's data
+    require!(
+        read_u16(4) == u16::MAX && read_u16(8) == u16::MAX && read_u16(14) == u16::MAX,
+        MorphoError::InvalidSignature
+    );
+
+    let public_key = data
+        .get(public_key_offset..public_key_offset + 32)
+        .ok_or(MorphoError::InvalidSignature)?;
+    let signed = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(MorphoError::InvalidSignature)?;
+
+    require!(public_key == signer.as_ref(), MorphoError::InvalidSignature);
+    require!(signed == message, MorphoError::InvalidSignature);
+    Ok(())
+}
+
 // ============================================================================
 // Revoke Authorization
 // ============================================================================
@@ -135,6 +425,43 @@ pub fn revoke_authorization(ctx: Context<RevokeAuthorization>) -> Result<()> {
     Ok(())
 }
 
+// ============================================================================
+// Authorization Check
+// ============================================================================
+
+/// Validate authorization for delegated operations
+///
+/// Withdraw, borrow and withdraw-collateral call this; supply and repay stay
+/// permissionless toward any position.
+pub(crate) fn validate_authorization(
+    caller: &Signer,
+    owner: &Pubkey,
+    authorization: Option<&Account<Authorization>>,
+) -> Result<()> {
+    if caller.key() == *owner {
+        return Ok(());
+    }
+
+    let current_time = Clock::get()?.unix_timestamp;
+
+    if let Some(auth) = authorization {
+        if auth.permits(owner, &caller.key(), current_time) {
+            return Ok(());
+        }
+    }
+
+    Err(MorphoError::Unauthorized.into())
+}
+
+/// Reject a transaction landing after `deadline_slot` (0 = no deadline)
+pub(crate) fn check_deadline(deadline_slot: u64, current_slot: u64) -> Result<()> {
+    require!(
+        deadline_slot == 0 || current_slot <= deadline_slot,
+        MorphoError::DeadlineExceeded
+    );
+    Ok(())
+}
+
 // ============================================================================
 // Claim Fees
 // ============================================================================
@@ -151,9 +478,9 @@ pub struct ClaimFees<'info> {
     #[account(
         mut,
         seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
-        bump = market.bump,
+        bump = market.load()?.bump,
     )]
-    pub market: Account<'info, Market>,
+    pub market: AccountLoader<'info, Market>,
 
     #[account(
         mut,
@@ -169,18 +496,17 @@ pub struct ClaimFees<'info> {
 }
 
 pub fn claim_fees(ctx: Context<ClaimFees>, market_id: [u8; 32]) -> Result<()> {
-    let pending = ctx.accounts.market.pending_fee_shares;
-    
+    let market = &mut ctx.accounts.market.load_mut()?;
+    let pending = market.pending_fee_shares;
+
     if pending == 0 {
         return Ok(());
     }
 
     // Transfer pending fee shares to fee recipient's position
-    ctx.accounts.fee_position.supply_shares = checked_add(
-        ctx.accounts.fee_position.supply_shares,
-        pending,
-    )?;
-    ctx.accounts.market.pending_fee_shares = 0;
+    ctx.accounts.fee_position.add_supply_shares(pending, market.supply_index())?;
+    ctx.accounts.fee_position.touch()?;
+    market.pending_fee_shares = 0;
 
     emit!(FeesClaimed {
         market_id,
@@ -190,3 +516,198 @@ pub fn claim_fees(ctx: Context<ClaimFees>, market_id: [u8; 32]) -> Result<()> {
 
     Ok(())
 }
+
+// ============================================================================
+// Set Static Price (testing only)
+// ============================================================================
+
+#[derive(Accounts)]
+pub struct SetStaticPrice<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = admin @ MorphoError::Unauthorized,
+    )]
+    pub static_oracle: Account<'info, StaticOracle>,
+}
+
+/// Move a StaticOracle's price, within the same bounds readers enforce
+///
+/// `last_update_slot` is stored as given (0 = never stale), so tests can
+/// age the feed past a market's staleness limit deterministically.
+/// Markets cache their oracle price per slot, so the new price is seen
+/// from the next slot on.
+pub fn set_static_price(
+    ctx: Context<SetStaticPrice>,
+    price: u128,
+    last_update_slot: u64,
+) -> Result<()> {
+    require!(price >= MIN_ORACLE_PRICE, MorphoError::OraclePriceTooLow);
+    require!(price <= max_oracle_price(), MorphoError::OraclePriceTooHigh);
+
+    ctx.accounts.static_oracle.price = price;
+    ctx.accounts.static_oracle.last_update_slot = last_update_slot;
+
+    emit!(StaticPriceUpdated {
+        oracle: ctx.accounts.static_oracle.key(),
+        admin: ctx.accounts.admin.key(),
+        price,
+        last_update_slot,
+    });
+    Ok(())
+}
+
+// ============================================================================
+// Update TWAP (Public)
+// ============================================================================
+
+#[derive(Accounts)]
+pub struct UpdateTwap<'info> {
+    #[account(mut)]
+    pub twap_oracle: Box<Account<'info, TwapOracle>>,
+
+    /// CHECK: Must be the TWAP's source feed; validated by `read_feed`
+    #[account(constraint = source.key() == twap_oracle.source @ MorphoError::InvalidOracle)]
+    pub source: UncheckedAccount<'info>,
+}
+
+/// Append the source feed's current price to a TWAP oracle
+///
+/// Permissionless crank. Samples closer together than `min_spacing()`
+/// are rejected so the buffer always spans at least two windows.
+pub fn update_twap(ctx: Context<UpdateTwap>) -> Result<()> {
+    let clock = Clock::get()?;
+    let twap = &mut ctx.accounts.twap_oracle;
+    let limits = FeedLimits {
+        max_staleness_slots: twap.max_staleness_slots,
+        min_samples: MIN_ORACLE_SAMPLES,
+        max_confidence_bps: MAX_ORACLE_CONFIDENCE_BPS,
+        max_std_bps: MAX_ORACLE_STD_BPS,
+        pyth_feed_id: twap.pyth_feed_id,
+    };
+    let kind = OracleKind::try_from(twap.source_kind)?;
+    let price = read_feed(&ctx.accounts.source.to_account_info(), kind, &limits, &clock)?.price;
+
+    twap.push(clock.slot, price)?;
+
+    emit!(TwapUpdated {
+        twap_oracle: twap.key(),
+        slot: clock.slot,
+        price,
+    });
+    Ok(())
+}
+
+// ============================================================================
+// Update Median (Public)
+// ============================================================================
+
+#[derive(Accounts)]
+pub struct UpdateMedian<'info> {
+    #[account(mut)]
+    pub median_oracle: Box<Account<'info, MedianOracle>>,
+    // remaining_accounts: the aggregator's feeds, in `MedianOracle::feeds` order
+}
+
+/// Store the current median of a `MedianOracle`'s feeds
+///
+/// Permissionless crank; see `get_median_price` for which feeds count.
+pub fn update_median<'info>(
+    ctx: Context<'_, '_, 'info, 'info, UpdateMedian<'info>>,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let median = &mut ctx.accounts.median_oracle;
+    let price = get_median_price(ctx.remaining_accounts, median, &clock)?;
+
+    median.price = price;
+    median.last_update_slot = clock.slot;
+
+    emit!(MedianUpdated {
+        median_oracle: median.key(),
+        slot: clock.slot,
+        price,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_deadline() {
+        assert!(check_deadline(0, u64::MAX).is_ok(), "Zero disables the deadline");
+        assert!(check_deadline(100, 99).is_ok());
+        assert!(check_deadline(100, 100).is_ok(), "Deadline slot itself is allowed");
+        assert_eq!(check_deadline(100, 101).unwrap_err(), MorphoError::DeadlineExceeded.into());
+    }
+
+    /// Ed25519 program instruction data with key and message inline
+    fn ed25519_ix(signer: &Pubkey, message: &[u8]) -> Instruction {
+        let public_key_offset: u16 = 16;
+        let signature_offset: u16 = public_key_offset + 32;
+        let message_offset: u16 = signature_offset + 64;
+
+        let mut data = vec![1u8, 0];
+        for field in [
+            signature_offset, u16::MAX,
+            public_key_offset, u16::MAX,
+            message_offset, message.len() as u16, u16::MAX,
+        ] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(signer.as_ref());
+        data.extend_from_slice(&[0u8; 64]);
+        data.extend_from_slice(message);
+
+        Instruction { program_id: ed25519_program::ID, accounts: vec![], data }
+    }
+
+    #[test]
+    fn test_ed25519_matching_signer_and_message() {
+        let signer = Pubkey::new_unique();
+        let message = authorization_message(&crate::ID, &signer, &Pubkey::new_unique(), true, 0, 0, 100);
+        assert!(verify_ed25519_instruction(&ed25519_ix(&signer, &message), &signer, &message).is_ok());
+    }
+
+    #[test]
+    fn test_ed25519_rejects_wrong_signer() {
+        let signer = Pubkey::new_unique();
+        let impostor = Pubkey::new_unique();
+        let message = authorization_message(&crate::ID, &signer, &Pubkey::new_unique(), true, 0, 0, 100);
+        assert!(verify_ed25519_instruction(&ed25519_ix(&impostor, &message), &signer, &message).is_err());
+    }
+
+    #[test]
+    fn test_ed25519_rejects_other_message() {
+        let signer = Pubkey::new_unique();
+        let authorized = Pubkey::new_unique();
+        let signed = authorization_message(&crate::ID, &signer, &authorized, true, 0, 0, 100);
+        let claimed = authorization_message(&crate::ID, &signer, &authorized, true, 0, 1, 100);
+        assert!(verify_ed25519_instruction(&ed25519_ix(&signer, &signed), &signer, &claimed).is_err());
+    }
+
+    #[test]
+    fn test_ed25519_rejects_other_program() {
+        let signer = Pubkey::new_unique();
+        let message = authorization_message(&crate::ID, &signer, &Pubkey::new_unique(), true, 0, 0, 100);
+        let mut ix = ed25519_ix(&signer, &message);
+        ix.program_id = Pubkey::new_unique();
+        assert!(verify_ed25519_instruction(&ix, &signer, &message).is_err());
+    }
+
+    #[test]
+    fn test_signature_rejects_expired_deadline() {
+        assert!(validate_signature_terms(100, 100, 0, 0).is_ok());
+        assert!(validate_signature_terms(101, 100, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_signature_rejects_nonce_replay() {
+        // Nonce 0 was consumed, so the account now expects 1
+        assert!(validate_signature_terms(0, 100, 1, 0).is_err());
+        assert!(validate_signature_terms(0, 100, 1, 1).is_ok());
+        assert!(validate_signature_terms(0, 100, 1, 2).is_err());
+    }
+}
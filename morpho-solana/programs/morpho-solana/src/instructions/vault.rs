@@ -0,0 +1,1043 @@
+//! Vault instructions
+//!
+//! - Create a vault for one loan token
+//! - List markets and set per-market caps (curator)
+//! - Reorder the supply and withdraw queues, or drop markets from them (curator)
+//! - Remove a market after a notice period, once emptied (curator)
+//! - Deposit / withdraw against vault shares
+//! - Reallocate supply between listed markets (curator)
+//! - Performance fee on yield, minted as vault shares (curator sets, anyone claims)
+//!
+//! The vault supplies to markets directly (no CPI): it owns an ordinary
+//! `Position` in every listed market and updates market totals the same way
+//! `supply` and `withdraw` do. Deposit, withdraw, reallocate, set_vault_fee,
+//! set_withdraw_queue and remove_vault_market take, for each listed market
+//! in listing order, three remaining accounts:
+//!
+//! 0. market (writable)
+//! 1. the vault's position in that market (writable)
+//! 2. the market's loan vault (writable)
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface, transfer_checked, TransferChecked};
+use crate::constants::{PROGRAM_SEED_PREFIX, BPS, MAX_VAULT_FEE};
+use crate::errors::MorphoError;
+use crate::events;
+use crate::state::{ProtocolState, Market, Position, Vault, VaultPosition, VaultAllocation};
+use crate::math::{
+    checked_add, checked_sub, safe_u128_to_u64,
+    to_shares_down, to_shares_up, to_assets_down,
+    accrue_interest_on_market,
+};
+use crate::interfaces::{compute_borrow_rate, has_transfer_fee, validate_mint_extensions};
+
+// ============================================================================
+// Create Vault
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(vault_id: [u8; 32])]
+pub struct CreateVault<'info> {
+    #[account(mut)]
+    pub curator: Signer<'info>,
+
+    #[account(
+        init,
+        payer = curator,
+        space = Vault::space(),
+        seeds = [PROGRAM_SEED_PREFIX, Vault::SEED, &vault_id],
+        bump,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        init,
+        payer = curator,
+        token::mint = asset_mint,
+        token::authority = vault,
+        token::token_program = token_program,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::IDLE_VAULT_SEED, &vault_id],
+        bump,
+    )]
+    pub idle_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub asset_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Create a vault; `idle_buffer_bps` of total assets stays idle on deposit
+pub fn create_vault(
+    ctx: Context<CreateVault>,
+    vault_id: [u8; 32],
+    idle_buffer_bps: u64,
+) -> Result<()> {
+    require!(idle_buffer_bps <= BPS, MorphoError::InvalidInput);
+    let asset_mint = ctx.accounts.asset_mint.to_account_info();
+    validate_mint_extensions(&asset_mint)?;
+    // Every hop between idle and market vaults would pay the fee
+    require!(!has_transfer_fee(&asset_mint)?, MorphoError::UnsupportedMintExtension);
+
+    let vault = &mut ctx.accounts.vault;
+    vault.bump = ctx.bumps.vault;
+    vault.vault_id = vault_id;
+    vault.curator = ctx.accounts.curator.key();
+    vault.asset_mint = ctx.accounts.asset_mint.key();
+    vault.idle_vault_bump = ctx.bumps.idle_vault;
+    vault.idle_buffer_bps = idle_buffer_bps;
+    vault.idle_assets = 0;
+    vault.total_shares = 0;
+    vault.market_count = 0;
+    vault.fee_bps = 0;
+    vault.fee_recipient = Pubkey::default();
+    vault.last_total_assets = 0;
+    vault.pending_fee_shares = 0;
+    vault.pending_market_removal = Pubkey::default();
+    vault.removal_after_slot = 0;
+
+    emit!(events::VaultCreated {
+        vault_id,
+        curator: vault.curator,
+        asset_mint: vault.asset_mint,
+        idle_buffer_bps,
+    });
+
+    Ok(())
+}
+
+// ============================================================================
+// Set Vault Market
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(vault_id: [u8; 32])]
+pub struct SetVaultMarket<'info> {
+    pub curator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::SEED, &vault_id],
+        bump = vault.bump,
+        constraint = vault.curator == curator.key() @ MorphoError::Unauthorized,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market.load()?.market_id],
+        bump = market.load()?.bump,
+        constraint = market.load()?.loan_mint == vault.asset_mint @ MorphoError::InvalidMint,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    /// Vault's position in the market, created beforehand via `create_position`
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Position::SEED, &market.load()?.market_id, vault.key().as_ref()],
+        bump = position.bump,
+    )]
+    pub position: Box<Account<'info, Position>>,
+}
+
+/// List a market at the end of both queues, or update its supply cap if listed
+///
+/// Lowering a cap below the current allocation does not force a withdrawal;
+/// it only stops new supply until `reallocate` moves funds out.
+pub fn set_vault_market(
+    ctx: Context<SetVaultMarket>,
+    vault_id: [u8; 32],
+    supply_cap: u128,
+) -> Result<()> {
+    let market = ctx.accounts.market.key();
+    let vault = &mut ctx.accounts.vault;
+
+    match vault.market_index(&market) {
+        Some(index) => vault.supply_caps[index] = supply_cap,
+        None => vault.list_market(market, supply_cap)?,
+    }
+
+    emit!(events::VaultMarketSet {
+        vault_id,
+        market,
+        supply_cap,
+    });
+
+    Ok(())
+}
+
+// ============================================================================
+// Set Vault Queues
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(vault_id: [u8; 32])]
+pub struct SetVaultQueues<'info> {
+    pub curator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::SEED, &vault_id],
+        bump = vault.bump,
+        constraint = vault.curator == curator.key() @ MorphoError::Unauthorized,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+}
+
+/// Replace the supply and withdraw queues (each a permutation of listed markets)
+pub fn set_vault_queues(
+    ctx: Context<SetVaultQueues>,
+    vault_id: [u8; 32],
+    supply_queue: Vec<Pubkey>,
+    withdraw_queue: Vec<Pubkey>,
+) -> Result<()> {
+    ctx.accounts.vault.set_queues(&supply_queue, &withdraw_queue)?;
+
+    emit!(events::VaultQueuesSet { vault_id });
+    Ok(())
+}
+
+/// Replace the supply queue with an ordered subset of the withdraw queue
+pub fn set_supply_queue(
+    ctx: Context<SetVaultQueues>,
+    vault_id: [u8; 32],
+    queue: Vec<Pubkey>,
+) -> Result<()> {
+    ctx.accounts.vault.set_supply_queue(&queue)?;
+
+    emit!(events::QueueUpdated {
+        vault_id,
+        withdraw_queue: false,
+        queue,
+    });
+    Ok(())
+}
+
+/// Replace the withdraw queue with an ordered subset of the listed markets
+///
+/// Takes the listed markets' remaining accounts, so a market the vault
+/// still supplies to cannot be dropped.
+pub fn set_withdraw_queue<'info>(
+    ctx: Context<'_, '_, 'info, 'info, SetVaultQueues<'info>>,
+    vault_id: [u8; 32],
+    queue: Vec<Pubkey>,
+) -> Result<()> {
+    let markets = load_vault_markets(&ctx.accounts.vault, ctx.remaining_accounts)?;
+    let supply_shares = markets
+        .iter()
+        .map(|accounts| accounts.position.supply_shares)
+        .collect::<Vec<_>>();
+    ctx.accounts.vault.set_withdraw_queue(&queue, &supply_shares)?;
+
+    emit!(events::QueueUpdated {
+        vault_id,
+        withdraw_queue: true,
+        queue,
+    });
+    Ok(())
+}
+
+// ============================================================================
+// Vault Market Removal
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(vault_id: [u8; 32])]
+pub struct VaultMarketRemoval<'info> {
+    pub curator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::SEED, &vault_id],
+        bump = vault.bump,
+        constraint = vault.curator == curator.key() @ MorphoError::Unauthorized,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+}
+
+/// Start removing a listed market
+///
+/// Its supply cap drops to 0 at once. After VAULT_MARKET_REMOVAL_DELAY_SLOTS
+/// the curator can call `remove_vault_market`, having reallocated out.
+/// One removal can be pending at a time.
+pub fn submit_vault_market_removal(
+    ctx: Context<VaultMarketRemoval>,
+    vault_id: [u8; 32],
+    market: Pubkey,
+) -> Result<()> {
+    let vault = &mut ctx.accounts.vault;
+    vault.submit_removal(market, Clock::get()?.slot)?;
+
+    emit!(events::VaultMarketRemovalSubmitted {
+        vault_id,
+        market,
+        removal_after_slot: vault.removal_after_slot,
+    });
+    Ok(())
+}
+
+/// Cancel a pending market removal (the market's cap stays at 0)
+pub fn cancel_vault_market_removal(
+    ctx: Context<VaultMarketRemoval>,
+    vault_id: [u8; 32],
+    market: Pubkey,
+) -> Result<()> {
+    ctx.accounts.vault.cancel_removal(market)?;
+
+    emit!(events::VaultMarketRemovalCancelled { vault_id, market });
+    Ok(())
+}
+
+/// Unlist the pending market once its delay has elapsed
+///
+/// Fails with `MarketNotEmpty` while the vault still has assets supplied
+/// there.
+pub fn remove_vault_market<'info>(
+    ctx: Context<'_, '_, 'info, 'info, VaultMarketRemoval<'info>>,
+    vault_id: [u8; 32],
+    market: Pubkey,
+) -> Result<()> {
+    let markets = load_vault_markets(&ctx.accounts.vault, ctx.remaining_accounts)?;
+    let index = ctx.accounts.vault
+        .market_index(&market)
+        .ok_or(MorphoError::VaultMarketNotListed)?;
+    let supplied = supplied_assets(&markets[index])?;
+    ctx.accounts.vault.remove_market(market, Clock::get()?.slot, supplied)?;
+
+    emit!(events::VaultMarketRemoved { vault_id, market });
+    Ok(())
+}
+
+// ============================================================================
+// Vault Deposit
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(vault_id: [u8; 32])]
+pub struct VaultDeposit<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::SEED, &vault_id],
+        bump = vault.bump,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        init_if_needed,
+        payer = depositor,
+        space = VaultPosition::space(),
+        seeds = [PROGRAM_SEED_PREFIX, VaultPosition::SEED, &vault_id, on_behalf_of.key().as_ref()],
+        bump,
+    )]
+    pub vault_position: Box<Account<'info, VaultPosition>>,
+
+    /// CHECK: Vault position owner - shares credited to this account
+    pub on_behalf_of: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = depositor_token_account.mint == vault.asset_mint,
+    )]
+    pub depositor_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::IDLE_VAULT_SEED, &vault_id],
+        bump = vault.idle_vault_bump,
+    )]
+    pub idle_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(constraint = asset_mint.key() == vault.asset_mint @ MorphoError::InvalidMint)]
+    pub asset_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Deposit assets for vault shares
+///
+/// Assets above the idle buffer are supplied to markets in supply-queue
+/// order, each clamped to its supply cap with the remainder moving on to the
+/// next entry. Fails with `AllCapsReached` if the part of the deposit no
+/// market can take does not fit in the idle buffer.
+pub fn vault_deposit<'info>(
+    ctx: Context<'_, '_, 'info, 'info, VaultDeposit<'info>>,
+    vault_id: [u8; 32],
+    assets: u128,
+    min_shares: u128,
+) -> Result<()> {
+    // ===== CHECKS =====
+    require!(assets > 0, MorphoError::ZeroAmount);
+
+    let mut markets = load_vault_markets(&ctx.accounts.vault, ctx.remaining_accounts)?;
+    let total_assets = vault_total_assets(&ctx.accounts.vault, &markets)?;
+    accrue_vault_fee(&mut ctx.accounts.vault, total_assets)?;
+
+    // Round DOWN - depositor gets fewer shares
+    let shares = to_shares_down(assets, total_assets, ctx.accounts.vault.total_shares)?;
+    require!(shares > 0, MorphoError::ZeroAmount);
+    require!(shares >= min_shares, MorphoError::SlippageExceeded);
+
+    // ===== EFFECTS =====
+    let vault_position = &mut ctx.accounts.vault_position;
+    if vault_position.owner == Pubkey::default() {
+        vault_position.bump = ctx.bumps.vault_position;
+        vault_position.vault_id = vault_id;
+        vault_position.owner = ctx.accounts.on_behalf_of.key();
+    }
+    vault_position.shares = checked_add(vault_position.shares, shares)?;
+
+    let vault = &mut ctx.accounts.vault;
+    vault.total_shares = checked_add(vault.total_shares, shares)?;
+    vault.idle_assets = checked_add(vault.idle_assets, assets)?;
+
+    // ===== INTERACTIONS =====
+    transfer_checked(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.depositor_token_account.to_account_info(),
+                to: ctx.accounts.idle_vault.to_account_info(),
+                authority: ctx.accounts.depositor.to_account_info(),
+                mint: ctx.accounts.asset_mint.to_account_info(),
+            },
+        ),
+        safe_u128_to_u64(assets)?,
+        ctx.accounts.asset_mint.decimals,
+    )?;
+
+    // Allocate everything above the idle buffer along the supply queue
+    let tokens = VaultTokenAccounts {
+        vault: ctx.accounts.vault.to_account_info(),
+        idle_vault: ctx.accounts.idle_vault.to_account_info(),
+        asset_mint: ctx.accounts.asset_mint.to_account_info(),
+        decimals: ctx.accounts.asset_mint.decimals,
+        token_program: ctx.accounts.token_program.to_account_info(),
+    };
+    let vault = &mut ctx.accounts.vault;
+    let idle_before = checked_sub(vault.idle_assets, assets)?;
+    let new_total_assets = checked_add(total_assets, assets)?;
+    let idle_target = vault.idle_target(new_total_assets)?;
+    vault.last_total_assets = new_total_assets;
+    let to_allocate = checked_sub(vault.idle_assets, idle_target).unwrap_or(0);
+    if to_allocate > 0 {
+        let supplied = markets.iter().map(supplied_assets).collect::<Result<Vec<_>>>()?;
+        let market_room = markets
+            .iter()
+            .map(|accounts| Ok(accounts.market.load()?.supply_cap_room()))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (plan, _) = vault.plan_supply(to_allocate, &supplied, &market_room);
+        for (index, amount) in plan {
+            supply_to_market(vault, &tokens, &mut markets[index], amount)?;
+        }
+    }
+    require!(
+        vault.idle_assets <= std::cmp::max(idle_target, idle_before),
+        MorphoError::AllCapsReached
+    );
+
+    persist_vault_markets(&markets)?;
+
+    emit!(events::VaultDeposit {
+        vault_id,
+        depositor: ctx.accounts.depositor.key(),
+        on_behalf_of: ctx.accounts.on_behalf_of.key(),
+        assets,
+        shares,
+        total_assets: new_total_assets,
+        total_shares: vault.total_shares,
+    });
+
+    Ok(())
+}
+
+// ============================================================================
+// Vault Withdraw
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(vault_id: [u8; 32])]
+pub struct VaultWithdraw<'info> {
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+    )]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::SEED, &vault_id],
+        bump = vault.bump,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, VaultPosition::SEED, &vault_id, owner.key().as_ref()],
+        bump = vault_position.bump,
+    )]
+    pub vault_position: Box<Account<'info, VaultPosition>>,
+
+    #[account(
+        mut,
+        constraint = receiver_token_account.mint == vault.asset_mint,
+    )]
+    pub receiver_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::IDLE_VAULT_SEED, &vault_id],
+        bump = vault.idle_vault_bump,
+    )]
+    pub idle_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(constraint = asset_mint.key() == vault.asset_mint @ MorphoError::InvalidMint)]
+    pub asset_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Redeem vault shares for assets
+///
+/// Idle assets are used first, then liquidity is pulled from markets in
+/// withdraw-queue order, each limited to its available liquidity, so a
+/// fully-utilized market cascades to the next. Paused markets are skipped.
+/// Fails with `InsufficientLiquidity` only once the whole queue is exhausted.
+pub fn vault_withdraw<'info>(
+    ctx: Context<'_, '_, 'info, 'info, VaultWithdraw<'info>>,
+    vault_id: [u8; 32],
+    shares: u128,
+) -> Result<()> {
+    // ===== CHECKS =====
+    require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
+    require!(shares > 0, MorphoError::ZeroAmount);
+    require!(
+        ctx.accounts.vault_position.shares >= shares,
+        MorphoError::InsufficientBalance
+    );
+
+    let mut markets = load_vault_markets(&ctx.accounts.vault, ctx.remaining_accounts)?;
+    let total_assets = vault_total_assets(&ctx.accounts.vault, &markets)?;
+    accrue_vault_fee(&mut ctx.accounts.vault, total_assets)?;
+
+    // Round DOWN - owner receives fewer assets
+    let assets = to_assets_down(shares, total_assets, ctx.accounts.vault.total_shares)?;
+    require!(assets > 0, MorphoError::ZeroAmount);
+
+    // ===== EFFECTS =====
+    ctx.accounts.vault_position.shares = checked_sub(ctx.accounts.vault_position.shares, shares)?;
+    let vault = &mut ctx.accounts.vault;
+    vault.total_shares = checked_sub(vault.total_shares, shares)?;
+    vault.last_total_assets = checked_sub(total_assets, assets)?;
+
+    // ===== INTERACTIONS =====
+    let tokens = VaultTokenAccounts {
+        vault: vault.to_account_info(),
+        idle_vault: ctx.accounts.idle_vault.to_account_info(),
+        asset_mint: ctx.accounts.asset_mint.to_account_info(),
+        decimals: ctx.accounts.asset_mint.decimals,
+        token_program: ctx.accounts.token_program.to_account_info(),
+    };
+
+    // Top up idle along the withdraw queue, skipping borrowed-out markets
+    let needed = checked_sub(assets, vault.idle_assets).unwrap_or(0);
+    if needed > 0 {
+        let withdrawable = markets
+            .iter()
+            .map(|accounts| {
+                let market = accounts.market.load()?;
+                if market.is_paused() {
+                    return Ok(0);
+                }
+                Ok(std::cmp::min(supplied_assets(accounts)?, market.available_liquidity()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let (plan, shortfall) = vault.plan_withdraw(needed, &withdrawable);
+        require!(shortfall == 0, MorphoError::InsufficientLiquidity);
+        for (index, amount) in plan {
+            withdraw_from_market(vault, &tokens, &mut markets[index], amount)?;
+        }
+    }
+
+    vault.idle_assets = checked_sub(vault.idle_assets, assets)?;
+    persist_vault_markets(&markets)?;
+
+    let vault_bump = [vault.bump];
+    let seeds = vault_signer_seeds(&vault_id, &vault_bump);
+    transfer_checked(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.idle_vault.to_account_info(),
+                to: ctx.accounts.receiver_token_account.to_account_info(),
+                authority: vault.to_account_info(),
+                mint: ctx.accounts.asset_mint.to_account_info(),
+            },
+            &[&seeds],
+        ),
+        safe_u128_to_u64(assets)?,
+        ctx.accounts.asset_mint.decimals,
+    )?;
+
+    emit!(events::VaultWithdraw {
+        vault_id,
+        owner: ctx.accounts.owner.key(),
+        receiver: ctx.accounts.receiver_token_account.key(),
+        assets,
+        shares,
+        total_assets: vault.last_total_assets,
+        total_shares: vault.total_shares,
+    });
+
+    Ok(())
+}
+
+// ============================================================================
+// Set Vault Fee
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(vault_id: [u8; 32])]
+pub struct SetVaultFee<'info> {
+    pub curator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::SEED, &vault_id],
+        bump = vault.bump,
+        constraint = vault.curator == curator.key() @ MorphoError::Unauthorized,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+}
+
+/// Set the performance fee and its recipient
+///
+/// Yield up to now is charged at the old fee first. Pending fee shares must
+/// be claimed before the recipient can change.
+pub fn set_vault_fee<'info>(
+    ctx: Context<'_, '_, 'info, 'info, SetVaultFee<'info>>,
+    vault_id: [u8; 32],
+    fee_bps: u64,
+    fee_recipient: Pubkey,
+) -> Result<()> {
+    require!(fee_bps <= MAX_VAULT_FEE, MorphoError::FeeTooHigh);
+    require!(
+        fee_bps == 0 || fee_recipient != Pubkey::default(),
+        MorphoError::InvalidInput
+    );
+
+    let markets = load_vault_markets(&ctx.accounts.vault, ctx.remaining_accounts)?;
+    let total_assets = vault_total_assets(&ctx.accounts.vault, &markets)?;
+    accrue_vault_fee(&mut ctx.accounts.vault, total_assets)?;
+    persist_vault_markets(&markets)?;
+
+    let vault = &mut ctx.accounts.vault;
+    require!(
+        vault.pending_fee_shares == 0 || fee_recipient == vault.fee_recipient,
+        MorphoError::InvalidInput
+    );
+    vault.fee_bps = fee_bps;
+    vault.fee_recipient = fee_recipient;
+
+    emit!(events::VaultFeeSet {
+        vault_id,
+        fee_bps,
+        fee_recipient,
+    });
+
+    Ok(())
+}
+
+// ============================================================================
+// Claim Vault Fees
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(vault_id: [u8; 32])]
+pub struct ClaimVaultFees<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::SEED, &vault_id],
+        bump = vault.bump,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = VaultPosition::space(),
+        seeds = [PROGRAM_SEED_PREFIX, VaultPosition::SEED, &vault_id, vault.fee_recipient.as_ref()],
+        bump,
+    )]
+    pub fee_position: Box<Account<'info, VaultPosition>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Credit pending fee shares to the fee recipient's vault position
+pub fn claim_vault_fees(ctx: Context<ClaimVaultFees>, vault_id: [u8; 32]) -> Result<()> {
+    let pending = ctx.accounts.vault.pending_fee_shares;
+    if pending == 0 {
+        return Ok(());
+    }
+
+    let fee_position = &mut ctx.accounts.fee_position;
+    if fee_position.owner == Pubkey::default() {
+        fee_position.bump = ctx.bumps.fee_position;
+        fee_position.vault_id = vault_id;
+        fee_position.owner = ctx.accounts.vault.fee_recipient;
+    }
+    fee_position.shares = checked_add(fee_position.shares, pending)?;
+    ctx.accounts.vault.pending_fee_shares = 0;
+
+    emit!(events::VaultFeesClaimed {
+        vault_id,
+        recipient: fee_position.owner,
+        shares: pending,
+    });
+
+    Ok(())
+}
+
+// ============================================================================
+// Reallocate
+// ============================================================================
+
+#[derive(Accounts)]
+#[instruction(vault_id: [u8; 32])]
+pub struct Reallocate<'info> {
+    pub curator: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+    )]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::SEED, &vault_id],
+        bump = vault.bump,
+        constraint = vault.curator == curator.key() @ MorphoError::Unauthorized,
+    )]
+    pub vault: Box<Account<'info, Vault>>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_SEED_PREFIX, Vault::IDLE_VAULT_SEED, &vault_id],
+        bump = vault.idle_vault_bump,
+    )]
+    pub idle_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(constraint = asset_mint.key() == vault.asset_mint @ MorphoError::InvalidMint)]
+    pub asset_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Move supply between listed markets
+///
+/// Each allocation sets the assets the vault should have supplied to that
+/// market afterwards, clamped to its supply cap (the excess stays idle).
+/// Decreases are applied first so their proceeds can fund the increases,
+/// which must be covered by idle assets. Markets without an allocation are
+/// left untouched; an allocation that would move supply in or out of a
+/// paused market is rejected.
+pub fn reallocate<'info>(
+    ctx: Context<'_, '_, 'info, 'info, Reallocate<'info>>,
+    vault_id: [u8; 32],
+    allocations: Vec<VaultAllocation>,
+) -> Result<()> {
+    require!(!ctx.accounts.protocol_state.paused, MorphoError::ProtocolPaused);
+
+    let mut markets = load_vault_markets(&ctx.accounts.vault, ctx.remaining_accounts)?;
+
+    let mut targets = Vec::with_capacity(allocations.len());
+    for allocation in &allocations {
+        let index = ctx.accounts.vault
+            .market_index(&allocation.market)
+            .ok_or(MorphoError::VaultMarketNotListed)?;
+        let target = std::cmp::min(allocation.assets, ctx.accounts.vault.supply_caps[index]);
+        if target != supplied_assets(&markets[index])? {
+            require!(!markets[index].market.load()?.is_paused(), MorphoError::MarketPaused);
+        }
+        targets.push((index, target));
+    }
+
+    let tokens = VaultTokenAccounts {
+        vault: ctx.accounts.vault.to_account_info(),
+        idle_vault: ctx.accounts.idle_vault.to_account_info(),
+        asset_mint: ctx.accounts.asset_mint.to_account_info(),
+        decimals: ctx.accounts.asset_mint.decimals,
+        token_program: ctx.accounts.token_program.to_account_info(),
+    };
+    let vault = &mut ctx.accounts.vault;
+
+    // Withdrawals first
+    for &(index, target) in &targets {
+        let supplied = supplied_assets(&markets[index])?;
+        if target < supplied {
+            withdraw_from_market(vault, &tokens, &mut markets[index], supplied - target)?;
+        }
+    }
+
+    // Then supplies, funded from idle
+    for &(index, target) in &targets {
+        let supplied = supplied_assets(&markets[index])?;
+        if target > supplied {
+            let amount = target - supplied;
+            require!(amount <= vault.idle_assets, MorphoError::VaultInsufficientIdle);
+            supply_to_market(vault, &tokens, &mut markets[index], amount)?;
+        }
+    }
+
+    persist_vault_markets(&markets)?;
+
+    emit!(events::VaultReallocated {
+        vault_id,
+        total_assets: vault_total_assets(vault, &markets)?,
+        idle_assets: vault.idle_assets,
+    });
+
+    Ok(())
+}
+
+// ============================================================================
+// Helpers
+// ============================================================================
+
+/// Market, vault position and loan vault for one listed market
+struct VaultMarketAccounts<'info> {
+    market: AccountLoader<'info, Market>,
+    position: Account<'info, Position>,
+    loan_vault: InterfaceAccount<'info, TokenAccount>,
+}
+
+/// Accounts used to move assets between the idle vault and markets
+struct VaultTokenAccounts<'info> {
+    vault: AccountInfo<'info>,
+    idle_vault: AccountInfo<'info>,
+    asset_mint: AccountInfo<'info>,
+    decimals: u8,
+    token_program: AccountInfo<'info>,
+}
+
+fn vault_signer_seeds<'a>(vault_id: &'a [u8; 32], bump: &'a [u8; 1]) -> [&'a [u8]; 4] {
+    [PROGRAM_SEED_PREFIX, Vault::SEED, vault_id, bump]
+}
+
+/// Deserialize and check the listed markets' accounts, accruing interest on each
+fn load_vault_markets<'info>(
+    vault: &Account<'info, Vault>,
+    remaining_accounts: &'info [AccountInfo<'info>],
+) -> Result<Vec<VaultMarketAccounts<'info>>> {
+    let listed = vault.listed_markets();
+    require!(
+        remaining_accounts.len() == listed.len() * 3,
+        MorphoError::VaultAccountsMismatch
+    );
+
+    let clock = Clock::get()?;
+    let mut markets = Vec::with_capacity(listed.len());
+    for (expected, chunk) in listed.iter().zip(remaining_accounts.chunks(3)) {
+        require!(chunk[0].key() == *expected, MorphoError::VaultAccountsMismatch);
+
+        let market = AccountLoader::<Market>::try_from(&chunk[0])?;
+        let position = Account::<Position>::try_from(&chunk[1])?;
+        let loan_vault = InterfaceAccount::<TokenAccount>::try_from(&chunk[2])?;
+
+        let mut state = market.load_mut()?;
+        ensure_market_unlocked(&state)?;
+        require!(
+            position.owner == vault.key() && position.market_id == state.market_id,
+            MorphoError::VaultAccountsMismatch
+        );
+        let expected_loan_vault = Pubkey::create_program_address(
+            &[
+                PROGRAM_SEED_PREFIX,
+                Market::LOAN_VAULT_SEED,
+                &state.market_id,
+                &[state.loan_vault_bump],
+            ],
+            &crate::ID,
+        )
+        .map_err(|_| MorphoError::VaultAccountsMismatch)?;
+        require!(loan_vault.key() == expected_loan_vault, MorphoError::VaultAccountsMismatch);
+
+        let current_time = state.accrual_time(&clock)?;
+        let elapsed = current_time - state.last_update;
+        let borrow_rate = compute_borrow_rate(&mut state, elapsed)?;
+        accrue_interest_on_market(&mut state, current_time, borrow_rate)?;
+        drop(state);
+
+        markets.push(VaultMarketAccounts { market, position, loan_vault });
+    }
+
+    Ok(markets)
+}
+
+/// Reject a market whose callback or flash-loan lock is held, as the market
+/// handlers do, so a callback cannot route vault funds through it mid-operation
+fn ensure_market_unlocked(market: &Market) -> Result<()> {
+    require!(!market.is_callback_active(), MorphoError::ReentrancyDetected);
+    require!(!market.is_flash_loan_active(), MorphoError::FlashLoanInProgress);
+    Ok(())
+}
+
+/// Mint the performance fee on yield since the last deposit / withdraw
+fn accrue_vault_fee(vault: &mut Vault, total_assets: u128) -> Result<()> {
+    let (interest, fee_shares) = vault.accrue_fee(total_assets)?;
+    if fee_shares > 0 {
+        emit!(events::VaultFeeAccrued {
+            vault_id: vault.vault_id,
+            fee_recipient: vault.fee_recipient,
+            interest,
+            fee_shares,
+        });
+    }
+    Ok(())
+}
+
+/// Write updated position state back (markets are written in place)
+fn persist_vault_markets(markets: &[VaultMarketAccounts]) -> Result<()> {
+    for accounts in markets {
+        accounts.position.exit(&crate::ID)?;
+    }
+    Ok(())
+}
+
+/// Assets the vault has supplied to one market (rounded down)
+fn supplied_assets(accounts: &VaultMarketAccounts) -> Result<u128> {
+    let market = accounts.market.load()?;
+    to_assets_down(
+        accounts.position.supply_shares,
+        market.total_supply_assets,
+        market.total_supply_shares,
+    )
+}
+
+/// Idle assets plus the vault's supply in every listed market
+fn vault_total_assets(vault: &Vault, markets: &[VaultMarketAccounts]) -> Result<u128> {
+    markets.iter().try_fold(vault.idle_assets, |total, accounts| {
+        checked_add(total, supplied_assets(accounts)?)
+    })
+}
+
+/// Supply `assets` from the idle vault, as `supply` would for the vault's position
+fn supply_to_market<'info>(
+    vault: &mut Vault,
+    tokens: &VaultTokenAccounts<'info>,
+    accounts: &mut VaultMarketAccounts<'info>,
+    assets: u128,
+) -> Result<()> {
+    let mut market = accounts.market.load_mut()?;
+    ensure_market_unlocked(&market)?;
+    market.ensure_supply_cap(assets)?;
+    let shares = to_shares_down(assets, market.total_supply_assets, market.total_supply_shares)?;
+    require!(shares > 0, MorphoError::ZeroAmount);
+
+    market.total_supply_assets = checked_add(market.total_supply_assets, assets)?;
+    market.total_supply_shares = checked_add(market.total_supply_shares, shares)?;
+    accounts.position.add_supply_shares(shares, market.supply_index())?;
+    accounts.position.touch()?;
+    vault.idle_assets = checked_sub(vault.idle_assets, assets)?;
+
+    let vault_bump = [vault.bump];
+    let seeds = vault_signer_seeds(&vault.vault_id, &vault_bump);
+    transfer_checked(
+        CpiContext::new_with_signer(
+            tokens.token_program.clone(),
+            TransferChecked {
+                from: tokens.idle_vault.clone(),
+                to: accounts.loan_vault.to_account_info(),
+                authority: tokens.vault.clone(),
+                mint: tokens.asset_mint.clone(),
+            },
+            &[&seeds],
+        ),
+        safe_u128_to_u64(assets)?,
+        tokens.decimals,
+    )?;
+
+    emit!(events::Supply {
+        market_id: market.market_id,
+        supplier: tokens.vault.key(),
+        on_behalf_of: tokens.vault.key(),
+        assets,
+        shares,
+        total_supply_assets: market.total_supply_assets,
+        total_supply_shares: market.total_supply_shares,
+    });
+
+    Ok(())
+}
+
+/// Withdraw `assets` into the idle vault, as `withdraw` would for the vault's position
+fn withdraw_from_market<'info>(
+    vault: &mut Vault,
+    tokens: &VaultTokenAccounts<'info>,
+    accounts: &mut VaultMarketAccounts<'info>,
+    assets: u128,
+) -> Result<()> {
+    let mut market = accounts.market.load_mut()?;
+    ensure_market_unlocked(&market)?;
+    let shares = to_shares_up(assets, market.total_supply_assets, market.total_supply_shares)?;
+    require!(
+        accounts.position.supply_shares >= shares,
+        MorphoError::InsufficientBalance
+    );
+    market.ensure_liquidity(assets)?;
+
+    accounts.position.supply_shares = checked_sub(accounts.position.supply_shares, shares)?;
+    accounts.position.touch()?;
+    market.total_supply_assets = checked_sub(market.total_supply_assets, assets)?;
+    market.total_supply_shares = checked_sub(market.total_supply_shares, shares)?;
+    vault.idle_assets = checked_add(vault.idle_assets, assets)?;
+
+    let market_id = market.market_id;
+    let bump = market.bump;
+    let (total_supply_assets, total_supply_shares) =
+        (market.total_supply_assets, market.total_supply_shares);
+    drop(market);
+    let seeds = &[
+        PROGRAM_SEED_PREFIX,
+        Market::SEED,
+        market_id.as_ref(),
+        &[bump],
+    ];
+    transfer_checked(
+        CpiContext::new_with_signer(
+            tokens.token_program.clone(),
+            TransferChecked {
+                from: accounts.loan_vault.to_account_info(),
+                to: tokens.idle_vault.clone(),
+                authority: accounts.market.to_account_info(),
+                mint: tokens.asset_mint.clone(),
+            },
+            &[seeds],
+        ),
+        safe_u128_to_u64(assets)?,
+        tokens.decimals,
+    )?;
+
+    emit!(events::Withdraw {
+        market_id,
+        caller: tokens.vault.key(),
+        on_behalf_of: tokens.vault.key(),
+        receiver: tokens.idle_vault.key(),
+        assets,
+        shares,
+        total_supply_assets,
+        total_supply_shares,
+    });
+
+    Ok(())
+}
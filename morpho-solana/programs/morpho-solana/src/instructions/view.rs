@@ -0,0 +1,388 @@
+//! Read-only view instructions for keepers and frontends
+//!
+//! Results are returned via `set_return_data` (little-endian, borsh compatible)
+//! and read back from transaction simulation. Interest is accrued on an
+//! in-memory copy of the market, so the numbers match what a liquidation in
+//! the same slot would see. Nothing is written back to any account.
+//!
+//! Rates from `view_rates` are per second and WAD-scaled. Interest compounds
+//! continuously, so clients convert with
+//! `APY = exp(rate * SECONDS_PER_YEAR / WAD) - 1` (APR is just
+//! `rate * SECONDS_PER_YEAR / WAD`).
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program::set_return_data;
+use crate::constants::{PROGRAM_SEED_PREFIX, MAX_SCAN_POSITIONS, MAX_MARKETS_PER_PAGE, ORACLE_SCALE};
+use crate::errors::MorphoError;
+use crate::state::{ProtocolState, Market, MarketIndex, MarketSummary, Position};
+use crate::math::{to_assets_down, to_assets_up, checked_add, mul_div_up, accrue_interest_on_market, interest_since_entry};
+use crate::interfaces::{
+    compute_borrow_rate, supply_rate, get_oracle_price_validated,
+    health_factor, is_liquidatable, max_additional_borrow, max_withdrawable_collateral,
+};
+
+/// Return data of `view_position_limits`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PositionLimits {
+    /// Loan tokens borrowable now (also capped by market liquidity and the
+    /// utilization cap, 0 if the position would stay below the dust floor)
+    pub max_borrow_assets: u128,
+    /// Collateral withdrawable now without becoming liquidatable or dropping
+    /// below the dust floor (all of it once the debt is repaid)
+    pub max_withdrawable_collateral: u128,
+}
+
+/// Return data of `view_rates` (all WAD-scaled)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarketRates {
+    /// Instantaneous borrow rate per second
+    pub borrow_rate: u128,
+    /// Supply rate per second, net of the protocol fee
+    pub supply_rate: u128,
+    /// Borrow / supply
+    pub utilization: u128,
+}
+
+/// Return data of `view_position_interest` (loan tokens)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PositionInterest {
+    /// Interest earned by the supply shares since their entry
+    pub supply_interest: u128,
+    /// Interest owed by the borrow shares since their entry
+    pub borrow_interest: u128,
+}
+
+/// Return data of `view_markets`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MarketPage {
+    /// Markets created so far (`ProtocolState::market_count`)
+    pub total: u64,
+    /// Entries for the requested indices, in order
+    pub markets: Vec<MarketSummary>,
+}
+
+#[derive(Accounts)]
+pub struct ViewMarkets<'info> {
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, ProtocolState::SEED],
+        bump = protocol_state.bump,
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+    // remaining_accounts: MarketIndex entries `start`, `start + 1`, ...
+    // (up to MAX_MARKETS_PER_PAGE)
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct ViewMarket<'info> {
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct ViewPosition<'info> {
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Position::SEED, &market_id, position.owner.as_ref()],
+        bump = position.bump,
+    )]
+    pub position: Account<'info, Position>,
+
+    /// CHECK: Oracle for price
+    pub oracle: UncheckedAccount<'info>,
+
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct ViewPositionInterest<'info> {
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Position::SEED, &market_id, position.owner.as_ref()],
+        bump = position.bump,
+    )]
+    pub position: Account<'info, Position>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: [u8; 32])]
+pub struct ScanPositions<'info> {
+    #[account(
+        seeds = [PROGRAM_SEED_PREFIX, Market::SEED, &market_id],
+        bump = market.load()?.bump,
+    )]
+    pub market: AccountLoader<'info, Market>,
+
+    /// CHECK: Oracle for price
+    pub oracle: UncheckedAccount<'info>,
+
+    /// CHECK: Loan/USD feed, required only for dual-feed markets
+    pub oracle_secondary: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Fallback oracle, used only if the primary is stale or out of bounds
+    pub oracle_fallback: Option<UncheckedAccount<'info>>,
+    // remaining_accounts: up to MAX_SCAN_POSITIONS positions in this market
+}
+
+/// Copy of the market with interest accrued to now
+fn projected_market(market: &Market) -> Result<Market> {
+    let mut market = *market;
+
+    let clock = Clock::get()?;
+    let current_time = market.accrual_time(&clock)?;
+    let elapsed = current_time - market.last_update;
+    let borrow_rate = compute_borrow_rate(&mut market, elapsed)?;
+    accrue_interest_on_market(&mut market, current_time, borrow_rate)?;
+    Ok(market)
+}
+
+/// Accrue interest on a copy of the market and read the oracle against it
+fn projected_market_and_price(
+    market: &Market,
+    oracle: &UncheckedAccount,
+    oracle_secondary: Option<&UncheckedAccount>,
+    oracle_fallback: Option<&UncheckedAccount>,
+) -> Result<(Market, u128)> {
+    let mut market = projected_market(market)?;
+
+    let oracle_price = get_oracle_price_validated(
+        &oracle.to_account_info(),
+        oracle_secondary.map(|a| a.as_ref()),
+        oracle_fallback.map(|a| a.as_ref()),
+        &mut market,
+    )?;
+
+    Ok((market, oracle_price))
+}
+
+/// Return the market's current borrow and supply rates (`MarketRates`)
+///
+/// Accrues first, then evaluates the IRM with no elapsed time, so the rate is
+/// the one the next second of interest accrues at.
+pub fn view_rates(ctx: Context<ViewMarket>, _market_id: [u8; 32]) -> Result<()> {
+    let mut market = projected_market(&*ctx.accounts.market.load()?)?;
+    let borrow_rate = compute_borrow_rate(&mut market, 0)?;
+    let utilization = market.utilization();
+    let rates = MarketRates {
+        borrow_rate,
+        supply_rate: supply_rate(borrow_rate, utilization, market.fee)?,
+        utilization,
+    };
+    set_return_data(&rates.try_to_vec()?);
+    Ok(())
+}
+
+/// Return the position's health factor (u128, WAD-scaled; u128::MAX if no debt)
+pub fn view_health_factor(ctx: Context<ViewPosition>, _market_id: [u8; 32]) -> Result<()> {
+    let (market, oracle_price) = projected_market_and_price(
+        &*ctx.accounts.market.load()?,
+        &ctx.accounts.oracle,
+        ctx.accounts.oracle_secondary.as_ref(),
+        ctx.accounts.oracle_fallback.as_ref(),
+    )?;
+    let position = &ctx.accounts.position;
+
+    let borrowed = to_assets_up(
+        position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+    )?;
+    let health = health_factor(position.collateral, borrowed, oracle_price, market.lltv)?;
+
+    set_return_data(&health.to_le_bytes());
+    Ok(())
+}
+
+/// Return whether the position can be liquidated right now (bool, 1 byte)
+///
+/// A position still inside the market's liquidation grace period reads
+/// false, as `liquidate` would reject it.
+pub fn view_is_liquidatable(ctx: Context<ViewPosition>, _market_id: [u8; 32]) -> Result<()> {
+    let (market, oracle_price) = projected_market_and_price(
+        &*ctx.accounts.market.load()?,
+        &ctx.accounts.oracle,
+        ctx.accounts.oracle_secondary.as_ref(),
+        ctx.accounts.oracle_fallback.as_ref(),
+    )?;
+    let position = &ctx.accounts.position;
+
+    let liquidatable = is_liquidatable(
+        position.collateral,
+        position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+        oracle_price,
+        market.lltv,
+    )? && market.liquidation_grace_elapsed(position.became_unhealthy_slot, Clock::get()?.slot);
+
+    set_return_data(&[liquidatable as u8]);
+    Ok(())
+}
+
+/// Return the position's borrow and withdraw limits (borsh `PositionLimits`)
+pub fn view_position_limits(ctx: Context<ViewPosition>, _market_id: [u8; 32]) -> Result<()> {
+    let (market, oracle_price) = projected_market_and_price(
+        &*ctx.accounts.market.load()?,
+        &ctx.accounts.oracle,
+        ctx.accounts.oracle_secondary.as_ref(),
+        ctx.accounts.oracle_fallback.as_ref(),
+    )?;
+    let position = &ctx.accounts.position;
+
+    let borrowed = to_assets_up(
+        position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+    )?;
+    let mut max_borrow_assets = max_additional_borrow(position.collateral, borrowed, oracle_price, market.lltv)?
+        .min(market.available_liquidity())
+        .min(market.utilization_cap_room()?);
+    // Smaller borrows only end further below the floor
+    if max_borrow_assets > 0
+        && !market.above_dust_floor(position.collateral, checked_add(borrowed, max_borrow_assets)?, oracle_price)?
+    {
+        max_borrow_assets = 0;
+    }
+
+    let mut max_withdrawable = max_withdrawable_collateral(
+        position.collateral,
+        borrowed,
+        oracle_price,
+        market.lltv,
+    )?;
+    if borrowed > 0 {
+        // Keep enough collateral to stay worth `min_collateral`
+        let min_kept = mul_div_up(market.min_collateral as u128, ORACLE_SCALE, oracle_price)?;
+        max_withdrawable = max_withdrawable.min(position.collateral.saturating_sub(min_kept));
+        if !market.above_dust_floor(position.collateral - max_withdrawable, borrowed, oracle_price)? {
+            max_withdrawable = 0;
+        }
+    }
+
+    let limits = PositionLimits {
+        max_borrow_assets,
+        max_withdrawable_collateral: max_withdrawable,
+    };
+
+    set_return_data(&limits.try_to_vec()?);
+    Ok(())
+}
+
+/// Return the interest the position earned and owes since its shares were
+/// acquired (borsh `PositionInterest`)
+///
+/// Read from the index delta against the position's share-weighted entry
+/// index, on the market accrued to now. Shares removed since entry take
+/// their interest with them; positions that have not added shares since the
+/// indices existed report 0.
+pub fn view_position_interest(ctx: Context<ViewPositionInterest>, _market_id: [u8; 32]) -> Result<()> {
+    let market = projected_market(&*ctx.accounts.market.load()?)?;
+    let position = &ctx.accounts.position;
+
+    let supplied = to_assets_down(
+        position.supply_shares,
+        market.total_supply_assets,
+        market.total_supply_shares,
+    )?;
+    let borrowed = to_assets_up(
+        position.borrow_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+    )?;
+    let interest = PositionInterest {
+        supply_interest: interest_since_entry(supplied, position.supply_entry_index, market.supply_index())?,
+        borrow_interest: interest_since_entry(borrowed, position.borrow_entry_index, market.borrow_index())?,
+    };
+
+    set_return_data(&interest.try_to_vec()?);
+    Ok(())
+}
+
+/// Return a bitmap of which remaining-account positions are liquidatable
+///
+/// Bit `i % 8` of byte `i / 8` is set when position `i` can be liquidated,
+/// so `ceil(n / 8)` bytes come back for `n` positions. Interest is accrued
+/// and the oracle read once for the whole batch. Positions still inside the
+/// liquidation grace period are left unset.
+pub fn scan_liquidatable<'info>(
+    ctx: Context<'_, '_, 'info, 'info, ScanPositions<'info>>,
+    market_id: [u8; 32],
+) -> Result<()> {
+    let positions = ctx.remaining_accounts;
+    require!(!positions.is_empty(), MorphoError::InvalidInput);
+    require!(positions.len() <= MAX_SCAN_POSITIONS, MorphoError::InvalidInput);
+
+    let (market, oracle_price) = projected_market_and_price(
+        &*ctx.accounts.market.load()?,
+        &ctx.accounts.oracle,
+        ctx.accounts.oracle_secondary.as_ref(),
+        ctx.accounts.oracle_fallback.as_ref(),
+    )?;
+
+    let slot = Clock::get()?.slot;
+    let mut bitmap = vec![0u8; positions.len().div_ceil(8)];
+    for (i, info) in positions.iter().enumerate() {
+        let position = Account::<Position>::try_from(info)?;
+        require!(position.market_id == market_id, MorphoError::InvalidMarketId);
+
+        let liquidatable = is_liquidatable(
+            position.collateral,
+            position.borrow_shares,
+            market.total_borrow_assets,
+            market.total_borrow_shares,
+            oracle_price,
+            market.lltv,
+        )? && market.liquidation_grace_elapsed(position.became_unhealthy_slot, slot);
+        if liquidatable {
+            bitmap[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    set_return_data(&bitmap);
+    Ok(())
+}
+
+/// Return one page of the market registry (borsh `MarketPage`)
+///
+/// Entries must be passed in index order starting at `start`; clients
+/// derive them with `derive_market_index` up to `total`.
+pub fn view_markets<'info>(
+    ctx: Context<'_, '_, 'info, 'info, ViewMarkets<'info>>,
+    start: u64,
+) -> Result<()> {
+    let entries = ctx.remaining_accounts;
+    require!(entries.len() <= MAX_MARKETS_PER_PAGE, MorphoError::InvalidInput);
+
+    let mut markets = Vec::with_capacity(entries.len());
+    for (i, info) in entries.iter().enumerate() {
+        let entry = Account::<MarketIndex>::try_from(info)?;
+        require!(entry.index == start + i as u64, MorphoError::InvalidInput);
+        markets.push(MarketSummary::from(&*entry));
+    }
+
+    let page = MarketPage {
+        total: ctx.accounts.protocol_state.market_count,
+        markets,
+    };
+    set_return_data(&page.try_to_vec()?);
+    Ok(())
+}
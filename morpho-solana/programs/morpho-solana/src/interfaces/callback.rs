@@ -0,0 +1,93 @@
+//! Callback interface for integrator programs
+//!
+//! Callbacks are plain Anchor instructions on the integrator's program:
+//! 8-byte `global:<name>` discriminator followed by borsh-encoded args.
+//! Signer and writable flags of every passed account are forwarded as-is.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    hash::hash,
+    instruction::{AccountMeta, Instruction},
+    program::invoke,
+};
+use crate::errors::MorphoError;
+
+/// Invoked by `flash_loan` after the loan is sent
+pub const ON_FLASH_LOAN: &str = "on_flash_loan";
+
+/// Invoked by `liquidate` after seized collateral is sent
+pub const ON_MORPHO_LIQUIDATE: &str = "on_morpho_liquidate";
+
+/// Invoked by `supply` after shares are minted
+pub const ON_MORPHO_SUPPLY: &str = "on_morpho_supply";
+
+/// Invoked by `repay` after debt is reduced
+pub const ON_MORPHO_REPAY: &str = "on_morpho_repay";
+
+/// Reject callback targets that could repay from protocol funds
+///
+/// Calling back into this program would let e.g. a supply inside the
+/// callback count as repayment.
+pub fn validate_callback_program(program: &AccountInfo) -> Result<()> {
+    require!(program.executable, MorphoError::InvalidCallbackProgram);
+    require!(program.key() != crate::ID, MorphoError::InvalidCallbackProgram);
+    Ok(())
+}
+
+/// Build and invoke `name(args)` on `program` with `accounts` in order
+pub fn invoke_callback<'info, A: AnchorSerialize>(
+    program: &AccountInfo<'info>,
+    name: &str,
+    args: A,
+    accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let mut data = hash(format!("global:{}", name).as_bytes()).to_bytes()[..8].to_vec();
+    args.serialize(&mut data)?;
+
+    let metas = accounts
+        .iter()
+        .map(|a| AccountMeta {
+            pubkey: a.key(),
+            is_signer: a.is_signer,
+            is_writable: a.is_writable,
+        })
+        .collect();
+
+    let mut account_infos = accounts.to_vec();
+    account_infos.push(program.clone());
+
+    let ix = Instruction {
+        program_id: program.key(),
+        accounts: metas,
+        data,
+    };
+
+    invoke(&ix, &account_infos).map_err(|_| MorphoError::CallbackFailed.into())
+}
+
+/// Check that a vault received exactly `expected` tokens
+///
+/// Catches transfer-fee mints and callbacks that move vault funds.
+pub fn verify_vault_delta(before: u64, after: u64, expected: u64) -> Result<()> {
+    require!(
+        after.checked_sub(before) == Some(expected),
+        MorphoError::CallbackTransferMismatch
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_vault_delta() {
+        assert!(verify_vault_delta(100, 150, 50).is_ok());
+        // Short transfer
+        assert!(verify_vault_delta(100, 149, 50).is_err());
+        // Extra tokens are also a mismatch
+        assert!(verify_vault_delta(100, 151, 50).is_err());
+        // Vault balance went down
+        assert!(verify_vault_delta(100, 90, 0).is_err());
+    }
+}
@@ -5,9 +5,10 @@
 //! Example: 5% APY ≈ 1.58e-9 per second = 1_580_000_000 when scaled by WAD
 
 use anchor_lang::prelude::*;
-use crate::constants::{WAD, SECONDS_PER_YEAR, MAX_BORROW_RATE_PER_SECOND};
+use crate::constants::{WAD, BPS, SECONDS_PER_YEAR, MAX_BORROW_RATE_PER_SECOND, MAX_ACCRUAL_ELAPSED};
 use crate::errors::MorphoError;
-use crate::math::{mul_div_down, checked_add, wad_mul_down};
+use crate::math::{mul_div_down, checked_add, wad_mul_down, wad_exp};
+use crate::state::Market;
 
 /// Linear (Kinked) IRM configuration
 #[account]
@@ -68,10 +69,10 @@ impl LinearIrm {
     }
 }
 
-/// Get borrow rate from IRM - for internal use during interest accrual
+/// Get borrow rate from a fixed linear curve
 /// 
-/// This simplified version calculates rate based on utilization.
-/// In production with external IRM programs, this would do CPI.
+/// This simplified version calculates rate based on utilization only.
+/// Interest accrual uses `compute_borrow_rate` (adaptive curve) instead.
 pub fn get_borrow_rate_internal(
     total_supply_assets: u128,
     total_borrow_assets: u128,
@@ -96,6 +97,168 @@ pub fn get_borrow_rate_internal(
     Ok(std::cmp::min(per_second, MAX_BORROW_RATE_PER_SECOND))
 }
 
+// ============================================================================
+// Adaptive Curve IRM
+// ============================================================================
+//
+// Port of Morpho's AdaptiveCurveIrm. The rate follows a fixed curve around
+// `rate_at_target` (1/4x at 0% utilization, 1x at target, 4x at 100%), and
+// `rate_at_target` itself drifts exponentially towards keeping utilization
+// at the target: up while above it, down while below it.
+//
+// All values are signed WAD (1e18 = 1.0) and rates are per second.
+
+/// Curve steepness: rate multiplier at 100% utilization (4.0)
+pub const CURVE_STEEPNESS: i128 = 4 * WAD as i128;
+
+/// Speed at which `rate_at_target` adapts (50 per year at full error)
+pub const ADJUSTMENT_SPEED: i128 = 50 * WAD as i128 / SECONDS_PER_YEAR as i128;
+
+/// Utilization the curve is centered on (90%)
+pub const TARGET_UTILIZATION: i128 = 9 * WAD as i128 / 10;
+
+/// Rate at target for a market's first accrual (4% APR)
+pub const INITIAL_RATE_AT_TARGET: i128 = 4 * WAD as i128 / 100 / SECONDS_PER_YEAR as i128;
+
+/// Lower bound on `rate_at_target` (0.1% APR)
+pub const MIN_RATE_AT_TARGET: i128 = WAD as i128 / 1000 / SECONDS_PER_YEAR as i128;
+
+/// Upper bound on `rate_at_target` (200% APR)
+pub const MAX_RATE_AT_TARGET: i128 = 2 * WAD as i128 / SECONDS_PER_YEAR as i128;
+
+/// Borrow rate for a market over the elapsed period, updating its
+/// `rate_at_target`
+/// 
+/// Returns the average per-second borrow rate (WAD-scaled) to accrue over
+/// `elapsed_seconds`, clamped to the market's `min_rate`/`max_rate`.
+/// Stores the end-of-period `rate_at_target` on the market, so this must
+/// be called once per accrual, right before `accrue_interest_on_market`.
+pub fn compute_borrow_rate(market: &mut Market, elapsed_seconds: i64) -> Result<u128> {
+    let utilization = if market.total_supply_assets == 0 {
+        0
+    } else {
+        mul_div_down(market.total_borrow_assets, WAD, market.total_supply_assets)?
+    };
+
+    let (avg_rate, end_rate_at_target) = adaptive_curve_rate(
+        utilization,
+        market.rate_at_target,
+        std::cmp::min(elapsed_seconds.max(0) as u128, MAX_ACCRUAL_ELAPSED),
+    )?;
+
+    market.rate_at_target = end_rate_at_target;
+    Ok(clamp_borrow_rate(avg_rate, market.min_rate, market.max_rate))
+}
+
+/// Clamp an IRM rate to a market's `[min_rate, max_rate]` band
+///
+/// `max_rate` 0 leaves only the protocol-wide MAX_BORROW_RATE_PER_SECOND.
+pub fn clamp_borrow_rate(rate: u128, min_rate: u128, max_rate: u128) -> u128 {
+    let ceiling = if max_rate == 0 {
+        MAX_BORROW_RATE_PER_SECOND
+    } else {
+        std::cmp::min(max_rate, MAX_BORROW_RATE_PER_SECOND)
+    };
+    rate.clamp(std::cmp::min(min_rate, ceiling), ceiling)
+}
+
+/// Check a market's borrow rate band: both ends within
+/// MAX_BORROW_RATE_PER_SECOND and `min_rate <= max_rate` when a ceiling is set
+pub fn validate_rate_bounds(min_rate: u128, max_rate: u128) -> Result<()> {
+    require!(min_rate <= MAX_BORROW_RATE_PER_SECOND, MorphoError::InvalidInput);
+    require!(max_rate <= MAX_BORROW_RATE_PER_SECOND, MorphoError::InvalidInput);
+    require!(max_rate == 0 || min_rate <= max_rate, MorphoError::InvalidInput);
+    Ok(())
+}
+
+/// Per-second supply rate (WAD-scaled) earned at a given borrow rate
+///
+/// Suppliers receive the interest paid on the borrowed fraction of their
+/// deposits, less the protocol fee: borrow_rate * utilization * (BPS - fee) / BPS.
+pub fn supply_rate(borrow_rate: u128, utilization: u128, fee: u64) -> Result<u128> {
+    let gross = wad_mul_down(borrow_rate, utilization)?;
+    mul_div_down(gross, BPS.saturating_sub(fee) as u128, BPS as u128)
+}
+
+/// Core of the adaptive curve: (average borrow rate, new rate_at_target)
+/// 
+/// # Arguments
+/// * `utilization` - Borrow / supply (WAD-scaled, may exceed WAD)
+/// * `start_rate_at_target` - Stored rate at target (0 = uninitialized)
+/// * `elapsed` - Seconds since the last update
+/// 
+/// The average rate at target over the period is approximated with the
+/// trapezoidal rule on [start, mid, end], as in the reference implementation.
+pub fn adaptive_curve_rate(
+    utilization: u128,
+    start_rate_at_target: u128,
+    elapsed: u128,
+) -> Result<(u128, u128)> {
+    let utilization = i128::try_from(utilization).map_err(|_| MorphoError::MathOverflow)?;
+    let wad = WAD as i128;
+
+    // Normalized distance from target, in [-1, 1] (above 1 if over 100% utilized)
+    let err_norm_factor = if utilization > TARGET_UTILIZATION {
+        wad - TARGET_UTILIZATION
+    } else {
+        TARGET_UTILIZATION
+    };
+    let err = w_div_to_zero(utilization - TARGET_UTILIZATION, err_norm_factor)?;
+
+    let (avg_rate_at_target, end_rate_at_target) = if start_rate_at_target == 0 {
+        // First interaction
+        (INITIAL_RATE_AT_TARGET, INITIAL_RATE_AT_TARGET)
+    } else {
+        let start = i128::try_from(start_rate_at_target).map_err(|_| MorphoError::MathOverflow)?;
+        let speed = w_mul_to_zero(ADJUSTMENT_SPEED, err)?;
+        let elapsed = i128::try_from(elapsed).map_err(|_| MorphoError::MathOverflow)?;
+        let linear_adaptation = speed.checked_mul(elapsed).ok_or(MorphoError::MathOverflow)?;
+
+        if linear_adaptation == 0 {
+            (start, start)
+        } else {
+            let end = new_rate_at_target(start, linear_adaptation)?;
+            let mid = new_rate_at_target(start, linear_adaptation / 2)?;
+            (((start + end) + 2 * mid) / 4, end)
+        }
+    };
+
+    let avg_rate = curve(avg_rate_at_target, err)?;
+    Ok((avg_rate as u128, end_rate_at_target as u128))
+}
+
+/// Rate along the curve for a given rate at target and utilization error
+fn curve(rate_at_target: i128, err: i128) -> Result<i128> {
+    let wad = WAD as i128;
+    // Non-negative since err >= -WAD and coefficients are below WAD / above 0
+    let coeff = if err < 0 {
+        wad - w_div_to_zero(wad, CURVE_STEEPNESS)?
+    } else {
+        CURVE_STEEPNESS - wad
+    };
+    w_mul_to_zero(w_mul_to_zero(coeff, err)? + wad, rate_at_target)
+}
+
+/// `start * exp(linear_adaptation)`, clamped to [MIN, MAX]_RATE_AT_TARGET
+fn new_rate_at_target(start: i128, linear_adaptation: i128) -> Result<i128> {
+    let exp = wad_exp(linear_adaptation);
+    let rate = mul_div_down(start as u128, exp, WAD)?;
+    Ok((rate.min(MAX_RATE_AT_TARGET as u128) as i128).max(MIN_RATE_AT_TARGET))
+}
+
+/// Signed WAD multiplication, rounding towards zero
+fn w_mul_to_zero(a: i128, b: i128) -> Result<i128> {
+    Ok(a.checked_mul(b).ok_or(MorphoError::MathOverflow)? / WAD as i128)
+}
+
+/// Signed WAD division, rounding towards zero
+fn w_div_to_zero(a: i128, b: i128) -> Result<i128> {
+    if b == 0 {
+        return Err(MorphoError::DivisionByZero.into());
+    }
+    Ok(a.checked_mul(WAD as i128).ok_or(MorphoError::MathOverflow)? / b)
+}
+
 // Example IRM configurations:
 // 
 // STABLE (USDC lending):
@@ -109,3 +272,112 @@ pub fn get_borrow_rate_internal(
 //   slope1:    0.08e18  (8% slope below kink)
 //   slope2:    1.00e18  (100% slope above kink)
 //   kink:      0.70e18  (70% target utilization)
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Utilization as a WAD fraction of percent
+    fn pct(p: u128) -> u128 {
+        WAD * p / 100
+    }
+
+    #[test]
+    fn test_supply_rate() {
+        let borrow_rate = 1_000_000_000u128;
+
+        assert_eq!(supply_rate(borrow_rate, 0, 0).unwrap(), 0);
+        assert_eq!(supply_rate(borrow_rate, WAD, 0).unwrap(), borrow_rate);
+        // 50% utilized with a 10% fee: 45% of the borrow rate
+        assert_eq!(supply_rate(borrow_rate, pct(50), 1_000).unwrap(), 450_000_000);
+    }
+
+    #[test]
+    fn test_curve_at_0_90_100_percent() {
+        let rate_at_target = INITIAL_RATE_AT_TARGET as u128;
+
+        // At target the rate is exactly rate_at_target
+        let (rate, end) = adaptive_curve_rate(pct(90), rate_at_target, 0).unwrap();
+        assert_eq!(rate, rate_at_target);
+        assert_eq!(end, rate_at_target);
+
+        // At 100% the rate is CURVE_STEEPNESS times rate_at_target
+        let (rate, _) = adaptive_curve_rate(pct(100), rate_at_target, 0).unwrap();
+        assert_eq!(rate, rate_at_target * 4);
+
+        // At 0% the rate is rate_at_target / CURVE_STEEPNESS
+        let (rate, _) = adaptive_curve_rate(0, rate_at_target, 0).unwrap();
+        assert_eq!(rate, rate_at_target / 4);
+    }
+
+    #[test]
+    fn test_first_interaction_uses_initial_rate() {
+        let (rate, end) = adaptive_curve_rate(pct(90), 0, 1_000).unwrap();
+        assert_eq!(rate, INITIAL_RATE_AT_TARGET as u128);
+        assert_eq!(end, INITIAL_RATE_AT_TARGET as u128);
+    }
+
+    #[test]
+    fn test_rate_at_target_adapts_over_time() {
+        let start = INITIAL_RATE_AT_TARGET as u128;
+        let day = 86_400;
+
+        // Above target: rate at target rises, and the average sits between start and end
+        let (avg, end) = adaptive_curve_rate(pct(100), start, day).unwrap();
+        assert!(end > start);
+        assert!(avg > start * 4 && avg < end * 4);
+
+        // Below target: rate at target falls
+        let (_, end) = adaptive_curve_rate(pct(50), start, day).unwrap();
+        assert!(end < start);
+
+        // At target: no drift
+        let (_, end) = adaptive_curve_rate(pct(90), start, day).unwrap();
+        assert_eq!(end, start);
+
+        // Clamped to bounds after long periods
+        let (_, end) = adaptive_curve_rate(pct(100), start, 10 * SECONDS_PER_YEAR).unwrap();
+        assert_eq!(end, MAX_RATE_AT_TARGET as u128);
+        let (_, end) = adaptive_curve_rate(0, start, 10 * SECONDS_PER_YEAR).unwrap();
+        assert_eq!(end, MIN_RATE_AT_TARGET as u128);
+    }
+
+    #[test]
+    fn test_borrow_rate_clamped_at_0_and_100_percent() {
+        let floor = 2 * WAD / 100 / SECONDS_PER_YEAR;
+        let ceiling = 10 * WAD / 100 / SECONDS_PER_YEAR;
+        let mut market = Market {
+            rate_at_target: INITIAL_RATE_AT_TARGET as u128,
+            min_rate: 2 * WAD / 100 / SECONDS_PER_YEAR,
+            max_rate: 10 * WAD / 100 / SECONDS_PER_YEAR,
+            ..bytemuck::Zeroable::zeroed()
+        };
+
+        // 0% utilization: the curve gives 1% APR, lifted to the 2% floor
+        assert_eq!(compute_borrow_rate(&mut market, 0).unwrap(), floor);
+
+        // 100% utilization: the curve gives 16% APR, held at the 10% ceiling
+        market.total_supply_assets = 1_000;
+        market.total_borrow_assets = 1_000;
+        assert_eq!(compute_borrow_rate(&mut market, 0).unwrap(), ceiling);
+
+        // At target the curve's 4% APR lies inside the band
+        market.total_borrow_assets = 900;
+        assert_eq!(compute_borrow_rate(&mut market, 0).unwrap(), INITIAL_RATE_AT_TARGET as u128);
+
+        // Without a ceiling only the protocol maximum applies
+        assert_eq!(clamp_borrow_rate(u128::MAX, 0, 0), MAX_BORROW_RATE_PER_SECOND);
+        assert_eq!(clamp_borrow_rate(0, floor, 0), floor);
+    }
+
+    #[test]
+    fn test_validate_rate_bounds() {
+        let max = MAX_BORROW_RATE_PER_SECOND;
+        assert!(validate_rate_bounds(0, 0).is_ok());
+        assert!(validate_rate_bounds(max, 0).is_ok());
+        assert!(validate_rate_bounds(5, 5).is_ok());
+        assert!(validate_rate_bounds(6, 5).is_err());
+        assert!(validate_rate_bounds(max + 1, 0).is_err());
+        assert!(validate_rate_bounds(0, max + 1).is_err());
+    }
+}
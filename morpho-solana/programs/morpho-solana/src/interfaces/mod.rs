@@ -1,7 +1,11 @@
-//! Interfaces for external integrations (Oracle, IRM)
+//! Interfaces for external integrations (Oracle, IRM, callbacks, tokens)
 
 pub mod oracle;
 pub mod irm;
+pub mod callback;
+pub mod token;
 
 pub use oracle::*;
 pub use irm::*;
+pub use callback::*;
+pub use token::*;
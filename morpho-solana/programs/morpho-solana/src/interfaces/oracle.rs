@@ -1,4 +1,4 @@
-//! Oracle interface with Switchboard integration
+//! Oracle interface with Switchboard, Pyth, TWAP and median integration
 //! 
 //! Oracles return: collateral tokens per 1 loan token (scaled 1e36 = ORACLE_SCALE)
 //! 
@@ -6,12 +6,17 @@
 //! - For ETH/USDC market: oracle returns 2000 * 1e36
 
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::pubkey;
 use switchboard_on_demand::on_demand::accounts::pull_feed::PullFeedAccountData;
 use rust_decimal::Decimal;
-use crate::constants::{ORACLE_SCALE, MIN_ORACLE_PRICE, BPS, WAD};
+use crate::constants::{
+    ORACLE_SCALE, MIN_ORACLE_PRICE, BPS, WAD, VIRTUAL_ASSETS, VIRTUAL_SHARES, MAX_ORACLE_MIGRATION_BPS,
+    SLOT_DURATION_MS,
+};
 use crate::errors::MorphoError;
-use crate::state::Market;
-use crate::math::{mul_div_down, mul_div_up, to_assets_up};
+use crate::events::{BadDebtSocialized, OracleFallbackUsed};
+use crate::state::{Market, MedianOracle, OracleKind, Position, TwapOracle, median_price};
+use crate::math::{checked_add, checked_sub, mul_div_down, mul_div_up, to_assets_up};
 
 /// Maximum oracle price (1 billion ratio) - computed at runtime to avoid const overflow
 pub fn max_oracle_price() -> u128 {
@@ -24,11 +29,93 @@ pub fn max_oracle_price() -> u128 {
 
 /// Maximum staleness for oracle data (in slots, ~400ms per slot)
 /// 50 slots ≈ 20 seconds
+/// Used when the market's `max_staleness_slots` is zero
 pub const MAX_ORACLE_STALENESS: u64 = 50;
 
+/// Bounds for a market's configured `max_staleness_slots`
+/// 300 slots ≈ 2 minutes
+pub const MIN_STALENESS_SLOTS: u64 = 1;
+pub const MAX_STALENESS_SLOTS: u64 = 300;
+
 /// Minimum number of oracle samples required
+/// Used when the market's `min_oracle_samples` is zero
 pub const MIN_ORACLE_SAMPLES: u32 = 1;
 
+/// Default maximum standard deviation across Switchboard responses as a
+/// fraction of price (1% = 100 bps)
+/// Used when the market's `max_std_bps` is zero
+pub const MAX_ORACLE_STD_BPS: u64 = 100;
+
+/// Validation limits for reading a single feed
+/// 
+/// Markets take theirs from `FeedLimits::of`; TWAP oracles read their
+/// source with their own staleness limit and the default sample and
+/// confidence requirements. Pyth reads also require the update to carry
+/// the pinned `pyth_feed_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedLimits {
+    /// Maximum age of the feed's last update (slots)
+    pub max_staleness_slots: u64,
+    /// Minimum Switchboard oracle responses
+    pub min_samples: u32,
+    /// Maximum Pyth confidence interval as a fraction of price (bps)
+    pub max_confidence_bps: u64,
+    /// Maximum Switchboard response standard deviation as a fraction of price (bps)
+    pub max_std_bps: u64,
+    /// Pyth feed id the update must carry (all zero matches no feed)
+    pub pyth_feed_id: [u8; 32],
+}
+
+impl FeedLimits {
+    /// Effective limits configured on a market
+    pub fn of(market: &Market) -> Self {
+        Self {
+            max_staleness_slots: max_staleness_slots(market),
+            min_samples: min_oracle_samples(market),
+            max_confidence_bps: if market.max_oracle_confidence_bps == 0 {
+                MAX_ORACLE_CONFIDENCE_BPS
+            } else {
+                market.max_oracle_confidence_bps
+            },
+            max_std_bps: if market.max_std_bps == 0 {
+                MAX_ORACLE_STD_BPS
+            } else {
+                market.max_std_bps
+            },
+            pyth_feed_id: market.pyth_feed_id,
+        }
+    }
+
+    /// Limits for the market's loan/USD feed, which has its own Pyth feed id
+    pub fn of_secondary(market: &Market) -> Self {
+        Self {
+            pyth_feed_id: market.pyth_secondary_feed_id,
+            ..Self::of(market)
+        }
+    }
+}
+
+/// A validated price with the metadata of the read that produced it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OraclePrice {
+    /// Price scaled by ORACLE_SCALE
+    pub price: u128,
+    /// Slot the underlying feed value was published in
+    pub published_slot: u64,
+    /// Feed uncertainty as a fraction of price (basis points, rounded up,
+    /// 0 = none reported)
+    pub confidence_bps: u64,
+}
+
+/// `spread` as a fraction of `price` in basis points, rounded up and
+/// saturating at u64::MAX
+fn confidence_bps(spread: u128, price: u128) -> u64 {
+    mul_div_up(spread, BPS as u128, price)
+        .ok()
+        .and_then(|bps| u64::try_from(bps).ok())
+        .unwrap_or(u64::MAX)
+}
+
 /// Get validated oracle price from Switchboard pull feed
 /// 
 /// # Arguments
@@ -38,20 +125,27 @@ pub const MIN_ORACLE_SAMPLES: u32 = 1;
 /// 
 /// # Security Checks
 /// 1. Oracle account matches market's configured oracle
-/// 2. Price data is fresh (within MAX_ORACLE_STALENESS slots)
-/// 3. Minimum number of oracle responses received
+/// 2. Price data is fresh (within the market's max staleness)
+/// 3. Minimum number of oracle responses received, and their standard
+///    deviation is within the market's `max_std_bps` of the price
 /// 4. Price is within valid bounds (MIN_ORACLE_PRICE, max_oracle_price())
 pub fn get_switchboard_price_validated(
     oracle_account: &AccountInfo,
     market: &Market,
     clock: &Clock,
-) -> Result<u128> {
+) -> Result<OraclePrice> {
     // Check 1: Oracle account matches market configuration
     require!(
         oracle_account.key() == market.oracle,
         MorphoError::InvalidOracle
     );
 
+    read_switchboard_feed(oracle_account, &FeedLimits::of(market), clock)
+}
+
+/// Read and validate a Switchboard pull feed without checking which market
+/// slot it is configured in (checks 2-4 of `get_switchboard_price_validated`)
+fn read_switchboard_feed(oracle_account: &AccountInfo, limits: &FeedLimits, clock: &Clock) -> Result<OraclePrice> {
     // Parse Switchboard PullFeed account
     let data = oracle_account.try_borrow_data()?;
     let feed = PullFeedAccountData::parse(data)
@@ -60,19 +154,158 @@ pub fn get_switchboard_price_validated(
     // Check 2 & 3: Get validated price with staleness and sample checks
     let price_decimal = feed.get_value(
         clock.slot,
-        MAX_ORACLE_STALENESS,
-        MIN_ORACLE_SAMPLES,
+        limits.max_staleness_slots,
+        limits.min_samples,
         true, // only_positive
     ).map_err(|_| error!(MorphoError::OracleStale))?;
 
     // Convert Decimal to u128 scaled by ORACLE_SCALE
     let price = decimal_to_oracle_scale(&price_decimal)?;
 
+    // Check 3: Oracles must roughly agree
+    let std_dev = feed.std_dev()
+        .ok_or_else(|| error!(MorphoError::OracleStale))?;
+    let std_dev = decimal_to_oracle_scale(&std_dev)?;
+    check_std_dev(price, std_dev, limits.max_std_bps)?;
+
     // Check 4: Price sanity bounds
+    check_price_bounds(price)?;
+
+    Ok(OraclePrice {
+        price,
+        published_slot: feed.result.slot,
+        confidence_bps: confidence_bps(std_dev, price),
+    })
+}
+
+/// Effective staleness limit for a market
+/// 
+/// Markets created before `max_staleness_slots` existed store zero and fall
+/// back to MAX_ORACLE_STALENESS.
+pub fn max_staleness_slots(market: &Market) -> u64 {
+    if market.max_staleness_slots == 0 {
+        MAX_ORACLE_STALENESS
+    } else {
+        market.max_staleness_slots
+    }
+}
+
+/// Effective sample requirement for a market
+/// 
+/// Markets created before `min_oracle_samples` existed store zero and fall
+/// back to MIN_ORACLE_SAMPLES.
+pub fn min_oracle_samples(market: &Market) -> u32 {
+    if market.min_oracle_samples == 0 {
+        MIN_ORACLE_SAMPLES
+    } else {
+        market.min_oracle_samples
+    }
+}
+
+/// Check a market's configured sample requirement is at least one
+pub fn validate_min_oracle_samples(min_oracle_samples: u32) -> Result<()> {
+    require!(min_oracle_samples >= 1, MorphoError::InvalidInput);
+    Ok(())
+}
+
+/// Effective upper bound on a market's validated price
+/// 
+/// `market.max_price` tightens max_oracle_price() when set, e.g. to keep a
+/// stablecoin pair near 1:1.
+pub fn max_market_price(market: &Market) -> u128 {
+    if market.max_price == 0 {
+        max_oracle_price()
+    } else {
+        std::cmp::min(market.max_price, max_oracle_price())
+    }
+}
+
+/// Reject a feed price outside [MIN_ORACLE_PRICE, max_oracle_price()]
+pub fn check_price_bounds(price: u128) -> Result<()> {
     require!(price >= MIN_ORACLE_PRICE, MorphoError::OraclePriceTooLow);
     require!(price <= max_oracle_price(), MorphoError::OraclePriceTooHigh);
+    Ok(())
+}
 
-    Ok(price)
+/// Check a market's price cap is unset (0) or at least MIN_ORACLE_PRICE
+pub fn validate_max_price(max_price: u128) -> Result<()> {
+    require!(
+        max_price == 0 || max_price >= MIN_ORACLE_PRICE,
+        MorphoError::InvalidInput
+    );
+    Ok(())
+}
+
+/// Sanity-check a new market's first oracle read
+///
+/// `price` is in raw token units. Undoing the decimal normalization gives
+/// the per-whole-token quote, which must lie in
+/// [MIN_ORACLE_PRICE, max_oracle_price()]. A wrong feed, wrong decimals or a
+/// quote that was already in raw units lands orders of magnitude outside.
+pub fn check_price_plausible(price: u128, collateral_decimals: u8, loan_decimals: u8) -> Result<()> {
+    let quote = normalize_decimals(price, loan_decimals, collateral_decimals)
+        .map_err(|_| MorphoError::OraclePriceImplausible)?;
+    require!(
+        (MIN_ORACLE_PRICE..=max_oracle_price()).contains(&quote),
+        MorphoError::OraclePriceImplausible
+    );
+    Ok(())
+}
+
+/// Reject a new market's first oracle read when its per-whole-token quote
+/// is exactly MIN_ORACLE_PRICE or max_oracle_price()
+///
+/// Real assets do not price at the bounds; a feed that does is stuck at a
+/// default or clamped (inverting a near-zero price saturates at the
+/// maximum), and would leave every position instantly liquidatable or
+/// never liquidatable.
+pub fn check_price_off_boundary(price: u128, collateral_decimals: u8, loan_decimals: u8) -> Result<()> {
+    let quote = normalize_decimals(price, loan_decimals, collateral_decimals)
+        .map_err(|_| MorphoError::OraclePriceImplausible)?;
+    require!(
+        quote != MIN_ORACLE_PRICE && quote != max_oracle_price(),
+        MorphoError::OracleAtBoundary
+    );
+    Ok(())
+}
+
+/// Check a market's configured staleness is within
+/// [MIN_STALENESS_SLOTS, MAX_STALENESS_SLOTS]
+pub fn validate_max_staleness_slots(max_staleness_slots: u64) -> Result<()> {
+    require!(
+        (MIN_STALENESS_SLOTS..=MAX_STALENESS_SLOTS).contains(&max_staleness_slots),
+        MorphoError::InvalidOracleStaleness
+    );
+    Ok(())
+}
+
+/// Check a Pyth feed id is pinned exactly when the feed is read as Pyth
+pub fn validate_pyth_feed_id(kind: OracleKind, pyth_feed_id: &[u8; 32]) -> Result<()> {
+    require!(
+        (kind == OracleKind::Pyth) == (*pyth_feed_id != [0u8; 32]),
+        MorphoError::InvalidOracle
+    );
+    Ok(())
+}
+
+/// Check a market pins a Pyth feed id for each of its feeds exactly when it
+/// reads them as Pyth (the fallback shares the primary's id)
+pub fn validate_market_pyth_feed_ids(market: &Market) -> Result<()> {
+    let kind = market.oracle_kind()?;
+    validate_pyth_feed_id(kind, &market.pyth_feed_id)?;
+    if market.oracle_secondary == Pubkey::default() {
+        require!(market.pyth_secondary_feed_id == [0u8; 32], MorphoError::InvalidOracle);
+    } else {
+        validate_pyth_feed_id(kind, &market.pyth_secondary_feed_id)?;
+    }
+    Ok(())
+}
+
+/// Reject a feed whose responses spread more than `max_std_bps` of its price
+fn check_std_dev(price: u128, std_dev: u128, max_std_bps: u64) -> Result<()> {
+    let limit = mul_div_down(price, max_std_bps as u128, BPS as u128)?;
+    require!(std_dev <= limit, MorphoError::OracleDeviationTooHigh);
+    Ok(())
 }
 
 /// Convert Switchboard Decimal to ORACLE_SCALE (1e36)
@@ -89,16 +322,201 @@ fn decimal_to_oracle_scale(decimal: &Decimal) -> Result<u128> {
     // If Switchboard gives us a value like 2000.0 with scale 18
     // We need: mantissa * 1e(36 - scale)
     
-    let mantissa_u128 = mantissa.unsigned_abs();
-    
+    // A negative price is never valid collateral pricing - reject it rather
+    // than silently taking the absolute value
+    if decimal.is_sign_negative() && mantissa != 0 {
+        return Err(MorphoError::OraclePriceTooLow.into());
+    }
+
+    scaled_to_oracle_scale(mantissa.unsigned_abs(), scale)
+}
+
+/// Largest decimal scale accepted from a feed; 10^38 is the last power of
+/// ten that fits in a u128
+const MAX_DECIMAL_SCALE: u32 = 38;
+
+/// Convert `mantissa * 10^-scale` to ORACLE_SCALE (1e36)
+fn scaled_to_oracle_scale(mantissa: u128, scale: u32) -> Result<u128> {
+    require!(scale <= MAX_DECIMAL_SCALE, MorphoError::MathOverflow);
+
     if scale <= 36 {
-        let scale_factor = 10u128.pow(36 - scale);
-        mantissa_u128.checked_mul(scale_factor)
+        let scale_factor = 10u128.checked_pow(36 - scale)
+            .ok_or_else(|| error!(MorphoError::MathOverflow))?;
+        mantissa.checked_mul(scale_factor)
             .ok_or_else(|| error!(MorphoError::MathOverflow))
     } else {
         // Scale down if Switchboard uses more than 36 decimals (unlikely)
-        let scale_factor = 10u128.pow(scale - 36);
-        Ok(mantissa_u128 / scale_factor)
+        let scale_factor = 10u128.checked_pow(scale - 36)
+            .ok_or_else(|| error!(MorphoError::MathOverflow))?;
+        Ok(mantissa / scale_factor)
+    }
+}
+
+// ============================================================================
+// Pyth Pull Oracle Integration
+// ============================================================================
+
+/// Pyth Solana Receiver program (owner of all `PriceUpdateV2` accounts)
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey = pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+
+/// Anchor discriminator of Pyth `PriceUpdateV2` accounts
+pub const PYTH_PRICE_UPDATE_V2_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+/// Default maximum confidence interval as a fraction of price (2% = 200 bps)
+/// Used when the market's `max_oracle_confidence_bps` is zero
+pub const MAX_ORACLE_CONFIDENCE_BPS: u64 = 200;
+
+/// Price fields read from a Pyth `PriceUpdateV2` account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PythPrice {
+    /// Price mantissa (value = price * 10^exponent)
+    pub price: i64,
+    /// Confidence interval, same exponent as price
+    pub conf: u64,
+    /// Price exponent
+    pub exponent: i32,
+    /// Pyth price feed the message belongs to
+    pub feed_id: [u8; 32],
+    /// Unix time the price was published by Pyth
+    pub publish_time: i64,
+    /// Slot at which the update was posted on-chain
+    pub posted_slot: u64,
+}
+
+/// Get validated oracle price from a Pyth `PriceUpdateV2` account
+/// 
+/// # Arguments
+/// * `oracle_account` - The Pyth PriceUpdateV2 account
+/// * `market` - The market to validate against
+/// * `clock` - The current clock for staleness checks
+/// 
+/// # Security Checks
+/// 1. Oracle account matches market's configured oracle and is owned by Pyth
+/// 2. Update is fully verified, carries the market's pinned `pyth_feed_id`
+///    and was published within the market's max staleness
+/// 3. Price is positive and confidence is within the market's tolerance
+/// 4. Price is within valid bounds (MIN_ORACLE_PRICE, max_oracle_price())
+pub fn get_pyth_price_validated(
+    oracle_account: &AccountInfo,
+    market: &Market,
+    clock: &Clock,
+) -> Result<OraclePrice> {
+    // Check 1: Oracle account matches market configuration
+    require!(
+        oracle_account.key() == market.oracle,
+        MorphoError::InvalidOracle
+    );
+
+    read_pyth_feed(oracle_account, &FeedLimits::of(market), clock)
+}
+
+/// Read and validate a Pyth `PriceUpdateV2` account without checking which
+/// market slot it is configured in (owner and checks 2-4 of `get_pyth_price_validated`)
+fn read_pyth_feed(oracle_account: &AccountInfo, limits: &FeedLimits, clock: &Clock) -> Result<OraclePrice> {
+    require!(
+        *oracle_account.owner == PYTH_RECEIVER_PROGRAM_ID,
+        MorphoError::InvalidOracle
+    );
+
+    let data = oracle_account.try_borrow_data()?;
+    let update = parse_pyth_price_update(&data)?;
+    check_pyth_update(&update, limits, clock)?;
+
+    let price = pyth_to_oracle_scale(update.price as u64, update.exponent)?;
+
+    // Check 4: Price sanity bounds
+    check_price_bounds(price)?;
+
+    Ok(OraclePrice {
+        price,
+        published_slot: update.posted_slot,
+        confidence_bps: confidence_bps(update.conf as u128, update.price as u128),
+    })
+}
+
+/// Checks 2 and 3 of `get_pyth_price_validated` on a parsed update
+/// 
+/// Anyone holding an update account's write authority can post any feed
+/// into it, so the feed id is checked on every read. Freshness is measured
+/// from `publish_time`, since an old update can be posted in a new slot;
+/// the slot limit is converted at SLOT_DURATION_MS per slot, rounded up.
+fn check_pyth_update(update: &PythPrice, limits: &FeedLimits, clock: &Clock) -> Result<()> {
+    // Check 2: Feed identity and staleness
+    require!(
+        limits.pyth_feed_id != [0u8; 32] && update.feed_id == limits.pyth_feed_id,
+        MorphoError::InvalidOracle
+    );
+    let max_age_seconds = limits.max_staleness_slots.saturating_mul(SLOT_DURATION_MS).div_ceil(1_000);
+    require!(
+        clock.unix_timestamp.saturating_sub(update.publish_time) <= max_age_seconds.min(i64::MAX as u64) as i64,
+        MorphoError::OracleStale
+    );
+
+    // Check 3: Positivity and confidence
+    require!(update.price > 0, MorphoError::OracleInvalidPrice);
+    require!(
+        (update.conf as u128) * (BPS as u128)
+            <= (update.price as u128) * (limits.max_confidence_bps as u128),
+        MorphoError::OracleConfidenceTooWide
+    );
+    Ok(())
+}
+
+/// Parse the price message out of raw `PriceUpdateV2` account data
+/// 
+/// Layout (borsh): discriminator(8) + write_authority(32) + verification_level
+/// (1 byte tag, plus 1 byte num_signatures when Partial) + PriceFeedMessage
+/// (feed_id(32), price(8), conf(8), exponent(4), publish_time(8),
+/// prev_publish_time(8), ema_price(8), ema_conf(8)) + posted_slot(8)
+/// 
+/// Only fully verified updates are accepted.
+pub fn parse_pyth_price_update(data: &[u8]) -> Result<PythPrice> {
+    const VERIFICATION_OFFSET: usize = 8 + 32;
+    const VERIFICATION_FULL: u8 = 1;
+
+    require!(
+        data.len() > VERIFICATION_OFFSET
+            && data[..8] == PYTH_PRICE_UPDATE_V2_DISCRIMINATOR,
+        MorphoError::OracleInvalidReturnData
+    );
+    require!(
+        data[VERIFICATION_OFFSET] == VERIFICATION_FULL,
+        MorphoError::OracleInvalidReturnData
+    );
+
+    // Message starts right after the Full verification tag
+    let msg = VERIFICATION_OFFSET + 1;
+    require!(data.len() >= msg + 92, MorphoError::OracleInvalidReturnData);
+
+    let read = |offset: usize, len: usize| &data[msg + offset..msg + offset + len];
+    let feed_id = read(0, 32).try_into().unwrap();
+    let price = i64::from_le_bytes(read(32, 8).try_into().unwrap());
+    let conf = u64::from_le_bytes(read(40, 8).try_into().unwrap());
+    let exponent = i32::from_le_bytes(read(48, 4).try_into().unwrap());
+    let publish_time = i64::from_le_bytes(read(52, 8).try_into().unwrap());
+    let posted_slot = u64::from_le_bytes(read(84, 8).try_into().unwrap());
+
+    Ok(PythPrice { price, conf, exponent, feed_id, publish_time, posted_slot })
+}
+
+/// Convert a Pyth price (value = price * 10^exponent) to ORACLE_SCALE (1e36)
+fn pyth_to_oracle_scale(price: u64, exponent: i32) -> Result<u128> {
+    // price * 10^(36 + exponent)
+    let shift = 36i64 + exponent as i64;
+
+    if shift >= 0 {
+        let scale_factor = u32::try_from(shift)
+            .ok()
+            .and_then(|s| 10u128.checked_pow(s))
+            .ok_or_else(|| error!(MorphoError::MathOverflow))?;
+        (price as u128).checked_mul(scale_factor)
+            .ok_or_else(|| error!(MorphoError::MathOverflow))
+    } else {
+        // Exponents below -36 lose all precision; treat as a zero price
+        let scale_factor = u32::try_from(-shift)
+            .ok()
+            .and_then(|s| 10u128.checked_pow(s));
+        Ok(scale_factor.map_or(0, |f| price as u128 / f))
     }
 }
 
@@ -114,59 +532,488 @@ pub struct StaticOracle {
     pub price: u128,
     /// Admin who can update price
     pub admin: Pubkey,
+    /// Slot the price was "published" (0 = never stale). Lets tests
+    /// exercise the staleness path without a live feed.
+    pub last_update_slot: u64,
 }
 
 impl StaticOracle {
     pub const SEED: &'static [u8] = b"static_oracle";
 
     pub fn space() -> usize {
-        8 + 1 + 16 + 32
+        8 + 1 + 16 + 32 + 8
     }
 }
 
-/// Get validated oracle price (supports both Switchboard and Static Oracle)
+/// Get validated oracle price (supports Switchboard, Pyth, TWAP, median and Static Oracle)
 /// 
-/// This function auto-detects the oracle type based on account size:
-/// - Large accounts (>1KB) are treated as Switchboard PullFeed
-/// - Small accounts are treated as StaticOracle (for testing)
+/// Dispatches on the market's configured `oracle_kind`
+/// (zero-valued markets created before the field existed are Switchboard).
+/// Markets with an `oracle_secondary` feed are priced via `get_composed_price`
+/// and require `oracle_secondary_account` to be passed. Single-feed markets
+/// with an `oracle_fallback` configured go through
+/// `get_oracle_price_with_fallback` using `oracle_fallback_account`.
+/// 
+/// Markets with `invert_oracle` set return `ORACLE_SCALE^2 / raw_price`
+/// (see `invert_price`), applied before decimal normalization.
+/// 
+/// Feed prices are quoted per whole token, so they are normalized by
+/// `10^(loan_decimals - collateral_decimals)` into raw token units before
+/// being returned. Static oracle prices are set in raw units and are returned
+/// as-is.
 /// 
 /// # Security Checks
 /// 1. Oracle account matches market's configured oracle
-/// 2. Price is within valid bounds (MIN_ORACLE_PRICE, max_oracle_price())
+/// 2. Price is within valid bounds (MIN_ORACLE_PRICE, max_oracle_price()),
+///    and the final price is at most the market's `max_market_price`
+/// 3. Price has not jumped past the market's deviation circuit breaker
+///    (records the accepted price on the market)
+/// 
+/// Once a price passes these checks it is cached on the market for the
+/// rest of the slot (`Market::cached_oracle_price`), so repeated reads in
+/// one transaction skip the ~3KB feed parse. Only the oracle key is
+/// re-checked on a cache hit.
+/// 
+/// Returns just the price; see `get_oracle_price_detailed` for the
+/// publication slot and confidence.
 pub fn get_oracle_price_validated(
     oracle_account: &AccountInfo,
-    market: &Market,
+    oracle_secondary_account: Option<&AccountInfo>,
+    oracle_fallback_account: Option<&AccountInfo>,
+    market: &mut Market,
 ) -> Result<u128> {
+    get_oracle_price_detailed(oracle_account, oracle_secondary_account, oracle_fallback_account, market)
+        .map(|read| read.price)
+}
+
+/// `get_oracle_price_validated` with the read's metadata
+/// 
+/// `published_slot` is the oldest publication slot among the feeds used,
+/// and composed prices add their feeds' `confidence_bps`. A cache hit
+/// returns the metadata of the read that filled the cache.
+pub fn get_oracle_price_detailed(
+    oracle_account: &AccountInfo,
+    oracle_secondary_account: Option<&AccountInfo>,
+    oracle_fallback_account: Option<&AccountInfo>,
+    market: &mut Market,
+) -> Result<OraclePrice> {
     // Check 1: Oracle account matches market configuration
     require!(
         oracle_account.key() == market.oracle,
         MorphoError::InvalidOracle
     );
 
-    let data = oracle_account.try_borrow_data()?;
-    let data_len = data.len();
-    
-    // Try to parse as Switchboard PullFeed first (accounts are fairly large ~3KB)
-    if data_len >= 1000 {
-        // Use slot-aware validation to avoid Switchboard underflow panics.
-        let clock = Clock::get()?;
-        if let Ok(price) = get_switchboard_price_validated(oracle_account, market, &clock) {
-            return Ok(price);
+    // Already validated in this slot (e.g. an earlier liquidation in the tx)
+    let clock = Clock::get()?;
+    if let Some(price) = market.cached_oracle_price(clock.slot) {
+        return Ok(OraclePrice {
+            price,
+            published_slot: market.oracle_cache_published_slot,
+            confidence_bps: market.oracle_cache_confidence_bps,
+        });
+    }
+
+    let read = if market.oracle_secondary != Pubkey::default() {
+        let loan_feed = oracle_secondary_account.ok_or(MorphoError::InvalidOracle)?;
+        get_composed_price(oracle_account, loan_feed, market, &clock)?
+    } else {
+        get_oracle_price_with_fallback(oracle_account, oracle_fallback_account, market, &clock)?
+    };
+    let price = scale_price(read.price, &PriceScaling::of(market)?)?;
+
+    // Check 3: Deviation circuit breaker
+    check_price_deviation(market, price, clock.slot)?;
+    market.oracle_cache_slot = clock.slot;
+    market.oracle_cache_published_slot = read.published_slot;
+    market.oracle_cache_confidence_bps = read.confidence_bps;
+
+    // Inversion and normalization rescale the price, not its relative error
+    Ok(OraclePrice { price, ..read })
+}
+
+/// Reject a price that moved more than `max_deviation_bps` from the last
+/// accepted price, then record it as the new reference
+/// 
+/// The comparison only applies while the last price is within the market's
+/// staleness window, so the breaker clears itself after `max_staleness_slots`
+/// and a sustained move is eventually accepted.
+pub fn check_price_deviation(market: &mut Market, price: u128, slot: u64) -> Result<()> {
+    let within_window = market.last_oracle_price != 0
+        && slot.saturating_sub(market.last_oracle_slot) <= max_staleness_slots(market);
+
+    if market.max_deviation_bps != 0 && within_window {
+        let deviation = price_deviation_bps(market.last_oracle_price, price)?;
+        require!(
+            deviation <= market.max_deviation_bps as u128,
+            MorphoError::OracleDeviationTooHigh
+        );
+    }
+
+    market.last_oracle_price = price;
+    market.last_oracle_slot = slot;
+    Ok(())
+}
+
+/// Reject an oracle migration whose new price is more than
+/// MAX_ORACLE_MIGRATION_BPS away from the old oracle's
+///
+/// Both prices are the market's final (scaled) price read in the same
+/// slot, so a new feed with wrong decimals, inversion or a different pair
+/// cannot reprice every position at once.
+pub fn check_oracle_migration(old_price: u128, new_price: u128) -> Result<()> {
+    require!(
+        price_deviation_bps(old_price, new_price)? <= MAX_ORACLE_MIGRATION_BPS as u128,
+        MorphoError::OracleMigrationDeviationTooHigh
+    );
+    Ok(())
+}
+
+/// Relative move from `last` to `price` in basis points, rounded up
+fn price_deviation_bps(last: u128, price: u128) -> Result<u128> {
+    let diff = last.abs_diff(price);
+    mul_div_up(diff, BPS as u128, last)
+}
+
+/// Read the primary oracle, falling back to `market.oracle_fallback`
+/// 
+/// # Arguments
+/// * `primary` - The market's configured oracle
+/// * `secondary` - The market's fallback oracle, if passed
+/// * `market` - The market to validate against
+/// * `clock` - The current clock for staleness checks
+/// 
+/// The fallback is only consulted when the primary is stale, malformed or
+/// out of bounds, and only if the market has a fallback configured. Both
+/// feeds are read with the market's `oracle_kind` (and, for Pyth, must carry
+/// its `pyth_feed_id`). If the fallback also fails, its error is returned.
+pub fn get_oracle_price_with_fallback(
+    primary: &AccountInfo,
+    secondary: Option<&AccountInfo>,
+    market: &Market,
+    clock: &Clock,
+) -> Result<OraclePrice> {
+    let primary_err = match read_primary_price(primary, market, clock) {
+        Ok(price) => return Ok(price),
+        Err(err) if is_fallback_eligible(&err) => err,
+        Err(err) => return Err(err),
+    };
+
+    let fallback = match secondary {
+        Some(fallback) if market.oracle_fallback != Pubkey::default() => fallback,
+        _ => return Err(primary_err),
+    };
+    require!(
+        fallback.key() == market.oracle_fallback,
+        MorphoError::InvalidOracle
+    );
+
+    let read = read_feed_price(fallback, market, clock)?;
+
+    emit!(OracleFallbackUsed {
+        market_id: market.market_id,
+        primary: market.oracle,
+        fallback: market.oracle_fallback,
+        slot: clock.slot,
+    });
+
+    Ok(read)
+}
+
+/// Read the market's primary oracle with its full validated path
+fn read_primary_price(oracle_account: &AccountInfo, market: &Market, clock: &Clock) -> Result<OraclePrice> {
+    match market.oracle_kind()? {
+        OracleKind::Switchboard => get_switchboard_price_validated(oracle_account, market, clock),
+        OracleKind::Pyth => get_pyth_price_validated(oracle_account, market, clock),
+        OracleKind::Static | OracleKind::Twap | OracleKind::Median => {
+            read_feed_price(oracle_account, market, clock)
         }
-        // If Switchboard parsing fails, try static oracle
-        // Need to re-borrow data
-        let data = oracle_account.try_borrow_data()?;
-        return parse_static_oracle_price(&data);
     }
-    
-    // Fall back to Static Oracle for testing
-    parse_static_oracle_price(&data)
+}
+
+/// Primary oracle failures that should be retried against the fallback
+fn is_fallback_eligible(err: &Error) -> bool {
+    [
+        MorphoError::OracleStale,
+        MorphoError::OracleInvalidReturnData,
+        MorphoError::OraclePriceTooLow,
+        MorphoError::OraclePriceTooHigh,
+        MorphoError::OracleTwapInsufficientCoverage,
+    ]
+    .into_iter()
+    .any(|code| *err == code.into())
+}
+
+/// Invert an ORACLE_SCALE price: `ORACLE_SCALE * ORACLE_SCALE / raw_price`
+/// 
+/// Rounds down and clamps to max_oracle_price() when the inverse of a tiny
+/// price does not fit.
+pub fn invert_price(raw_price: u128) -> Result<u128> {
+    require!(raw_price > 0, MorphoError::OracleZeroPrice);
+    let inverted = mul_div_down(ORACLE_SCALE, ORACLE_SCALE, raw_price)
+        .unwrap_or(u128::MAX);
+    Ok(std::cmp::min(inverted, max_oracle_price()))
+}
+
+/// Convert a whole-token price into raw token units
+/// 
+/// `price * 10^(loan_decimals - collateral_decimals)`, dividing instead when
+/// the collateral has more decimals. Errors with MathOverflow when the gap
+/// cannot be represented, and MathUnderflow when the price scales to zero.
+pub fn normalize_decimals(price: u128, collateral_decimals: u8, loan_decimals: u8) -> Result<u128> {
+    let normalized = if loan_decimals >= collateral_decimals {
+        let factor = 10u128
+            .checked_pow((loan_decimals - collateral_decimals) as u32)
+            .ok_or(MorphoError::MathOverflow)?;
+        price.checked_mul(factor).ok_or(MorphoError::MathOverflow)?
+    } else {
+        let factor = 10u128
+            .checked_pow((collateral_decimals - loan_decimals) as u32)
+            .ok_or(MorphoError::MathOverflow)?;
+        price / factor
+    };
+
+    require!(normalized > 0, MorphoError::MathUnderflow);
+    Ok(normalized)
+}
+
+// ============================================================================
+// Price Scaling
+// ============================================================================
+
+/// How a market turns a validated feed price into the price it lends against
+///
+/// Holds only plain values, so clients can build one from a decoded market
+/// (or by hand) and reproduce the scaling without any account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceScaling {
+    /// Collateral token decimals
+    pub collateral_decimals: u8,
+    /// Loan token decimals
+    pub loan_decimals: u8,
+    /// Invert the feed price first (`invert_oracle`)
+    pub invert: bool,
+    /// Normalize whole-token prices to raw units (false for static oracles,
+    /// which are set in raw units)
+    pub normalize: bool,
+    /// Upper bound on the final price (`max_market_price`)
+    pub max_price: u128,
+}
+
+impl PriceScaling {
+    /// Scaling configured on a market
+    pub fn of(market: &Market) -> Result<Self> {
+        Ok(Self {
+            collateral_decimals: market.collateral_decimals,
+            loan_decimals: market.loan_decimals,
+            invert: market.is_oracle_inverted(),
+            normalize: market.oracle_kind()? != OracleKind::Static,
+            max_price: max_market_price(market),
+        })
+    }
+}
+
+/// Invert, normalize and cap a feed price (already bounds-checked and
+/// scaled by ORACLE_SCALE), as `get_oracle_price_detailed` does before the
+/// deviation circuit breaker
+pub fn scale_price(feed_price: u128, scaling: &PriceScaling) -> Result<u128> {
+    let price = if scaling.invert {
+        invert_price(feed_price)?
+    } else {
+        feed_price
+    };
+
+    let price = if scaling.normalize {
+        normalize_decimals(price, scaling.collateral_decimals, scaling.loan_decimals)?
+    } else {
+        price
+    };
+    require!(price <= scaling.max_price, MorphoError::OraclePriceTooHigh);
+    Ok(price)
+}
+
+/// Off-chain equivalent of `get_oracle_price_validated` for a single
+/// Switchboard-style feed value
+///
+/// Applies the same conversion, bounds and scaling as the on-chain read, so
+/// a bot holding the feed's `Decimal` gets the exact price a transaction in
+/// the same slot would. Staleness, sample and deviation checks depend on
+/// chain state and are not repeated.
+#[cfg(feature = "client")]
+pub fn scale_and_validate(decimal: &Decimal, scaling: &PriceScaling) -> Result<u128> {
+    let price = decimal_to_oracle_scale(decimal)?;
+    check_price_bounds(price)?;
+    scale_price(price, scaling)
+}
+
+/// Compose a collateral/loan price from two USD-denominated feeds
+/// 
+/// # Arguments
+/// * `collateral_feed` - Collateral/USD feed (the market's `oracle`)
+/// * `loan_feed` - Loan/USD feed (the market's `oracle_secondary`)
+/// * `market` - The market to validate against
+/// * `clock` - The current clock for staleness checks
+/// 
+/// Both feeds are read with the market's `oracle_kind` and each is validated
+/// for staleness and bounds on its own before composing:
+/// 
+/// `price = collateral_usd * ORACLE_SCALE / loan_usd`
+/// 
+/// The division rounds DOWN, so collateral is never valued above what the
+/// two feeds imply. The composed price is bounds-checked again.
+pub fn get_composed_price(
+    collateral_feed: &AccountInfo,
+    loan_feed: &AccountInfo,
+    market: &Market,
+    clock: &Clock,
+) -> Result<OraclePrice> {
+    require!(
+        market.oracle_secondary != Pubkey::default(),
+        MorphoError::InvalidOracle
+    );
+    require!(
+        collateral_feed.key() == market.oracle,
+        MorphoError::InvalidOracle
+    );
+    require!(
+        loan_feed.key() == market.oracle_secondary,
+        MorphoError::InvalidOracle
+    );
+
+    let kind = market.oracle_kind()?;
+    let collateral_usd = read_feed(collateral_feed, kind, &FeedLimits::of(market), clock)?;
+    let loan_usd = read_feed(loan_feed, kind, &FeedLimits::of_secondary(market), clock)?;
+
+    let price = compose_prices(collateral_usd.price, loan_usd.price)?;
+
+    // Price sanity bounds
+    check_price_bounds(price)?;
+
+    Ok(OraclePrice {
+        price,
+        published_slot: std::cmp::min(collateral_usd.published_slot, loan_usd.published_slot),
+        // Relative errors of a quotient add (to first order)
+        confidence_bps: collateral_usd.confidence_bps.saturating_add(loan_usd.confidence_bps),
+    })
+}
+
+/// Read a single feed according to the market's oracle kind
+fn read_feed_price(account: &AccountInfo, market: &Market, clock: &Clock) -> Result<OraclePrice> {
+    read_feed(account, market.oracle_kind()?, &FeedLimits::of(market), clock)
+}
+
+/// Read a single feed of the given kind under `limits`
+/// 
+/// Does not check which account this is; callers match it against the
+/// market (or TWAP) configuration first.
+pub fn read_feed(
+    account: &AccountInfo,
+    kind: OracleKind,
+    limits: &FeedLimits,
+    clock: &Clock,
+) -> Result<OraclePrice> {
+    match kind {
+        OracleKind::Switchboard => read_switchboard_feed(account, limits, clock),
+        OracleKind::Pyth => read_pyth_feed(account, limits, clock),
+        OracleKind::Static => {
+            require!(*account.owner == crate::ID, MorphoError::InvalidOracle);
+            let data = account.try_borrow_data()?;
+            parse_static_oracle_price(&data, clock.slot, limits.max_staleness_slots)
+        }
+        OracleKind::Twap => read_twap_feed(account, clock),
+        OracleKind::Median => read_median_feed(account, limits, clock),
+    }
+}
+
+/// Read the average price of a `TwapOracle` account
+/// 
+/// The average is in the source feed's units, so it is inverted and
+/// normalized like any other feed price. It is as fresh as the newest
+/// sample and reports no confidence.
+fn read_twap_feed(account: &AccountInfo, clock: &Clock) -> Result<OraclePrice> {
+    require!(*account.owner == crate::ID, MorphoError::InvalidOracle);
+    let data = account.try_borrow_data()?;
+    let twap = TwapOracle::try_deserialize(&mut &data[..])
+        .map_err(|_| error!(MorphoError::OracleInvalidReturnData))?;
+    let price = twap.twap(clock.slot)?;
+    let (published_slot, _) = twap.sample(0).ok_or(MorphoError::OracleTwapInsufficientCoverage)?;
+    Ok(OraclePrice { price, published_slot, confidence_bps: 0 })
+}
+
+/// Read the stored median of a `MedianOracle` account
+/// 
+/// The median is only as fresh as the last `update_median` crank, so it is
+/// held to the same staleness limit as a direct feed read.
+fn read_median_feed(account: &AccountInfo, limits: &FeedLimits, clock: &Clock) -> Result<OraclePrice> {
+    require!(*account.owner == crate::ID, MorphoError::InvalidOracle);
+    let data = account.try_borrow_data()?;
+    let median = MedianOracle::try_deserialize(&mut &data[..])
+        .map_err(|_| error!(MorphoError::OracleInvalidReturnData))?;
+    require!(
+        median.last_update_slot != 0
+            && clock.slot.saturating_sub(median.last_update_slot) <= limits.max_staleness_slots,
+        MorphoError::OracleStale
+    );
+    Ok(OraclePrice { price: median.price, published_slot: median.last_update_slot, confidence_bps: 0 })
+}
+
+/// Median price across an aggregator's feeds
+/// 
+/// # Arguments
+/// * `feeds` - The aggregator's feed accounts, in `MedianOracle::feeds` order
+/// * `aggregator` - The aggregator listing the feeds and their limits
+/// * `clock` - The current clock for staleness checks
+/// 
+/// Each feed is read with the aggregator's `feed_kind` and staleness limit.
+/// Feeds failing with a fallback-eligible error (stale, malformed, out of
+/// bounds) are skipped; any other error aborts. Fails with
+/// `OracleTooFewHealthyFeeds` when fewer than `min_healthy_feeds` remain.
+pub fn get_median_price(
+    feeds: &[AccountInfo],
+    aggregator: &MedianOracle,
+    clock: &Clock,
+) -> Result<u128> {
+    let expected = aggregator.feeds();
+    require!(feeds.len() == expected.len(), MorphoError::InvalidOracle);
+
+    let kind = OracleKind::try_from(aggregator.feed_kind)?;
+    let limits = FeedLimits {
+        max_staleness_slots: aggregator.max_staleness_slots,
+        min_samples: MIN_ORACLE_SAMPLES,
+        max_confidence_bps: MAX_ORACLE_CONFIDENCE_BPS,
+        max_std_bps: MAX_ORACLE_STD_BPS,
+        pyth_feed_id: aggregator.pyth_feed_id,
+    };
+
+    let mut prices = Vec::with_capacity(feeds.len());
+    for (feed, key) in feeds.iter().zip(expected) {
+        require!(feed.key() == *key, MorphoError::InvalidOracle);
+        match read_feed(feed, kind, &limits, clock) {
+            Ok(read) => prices.push(read.price),
+            Err(err) if is_fallback_eligible(&err) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    require!(
+        prices.len() >= aggregator.min_healthy_feeds as usize,
+        MorphoError::OracleTooFewHealthyFeeds
+    );
+    median_price(&mut prices).ok_or_else(|| error!(MorphoError::OracleTooFewHealthyFeeds))
+}
+
+/// Collateral price in loan tokens from two USD prices (ORACLE_SCALE), rounded down
+fn compose_prices(collateral_usd: u128, loan_usd: u128) -> Result<u128> {
+    mul_div_down(collateral_usd, ORACLE_SCALE, loan_usd)
 }
 
 /// Parse price from StaticOracle account data
-fn parse_static_oracle_price(data: &[u8]) -> Result<u128> {
-    // Skip discriminator (8 bytes) and bump (1 byte)
-    if data.len() < 25 {
+/// 
+/// Accounts written before `last_update_slot` existed are 8 bytes short
+/// and, like a zero slot, are never stale; they report `current_slot` as
+/// their publication slot.
+fn parse_static_oracle_price(data: &[u8], current_slot: u64, max_staleness: u64) -> Result<OraclePrice> {
+    // Discriminator (8 bytes) and bump (1 byte) precede the price
+    if data.len() < 25 || !data.starts_with(StaticOracle::DISCRIMINATOR) {
         return Err(MorphoError::OracleInvalidReturnData.into());
     }
     
@@ -175,10 +1022,23 @@ fn parse_static_oracle_price(data: &[u8]) -> Result<u128> {
     );
 
     // Price sanity bounds
-    require!(price >= MIN_ORACLE_PRICE, MorphoError::OraclePriceTooLow);
-    require!(price <= max_oracle_price(), MorphoError::OraclePriceTooHigh);
+    check_price_bounds(price)?;
 
-    Ok(price)
+    let mut published_slot = current_slot;
+    if let Some(slot_bytes) = data.get(57..65) {
+        let last_update_slot = u64::from_le_bytes(
+            slot_bytes.try_into().map_err(|_| MorphoError::OracleInvalidReturnData)?
+        );
+        require!(
+            last_update_slot == 0 || current_slot.saturating_sub(last_update_slot) <= max_staleness,
+            MorphoError::OracleStale
+        );
+        if last_update_slot != 0 {
+            published_slot = last_update_slot;
+        }
+    }
+
+    Ok(OraclePrice { price, published_slot, confidence_bps: 0 })
 }
 
 // ============================================================================
@@ -215,10 +1075,40 @@ pub fn is_liquidatable(
     Ok(borrowed > max_borrow)
 }
 
+/// Require a position to be liquidatable at `lltv`, else `PositionHealthy`
+///
+/// The gate every liquidation path runs before moving any tokens.
+pub fn ensure_liquidatable(
+    collateral: u128,
+    borrow_shares: u128,
+    total_borrow_assets: u128,
+    total_borrow_shares: u128,
+    oracle_price: u128,
+    lltv: u64,
+) -> Result<()> {
+    require!(
+        is_liquidatable(
+            collateral,
+            borrow_shares,
+            total_borrow_assets,
+            total_borrow_shares,
+            oracle_price,
+            lltv,
+        )?,
+        MorphoError::PositionHealthy
+    );
+    Ok(())
+}
+
 /// Calculate health factor (scaled by WAD)
 /// 
-/// health > WAD means healthy
-/// health <= WAD means liquidatable
+/// health >= WAD means healthy
+/// health < WAD means liquidatable
+///
+/// Matches `is_liquidatable` exactly when `borrowed` is rounded up like it
+/// is there: flooring max_borrow * WAD / borrowed yields WAD or more iff
+/// borrowed <= max_borrow, so a position exactly at the LLTV is healthy
+/// under both.
 pub fn health_factor(
     collateral: u128,
     borrowed: u128,
@@ -236,40 +1126,131 @@ pub fn health_factor(
     mul_div_down(max_borrow, WAD, borrowed)
 }
 
+/// Extra debt a position can take on before the borrow guard rejects it
+///
+/// One unit is held back: the new borrow's shares round up, so its debt
+/// can exceed the requested assets by up to one unit.
+pub fn max_additional_borrow(
+    collateral: u128,
+    borrowed: u128,
+    oracle_price: u128,
+    lltv: u64,
+) -> Result<u128> {
+    let collateral_value = mul_div_down(collateral, oracle_price, ORACLE_SCALE)?;
+    let max_borrow = mul_div_down(collateral_value, lltv as u128, BPS as u128)?;
+    Ok(max_borrow.saturating_sub(borrowed).saturating_sub(1))
+}
+
+/// Collateral that can be withdrawn while keeping `is_liquidatable` false
+///
+/// Inverts the guard's rounding exactly: the smallest collateral c with
+/// floor(floor(c·price / SCALE)·lltv / BPS) >= borrowed must stay behind.
+pub fn max_withdrawable_collateral(
+    collateral: u128,
+    borrowed: u128,
+    oracle_price: u128,
+    lltv: u64,
+) -> Result<u128> {
+    if borrowed == 0 {
+        return Ok(collateral);
+    }
+    let min_value = mul_div_up(borrowed, BPS as u128, lltv as u128)?;
+    let min_collateral = mul_div_up(min_value, ORACLE_SCALE, oracle_price)?;
+    Ok(collateral.saturating_sub(min_collateral))
+}
+
 /// Calculate Liquidation Incentive Factor (LIF)
 /// 
 /// LIF = min(maxLIF, 1 / (1 - cursor * (1 - LLTV/BPS)))
 /// 
 /// Higher LLTV = lower LIF (less incentive needed)
 /// Lower LLTV = higher LIF (more buffer, more incentive)
-pub fn calculate_lif(lltv: u64) -> u64 {
-    use crate::constants::{MAX_LIF, LIF_CURSOR, LIF_BPS};
-    
+/// 
+/// `lif_cursor` and `max_lif` are the market's curve (see `market_lif_curve`).
+pub fn calculate_lif(lltv: u64, lif_cursor: u64, max_lif: u64) -> Result<u64> {
+    use crate::constants::LIF_BPS;
+
     // (1 - LLTV/BPS) in basis points = (BPS - lltv)
-    let one_minus_lltv = BPS.saturating_sub(lltv);
+    let one_minus_lltv = checked_sub(BPS as u128, lltv as u128)?;
 
     // cursor * (1 - LLTV) / BPS
-    let cursor_term = (LIF_CURSOR as u128)
-        .checked_mul(one_minus_lltv as u128)
-        .unwrap_or(0)
-        .checked_div(LIF_BPS as u128)
-        .unwrap_or(0) as u64;
+    let cursor_term = mul_div_down(lif_cursor as u128, one_minus_lltv, LIF_BPS as u128)?;
 
     // 1 - cursor_term (in BPS)
-    let denominator = LIF_BPS.saturating_sub(cursor_term);
+    let denominator = checked_sub(LIF_BPS as u128, cursor_term)?;
+
+    // BPS * BPS / denominator (scaled result)
+    let lif = mul_div_down(LIF_BPS as u128, LIF_BPS as u128, denominator)?;
+
+    Ok(std::cmp::min(lif, max_lif as u128) as u64)
+}
 
-    if denominator == 0 {
-        return MAX_LIF;
+/// Effective LIF curve of a market as (cursor, max LIF)
+/// 
+/// Zero fields, including those of markets created before the curve was
+/// configurable, fall back to LIF_CURSOR and MAX_LIF.
+pub fn market_lif_curve(market: &Market) -> (u64, u64) {
+    use crate::constants::{MAX_LIF, LIF_CURSOR};
+
+    let lif_cursor = if market.lif_cursor == 0 { LIF_CURSOR } else { market.lif_cursor };
+    let max_lif = if market.max_lif == 0 { MAX_LIF } else { market.max_lif };
+    (lif_cursor, max_lif)
+}
+
+/// Check a market's LIF curve: each field unset (0) or within bounds
+/// 
+/// Any cursor below 100% keeps `lltv * lif` under 100%, so a liquidation
+/// never seizes more value than the debt it repays is backed by.
+pub fn validate_lif_curve(lif_cursor: u64, max_lif: u64) -> Result<()> {
+    use crate::constants::{LIF_BPS, MAX_LIF_CURSOR, MAX_LIF_CAP};
+
+    require!(lif_cursor <= MAX_LIF_CURSOR, MorphoError::InvalidInput);
+    require!(
+        max_lif == 0 || (LIF_BPS..=MAX_LIF_CAP).contains(&max_lif),
+        MorphoError::InvalidInput
+    );
+    Ok(())
+}
+
+/// Liquidation Incentive Factor of a Dutch auction
+///
+/// Ramps linearly from LIF_BPS (no bonus) at the start of the auction to
+/// `max_lif` once `duration_slots` have elapsed, so a liquidator takes the
+/// position at the smallest bonus that is worth their while.
+pub fn auction_lif(elapsed_slots: u64, duration_slots: u64, max_lif: u64) -> u64 {
+    use crate::constants::LIF_BPS;
+
+    if duration_slots == 0 || elapsed_slots >= duration_slots {
+        return max_lif;
     }
 
-    // BPS * BPS / denominator (scaled result)
-    let lif = (LIF_BPS as u128)
-        .checked_mul(LIF_BPS as u128)
-        .unwrap_or(u128::MAX)
-        .checked_div(denominator as u128)
-        .unwrap_or(u128::MAX) as u64;
+    let ramp = ((max_lif - LIF_BPS) as u128) * (elapsed_slots as u128) / (duration_slots as u128);
+    LIF_BPS + ramp as u64
+}
 
-    std::cmp::min(lif, MAX_LIF)
+/// Maximum loan assets a single liquidation may repay
+/// 
+/// Capped at `close_factor_bps` of the position's debt (rounded up), unless
+/// the position is deeply underwater (health below FULL_LIQUIDATION_HEALTH),
+/// in which case the whole debt can be repaid. A zero close factor uses
+/// DEFAULT_CLOSE_FACTOR_BPS.
+pub fn max_liquidation_repay(
+    total_debt: u128,
+    health: u128,
+    close_factor_bps: u64,
+) -> Result<u128> {
+    use crate::constants::{DEFAULT_CLOSE_FACTOR_BPS, FULL_LIQUIDATION_HEALTH};
+
+    if health < FULL_LIQUIDATION_HEALTH {
+        return Ok(total_debt);
+    }
+
+    let close_factor = if close_factor_bps == 0 {
+        DEFAULT_CLOSE_FACTOR_BPS
+    } else {
+        close_factor_bps
+    };
+    mul_div_up(total_debt, close_factor as u128, BPS as u128)
 }
 
 /// Calculate seized collateral for liquidation
@@ -297,12 +1278,72 @@ pub fn calculate_seized_collateral(
     )
 }
 
+/// Repay owed for seizing exactly `seized_collateral` (inverse of
+/// `calculate_seized_collateral`)
+///
+/// repaid = seized * LIF_BPS / LIF * ORACLE_SCALE / oracle_price, rounded up
+/// at each step so the liquidator never underpays for the collateral.
+pub fn calculate_repaid_assets(
+    seized_collateral: u128,
+    oracle_price: u128,
+    lif: u64,
+) -> Result<u128> {
+    use crate::constants::LIF_BPS;
+
+    let collateral_value = mul_div_up(seized_collateral, LIF_BPS as u128, lif as u128)?;
+    mul_div_up(collateral_value, ORACLE_SCALE, oracle_price)
+}
+
+/// Reject a liquidation seizing more than `repaid_assets` is worth at
+/// `lif` plus OVER_SEIZURE_TOLERANCE_BPS
+///
+/// Checked on the final quote, after share rounding and clamping, so it
+/// holds whichever way the seizure was derived. `repaid_assets` must be
+/// the debt actually removed from the position, not the amount requested.
+pub fn check_seizure(
+    seized_collateral: u128,
+    repaid_assets: u128,
+    oracle_price: u128,
+    lif: u64,
+) -> Result<()> {
+    use crate::constants::OVER_SEIZURE_TOLERANCE_BPS;
+
+    let max_seize_multiple = lif.saturating_add(OVER_SEIZURE_TOLERANCE_BPS);
+    require!(
+        seized_collateral <= calculate_seized_collateral(repaid_assets, oracle_price, max_seize_multiple)?,
+        MorphoError::OverSeizure
+    );
+    Ok(())
+}
+
+/// Protocol's cut of a liquidation's seized collateral
+///
+/// The bonus is the collateral seized beyond the repaid debt's value (the
+/// LIF premium). `fee_bps` of it, rounded down, goes to the protocol and the
+/// rest stays with the liquidator.
+pub fn liquidation_protocol_fee(
+    seized_collateral: u128,
+    repaid_assets: u128,
+    oracle_price: u128,
+    fee_bps: u64,
+) -> Result<u128> {
+    use crate::constants::LIF_BPS;
+
+    if fee_bps == 0 {
+        return Ok(0);
+    }
+    let repaid_collateral = calculate_seized_collateral(repaid_assets, oracle_price, LIF_BPS)?;
+    let bonus = seized_collateral.saturating_sub(repaid_collateral);
+    mul_div_down(bonus, fee_bps as u128, BPS as u128)
+}
+
 /// Socialize bad debt across all suppliers
 /// 
 /// Called when liquidation leaves position with debt but no collateral.
+/// The market's reserve covers the loss first (see `charge_bad_debt`).
 /// 
 /// # Returns
-/// The amount of bad debt socialized
+/// The amount of bad debt written off
 pub fn socialize_bad_debt(
     market: &mut Market,
     remaining_borrow_shares: u128,
@@ -322,11 +1363,612 @@ pub fn socialize_bad_debt(
     market.total_borrow_shares = market.total_borrow_shares.saturating_sub(remaining_borrow_shares);
     market.total_borrow_assets = market.total_borrow_assets.saturating_sub(bad_debt);
 
-    // Remove from supply side (socializes loss)
-    market.total_supply_assets = market.total_supply_assets.saturating_sub(bad_debt);
+    // Remove from the reserve, then the supply side (socializes loss)
+    charge_bad_debt(market, bad_debt);
 
     // Note: total_supply_shares stays the same
-    // Each share is now worth slightly less
+    // Each share is now worth slightly less. Protocol fee shares (claimed or
+    // still pending) are part of total_supply_shares, so the fee recipient
+    // absorbs the same proportional loss as every other supplier.
 
     Ok(bad_debt)
 }
+
+/// Write a liquidated position's leftover debt off once its collateral is gone
+///
+/// Does nothing (returns None) while the position still has collateral or
+/// has no debt left. Otherwise clears its `borrow_shares`, removes the debt
+/// from the borrow side and adds it to `pending_bad_debt`, returning the
+/// written-off (assets, shares). Suppliers are charged separately by
+/// `socialize_pending_bad_debt`.
+pub fn write_off_bad_debt(market: &mut Market, position: &mut Position) -> Result<Option<(u128, u128)>> {
+    if position.collateral > 0 || position.borrow_shares == 0 {
+        return Ok(None);
+    }
+
+    let bad_debt_shares = position.borrow_shares;
+    let bad_debt_assets = to_assets_up(
+        bad_debt_shares,
+        market.total_borrow_assets,
+        market.total_borrow_shares,
+    )?;
+    market.total_borrow_shares = market.total_borrow_shares.saturating_sub(bad_debt_shares);
+    market.total_borrow_assets = market.total_borrow_assets.saturating_sub(bad_debt_assets);
+    market.pending_bad_debt = checked_add(market.pending_bad_debt, bad_debt_assets)?;
+    position.borrow_shares = 0;
+
+    Ok(Some((bad_debt_assets, bad_debt_shares)))
+}
+
+/// Whether burning `burn_shares` clears the debt of a position with no
+/// collateral left, i.e. debt `write_off_bad_debt` would otherwise charge
+/// to suppliers
+pub fn repay_backstops_position(position: &Position, burn_shares: u128) -> bool {
+    position.collateral == 0 && position.borrow_shares > 0 && burn_shares == position.borrow_shares
+}
+
+/// Charge `pending_bad_debt` to the reserve, then suppliers
+///
+/// Only once it reaches `bad_debt_threshold`, unless `force` (the public
+/// crank). Returns the `BadDebtSocialized` event to emit, or None when
+/// nothing was charged.
+pub fn socialize_pending_bad_debt(market: &mut Market, force: bool) -> Result<Option<BadDebtSocialized>> {
+    let pending = market.pending_bad_debt;
+    if pending == 0 || (!force && pending < market.bad_debt_threshold) {
+        return Ok(None);
+    }
+
+    let share_price_before = supply_share_price(market)?;
+    let reserve_assets_used = charge_bad_debt(market, pending);
+    market.pending_bad_debt = 0;
+
+    Ok(Some(BadDebtSocialized {
+        market_id: market.market_id,
+        bad_debt_assets: pending,
+        reserve_assets_used,
+        share_price_before,
+        share_price_after: supply_share_price(market)?,
+    }))
+}
+
+/// Charge `bad_debt` to the market's reserve, and what it cannot cover to
+/// suppliers through `total_supply_assets`
+///
+/// Returns the part taken from `reserve_assets`.
+pub fn charge_bad_debt(market: &mut Market, bad_debt: u128) -> u128 {
+    let from_reserve = std::cmp::min(bad_debt, market.reserve_assets);
+    market.reserve_assets -= from_reserve;
+    market.total_supply_assets = market.total_supply_assets.saturating_sub(bad_debt - from_reserve);
+    from_reserve
+}
+
+/// Value of one supply share in loan token units, scaled by WAD
+/// (includes the virtual offset, like every share conversion)
+pub fn supply_share_price(market: &Market) -> Result<u128> {
+    mul_div_down(
+        market.total_supply_assets.saturating_add(VIRTUAL_ASSETS),
+        WAD,
+        market.total_supply_shares.saturating_add(VIRTUAL_SHARES),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PYTH_FEED_ID: [u8; 32] = [7u8; 32];
+
+    fn pyth_update_data(price: i64, conf: u64, exponent: i32, publish_time: i64, posted_slot: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&PYTH_PRICE_UPDATE_V2_DISCRIMINATOR);
+        data.extend_from_slice(&[0u8; 32]); // write_authority
+        data.push(1); // VerificationLevel::Full
+        data.extend_from_slice(&PYTH_FEED_ID);
+        data.extend_from_slice(&price.to_le_bytes());
+        data.extend_from_slice(&conf.to_le_bytes());
+        data.extend_from_slice(&exponent.to_le_bytes());
+        data.extend_from_slice(&publish_time.to_le_bytes());
+        data.extend_from_slice(&0i64.to_le_bytes()); // prev_publish_time
+        data.extend_from_slice(&0i64.to_le_bytes()); // ema_price
+        data.extend_from_slice(&0u64.to_le_bytes()); // ema_conf
+        data.extend_from_slice(&posted_slot.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_decimal_to_oracle_scale_positive() {
+        // 1.5 -> 1.5e36
+        let price = decimal_to_oracle_scale(&Decimal::new(15, 1)).unwrap();
+        assert_eq!(price, ORACLE_SCALE + ORACLE_SCALE / 2);
+    }
+
+    #[test]
+    fn test_decimal_to_oracle_scale_extreme_scales() {
+        // Scale 0: whole units, overflowing rather than panicking past ~340
+        let price = decimal_to_oracle_scale(&Decimal::new(200, 0)).unwrap();
+        assert_eq!(price, 200 * ORACLE_SCALE);
+        assert_eq!(
+            decimal_to_oracle_scale(&Decimal::new(2000, 0)).unwrap_err(),
+            MorphoError::MathOverflow.into()
+        );
+
+        // Decimal caps its own scale at 28, so wider scales go through the helper
+        assert_eq!(scaled_to_oracle_scale(1_000, 38).unwrap(), 10);
+        assert_eq!(
+            scaled_to_oracle_scale(1_000, 40).unwrap_err(),
+            MorphoError::MathOverflow.into()
+        );
+        assert_eq!(
+            scaled_to_oracle_scale(u128::MAX, 0).unwrap_err(),
+            MorphoError::MathOverflow.into()
+        );
+    }
+
+    #[test]
+    fn test_decimal_to_oracle_scale_rejects_negative() {
+        let result = decimal_to_oracle_scale(&Decimal::new(-2000, 0));
+        assert_eq!(result.unwrap_err(), MorphoError::OraclePriceTooLow.into());
+    }
+
+    #[test]
+    fn test_parse_pyth_price_update() {
+        let data = pyth_update_data(200_000_000_000, 50_000_000, -8, 1_700_000_000, 1234);
+        let update = parse_pyth_price_update(&data).unwrap();
+
+        assert_eq!(update.price, 200_000_000_000);
+        assert_eq!(update.conf, 50_000_000);
+        assert_eq!(update.exponent, -8);
+        assert_eq!(update.feed_id, PYTH_FEED_ID);
+        assert_eq!(update.publish_time, 1_700_000_000);
+        assert_eq!(update.posted_slot, 1234);
+    }
+
+    #[test]
+    fn test_parse_pyth_rejects_partial_verification() {
+        let mut data = pyth_update_data(200_000_000_000, 50_000_000, -8, 1_700_000_000, 1234);
+        data[40] = 0; // VerificationLevel::Partial
+        assert!(parse_pyth_price_update(&data).is_err());
+    }
+
+    fn pyth_limits(pyth_feed_id: [u8; 32]) -> FeedLimits {
+        FeedLimits {
+            max_staleness_slots: MAX_ORACLE_STALENESS,
+            min_samples: MIN_ORACLE_SAMPLES,
+            max_confidence_bps: MAX_ORACLE_CONFIDENCE_BPS,
+            max_std_bps: MAX_ORACLE_STD_BPS,
+            pyth_feed_id,
+        }
+    }
+
+    #[test]
+    fn test_pyth_update_must_carry_pinned_feed() {
+        let data = pyth_update_data(200_000_000_000, 50_000_000, -8, 1_700_000_000, 1234);
+        let update = parse_pyth_price_update(&data).unwrap();
+        let clock = Clock { slot: 1234, unix_timestamp: 1_700_000_000, ..Clock::default() };
+
+        assert!(check_pyth_update(&update, &pyth_limits(PYTH_FEED_ID), &clock).is_ok());
+        assert_eq!(
+            check_pyth_update(&update, &pyth_limits([8u8; 32]), &clock).unwrap_err(),
+            MorphoError::InvalidOracle.into()
+        );
+        // An unpinned market accepts no feed at all
+        assert_eq!(
+            check_pyth_update(&update, &pyth_limits([0u8; 32]), &clock).unwrap_err(),
+            MorphoError::InvalidOracle.into()
+        );
+    }
+
+    #[test]
+    fn test_pyth_staleness_uses_publish_time() {
+        // 50 slots at 400ms = 20s
+        let limits = pyth_limits(PYTH_FEED_ID);
+        let now = 1_700_000_000;
+        let clock = Clock { slot: 5_000, unix_timestamp: now, ..Clock::default() };
+
+        let fresh = parse_pyth_price_update(
+            &pyth_update_data(200_000_000_000, 50_000_000, -8, now - 20, 1)
+        ).unwrap();
+        assert!(check_pyth_update(&fresh, &limits, &clock).is_ok(), "Slot age alone must not make it stale");
+
+        // Posted this slot, but published 21s ago
+        let replayed = parse_pyth_price_update(
+            &pyth_update_data(200_000_000_000, 50_000_000, -8, now - 21, 5_000)
+        ).unwrap();
+        assert_eq!(
+            check_pyth_update(&replayed, &limits, &clock).unwrap_err(),
+            MorphoError::OracleStale.into()
+        );
+    }
+
+    #[test]
+    fn test_validate_pyth_feed_id() {
+        assert!(validate_pyth_feed_id(OracleKind::Pyth, &PYTH_FEED_ID).is_ok());
+        assert!(validate_pyth_feed_id(OracleKind::Pyth, &[0u8; 32]).is_err());
+        assert!(validate_pyth_feed_id(OracleKind::Switchboard, &[0u8; 32]).is_ok());
+        assert!(validate_pyth_feed_id(OracleKind::Switchboard, &PYTH_FEED_ID).is_err());
+    }
+
+    #[test]
+    fn test_max_liquidation_repay() {
+        use crate::constants::FULL_LIQUIDATION_HEALTH;
+        let debt = 1_000_001u128;
+
+        // Slightly unhealthy: default 50% close factor, rounded up
+        assert_eq!(max_liquidation_repay(debt, WAD - 1, 0).unwrap(), 500_001);
+        assert_eq!(max_liquidation_repay(debt, WAD - 1, 2_500).unwrap(), 250_001);
+        assert_eq!(max_liquidation_repay(debt, FULL_LIQUIDATION_HEALTH, 0).unwrap(), 500_001);
+
+        // Deeply underwater: full liquidation allowed
+        assert_eq!(max_liquidation_repay(debt, FULL_LIQUIDATION_HEALTH - 1, 0).unwrap(), debt);
+        assert_eq!(max_liquidation_repay(debt, 0, 0).unwrap(), debt);
+    }
+
+    #[test]
+    fn test_price_deviation_bps() {
+        assert_eq!(price_deviation_bps(ORACLE_SCALE, ORACLE_SCALE).unwrap(), 0);
+
+        // 10% up or down is 1000 bps either way
+        assert_eq!(price_deviation_bps(ORACLE_SCALE, ORACLE_SCALE + ORACLE_SCALE / 10).unwrap(), 1_000);
+        assert_eq!(price_deviation_bps(ORACLE_SCALE, ORACLE_SCALE - ORACLE_SCALE / 10).unwrap(), 1_000);
+
+        // Any move below one bp still registers, rounded up
+        assert_eq!(price_deviation_bps(ORACLE_SCALE, ORACLE_SCALE + 1).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_check_seizure() {
+        use crate::constants::OVER_SEIZURE_TOLERANCE_BPS;
+        let price = 2 * ORACLE_SCALE;
+        let seized = calculate_seized_collateral(1_000_000, price, 10_500).unwrap();
+        assert!(check_seizure(seized, 1_000_000, price, 10_500).is_ok());
+
+        // The tolerance absorbs rounding, not a larger bonus
+        let slack = calculate_seized_collateral(1_000_000, price, 10_500 + OVER_SEIZURE_TOLERANCE_BPS).unwrap();
+        assert!(check_seizure(slack, 1_000_000, price, 10_500).is_ok());
+        assert_eq!(
+            check_seizure(slack + 1, 1_000_000, price, 10_500).unwrap_err(),
+            MorphoError::OverSeizure.into()
+        );
+        // Nothing may be seized for no repaid debt
+        assert!(check_seizure(0, 0, price, 10_500).is_ok());
+        assert_eq!(check_seizure(1, 0, price, 10_500).unwrap_err(), MorphoError::OverSeizure.into());
+    }
+
+    #[test]
+    fn test_check_oracle_migration() {
+        // SOL/USDC in raw units; a 2% gap between feeds is accepted
+        let old = 150 * ORACLE_SCALE / 1_000;
+        assert!(check_oracle_migration(old, old).is_ok());
+        assert!(check_oracle_migration(old, old * 102 / 100).is_ok());
+        assert!(check_oracle_migration(old, old * 95 / 100).is_ok());
+
+        // Past 5% either way, or a feed off by its decimals, is rejected
+        for new in [old * 106 / 100, old * 94 / 100, old * 1_000] {
+            assert_eq!(
+                check_oracle_migration(old, new).unwrap_err(),
+                MorphoError::OracleMigrationDeviationTooHigh.into()
+            );
+        }
+    }
+
+    #[test]
+    fn test_fallback_eligible_errors() {
+        assert!(is_fallback_eligible(&MorphoError::OracleStale.into()));
+        assert!(is_fallback_eligible(&MorphoError::OracleInvalidReturnData.into()));
+        assert!(is_fallback_eligible(&MorphoError::OraclePriceTooHigh.into()));
+
+        // Misconfiguration must not be papered over by the fallback
+        assert!(!is_fallback_eligible(&MorphoError::InvalidOracle.into()));
+        assert!(!is_fallback_eligible(&MorphoError::OracleConfidenceTooWide.into()));
+    }
+
+    #[test]
+    fn test_invert_price() {
+        // 1 ETH = 2 USDC inverted is 0.5 ETH per USDC
+        assert_eq!(invert_price(2 * ORACLE_SCALE).unwrap(), ORACLE_SCALE / 2);
+        assert_eq!(invert_price(ORACLE_SCALE).unwrap(), ORACLE_SCALE);
+
+        // Inexact inversions round down
+        assert_eq!(invert_price(3 * ORACLE_SCALE).unwrap(), ORACLE_SCALE / 3);
+
+        // Tiny prices clamp to the maximum instead of overflowing
+        assert_eq!(invert_price(1).unwrap(), max_oracle_price());
+
+        assert_eq!(invert_price(0).unwrap_err(), MorphoError::OracleZeroPrice.into());
+    }
+
+    #[test]
+    fn test_normalize_decimals() {
+        // SOL (9 decimals) priced at 150 USDC (6 decimals):
+        // 1 lamport is worth 150e-3 raw USDC units
+        let price = 150 * ORACLE_SCALE;
+        assert_eq!(normalize_decimals(price, 9, 6).unwrap(), 150 * ORACLE_SCALE / 1_000);
+        assert_eq!(normalize_decimals(ORACLE_SCALE / 10, 6, 9).unwrap(), 100 * ORACLE_SCALE);
+        assert_eq!(normalize_decimals(price, 6, 6).unwrap(), price);
+
+        // Gaps too large to represent
+        assert_eq!(normalize_decimals(price, 0, 39).unwrap_err(), MorphoError::MathOverflow.into());
+        assert_eq!(normalize_decimals(price, 0, 3).unwrap_err(), MorphoError::MathOverflow.into());
+        assert_eq!(normalize_decimals(1, 39, 0).unwrap_err(), MorphoError::MathOverflow.into());
+        assert_eq!(normalize_decimals(ORACLE_SCALE, 37, 0).unwrap_err(), MorphoError::MathUnderflow.into());
+    }
+
+    fn price_scaling(invert: bool, normalize: bool) -> PriceScaling {
+        PriceScaling {
+            collateral_decimals: 9,
+            loan_decimals: 6,
+            invert,
+            normalize,
+            max_price: max_oracle_price(),
+        }
+    }
+
+    #[test]
+    fn test_scale_price() {
+        let sol_usdc = 150 * ORACLE_SCALE;
+        assert_eq!(scale_price(sol_usdc, &price_scaling(false, true)).unwrap(), sol_usdc / 1_000);
+        assert_eq!(scale_price(sol_usdc, &price_scaling(false, false)).unwrap(), sol_usdc);
+
+        // Inversion comes before normalization
+        let inverted = invert_price(sol_usdc).unwrap();
+        assert_eq!(
+            scale_price(sol_usdc, &price_scaling(true, true)).unwrap(),
+            normalize_decimals(inverted, 9, 6).unwrap()
+        );
+
+        // The cap applies to the final price
+        let capped = PriceScaling { max_price: sol_usdc / 1_000 - 1, ..price_scaling(false, true) };
+        assert_eq!(scale_price(sol_usdc, &capped).unwrap_err(), MorphoError::OraclePriceTooHigh.into());
+    }
+
+    #[test]
+    fn test_price_scaling_of_market() {
+        let mut market: Market = bytemuck::Zeroable::zeroed();
+        market.collateral_decimals = 9;
+        market.loan_decimals = 6;
+        market.set_oracle_inverted(true);
+        market.max_price = 2 * ORACLE_SCALE;
+
+        let scaling = PriceScaling::of(&market).unwrap();
+        assert_eq!(scaling, PriceScaling { max_price: 2 * ORACLE_SCALE, ..price_scaling(true, true) });
+
+        market.oracle_kind = OracleKind::Static as u8;
+        assert!(!PriceScaling::of(&market).unwrap().normalize);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_scale_and_validate_matches_on_chain_path() {
+        let scaling = price_scaling(false, true);
+        for decimal in [Decimal::new(15_025, 2), Decimal::new(1, 6), Decimal::new(987_654_321, 7)] {
+            let feed_price = decimal_to_oracle_scale(&decimal).unwrap();
+            check_price_bounds(feed_price).unwrap();
+            assert_eq!(
+                scale_and_validate(&decimal, &scaling).unwrap(),
+                scale_price(feed_price, &scaling).unwrap()
+            );
+        }
+
+        assert_eq!(
+            scale_and_validate(&Decimal::new(-1, 0), &scaling).unwrap_err(),
+            MorphoError::OraclePriceTooLow.into()
+        );
+        assert_eq!(
+            scale_and_validate(&Decimal::new(2_000_000_000, 0), &scaling).unwrap_err(),
+            MorphoError::MathOverflow.into()
+        );
+    }
+
+    #[test]
+    fn test_validate_max_staleness_slots() {
+        assert!(validate_max_staleness_slots(MIN_STALENESS_SLOTS).is_ok());
+        assert!(validate_max_staleness_slots(MAX_STALENESS_SLOTS).is_ok());
+        assert_eq!(
+            validate_max_staleness_slots(0).unwrap_err(),
+            MorphoError::InvalidOracleStaleness.into()
+        );
+        assert!(validate_max_staleness_slots(MAX_STALENESS_SLOTS + 1).is_err());
+    }
+
+    #[test]
+    fn test_min_oracle_samples() {
+        assert!(validate_min_oracle_samples(1).is_ok());
+        assert!(validate_min_oracle_samples(5).is_ok());
+        assert_eq!(
+            validate_min_oracle_samples(0).unwrap_err(),
+            MorphoError::InvalidInput.into()
+        );
+    }
+
+    #[test]
+    fn test_check_std_dev() {
+        let price = 200 * ORACLE_SCALE;
+        // 1% of 200 = 2
+        assert!(check_std_dev(price, 2 * ORACLE_SCALE, MAX_ORACLE_STD_BPS).is_ok());
+        assert_eq!(
+            check_std_dev(price, 2 * ORACLE_SCALE + 1, MAX_ORACLE_STD_BPS).unwrap_err(),
+            MorphoError::OracleDeviationTooHigh.into()
+        );
+        assert!(check_std_dev(price, 0, 1).is_ok());
+        assert!(check_std_dev(price, 10 * ORACLE_SCALE, 500).is_ok());
+    }
+
+    #[test]
+    fn test_validate_max_price() {
+        assert!(validate_max_price(0).is_ok());
+        assert!(validate_max_price(MIN_ORACLE_PRICE).is_ok());
+        assert!(validate_max_price(ORACLE_SCALE).is_ok());
+        assert_eq!(
+            validate_max_price(MIN_ORACLE_PRICE - 1).unwrap_err(),
+            MorphoError::InvalidInput.into()
+        );
+    }
+
+    #[test]
+    fn test_check_price_plausible() {
+        // SOL (9 decimals) at 150 USDC (6 decimals), in raw units
+        assert!(check_price_plausible(150 * ORACLE_SCALE / 1_000, 9, 6).is_ok());
+        assert!(check_price_plausible(ORACLE_SCALE, 6, 6).is_ok());
+
+        // Raw prices are judged by the whole-token quote they came from
+        assert!(check_price_plausible(MIN_ORACLE_PRICE / 1_000, 9, 6).is_ok());
+        assert_eq!(
+            check_price_plausible(MIN_ORACLE_PRICE / 10_000, 9, 6).unwrap_err(),
+            MorphoError::OraclePriceImplausible.into()
+        );
+        assert_eq!(
+            check_price_plausible(MIN_ORACLE_PRICE - 1, 6, 6).unwrap_err(),
+            MorphoError::OraclePriceImplausible.into()
+        );
+        // Decimal gaps that cannot be undone are rejected, not overflowed
+        assert_eq!(
+            check_price_plausible(u128::MAX, 18, 0).unwrap_err(),
+            MorphoError::OraclePriceImplausible.into()
+        );
+    }
+
+    #[test]
+    fn test_check_price_off_boundary() {
+        assert!(check_price_off_boundary(150 * ORACLE_SCALE / 1_000, 9, 6).is_ok());
+        assert!(check_price_off_boundary(MIN_ORACLE_PRICE + 1, 6, 6).is_ok());
+        assert!(check_price_off_boundary(max_oracle_price() - 1, 6, 6).is_ok());
+
+        for price in [MIN_ORACLE_PRICE, max_oracle_price()] {
+            assert_eq!(
+                check_price_off_boundary(price, 6, 6).unwrap_err(),
+                MorphoError::OracleAtBoundary.into()
+            );
+        }
+        // Compared on the whole-token quote, so decimals are undone first
+        assert_eq!(
+            check_price_off_boundary(MIN_ORACLE_PRICE / 1_000, 9, 6).unwrap_err(),
+            MorphoError::OracleAtBoundary.into()
+        );
+        // An inverted near-zero feed saturates at the maximum
+        assert_eq!(
+            check_price_off_boundary(invert_price(1).unwrap(), 6, 6).unwrap_err(),
+            MorphoError::OracleAtBoundary.into()
+        );
+    }
+
+    #[test]
+    fn test_static_oracle_staleness() {
+        let mut data = vec![0u8; StaticOracle::space()];
+        data[..8].copy_from_slice(StaticOracle::DISCRIMINATOR);
+        data[9..25].copy_from_slice(&ORACLE_SCALE.to_le_bytes());
+
+        // Zero slot and pre-field accounts are never stale
+        assert_eq!(parse_static_oracle_price(&data, 1_000_000, 50).unwrap().price, ORACLE_SCALE);
+        assert!(parse_static_oracle_price(&data[..57], 1_000_000, 50).is_ok());
+        assert_eq!(parse_static_oracle_price(&data, 1_000_000, 50).unwrap().published_slot, 1_000_000);
+
+        data[57..65].copy_from_slice(&100u64.to_le_bytes());
+        let read = parse_static_oracle_price(&data, 150, 50).unwrap();
+        assert_eq!(read, OraclePrice { price: ORACLE_SCALE, published_slot: 100, confidence_bps: 0 });
+        assert_eq!(
+            parse_static_oracle_price(&data, 151, 50).unwrap_err(),
+            MorphoError::OracleStale.into()
+        );
+
+        // Any other account with a price-shaped payload is refused
+        data[..8].copy_from_slice(&[0u8; 8]);
+        assert_eq!(
+            parse_static_oracle_price(&data, 150, 50).unwrap_err(),
+            MorphoError::OracleInvalidReturnData.into()
+        );
+    }
+
+    #[test]
+    fn test_confidence_bps() {
+        assert_eq!(confidence_bps(0, ORACLE_SCALE), 0);
+        assert_eq!(confidence_bps(ORACLE_SCALE / 100, ORACLE_SCALE), 100);
+        // Rounds up, so any spread reports at least 1 bp
+        assert_eq!(confidence_bps(1, ORACLE_SCALE), 1);
+        // Spreads far wider than the price saturate
+        assert_eq!(confidence_bps(u128::MAX, 1), u64::MAX);
+    }
+
+    #[test]
+    fn test_compose_prices() {
+        // SOL = $150, USDC = $1 -> 150 USDC per SOL
+        let sol_usd = 150 * ORACLE_SCALE;
+        let usdc_usd = ORACLE_SCALE;
+        assert_eq!(compose_prices(sol_usd, usdc_usd).unwrap(), 150 * ORACLE_SCALE);
+
+        // $1 collateral against a $3 loan token rounds down
+        let composed = compose_prices(ORACLE_SCALE, 3 * ORACLE_SCALE).unwrap();
+        assert_eq!(composed, ORACLE_SCALE / 3);
+        assert!(composed * 3 <= ORACLE_SCALE);
+
+        assert!(compose_prices(sol_usd, 0).is_err());
+    }
+
+    #[test]
+    fn test_pyth_to_oracle_scale() {
+        // 150.00000000 with exponent -8 -> 150 * 1e36
+        let price = pyth_to_oracle_scale(15_000_000_000, -8).unwrap();
+        assert_eq!(price, 150 * ORACLE_SCALE);
+
+        // Positive exponent
+        let price = pyth_to_oracle_scale(15, 1).unwrap();
+        assert_eq!(price, 150 * ORACLE_SCALE);
+
+        // Overflowing exponent errors instead of panicking
+        assert!(pyth_to_oracle_scale(1, 10).is_err());
+    }
+
+    #[test]
+    fn test_position_limits_match_guards() {
+        let price = 2 * ORACLE_SCALE;
+        let lltv = 8000;
+
+        // 1_000 collateral backs up to 1_600; one unit is held back for rounding
+        assert_eq!(max_additional_borrow(1_000, 0, price, lltv).unwrap(), 1_599);
+        assert_eq!(max_additional_borrow(1_000, 1_600, price, lltv).unwrap(), 0);
+        assert_eq!(max_additional_borrow(1_000, 2_000, price, lltv).unwrap(), 0);
+
+        // Withdrawing the reported maximum stays healthy, one more does not
+        let shares = 1_600 * crate::constants::VIRTUAL_SHARES;
+        let max = max_withdrawable_collateral(5_000, 1_600, price, lltv).unwrap();
+        assert_eq!(max, 4_000);
+        assert!(!is_liquidatable(5_000 - max, shares, 1_600, shares, price, lltv).unwrap());
+        assert!(is_liquidatable(5_000 - max - 1, shares, 1_600, shares, price, lltv).unwrap());
+
+        // Debt-free positions can take everything; underwater ones nothing
+        assert_eq!(max_withdrawable_collateral(5_000, 0, price, lltv).unwrap(), 5_000);
+        assert_eq!(max_withdrawable_collateral(500, 1_600, price, lltv).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_auction_lif_ramps_to_max() {
+        use crate::constants::{MAX_LIF, LIF_BPS};
+
+        assert_eq!(auction_lif(0, 1_000, MAX_LIF), LIF_BPS, "No bonus at the moment of breach");
+        assert_eq!(auction_lif(500, 1_000, MAX_LIF), (LIF_BPS + MAX_LIF) / 2);
+        assert_eq!(auction_lif(1_000, 1_000, MAX_LIF), MAX_LIF);
+        assert_eq!(auction_lif(5_000, 1_000, MAX_LIF), MAX_LIF, "Capped at MAX_LIF");
+
+        let mut last = 0;
+        for elapsed in (0..=1_000).step_by(50) {
+            let lif = auction_lif(elapsed, 1_000, MAX_LIF);
+            assert!(lif >= last, "Monotonic in elapsed slots");
+            last = lif;
+        }
+
+        // A market-configured cap moves the end of the ramp
+        assert_eq!(auction_lif(1_000, 1_000, 12_000), 12_000);
+        assert_eq!(auction_lif(500, 1_000, 12_000), 11_000);
+    }
+
+    #[test]
+    fn test_validate_lif_curve() {
+        use crate::constants::{LIF_BPS, MAX_LIF_CURSOR, MAX_LIF_CAP};
+
+        assert!(validate_lif_curve(0, 0).is_ok(), "Defaults");
+        assert!(validate_lif_curve(MAX_LIF_CURSOR, MAX_LIF_CAP).is_ok());
+        assert!(validate_lif_curve(0, LIF_BPS).is_ok(), "No bonus is allowed");
+        assert!(validate_lif_curve(MAX_LIF_CURSOR + 1, 0).is_err());
+        assert!(validate_lif_curve(0, LIF_BPS - 1).is_err());
+        assert!(validate_lif_curve(0, MAX_LIF_CAP + 1).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! Token program interface (SPL Token and Token-2022)
+//!
+//! Transfers go through `token_interface::transfer_checked`, which works with
+//! either program as long as the program account passed matches the mint's
+//! owner. Markets whose mints live under different programs pass a separate
+//! `collateral_token_program` alongside the loan-side `token_program`.
+//!
+//! Mints with a `TransferFeeConfig` deliver less than the amount sent, so
+//! deposits are credited on the net amount and repayments are grossed up
+//! until the vault receives exactly what is owed.
+//!
+//! For wrapped SOL markets, `wrap_native` / `unwrap_native` let users move
+//! native lamports through a WSOL token account they own. The account is
+//! closed at the end, returning its rent to the user.
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{self, Transfer};
+use anchor_spl::token::spl_token;
+use anchor_spl::token_interface::{
+    close_account, sync_native, CloseAccount, SyncNative, TokenAccount, TokenInterface,
+};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{
+        default_account_state::DefaultAccountState,
+        transfer_fee::TransferFeeConfig,
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    state::{AccountState, Mint},
+};
+use crate::errors::MorphoError;
+
+/// Reject Token-2022 mints whose extensions break vault accounting
+///
+/// - `NonTransferable`: tokens could never leave the vaults
+/// - `DefaultAccountState` = Frozen: vaults would be created frozen
+///
+/// Legacy SPL Token mints have no extensions and always pass.
+pub fn validate_mint_extensions(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<Mint>::unpack(&data)
+        .map_err(|_| MorphoError::InvalidMint)?;
+    check_mint_extensions(&state)
+}
+
+fn check_mint_extensions<S: BaseStateWithExtensions<Mint>>(state: &S) -> Result<()> {
+    let extensions = state
+        .get_extension_types()
+        .map_err(|_| MorphoError::InvalidMint)?;
+
+    require!(
+        !extensions.contains(&ExtensionType::NonTransferable),
+        MorphoError::UnsupportedMintExtension
+    );
+
+    if let Ok(default_state) = state.get_extension::<DefaultAccountState>() {
+        require!(
+            default_state.state != AccountState::Frozen as u8,
+            MorphoError::UnsupportedMintExtension
+        );
+    }
+
+    Ok(())
+}
+
+/// Amount the destination receives when `amount` is sent
+///
+/// Equals `amount` for legacy mints and Token-2022 mints without a transfer fee.
+pub fn amount_received(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(amount);
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<Mint>::unpack(&data)
+        .map_err(|_| MorphoError::InvalidMint)?;
+    net_of_transfer_fee(&state, Clock::get()?.epoch, amount)
+}
+
+/// Amount that must be sent for the destination to receive `amount`
+pub fn amount_to_send(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(amount);
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<Mint>::unpack(&data)
+        .map_err(|_| MorphoError::InvalidMint)?;
+    gross_of_transfer_fee(&state, Clock::get()?.epoch, amount)
+}
+
+/// Check if `mint` is a Token-2022 mint with a transfer fee configured
+pub fn has_transfer_fee(mint: &AccountInfo) -> Result<bool> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(false);
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<Mint>::unpack(&data)
+        .map_err(|_| MorphoError::InvalidMint)?;
+    Ok(state.get_extension::<TransferFeeConfig>().is_ok())
+}
+
+fn net_of_transfer_fee<S: BaseStateWithExtensions<Mint>>(
+    state: &S,
+    epoch: u64,
+    amount: u64,
+) -> Result<u64> {
+    let Ok(config) = state.get_extension::<TransferFeeConfig>() else {
+        return Ok(amount);
+    };
+    let fee = config
+        .calculate_epoch_fee(epoch, amount)
+        .ok_or(MorphoError::MathOverflow)?;
+    Ok(amount.checked_sub(fee).ok_or(MorphoError::MathUnderflow)?)
+}
+
+fn gross_of_transfer_fee<S: BaseStateWithExtensions<Mint>>(
+    state: &S,
+    epoch: u64,
+    amount: u64,
+) -> Result<u64> {
+    let Ok(config) = state.get_extension::<TransferFeeConfig>() else {
+        return Ok(amount);
+    };
+    let fee = config
+        .calculate_inverse_epoch_fee(epoch, amount)
+        .ok_or(MorphoError::MathOverflow)?;
+    Ok(amount.checked_add(fee).ok_or(MorphoError::MathOverflow)?)
+}
+
+// ============================================================================
+// Native SOL
+// ============================================================================
+
+/// Check if `mint` is the wrapped SOL mint of either token program
+pub fn is_native_mint(mint: &Pubkey) -> bool {
+    *mint == spl_token::native_mint::ID || *mint == spl_token_2022::native_mint::ID
+}
+
+/// Move `lamports` from `owner` into its WSOL `token_account` and sync the balance
+pub fn wrap_native<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    system: &Program<'info, System>,
+    owner: &Signer<'info>,
+    token_account: &InterfaceAccount<'info, TokenAccount>,
+    lamports: u64,
+) -> Result<()> {
+    require!(is_native_mint(&token_account.mint), MorphoError::NativeMintRequired);
+
+    system_program::transfer(
+        CpiContext::new(
+            system.to_account_info(),
+            Transfer {
+                from: owner.to_account_info(),
+                to: token_account.to_account_info(),
+            },
+        ),
+        lamports,
+    )?;
+
+    sync_native(CpiContext::new(
+        token_program.to_account_info(),
+        SyncNative {
+            account: token_account.to_account_info(),
+        },
+    ))
+}
+
+/// Close `owner`'s WSOL `token_account`, sending its balance and rent to `destination`
+pub fn unwrap_native<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    owner: &Signer<'info>,
+    token_account: &InterfaceAccount<'info, TokenAccount>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    require!(is_native_mint(&token_account.mint), MorphoError::NativeMintRequired);
+    require!(token_account.owner == owner.key(), MorphoError::Unauthorized);
+
+    close_account(CpiContext::new(
+        token_program.to_account_info(),
+        CloseAccount {
+            account: token_account.to_account_info(),
+            destination: destination.clone(),
+            authority: owner.to_account_info(),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spl_token_2022::extension::{
+        non_transferable::NonTransferable, transfer_fee::TransferFee,
+        BaseStateWithExtensionsMut, StateWithExtensionsMut,
+    };
+    use crate::math::to_shares_down;
+    use anchor_lang::solana_program::program_pack::Pack;
+
+    /// Serialized Token-2022 mint with the given default account state / non-transferable flag
+    fn mint_data(default_state: Option<AccountState>, non_transferable: bool) -> Vec<u8> {
+        let mut types = vec![];
+        if default_state.is_some() {
+            types.push(ExtensionType::DefaultAccountState);
+        }
+        if non_transferable {
+            types.push(ExtensionType::NonTransferable);
+        }
+        let len = if types.is_empty() {
+            Mint::LEN
+        } else {
+            ExtensionType::try_calculate_account_len::<Mint>(&types).unwrap()
+        };
+        let mut data = vec![0u8; len];
+        let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+        if let Some(default_state) = default_state {
+            state.init_extension::<DefaultAccountState>(true).unwrap().state = default_state as u8;
+        }
+        if non_transferable {
+            state.init_extension::<NonTransferable>(true).unwrap();
+        }
+        state.base = Mint { decimals: 6, is_initialized: true, ..Mint::default() };
+        state.pack_base();
+        if !types.is_empty() {
+            state.init_account_type().unwrap();
+        }
+        data
+    }
+
+    fn check(data: &[u8]) -> Result<()> {
+        check_mint_extensions(&StateWithExtensions::<Mint>::unpack(data).unwrap())
+    }
+
+    #[test]
+    fn test_plain_mint_supported() {
+        assert!(check(&mint_data(None, false)).is_ok());
+    }
+
+    #[test]
+    fn test_non_transferable_rejected() {
+        assert!(check(&mint_data(None, true)).is_err());
+    }
+
+    #[test]
+    fn test_default_account_state() {
+        assert!(check(&mint_data(Some(AccountState::Initialized), false)).is_ok());
+        assert!(check(&mint_data(Some(AccountState::Frozen), false)).is_err());
+    }
+
+    /// Serialized Token-2022 mint charging `fee_bps` (capped at `maximum_fee`) from `epoch` on
+    fn fee_mint_data(epoch: u64, fee_bps: u16, maximum_fee: u64) -> Vec<u8> {
+        let len = ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::TransferFeeConfig]).unwrap();
+        let mut data = vec![0u8; len];
+        let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+        let fee = TransferFee {
+            epoch: epoch.into(),
+            maximum_fee: maximum_fee.into(),
+            transfer_fee_basis_points: fee_bps.into(),
+        };
+        let config = state.init_extension::<TransferFeeConfig>(true).unwrap();
+        config.older_transfer_fee = fee;
+        config.newer_transfer_fee = fee;
+        state.base = Mint { decimals: 6, is_initialized: true, ..Mint::default() };
+        state.pack_base();
+        state.init_account_type().unwrap();
+        data
+    }
+
+    #[test]
+    fn test_transfer_fee_mint_supported() {
+        assert!(check(&fee_mint_data(0, 100, u64::MAX)).is_ok());
+    }
+
+    #[test]
+    fn test_no_transfer_fee_is_identity() {
+        let data = mint_data(None, false);
+        let state = StateWithExtensions::<Mint>::unpack(&data).unwrap();
+        assert_eq!(net_of_transfer_fee(&state, 5, 1_000).unwrap(), 1_000);
+        assert_eq!(gross_of_transfer_fee(&state, 5, 1_000).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn test_transfer_fee_net_and_gross() {
+        let data = fee_mint_data(0, 100, u64::MAX); // 1%
+        let state = StateWithExtensions::<Mint>::unpack(&data).unwrap();
+
+        assert_eq!(net_of_transfer_fee(&state, 1, 1_000_000).unwrap(), 990_000);
+
+        // Sending the grossed-up amount delivers at least what is owed
+        let gross = gross_of_transfer_fee(&state, 1, 990_000).unwrap();
+        assert!(net_of_transfer_fee(&state, 1, gross).unwrap() >= 990_000);
+        assert!(gross <= 1_000_000);
+    }
+
+    #[test]
+    fn test_transfer_fee_respects_maximum() {
+        let data = fee_mint_data(0, 100, 500);
+        let state = StateWithExtensions::<Mint>::unpack(&data).unwrap();
+        assert_eq!(net_of_transfer_fee(&state, 1, 1_000_000).unwrap(), 999_500);
+        assert_eq!(gross_of_transfer_fee(&state, 1, 999_500).unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn test_shares_minted_on_net_received() {
+        let data = fee_mint_data(0, 250, u64::MAX); // 2.5%
+        let state = StateWithExtensions::<Mint>::unpack(&data).unwrap();
+
+        let sent = 1_000_000u64;
+        let received = net_of_transfer_fee(&state, 1, sent).unwrap();
+        assert_eq!(received, 975_000);
+
+        // Existing market: 10M assets backing 10M * 1e6 shares
+        let total_assets = 10_000_000u128;
+        let total_shares = 10_000_000_000_000u128;
+        let shares = to_shares_down(received as u128, total_assets, total_shares).unwrap();
+        let shares_if_gross = to_shares_down(sent as u128, total_assets, total_shares).unwrap();
+
+        assert!(shares < shares_if_gross);
+        assert_eq!(shares, to_shares_down(975_000, total_assets, total_shares).unwrap());
+    }
+
+    #[test]
+    fn test_is_native_mint() {
+        assert!(is_native_mint(&spl_token::native_mint::ID));
+        assert!(is_native_mint(&spl_token_2022::native_mint::ID));
+        assert!(!is_native_mint(&Pubkey::new_unique()));
+    }
+}
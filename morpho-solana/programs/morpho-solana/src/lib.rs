@@ -11,6 +11,7 @@
 //! - Protocol and per-market pause controls
 //! - Flash loans with lock mechanism
 //! - Liquidation with LIF-based incentives and bad debt socialization
+//! - Curated vaults allocating one deposit token across several markets
 
 use anchor_lang::prelude::*;
 
@@ -21,6 +22,7 @@ pub mod math;
 pub mod state;
 pub mod interfaces;
 pub mod instructions;
+pub mod profiling;
 
 use instructions::*;
 
@@ -72,6 +74,10 @@ pub mod morpho_solana {
         instructions::admin::set_market_paused(ctx, market_id, paused)
     }
 
+    pub fn set_guardian(ctx: Context<SetGuardian>, guardian: Pubkey) -> Result<()> {
+        instructions::admin::set_guardian(ctx, guardian)
+    }
+
     pub fn enable_lltv(ctx: Context<EnableLltv>, lltv: u64) -> Result<()> {
         instructions::admin::enable_lltv(ctx, lltv)
     }
@@ -84,10 +90,129 @@ pub mod morpho_solana {
         instructions::admin::set_fee(ctx, market_id, fee)
     }
 
+    pub fn set_oracle_confidence(
+        ctx: Context<SetOracleConfidence>,
+        market_id: [u8; 32],
+        max_oracle_confidence_bps: u64,
+    ) -> Result<()> {
+        instructions::admin::set_oracle_confidence(ctx, market_id, max_oracle_confidence_bps)
+    }
+
+    pub fn set_oracle_std_dev(
+        ctx: Context<SetOracleStdDev>,
+        market_id: [u8; 32],
+        max_std_bps: u64,
+    ) -> Result<()> {
+        instructions::admin::set_oracle_std_dev(ctx, market_id, max_std_bps)
+    }
+
+    pub fn set_bad_debt_threshold(
+        ctx: Context<SetBadDebtThreshold>,
+        market_id: [u8; 32],
+        bad_debt_threshold: u128,
+    ) -> Result<()> {
+        instructions::admin::set_bad_debt_threshold(ctx, market_id, bad_debt_threshold)
+    }
+
+    pub fn migrate_oracle_kind(
+        ctx: Context<MigrateOracleKind>,
+        market_id: [u8; 32],
+        oracle_kind: u8,
+        pyth_feed_id: [u8; 32],
+    ) -> Result<()> {
+        instructions::admin::migrate_oracle_kind(ctx, market_id, oracle_kind, pyth_feed_id)
+    }
+
+    pub fn set_max_deviation(
+        ctx: Context<SetMaxDeviation>,
+        market_id: [u8; 32],
+        max_deviation_bps: u64,
+    ) -> Result<()> {
+        instructions::admin::set_max_deviation(ctx, market_id, max_deviation_bps)
+    }
+
+    pub fn set_supply_cap(ctx: Context<SetSupplyCap>, market_id: [u8; 32], supply_cap: u128) -> Result<()> {
+        instructions::admin::set_supply_cap(ctx, market_id, supply_cap)
+    }
+
+    pub fn migrate_market(
+        ctx: Context<MigrateMarket>,
+        market_id: [u8; 32],
+        pyth_feed_id: [u8; 32],
+        pyth_secondary_feed_id: [u8; 32],
+    ) -> Result<()> {
+        instructions::admin::migrate_market(ctx, market_id, pyth_feed_id, pyth_secondary_feed_id)
+    }
+
+    pub fn create_twap_oracle(
+        ctx: Context<CreateTwapOracle>,
+        source: Pubkey,
+        source_kind: u8,
+        window_slots: u64,
+        max_staleness_slots: u64,
+        pyth_feed_id: [u8; 32],
+    ) -> Result<()> {
+        instructions::admin::create_twap_oracle(
+            ctx,
+            source,
+            source_kind,
+            window_slots,
+            max_staleness_slots,
+            pyth_feed_id,
+        )
+    }
+
+    pub fn create_median_oracle(
+        ctx: Context<CreateMedianOracle>,
+        aggregator_id: [u8; 32],
+        feed_kind: u8,
+        feeds: Vec<Pubkey>,
+        min_healthy_feeds: u8,
+        max_staleness_slots: u64,
+        pyth_feed_id: [u8; 32],
+    ) -> Result<()> {
+        instructions::admin::create_median_oracle(
+            ctx,
+            aggregator_id,
+            feed_kind,
+            feeds,
+            min_healthy_feeds,
+            max_staleness_slots,
+            pyth_feed_id,
+        )
+    }
+
+    pub fn queue_param_change(
+        ctx: Context<QueueParamChange>,
+        market_id: [u8; 32],
+        param: u8,
+        value: u64,
+        address: Pubkey,
+    ) -> Result<()> {
+        instructions::admin::queue_param_change(ctx, market_id, param, value, address)
+    }
+
+    pub fn execute_param_change(
+        ctx: Context<ExecuteParamChange>,
+        market_id: [u8; 32],
+        param: u8,
+    ) -> Result<()> {
+        instructions::admin::execute_param_change(ctx, market_id, param)
+    }
+
+    pub fn set_oracle(ctx: Context<SetOracle>, market_id: [u8; 32]) -> Result<()> {
+        instructions::admin::set_oracle(ctx, market_id)
+    }
+
+    pub fn set_oracle_fallback(ctx: Context<SetOracleFallback>, market_id: [u8; 32]) -> Result<()> {
+        instructions::admin::set_oracle_fallback(ctx, market_id)
+    }
+
     // =========================================================================
     // Market Instructions
     // =========================================================================
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create_market(
         ctx: Context<CreateMarket>,
         collateral_mint_key: Pubkey,
@@ -95,6 +220,8 @@ pub mod morpho_solana {
         oracle_key: Pubkey,
         irm_key: Pubkey,
         lltv: u64,
+        config: state::MarketConfig,
+        allow_boundary_price: bool,
     ) -> Result<()> {
         instructions::market::create_market(
             ctx,
@@ -103,6 +230,8 @@ pub mod morpho_solana {
             oracle_key,
             irm_key,
             lltv,
+            config,
+            allow_boundary_price,
         )
     }
 
@@ -122,13 +251,24 @@ pub mod morpho_solana {
     // Supply Instructions
     // =========================================================================
 
-    pub fn supply(
-        ctx: Context<Supply>,
+    pub fn supply<'info>(
+        ctx: Context<'_, '_, '_, 'info, Supply<'info>>,
         market_id: [u8; 32],
         assets: u128,
         min_shares: u128,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        instructions::supply::supply(ctx, market_id, assets, min_shares, data)
+    }
+
+    pub fn supply_shares<'info>(
+        ctx: Context<'_, '_, '_, 'info, Supply<'info>>,
+        market_id: [u8; 32],
+        shares: u128,
+        max_assets: u128,
+        data: Vec<u8>,
     ) -> Result<()> {
-        instructions::supply::supply(ctx, market_id, assets, min_shares)
+        instructions::supply::supply_shares(ctx, market_id, shares, max_assets, data)
     }
 
     pub fn withdraw(
@@ -136,8 +276,18 @@ pub mod morpho_solana {
         market_id: [u8; 32],
         assets: u128,
         shares: u128,
+        min_assets_out: u128,
+        deadline_slot: u64,
+    ) -> Result<()> {
+        instructions::supply::withdraw(ctx, market_id, assets, shares, min_assets_out, deadline_slot)
+    }
+
+    pub fn emergency_withdraw(
+        ctx: Context<EmergencyWithdraw>,
+        market_id: [u8; 32],
+        shares: u128,
     ) -> Result<()> {
-        instructions::supply::withdraw(ctx, market_id, assets, shares)
+        instructions::supply::emergency_withdraw(ctx, market_id, shares)
     }
 
     // =========================================================================
@@ -169,41 +319,84 @@ pub mod morpho_solana {
         market_id: [u8; 32],
         assets: u128,
         max_shares: u128,
+        min_assets_out: u128,
+        deadline_slot: u64,
     ) -> Result<()> {
-        instructions::borrow::borrow(ctx, market_id, assets, max_shares)
+        instructions::borrow::borrow(ctx, market_id, assets, max_shares, min_assets_out, deadline_slot)
     }
 
-    pub fn repay(
-        ctx: Context<Repay>,
+    pub fn repay<'info>(
+        ctx: Context<'_, '_, '_, 'info, Repay<'info>>,
         market_id: [u8; 32],
         assets: u128,
         shares: u128,
+        max_assets_in: u128,
+        data: Vec<u8>,
     ) -> Result<()> {
-        instructions::borrow::repay(ctx, market_id, assets, shares)
+        instructions::borrow::repay(ctx, market_id, assets, shares, max_assets_in, data)
     }
 
     // =========================================================================
     // Liquidation Instructions
     // =========================================================================
 
-    pub fn liquidate(
-        ctx: Context<Liquidate>,
+    pub fn liquidate<'info>(
+        ctx: Context<'_, '_, '_, 'info, Liquidate<'info>>,
         market_id: [u8; 32],
         seized_assets: u128,
+        seized_collateral: u128,
+        bonus_mode: u8,
+        deadline_slot: u64,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        instructions::liquidate::liquidate(ctx, market_id, seized_assets, seized_collateral, bonus_mode, deadline_slot, data)
+    }
+
+    pub fn liquidate_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LiquidateBatch<'info>>,
+        market_id: [u8; 32],
+        repaid_assets: Vec<u128>,
+        deadline_slot: u64,
+    ) -> Result<()> {
+        instructions::liquidate::liquidate_batch(ctx, market_id, repaid_assets, deadline_slot)
+    }
+
+    pub fn start_liquidation_auction(
+        ctx: Context<StartLiquidationAuction>,
+        market_id: [u8; 32],
+    ) -> Result<()> {
+        instructions::liquidate::start_liquidation_auction(ctx, market_id)
+    }
+
+    pub fn set_pre_liquidation(
+        ctx: Context<SetPreLiquidation>,
+        market_id: [u8; 32],
+        pre_lltv: u64,
+        pre_lif: u64,
+        liquidator: Pubkey,
+    ) -> Result<()> {
+        instructions::pre_liquidation::set_pre_liquidation(ctx, market_id, pre_lltv, pre_lif, liquidator)
+    }
+
+    pub fn pre_liquidate(
+        ctx: Context<PreLiquidate>,
+        market_id: [u8; 32],
+        repaid_assets: u128,
     ) -> Result<()> {
-        instructions::liquidate::liquidate(ctx, market_id, seized_assets)
+        instructions::pre_liquidation::pre_liquidate(ctx, market_id, repaid_assets)
     }
 
     // =========================================================================
     // Flash Loan Instructions
     // =========================================================================
 
-    pub fn flash_loan(
-        ctx: Context<FlashLoanStart>,
+    pub fn flash_loan<'info>(
+        ctx: Context<'_, '_, '_, 'info, FlashLoanWithCallback<'info>>,
         market_id: [u8; 32],
         amount: u128,
+        data: Vec<u8>,
     ) -> Result<()> {
-        instructions::flash_loan::flash_loan(ctx, market_id, amount)
+        instructions::flash_loan::flash_loan(ctx, market_id, amount, data)
     }
 
     pub fn flash_loan_start(
@@ -222,6 +415,113 @@ pub mod morpho_solana {
         instructions::flash_loan::flash_loan_end(ctx, market_id, borrowed_amount)
     }
 
+    // =========================================================================
+    // Vault Instructions
+    // =========================================================================
+
+    pub fn create_vault(
+        ctx: Context<CreateVault>,
+        vault_id: [u8; 32],
+        idle_buffer_bps: u64,
+    ) -> Result<()> {
+        instructions::vault::create_vault(ctx, vault_id, idle_buffer_bps)
+    }
+
+    pub fn set_vault_market(
+        ctx: Context<SetVaultMarket>,
+        vault_id: [u8; 32],
+        supply_cap: u128,
+    ) -> Result<()> {
+        instructions::vault::set_vault_market(ctx, vault_id, supply_cap)
+    }
+
+    pub fn set_vault_queues(
+        ctx: Context<SetVaultQueues>,
+        vault_id: [u8; 32],
+        supply_queue: Vec<Pubkey>,
+        withdraw_queue: Vec<Pubkey>,
+    ) -> Result<()> {
+        instructions::vault::set_vault_queues(ctx, vault_id, supply_queue, withdraw_queue)
+    }
+
+    pub fn set_supply_queue(
+        ctx: Context<SetVaultQueues>,
+        vault_id: [u8; 32],
+        queue: Vec<Pubkey>,
+    ) -> Result<()> {
+        instructions::vault::set_supply_queue(ctx, vault_id, queue)
+    }
+
+    pub fn set_withdraw_queue<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SetVaultQueues<'info>>,
+        vault_id: [u8; 32],
+        queue: Vec<Pubkey>,
+    ) -> Result<()> {
+        instructions::vault::set_withdraw_queue(ctx, vault_id, queue)
+    }
+
+    pub fn submit_vault_market_removal(
+        ctx: Context<VaultMarketRemoval>,
+        vault_id: [u8; 32],
+        market: Pubkey,
+    ) -> Result<()> {
+        instructions::vault::submit_vault_market_removal(ctx, vault_id, market)
+    }
+
+    pub fn cancel_vault_market_removal(
+        ctx: Context<VaultMarketRemoval>,
+        vault_id: [u8; 32],
+        market: Pubkey,
+    ) -> Result<()> {
+        instructions::vault::cancel_vault_market_removal(ctx, vault_id, market)
+    }
+
+    pub fn remove_vault_market<'info>(
+        ctx: Context<'_, '_, 'info, 'info, VaultMarketRemoval<'info>>,
+        vault_id: [u8; 32],
+        market: Pubkey,
+    ) -> Result<()> {
+        instructions::vault::remove_vault_market(ctx, vault_id, market)
+    }
+
+    pub fn vault_deposit<'info>(
+        ctx: Context<'_, '_, 'info, 'info, VaultDeposit<'info>>,
+        vault_id: [u8; 32],
+        assets: u128,
+        min_shares: u128,
+    ) -> Result<()> {
+        instructions::vault::vault_deposit(ctx, vault_id, assets, min_shares)
+    }
+
+    pub fn vault_withdraw<'info>(
+        ctx: Context<'_, '_, 'info, 'info, VaultWithdraw<'info>>,
+        vault_id: [u8; 32],
+        shares: u128,
+    ) -> Result<()> {
+        instructions::vault::vault_withdraw(ctx, vault_id, shares)
+    }
+
+    pub fn set_vault_fee<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SetVaultFee<'info>>,
+        vault_id: [u8; 32],
+        fee_bps: u64,
+        fee_recipient: Pubkey,
+    ) -> Result<()> {
+        instructions::vault::set_vault_fee(ctx, vault_id, fee_bps, fee_recipient)
+    }
+
+    pub fn claim_vault_fees(ctx: Context<ClaimVaultFees>, vault_id: [u8; 32]) -> Result<()> {
+        instructions::vault::claim_vault_fees(ctx, vault_id)
+    }
+
+    pub fn reallocate<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Reallocate<'info>>,
+        vault_id: [u8; 32],
+        allocations: Vec<state::VaultAllocation>,
+    ) -> Result<()> {
+        instructions::vault::reallocate(ctx, vault_id, allocations)
+    }
+
     // =========================================================================
     // Utility Instructions
     // =========================================================================
@@ -230,6 +530,14 @@ pub mod morpho_solana {
         instructions::utils::accrue_interest_ix(ctx, market_id)
     }
 
+    pub fn socialize_bad_debt(ctx: Context<SocializeBadDebt>, market_id: [u8; 32]) -> Result<()> {
+        instructions::utils::socialize_bad_debt_ix(ctx, market_id)
+    }
+
+    pub fn fund_reserve(ctx: Context<FundReserve>, market_id: [u8; 32], assets: u128) -> Result<()> {
+        instructions::utils::fund_reserve(ctx, market_id, assets)
+    }
+
     pub fn set_authorization(
         ctx: Context<SetAuthorization>,
         is_authorized: bool,
@@ -238,6 +546,16 @@ pub mod morpho_solana {
         instructions::utils::set_authorization(ctx, is_authorized, expires_at)
     }
 
+    pub fn set_authorization_with_sig(
+        ctx: Context<SetAuthorizationWithSig>,
+        is_authorized: bool,
+        expires_at: i64,
+        nonce: u64,
+        deadline: i64,
+    ) -> Result<()> {
+        instructions::utils::set_authorization_with_sig(ctx, is_authorized, expires_at, nonce, deadline)
+    }
+
     pub fn revoke_authorization(ctx: Context<RevokeAuthorization>) -> Result<()> {
         instructions::utils::revoke_authorization(ctx)
     }
@@ -245,4 +563,63 @@ pub mod morpho_solana {
     pub fn claim_fees(ctx: Context<ClaimFees>, market_id: [u8; 32]) -> Result<()> {
         instructions::utils::claim_fees(ctx, market_id)
     }
+
+    pub fn set_static_price(
+        ctx: Context<SetStaticPrice>,
+        price: u128,
+        last_update_slot: u64,
+    ) -> Result<()> {
+        instructions::utils::set_static_price(ctx, price, last_update_slot)
+    }
+
+    pub fn update_twap(ctx: Context<UpdateTwap>) -> Result<()> {
+        instructions::utils::update_twap(ctx)
+    }
+
+    pub fn update_median<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateMedian<'info>>,
+    ) -> Result<()> {
+        instructions::utils::update_median(ctx)
+    }
+
+    // =========================================================================
+    // View Instructions
+    // =========================================================================
+
+    pub fn view_health_factor(ctx: Context<ViewPosition>, market_id: [u8; 32]) -> Result<()> {
+        instructions::view::view_health_factor(ctx, market_id)
+    }
+
+    pub fn view_is_liquidatable(ctx: Context<ViewPosition>, market_id: [u8; 32]) -> Result<()> {
+        instructions::view::view_is_liquidatable(ctx, market_id)
+    }
+
+    pub fn view_position_limits(ctx: Context<ViewPosition>, market_id: [u8; 32]) -> Result<()> {
+        instructions::view::view_position_limits(ctx, market_id)
+    }
+
+    pub fn scan_liquidatable<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ScanPositions<'info>>,
+        market_id: [u8; 32],
+    ) -> Result<()> {
+        instructions::view::scan_liquidatable(ctx, market_id)
+    }
+
+    pub fn view_markets<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ViewMarkets<'info>>,
+        start: u64,
+    ) -> Result<()> {
+        instructions::view::view_markets(ctx, start)
+    }
+
+    pub fn view_rates(ctx: Context<ViewMarket>, market_id: [u8; 32]) -> Result<()> {
+        instructions::view::view_rates(ctx, market_id)
+    }
+
+    pub fn view_position_interest(
+        ctx: Context<ViewPositionInterest>,
+        market_id: [u8; 32],
+    ) -> Result<()> {
+        instructions::view::view_position_interest(ctx, market_id)
+    }
 }
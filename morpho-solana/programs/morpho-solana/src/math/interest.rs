@@ -1,13 +1,16 @@
 //! Interest accrual logic for lending markets
 //! 
-//! Interest is compounded using Taylor series approximation.
+//! Interest is compounded continuously (`wad_exp`), over at most
+//! MAX_ACCRUAL_ELAPSED per accrual.
 //! Fee shares are tracked separately for later claiming.
+//! `supply_index` and `borrow_index` follow the share prices, so per-user
+//! interest can be read from the index delta since a position's entry.
 
 use anchor_lang::prelude::*;
-use crate::constants::BPS;
+use crate::constants::{BPS, MAX_ACCRUAL_ELAPSED, VIRTUAL_ASSETS, VIRTUAL_SHARES};
 use crate::state::Market;
 use super::safe_math::{checked_add, checked_sub};
-use super::wad::{w_taylor_compounded, wad_mul_down, mul_div_down};
+use super::wad::{wad_exp_compounded, wad_mul_down, mul_div_down, mul_div_up};
 use super::shares::to_shares_down;
 
 /// Result of interest accrual
@@ -22,6 +25,12 @@ pub struct AccrualResult {
 /// Accrue interest on a market
 /// 
 /// MUST be called before any operation that reads/writes market totals.
+/// Idempotent within a timestamp: once `last_update` is current, further
+/// calls are no-ops, so every handler can accrue unconditionally.
+/// 
+/// The `total_borrow_assets * factor` product is formed in 256 bits (see
+/// `mul_div`) and narrowed after dividing by WAD, so large markets only
+/// overflow when the resulting totals themselves leave u128.
 /// 
 /// # Arguments
 /// * `market` - Market account to accrue interest on
@@ -40,7 +49,9 @@ pub fn accrue_interest_on_market(
         return Ok(AccrualResult { interest: 0, fee_shares: 0 });
     }
     
-    let elapsed = (current_time - market.last_update) as u128;
+    // Dormant markets: charge at most MAX_ACCRUAL_ELAPSED, still move
+    // last_update to now
+    let elapsed = std::cmp::min((current_time - market.last_update) as u128, MAX_ACCRUAL_ELAPSED);
     
     // No borrows = no interest
     if elapsed == 0 || market.total_borrow_assets == 0 {
@@ -48,10 +59,10 @@ pub fn accrue_interest_on_market(
         return Ok(AccrualResult { interest: 0, fee_shares: 0 });
     }
     
-    // Calculate interest using Taylor expansion
-    let interest_factor = w_taylor_compounded(borrow_rate, elapsed)?;
+    // Continuously compounded over the elapsed period
+    let interest_factor = wad_exp_compounded(borrow_rate, elapsed)?;
     
-    // Interest amount = borrow * factor / WAD
+    // Interest amount = borrow * factor / WAD (256-bit intermediate)
     let interest = wad_mul_down(market.total_borrow_assets, interest_factor)?;
     
     if interest == 0 {
@@ -59,6 +70,10 @@ pub fn accrue_interest_on_market(
         return Ok(AccrualResult { interest: 0, fee_shares: 0 });
     }
     
+    let supply_before = market.total_supply_assets;
+    let supply_shares_before = market.total_supply_shares;
+    let borrow_before = market.total_borrow_assets;
+
     // Update totals (interest goes to both supply and borrow)
     market.total_borrow_assets = checked_add(market.total_borrow_assets, interest)?;
     market.total_supply_assets = checked_add(market.total_supply_assets, interest)?;
@@ -95,15 +110,50 @@ pub fn accrue_interest_on_market(
         }
     }
     
+    // Scale each index by its share price change: (assets + VIRTUAL_ASSETS)
+    // / (shares + VIRTUAL_SHARES), as used by `to_assets_*`. Supply nets out
+    // the fee shares minted above, so interest is never counted for both
+    // suppliers and the fee recipient.
+    market.supply_index = mul_div_down(
+        mul_div_down(
+            market.supply_index(),
+            checked_add(market.total_supply_assets, VIRTUAL_ASSETS)?,
+            checked_add(supply_before, VIRTUAL_ASSETS)?,
+        )?,
+        checked_add(supply_shares_before, VIRTUAL_SHARES)?,
+        checked_add(market.total_supply_shares, VIRTUAL_SHARES)?,
+    )?;
+    market.borrow_index = mul_div_up(
+        market.borrow_index(),
+        checked_add(market.total_borrow_assets, VIRTUAL_ASSETS)?,
+        checked_add(borrow_before, VIRTUAL_ASSETS)?,
+    )?;
+
     market.last_update = current_time;
     
     Ok(AccrualResult { interest, fee_shares })
 }
 
+/// Interest accrued on `value` since an entry at `entry_index`
+///
+/// `value` is the position's current supply value or debt and `index` the
+/// market's current index on the same side. The index moves in step with
+/// the share price, so this is the part of `value` added by interest since
+/// entry: `value * (index - entry_index) / index`, rounded down. Returns 0
+/// for an untracked entry (0).
+pub fn interest_since_entry(value: u128, entry_index: u128, index: u128) -> Result<u128> {
+    if entry_index == 0 {
+        return Ok(0);
+    }
+    mul_div_down(value, index.saturating_sub(entry_index), index)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::constants::WAD;
+    use crate::math::{to_assets_down, to_assets_up, to_shares_up};
+    use crate::state::assert_invariants;
 
     fn create_test_market() -> Market {
         Market {
@@ -116,7 +166,7 @@ mod tests {
             oracle: Pubkey::default(),
             irm: Pubkey::default(),
             lltv: 8500,
-            paused: false,
+            paused: 0,
             fee: 0,
             total_supply_assets: 1_000_000_000_000,
             total_supply_shares: 1_000_000_000_000_000_000,
@@ -127,7 +177,46 @@ mod tests {
             collateral_vault_bump: 0,
             loan_vault_bump: 0,
             flash_loan_lock: 0,
-            reserved: [0u8; 127],
+            max_oracle_confidence_bps: 0,
+            oracle_kind: 0,
+            oracle_kind_migrated: 0,
+            oracle_secondary: Pubkey::default(),
+            max_staleness_slots: 0,
+            invert_oracle: 0,
+            oracle_fallback: Pubkey::default(),
+            last_oracle_price: 0,
+            last_oracle_slot: 0,
+            max_deviation_bps: 0,
+            close_factor_bps: 0,
+            rate_at_target: 0,
+            auction_duration_slots: 0,
+            min_borrow: 0,
+            min_collateral: 0,
+            oracle_cache_slot: 0,
+            min_oracle_samples: 0,
+            max_price: 0,
+            max_std_bps: 0,
+            callback_lock: 0,
+            bad_debt_threshold: 0,
+            pending_bad_debt: 0,
+            max_utilization_bps: 0,
+            liquidation_protocol_fee_bps: 0,
+            lif_cursor: 0,
+            max_lif: 0,
+            oracle_cache_published_slot: 0,
+            oracle_cache_confidence_bps: 0,
+            liquidation_grace_slots: 0,
+            supply_cap: 0,
+            min_rate: 0,
+            max_rate: 0,
+            padding: [0; 1],
+            reserve_assets: 0,
+            accrual_basis: 0,
+            supply_index: 0,
+            borrow_index: 0,
+            reserved: [0u8; 15],
+            pyth_feed_id: [0u8; 32],
+            pyth_secondary_feed_id: [0u8; 32],
         }
     }
 
@@ -161,5 +250,298 @@ mod tests {
         
         assert!(result.interest > 0);
         assert!(market.total_borrow_assets > initial_borrow);
+        assert_invariants(&market).unwrap();
+    }
+
+    #[test]
+    fn test_accrual_idempotent_within_timestamp() {
+        let mut market = create_test_market();
+        market.fee = 1000; // 10%
+        let rate = WAD / 20 / 31_536_000;
+
+        let first = accrue_interest_on_market(&mut market, 86_400, rate).unwrap();
+        assert!(first.interest > 0);
+        assert!(first.fee_shares > 0);
+        assert_invariants(&market).unwrap();
+
+        let borrow = market.total_borrow_assets;
+        let supply = market.total_supply_assets;
+        let shares = market.total_supply_shares;
+
+        // Second call at the same time changes nothing
+        let second = accrue_interest_on_market(&mut market, 86_400, rate).unwrap();
+        assert_eq!(second, AccrualResult { interest: 0, fee_shares: 0 });
+        assert_eq!(market.total_borrow_assets, borrow);
+        assert_eq!(market.total_supply_assets, supply);
+        assert_eq!(market.total_supply_shares, shares);
+        assert_eq!(market.pending_fee_shares, first.fee_shares);
+    }
+
+    #[test]
+    fn test_indices_follow_share_prices() {
+        use crate::math::{to_assets_down, to_assets_up};
+        let mut market = create_test_market();
+        market.fee = 1000; // 10%
+        let rate = WAD / 20 / 31_536_000;
+
+        // Untracked markets start from WAD
+        assert_eq!((market.supply_index(), market.borrow_index()), (WAD, WAD));
+        let supply_shares = market.total_supply_shares;
+        let borrow_shares = market.total_borrow_shares;
+        let supplied_before = to_assets_down(supply_shares, market.total_supply_assets, market.total_supply_shares).unwrap();
+        let borrowed_before = to_assets_up(borrow_shares, market.total_borrow_assets, market.total_borrow_shares).unwrap();
+
+        let mut interest = 0;
+        let mut fee_shares = 0;
+        for day in 1..=30 {
+            let result = accrue_interest_on_market(&mut market, day * 86_400, rate).unwrap();
+            interest += result.interest;
+            fee_shares += result.fee_shares;
+        }
+        assert!(market.supply_index() > WAD && market.borrow_index() > market.supply_index());
+
+        // Index deltas match the share value change up to rounding
+        let supplied = to_assets_down(supply_shares, market.total_supply_assets, market.total_supply_shares).unwrap();
+        let supplier_interest = interest_since_entry(supplied, WAD, market.supply_index()).unwrap();
+        assert!(supplier_interest <= supplied - supplied_before);
+        assert!(supplied - supplied_before - supplier_interest <= 30);
+
+        let borrowed = to_assets_up(borrow_shares, market.total_borrow_assets, market.total_borrow_shares).unwrap();
+        let borrower_interest = interest_since_entry(borrowed, WAD, market.borrow_index()).unwrap();
+        assert!(borrower_interest.abs_diff(borrowed - borrowed_before) <= 30);
+        assert!(borrower_interest.abs_diff(interest) <= 30);
+
+        // Suppliers' interest plus the fee shares' value never exceeds what
+        // borrowers paid
+        let fee_value = to_assets_down(fee_shares, market.total_supply_assets, market.total_supply_shares).unwrap();
+        assert!(supplier_interest + fee_value <= interest);
+        assert!(interest - supplier_interest - fee_value <= 30);
+
+        assert_eq!(interest_since_entry(supplied, 0, market.supply_index()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_accrual_bases_over_fixed_interval() {
+        use crate::state::AccrualBasis;
+        let rate = WAD / 20 / 31_536_000;
+        let start = Clock { slot: 250_000_000, unix_timestamp: 1_700_000_000, ..Clock::default() };
+        // One day: 86_400 s, or 216_000 slots at 400ms
+        let end = Clock { slot: start.slot + 216_000, unix_timestamp: start.unix_timestamp + 86_400, ..Clock::default() };
+
+        let mut by_time = create_test_market();
+        by_time.last_update = by_time.accrual_time(&start).unwrap();
+        assert_eq!(by_time.last_update, 1_700_000_000);
+        let now = by_time.accrual_time(&end).unwrap();
+        let time_result = accrue_interest_on_market(&mut by_time, now, rate).unwrap();
+
+        let mut by_slot = create_test_market();
+        by_slot.accrual_basis = AccrualBasis::Slots as u8;
+        by_slot.last_update = by_slot.accrual_time(&start).unwrap();
+        assert_eq!(by_slot.last_update, 100_000_000);
+        let now = by_slot.accrual_time(&end).unwrap();
+        assert_eq!(now - by_slot.last_update, 86_400);
+        let slot_result = accrue_interest_on_market(&mut by_slot, now, rate).unwrap();
+
+        assert!(time_result.interest > 0);
+        assert_eq!(slot_result, time_result);
+        assert_eq!(by_slot.total_borrow_assets, by_time.total_borrow_assets);
+
+        // Slots that land mid-second charge nothing until the second completes
+        let mut stepped = create_test_market();
+        stepped.accrual_basis = AccrualBasis::Slots as u8;
+        stepped.last_update = stepped.accrual_time(&start).unwrap();
+        let mut charged = Vec::new();
+        for slot in start.slot + 1..=start.slot + 5 {
+            let before = stepped.last_update;
+            let now = stepped.accrual_time(&Clock { slot, ..start }).unwrap();
+            accrue_interest_on_market(&mut stepped, now, rate).unwrap();
+            charged.push(stepped.last_update - before);
+        }
+        // 5 slots = 2 s, charged on the slots that cross a second boundary
+        assert_eq!(charged, vec![0, 0, 1, 0, 1]);
+
+        let mut unknown = create_test_market();
+        unknown.accrual_basis = 2;
+        assert!(unknown.accrual_time(&start).is_err());
+    }
+
+    #[test]
+    fn test_accrual_capped_after_long_dormancy() {
+        use crate::constants::MAX_BORROW_RATE_PER_SECOND;
+        let rate = MAX_BORROW_RATE_PER_SECOND;
+
+        let mut one_year = create_test_market();
+        let capped = accrue_interest_on_market(&mut one_year, MAX_ACCRUAL_ELAPSED as i64, rate).unwrap();
+
+        // A century at the maximum rate: no overflow, one year's interest
+        let mut dormant = create_test_market();
+        let century = 100 * MAX_ACCRUAL_ELAPSED as i64;
+        let result = accrue_interest_on_market(&mut dormant, century, rate).unwrap();
+        assert_eq!(result, capped);
+        assert_eq!(dormant.total_borrow_assets, one_year.total_borrow_assets);
+        assert_eq!(dormant.last_update, century);
+        assert_invariants(&dormant).unwrap();
+
+        // Bounded by e^10 - 1 (1000% APR for a year)
+        assert!(result.interest < 500_000_000_000 * 22_026);
+    }
+
+    #[test]
+    fn test_accrual_wide_intermediate() {
+        use crate::constants::{MAX_BORROW_RATE_PER_SECOND, VIRTUAL_SHARES};
+        let rate = MAX_BORROW_RATE_PER_SECOND;
+        let factor = wad_exp_compounded(rate, MAX_ACCRUAL_ELAPSED).unwrap();
+
+        // 1e30 borrowed at the maximum rate for a year: borrow * factor is
+        // ~2.2e52, far past u128, while the interest itself is ~2.2e34
+        let mut market = create_test_market();
+        market.fee = 1000; // 10%
+        market.total_borrow_assets = 10u128.pow(30);
+        market.total_borrow_shares = 10u128.pow(30) * VIRTUAL_SHARES;
+        market.total_supply_assets = 2 * 10u128.pow(30);
+        market.total_supply_shares = 2 * 10u128.pow(30) * VIRTUAL_SHARES;
+        assert!(market.total_borrow_assets.checked_mul(factor).is_none());
+
+        let result = accrue_interest_on_market(&mut market, MAX_ACCRUAL_ELAPSED as i64, rate).unwrap();
+
+        // Exact: 1e30 * factor / 1e18
+        assert_eq!(result.interest, 10u128.pow(12) * factor);
+        assert_eq!(market.total_borrow_assets, 10u128.pow(30) + result.interest);
+        assert!(result.fee_shares > 0);
+
+        // e^10 - 1 = 22_025.4657948...; the per-second rate truncates
+        // 1000% APR by ~2e-12, hence the 1e-10 tolerance
+        let expected = 22_025_465_794_806_718u128 * 10u128.pow(18);
+        assert!(result.interest.abs_diff(expected) < expected / 10u128.pow(10));
+        assert_invariants(&market).unwrap();
+    }
+
+    /// One step of the lending model; percentages keep every step valid so
+    /// shrinking only has to drop steps and shrink amounts
+    #[derive(Debug, Clone)]
+    enum LendingOp {
+        Supply { user: usize, assets: u128 },
+        Withdraw { user: usize, pct: u128 },
+        Borrow { pct: u128 },
+        Repay { pct: u128 },
+        Accrue { elapsed: i64, rate: u128 },
+    }
+
+    const LENDING_USERS: usize = 3;
+
+    fn lending_op() -> impl proptest::strategy::Strategy<Value = LendingOp> {
+        use proptest::strategy::Strategy;
+
+        proptest::prop_oneof![
+            (0..LENDING_USERS, 1u128..1_000_000_000_000)
+                .prop_map(|(user, assets)| LendingOp::Supply { user, assets }),
+            (0..LENDING_USERS, 1u128..=100)
+                .prop_map(|(user, pct)| LendingOp::Withdraw { user, pct }),
+            (1u128..=100).prop_map(|pct| LendingOp::Borrow { pct }),
+            (1u128..=100).prop_map(|pct| LendingOp::Repay { pct }),
+            // Up to 30 days at up to ~200% APR
+            (1i64..2_592_000, 0u128..63_419_583_967)
+                .prop_map(|(elapsed, rate)| LendingOp::Accrue { elapsed, rate }),
+        ]
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::test_runner::Config::with_cases(256))]
+
+        /// Property: across any sequence of supply/withdraw/borrow/repay/accrue,
+        /// no supplier takes out more than they put in plus their pro-rata
+        /// interest (with one asset of rounding slack per step), and every
+        /// step leaves the market's invariants intact
+        #[test]
+        fn prop_lending_sequence_never_overpays_suppliers(
+            ops in proptest::collection::vec(lending_op(), 1..60),
+        ) {
+            let mut market = create_test_market();
+            market.total_supply_assets = 0;
+            market.total_supply_shares = 0;
+            market.total_borrow_assets = 0;
+            market.total_borrow_shares = 0;
+
+            let mut supply_shares = [0u128; LENDING_USERS];
+            let mut supplied = [0u128; LENDING_USERS];
+            let mut withdrawn = [0u128; LENDING_USERS];
+            let mut fair_interest = [0u128; LENDING_USERS];
+            let mut debt_shares = 0u128;
+            let mut vault = 0u128;
+            let mut now = 0i64;
+
+            for (step, op) in ops.into_iter().enumerate() {
+                match op {
+                    LendingOp::Supply { user, assets } => {
+                        let shares = to_shares_down(assets, market.total_supply_assets, market.total_supply_shares).unwrap();
+                        supply_shares[user] += shares;
+                        supplied[user] += assets;
+                        vault += assets;
+                        market.total_supply_assets += assets;
+                        market.total_supply_shares += shares;
+                    }
+                    LendingOp::Withdraw { user, pct } => {
+                        let shares = supply_shares[user] * pct / 100;
+                        let assets = to_assets_down(shares, market.total_supply_assets, market.total_supply_shares).unwrap();
+                        if shares == 0 || assets > market.available_liquidity() {
+                            continue;
+                        }
+                        supply_shares[user] -= shares;
+                        withdrawn[user] += assets;
+                        vault = checked_sub(vault, assets).unwrap();
+                        market.total_supply_assets = checked_sub(market.total_supply_assets, assets).unwrap();
+                        market.total_supply_shares = checked_sub(market.total_supply_shares, shares).unwrap();
+
+                        proptest::prop_assert!(
+                            withdrawn[user] <= supplied[user] + fair_interest[user] + step as u128 + 1,
+                            "user {} withdrew {} of {} supplied + {} interest",
+                            user, withdrawn[user], supplied[user], fair_interest[user]
+                        );
+                    }
+                    LendingOp::Borrow { pct } => {
+                        let assets = market.available_liquidity() * pct / 100;
+                        if assets == 0 {
+                            continue;
+                        }
+                        let shares = to_shares_up(assets, market.total_borrow_assets, market.total_borrow_shares).unwrap();
+                        debt_shares += shares;
+                        vault = checked_sub(vault, assets).unwrap();
+                        market.total_borrow_assets += assets;
+                        market.total_borrow_shares += shares;
+                    }
+                    LendingOp::Repay { pct } => {
+                        let shares = debt_shares * pct / 100;
+                        if shares == 0 {
+                            continue;
+                        }
+                        let assets = to_assets_up(shares, market.total_borrow_assets, market.total_borrow_shares).unwrap();
+                        debt_shares -= shares;
+                        vault += assets;
+                        // Same saturation as the repay handler
+                        market.total_borrow_assets = market.total_borrow_assets.saturating_sub(assets);
+                        market.total_borrow_shares = checked_sub(market.total_borrow_shares, shares).unwrap();
+                    }
+                    LendingOp::Accrue { elapsed, rate } => {
+                        now += elapsed;
+                        let result = accrue_interest_on_market(&mut market, now, rate).unwrap();
+                        for user in 0..LENDING_USERS {
+                            if supply_shares[user] > 0 {
+                                fair_interest[user] += mul_div_up(
+                                    result.interest,
+                                    supply_shares[user],
+                                    market.total_supply_shares,
+                                ).unwrap();
+                            }
+                        }
+                    }
+                }
+
+                assert_invariants(&market).unwrap();
+                proptest::prop_assert_eq!(market.total_borrow_shares, debt_shares);
+                proptest::prop_assert_eq!(market.total_supply_shares, supply_shares.iter().sum::<u128>());
+                // Every supply claim not lent out is held by the vault
+                proptest::prop_assert!(vault >= market.available_liquidity());
+            }
+        }
     }
 }
@@ -5,12 +5,17 @@
 //! 
 //! ## Rounding Rules (Always favor protocol)
 //! 
-//! | Operation | Convert         | Rounding | Reason                  |
-//! |-----------|-----------------|----------|-------------------------|
-//! | Supply    | assets → shares | DOWN     | User gets fewer shares  |
-//! | Withdraw  | shares → assets | DOWN     | User gets fewer assets  |
-//! | Borrow    | assets → shares | UP       | User owes more shares   |
-//! | Repay     | shares → assets | UP       | User pays more assets   |
+//! | Instruction        | Input  | Convert         | Function         | Reason                   |
+//! |--------------------|--------|-----------------|------------------|--------------------------|
+//! | Supply             | assets | assets → shares | `to_shares_down` | User gets fewer shares   |
+//! | Supply             | shares | shares → assets | `to_assets_up`   | User pays more assets    |
+//! | Withdraw           | assets | assets → shares | `to_shares_up`   | User burns more shares   |
+//! | Withdraw           | shares | shares → assets | `to_assets_down` | User gets fewer assets   |
+//! | Borrow             | assets | assets → shares | `to_shares_up`   | User owes more shares    |
+//! | Repay              | assets | assets → shares | `to_shares_down` | User clears fewer shares |
+//! | Repay              | shares | shares → assets | `to_assets_up`   | User pays more assets    |
+//! | Liquidate          | assets | assets → shares | `to_shares_down` | Fewer shares cleared     |
+//! | Health / LTV check | shares | shares → assets | `to_assets_up`   | Debt is never understated |
 
 use anchor_lang::prelude::*;
 use crate::constants::{VIRTUAL_SHARES, VIRTUAL_ASSETS};
@@ -85,6 +90,30 @@ pub fn to_assets_up(
     )
 }
 
+/// Assets to pull and shares to burn for a repay of `assets` or `shares`
+/// 
+/// Exactly one of `assets` / `shares` is non-zero. Both modes are capped at
+/// the position's `borrow_shares`, and the assets pulled are always
+/// `to_assets_up` of the shares burned, so passing the full `borrow_shares`
+/// clears the debt exactly. The assets can exceed `total_assets` by a unit
+/// of rounding; callers subtract them from the market with a zero floor.
+pub fn repay_amounts(
+    assets: u128,
+    shares: u128,
+    borrow_shares: u128,
+    total_assets: u128,
+    total_shares: u128,
+) -> Result<(u128, u128)> {
+    let burn_shares = if assets > 0 {
+        to_shares_down(assets, total_assets, total_shares)?
+    } else {
+        shares
+    };
+    let burn_shares = std::cmp::min(burn_shares, borrow_shares);
+    let repay_assets = to_assets_up(burn_shares, total_assets, total_shares)?;
+    Ok((repay_assets, burn_shares))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,4 +194,78 @@ mod tests {
         // (with some profit from the attack, but limited)
         assert!(attacker_value < donated + victim_deposit);
     }
+
+    #[test]
+    fn test_repay_full_shares_clears_debt() {
+        // Two borrowers, then interest accrues
+        let alice_shares = to_shares_up(1_000_003, 0, 0).unwrap();
+        let mut total_assets = 1_000_003u128;
+        let mut total_shares = alice_shares;
+        let bob_shares = to_shares_up(777_777, total_assets, total_shares).unwrap();
+        total_assets += 777_777;
+        total_shares += bob_shares;
+        total_assets += 12_345;
+
+        // Repaying by assets leaves dust shares behind
+        let alice_debt = to_assets_up(alice_shares, total_assets, total_shares).unwrap();
+        let (_, burned) = repay_amounts(alice_debt - 1, 0, alice_shares, total_assets, total_shares).unwrap();
+        assert!(burned < alice_shares);
+
+        // Repaying by shares burns exactly the position's shares
+        for shares in [alice_shares, u128::MAX] {
+            let (assets, burned) = repay_amounts(0, shares, alice_shares, total_assets, total_shares).unwrap();
+            assert_eq!(burned, alice_shares);
+            assert_eq!(assets, alice_debt);
+        }
+        total_assets = total_assets.saturating_sub(alice_debt);
+        total_shares -= alice_shares;
+
+        // The last borrower can always close out, down to zero shares
+        let (assets, burned) = repay_amounts(0, bob_shares, bob_shares, total_assets, total_shares).unwrap();
+        assert_eq!(total_shares - burned, 0);
+        assert!(assets + 1 >= total_assets, "Repay never pays less than the remaining debt");
+    }
+
+    #[test]
+    fn test_supply_and_withdraw_by_shares_round_trip() {
+        let mut total_assets = 5_000_000u128;
+        let mut total_shares = to_shares_down(total_assets, 0, 0).unwrap();
+        total_assets += 1_234; // interest
+
+        // Supplying exact shares pays at least their value
+        let shares = 3_333_333_333u128;
+        let paid = to_assets_up(shares, total_assets, total_shares).unwrap();
+        assert!(paid >= to_assets_down(shares, total_assets, total_shares).unwrap());
+        total_assets += paid;
+        total_shares += shares;
+
+        // Withdrawing the full balance by shares burns exactly what was
+        // minted and never pays out more than was paid in
+        let out = to_assets_down(shares, total_assets, total_shares).unwrap();
+        assert!(out <= paid);
+        total_shares -= shares;
+        total_assets -= out;
+        assert_eq!(total_shares, to_shares_down(5_000_000, 0, 0).unwrap());
+        assert!(total_assets >= 5_000_000 + 1_234);
+    }
+
+    #[test]
+    fn test_donation_does_not_grief_second_depositor() {
+        // Attacker deposits 1 wei, then transfers a large amount directly
+        let attacker_shares = to_shares_down(1, 0, 0).unwrap();
+        let total_assets = 1 + 1_000_000_000_000u128;
+
+        // Victim deposits a normal amount after the donation
+        let victim_deposit = 1_000_000_000u128;
+        let victim_shares = to_shares_down(victim_deposit, total_assets, attacker_shares).unwrap();
+        assert!(victim_shares > 0);
+
+        // Victim can redeem almost all of it; the loss is bounded by rounding
+        let victim_value = to_assets_down(
+            victim_shares,
+            total_assets + victim_deposit,
+            attacker_shares + victim_shares,
+        ).unwrap();
+        assert!(victim_value >= victim_deposit - victim_deposit / 1_000);
+    }
 }
@@ -8,14 +8,28 @@ use crate::errors::MorphoError;
 use crate::constants::WAD;
 use super::safe_math::{checked_mul, checked_add};
 
-/// Multiply then divide, rounding DOWN
+/// Rounding direction for fixed-point division
+///
+/// Pick the direction that favors the protocol: `Down` when crediting a
+/// user, `Up` when charging one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+/// Multiply then divide with an explicit rounding direction
 /// Order: (a * b) / c
 /// 
+/// The intermediate product is computed in 256 bits when it does not fit in
+/// u128, so only a final result above u128::MAX is an overflow.
+/// 
 /// # Arguments
 /// * `a` - First multiplicand
 /// * `b` - Second multiplicand  
 /// * `c` - Divisor (must be non-zero)
-pub fn mul_div_down(a: u128, b: u128, c: u128) -> Result<u128> {
+/// * `rounding` - Direction to round an inexact quotient
+pub fn mul_div(a: u128, b: u128, c: u128, rounding: Rounding) -> Result<u128> {
     if c == 0 {
         return Err(MorphoError::DivisionByZero.into());
     }
@@ -24,34 +38,72 @@ pub fn mul_div_down(a: u128, b: u128, c: u128) -> Result<u128> {
         return Ok(0);
     }
     
-    let product = checked_mul(a, b)?;
-    Ok(product / c)
+    let (quotient, remainder) = match a.checked_mul(b) {
+        Some(product) => (product / c, product % c),
+        // Intermediate exceeds u128 - fall back to 256-bit division
+        None => div_wide(mul_wide(a, b), c)?,
+    };
+
+    // Round up when the division is inexact
+    if rounding == Rounding::Down || remainder == 0 {
+        Ok(quotient)
+    } else {
+        quotient.checked_add(1).ok_or_else(|| MorphoError::MathOverflow.into())
+    }
+}
+
+/// Multiply then divide, rounding DOWN
+/// Order: (a * b) / c
+pub fn mul_div_down(a: u128, b: u128, c: u128) -> Result<u128> {
+    mul_div(a, b, c, Rounding::Down)
 }
 
 /// Multiply then divide, rounding UP
 /// Formula: (a * b + c - 1) / c
-/// 
-/// # Arguments
-/// * `a` - First multiplicand
-/// * `b` - Second multiplicand
-/// * `c` - Divisor (must be non-zero)
 pub fn mul_div_up(a: u128, b: u128, c: u128) -> Result<u128> {
-    if c == 0 {
-        return Err(MorphoError::DivisionByZero.into());
+    mul_div(a, b, c, Rounding::Up)
+}
+
+/// Full 256-bit product of two u128 values as (high, low) halves
+fn mul_wide(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & MASK);
+    let (b_hi, b_lo) = (b >> 64, b & MASK);
+
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_hi = a_hi * b_hi;
+
+    // Middle column: three addends below 2^64, so the sum cannot overflow
+    let mid = (lo_lo >> 64) + (hi_lo & MASK) + (lo_hi & MASK);
+    let low = (mid << 64) | (lo_lo & MASK);
+    let high = hi_hi + (hi_lo >> 64) + (lo_hi >> 64) + (mid >> 64);
+    (high, low)
+}
+
+/// Divide a 256-bit (high, low) value by a non-zero u128
+/// 
+/// Returns (quotient, remainder). Errors with MathOverflow when the
+/// quotient does not fit in u128 (i.e. `high >= divisor`).
+fn div_wide((high, low): (u128, u128), divisor: u128) -> Result<(u128, u128)> {
+    if high >= divisor {
+        return Err(MorphoError::MathOverflow.into());
     }
-    
-    if a == 0 || b == 0 {
-        return Ok(0);
+
+    // Binary long division over the low 128 bits
+    let mut remainder = high;
+    let mut quotient = 0u128;
+    for i in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> i) & 1);
+        quotient <<= 1;
+        if carry == 1 || remainder >= divisor {
+            remainder = remainder.wrapping_sub(divisor);
+            quotient |= 1;
+        }
     }
-    
-    let product = checked_mul(a, b)?;
-    // (product + c - 1) / c = ceil division
-    let result = product
-        .checked_add(c - 1)
-        .ok_or(MorphoError::MathOverflow)?
-        / c;
-    
-    Ok(result)
+    Ok((quotient, remainder))
 }
 
 /// WAD multiplication (a * b / WAD), rounded down
@@ -78,6 +130,65 @@ pub fn wad_div_up(a: u128, b: u128) -> Result<u128> {
     mul_div_up(a, WAD, b)
 }
 
+/// ln(2), WAD-scaled
+pub const LN_2_WAD: i128 = 693_147_180_559_945_309;
+
+/// ln(1e-18): below this `wad_exp` rounds to zero
+pub const WAD_EXP_MIN_INPUT: i128 = -41_446_531_673_892_822_312;
+
+/// `wad_exp` clamps its input here (e^40 ≈ 2.4e17), keeping every
+/// intermediate and the result well inside u128
+pub const WAD_EXP_MAX_INPUT: i128 = 40 * WAD as i128;
+
+/// Taylor terms evaluated after range reduction
+const WAD_EXP_TERMS: i128 = 12;
+
+/// e^x for a signed WAD exponent, WAD-scaled
+///
+/// Reduces x = q·ln(2) + r with |r| <= ln(2)/2, evaluates a 12-term Taylor
+/// series for e^r in Horner form and shifts by q. The truncated tail is
+/// below 2e-16, so the result is within 1e-15 · max(e^x, 1) of exact
+/// (at most 1_000 wei per WAD). About 30 i128 mul/div, no loops over x.
+///
+/// Returns 0 below WAD_EXP_MIN_INPUT and clamps x at WAD_EXP_MAX_INPUT.
+pub fn wad_exp(x: i128) -> u128 {
+    let wad = WAD as i128;
+    if x < WAD_EXP_MIN_INPUT {
+        return 0;
+    }
+    let x = x.min(WAD_EXP_MAX_INPUT);
+
+    let rounding = if x < 0 { -(LN_2_WAD / 2) } else { LN_2_WAD / 2 };
+    let q = (x + rounding) / LN_2_WAD;
+    let r = x - q * LN_2_WAD;
+
+    // e^r = 1 + r(1 + r/2(1 + r/3(1 + ...)))
+    let mut exp_r = wad;
+    for k in (1..=WAD_EXP_TERMS).rev() {
+        exp_r = wad + exp_r * r / wad / k;
+    }
+
+    let exp_r = exp_r as u128;
+    if q >= 0 {
+        exp_r << q
+    } else {
+        exp_r >> (-q)
+    }
+}
+
+/// Continuously compounded interest factor e^(rate * time) - 1
+///
+/// The exponent is capped at WAD_EXP_MAX_INPUT, so arbitrarily long
+/// idle periods cannot overflow.
+///
+/// # Arguments
+/// * `rate` - Per-second interest rate (WAD-scaled)
+/// * `time` - Time elapsed in seconds
+pub fn wad_exp_compounded(rate: u128, time: u128) -> Result<u128> {
+    let rt = rate.saturating_mul(time).min(WAD_EXP_MAX_INPUT as u128);
+    Ok(wad_exp(rt as i128).saturating_sub(WAD))
+}
+
 /// Calculate compound interest factor using Taylor expansion
 /// e^(rate * time) - 1 ≈ rt + (rt)²/2 + (rt)³/6
 /// 
@@ -137,6 +248,36 @@ mod tests {
         assert_eq!(mul_div_up(100, 200, 200).unwrap(), 100);
     }
 
+    #[test]
+    fn test_mul_div_wide_intermediate() {
+        // (1e36 * 1e36) / 1e36 overflows u128 in the product, not the result
+        let scale = 10u128.pow(36);
+        assert_eq!(mul_div_down(scale, scale, scale).unwrap(), scale);
+        assert_eq!(mul_div_down(3 * scale, scale, 2 * scale).unwrap(), scale + scale / 2);
+
+        // 2^127 * 3 / 2 = 3 * 2^126, exact
+        assert_eq!(mul_div_down(1 << 127, 3, 2).unwrap(), 3 << 126);
+
+        // Inexact wide division rounds in the requested direction
+        assert_eq!(mul_div_down(u128::MAX, u128::MAX, u128::MAX - 1).unwrap_err(), MorphoError::MathOverflow.into());
+        assert_eq!(mul_div_down(u128::MAX, 7, 9).unwrap(), u128::MAX / 9 * 7 + (u128::MAX % 9) * 7 / 9);
+        assert_eq!(mul_div_up(u128::MAX, 7, 9).unwrap(), mul_div_down(u128::MAX, 7, 9).unwrap() + 1);
+
+        // A result that no longer fits in u128 is still an overflow
+        assert!(mul_div_down(u128::MAX, 2, 1).is_err());
+        assert!(mul_div_up(u128::MAX, u128::MAX, 1).is_err());
+    }
+
+    #[test]
+    fn test_mul_div_rounding_modes() {
+        assert_eq!(mul_div(100, 200, 300, Rounding::Down).unwrap(), 66);
+        assert_eq!(mul_div(100, 200, 300, Rounding::Up).unwrap(), 67);
+        // Exact division is the same either way
+        assert_eq!(mul_div(100, 300, 300, Rounding::Up).unwrap(), 100);
+        assert!(mul_div(1, 1, 0, Rounding::Up).is_err());
+        assert!(mul_div(u128::MAX, u128::MAX, 1, Rounding::Down).is_err());
+    }
+
     #[test]
     fn test_wad_mul() {
         let half_wad = WAD / 2;
@@ -163,4 +304,55 @@ mod tests {
         // Zero time should give zero factor
         assert_eq!(w_taylor_compounded(rate, 0).unwrap(), 0);
     }
+
+    /// |got - exact| <= max(exact, WAD) / 1e15
+    fn assert_exp_close(x: i128, exact: u128) {
+        let got = wad_exp(x);
+        let tolerance = exact.max(WAD) / 1_000_000_000_000_000;
+        assert!(got.abs_diff(exact) <= tolerance, "e^{x}: got {got}, want {exact}");
+    }
+
+    #[test]
+    fn test_wad_exp_reference_values() {
+        const WAD_I: i128 = WAD as i128;
+        assert_eq!(wad_exp(0), WAD);
+        assert_exp_close(WAD_I, 2_718_281_828_459_045_235);
+        assert_exp_close(-WAD_I, 367_879_441_171_442_321);
+        assert_exp_close(WAD_I / 20, 1_051_271_096_376_024_039);
+        assert_exp_close(WAD_I / 1_000_000, 1_000_001_000_000_500_000);
+        assert_exp_close(-3 * WAD_I, 49_787_068_367_863_942);
+        assert_exp_close(10 * WAD_I, 22_026_465_794_806_716_516_957);
+        assert_exp_close(20 * WAD_I, 485_165_195_409_790_277_969_106_830);
+        assert_exp_close(40 * WAD_I, 235_385_266_837_019_985_407_899_910_749_034_804);
+    }
+
+    #[test]
+    fn test_wad_exp_bounds() {
+        assert_eq!(wad_exp(WAD_EXP_MIN_INPUT - 1), 0);
+        assert_eq!(wad_exp(i128::MAX), wad_exp(WAD_EXP_MAX_INPUT));
+        assert!(wad_exp(WAD_EXP_MIN_INPUT) <= 1);
+    }
+
+    #[test]
+    fn test_exp_compounded() {
+        // 10% for one year: e^0.1 - 1 = 0.105170918075647624...
+        let rate = WAD / 10 / 31_536_000;
+        let factor = wad_exp_compounded(rate, 31_536_000).unwrap();
+        assert!(factor.abs_diff(105_170_918_075_647_624) < 100_000_000, "{factor}");
+
+        // Within 1e-6 of the three-term Taylor series over a day; the
+        // difference is the truncated (rt)^4/24 tail
+        let rate = 158_000_000_000u128;
+        let taylor = w_taylor_compounded(rate, 86_400).unwrap();
+        let exact = wad_exp_compounded(rate, 86_400).unwrap();
+        assert!(exact >= taylor);
+        assert!(exact - taylor < taylor / 1_000_000);
+
+        assert_eq!(wad_exp_compounded(0, 86_400).unwrap(), 0);
+        assert_eq!(wad_exp_compounded(rate, 0).unwrap(), 0);
+
+        // Centuries of maximum rate saturate instead of overflowing
+        let capped = wad_exp_compounded(u128::MAX, u128::MAX).unwrap();
+        assert_eq!(capped, wad_exp(WAD_EXP_MAX_INPUT) - WAD);
+    }
 }
@@ -0,0 +1,16 @@
+//! Compute unit checkpoints
+//!
+//! Built with the `profiling` feature, `cu_checkpoint` logs a label and the
+//! compute units left, so the cost of each phase of a handler is the
+//! difference between consecutive checkpoints in the transaction logs.
+//! Without the feature it compiles to nothing.
+
+/// Log `label` and the remaining compute units (`profiling` builds only)
+#[inline(always)]
+pub fn cu_checkpoint(_label: &str) {
+    #[cfg(feature = "profiling")]
+    {
+        anchor_lang::prelude::msg!("CU checkpoint: {}", _label);
+        anchor_lang::solana_program::log::sol_log_compute_units();
+    }
+}
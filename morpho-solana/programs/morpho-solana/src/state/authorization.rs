@@ -1,7 +1,8 @@
 //! Authorization state account
 //! 
 //! Enables delegation of position management to authorized addresses.
-//! Supports expiration and revocation.
+//! Supports expiration and revocation, and grants signed off-chain
+//! (see `authorization_message`) with a per-authorizer replay nonce.
 
 use anchor_lang::prelude::*;
 use crate::constants::PROGRAM_SEED_PREFIX;
@@ -59,6 +60,13 @@ impl Authorization {
         (self.expires_at == 0 || current_time < self.expires_at)
     }
 
+    /// Check if this authorization lets `caller` manage `owner`'s positions
+    pub fn permits(&self, owner: &Pubkey, caller: &Pubkey, current_time: i64) -> bool {
+        self.authorizer == *owner
+            && self.authorized == *caller
+            && self.is_valid(current_time)
+    }
+
     /// Revoke authorization permanently
     pub fn revoke(&mut self) {
         self.is_authorized = false;
@@ -66,6 +74,79 @@ impl Authorization {
     }
 }
 
+/// Replay protection for signed authorizations
+///
+/// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_authorization_nonce", authorizer]
+#[account]
+pub struct AuthorizationNonce {
+    /// PDA bump seed
+    pub bump: u8,
+
+    /// Account whose signatures this nonce tracks
+    pub authorizer: Pubkey,
+
+    /// Next nonce a signature must commit to
+    pub nonce: u64,
+
+    /// Reserved for future use
+    pub reserved: [u8; 16],
+}
+
+impl AuthorizationNonce {
+    pub const SEED: &'static [u8] = b"morpho_authorization_nonce";
+
+    pub fn space() -> usize {
+        8 +     // discriminator
+        1 +     // bump
+        32 +    // authorizer
+        8 +     // nonce
+        16      // reserved
+    }
+}
+
+/// Domain tag prepended to every signed authorization message
+pub const AUTHORIZATION_MESSAGE_PREFIX: &[u8] = b"morpho_set_authorization";
+
+/// Bytes the authorizer signs with Ed25519 to grant an authorization
+///
+/// Layout: prefix || program_id || authorizer || authorized || is_authorized (u8)
+/// || expires_at (i64 LE) || nonce (u64 LE) || deadline (i64 LE)
+pub fn authorization_message(
+    program_id: &Pubkey,
+    authorizer: &Pubkey,
+    authorized: &Pubkey,
+    is_authorized: bool,
+    expires_at: i64,
+    nonce: u64,
+    deadline: i64,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(AUTHORIZATION_MESSAGE_PREFIX.len() + 32 * 3 + 1 + 8 * 3);
+    message.extend_from_slice(AUTHORIZATION_MESSAGE_PREFIX);
+    message.extend_from_slice(program_id.as_ref());
+    message.extend_from_slice(authorizer.as_ref());
+    message.extend_from_slice(authorized.as_ref());
+    message.push(is_authorized as u8);
+    message.extend_from_slice(&expires_at.to_le_bytes());
+    message.extend_from_slice(&nonce.to_le_bytes());
+    message.extend_from_slice(&deadline.to_le_bytes());
+    message
+}
+
+/// Derive authorization nonce PDA
+pub fn derive_authorization_nonce(
+    program_id: &Pubkey,
+    authorizer: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PROGRAM_SEED_PREFIX,
+            AuthorizationNonce::SEED,
+            authorizer.as_ref(),
+        ],
+        program_id,
+    )
+}
+
 /// Derive authorization PDA
 pub fn derive_authorization(
     program_id: &Pubkey,
@@ -6,71 +6,254 @@
 
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::keccak;
-use crate::constants::{PROGRAM_SEED_PREFIX, WAD, BPS};
-use crate::math::{mul_div_down, checked_sub};
+use crate::constants::{PROGRAM_SEED_PREFIX, WAD, ORACLE_SCALE, BPS, SLOT_DURATION_MS};
+use crate::errors::MorphoError;
+use crate::math::{mul_div_down, mul_div_up, checked_add, checked_sub};
+
+/// Oracle type used to price a market
+/// 
+/// Stored on `Market` as a `u8`. Zero is Switchboard so markets
+/// created before the field existed keep their original behaviour.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OracleKind {
+    /// Switchboard on-demand pull feed
+    Switchboard = 0,
+    /// StaticOracle account (testing only)
+    Static = 1,
+    /// Pyth `PriceUpdateV2` account
+    Pyth = 2,
+    /// `TwapOracle` account averaging a Switchboard or Pyth feed
+    Twap = 3,
+    /// `MedianOracle` account aggregating several feeds
+    Median = 4,
+}
+
+impl TryFrom<u8> for OracleKind {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(OracleKind::Switchboard),
+            1 => Ok(OracleKind::Static),
+            2 => Ok(OracleKind::Pyth),
+            3 => Ok(OracleKind::Twap),
+            4 => Ok(OracleKind::Median),
+            _ => Err(MorphoError::InvalidOracleKind.into()),
+        }
+    }
+}
+
+/// Clock a market accrues interest against
+///
+/// Stored on `Market` as a `u8`; zero is Timestamp, the original behaviour.
+///
+/// Timestamp uses `Clock::unix_timestamp`, so interest follows wall time,
+/// but the leader can skew the timestamp within the cluster's allowed drift
+/// of the stake-weighted median. Slots counts `Clock::slot` at an assumed
+/// SLOT_DURATION_MS per slot: no single validator can move it, but interest
+/// runs ahead of wall time whenever slots come faster than the estimate and
+/// falls behind while they are slower or skipped leaders stall the chain.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccrualBasis {
+    /// Unix timestamp seconds
+    Timestamp = 0,
+    /// Slots, converted to seconds at SLOT_DURATION_MS per slot
+    Slots = 1,
+}
+
+impl TryFrom<u8> for AccrualBasis {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(AccrualBasis::Timestamp),
+            1 => Ok(AccrualBasis::Slots),
+            _ => Err(MorphoError::InvalidInput.into()),
+        }
+    }
+}
 
 /// Individual lending market state
 /// 
 /// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_market", market_id]
-#[account]
+/// 
+/// Zero-copy: handlers load it through `AccountLoader` and read and write
+/// fields in place, dropping the borrow before any CPI that passes the
+/// market (as a vault authority or to a callback). Fields are grouped by
+/// alignment (u128, 32-byte keys, u64, u32, u8) so the `repr(C)` layout has
+/// no padding on any target; see
+/// `test_market_layout_stable`. New fields are carved out of `reserved`,
+/// or appended once it is used up. Accounts written before the zero-copy
+/// layout, or before fields were appended, are converted with
+/// `migrate_market`.
+#[account(zero_copy)]
 pub struct Market {
-    /// PDA bump seed
-    pub bump: u8,
+    // === Totals (u128) ===
+
+    /// Total loan tokens supplied (increases with interest)
+    pub total_supply_assets: u128,
+
+    /// Total supply shares outstanding
+    pub total_supply_shares: u128,
+
+    /// Total loan tokens borrowed (increases with interest)
+    pub total_borrow_assets: u128,
+
+    /// Total borrow shares outstanding
+    pub total_borrow_shares: u128,
+
+    /// Accumulated fee shares owed to fee_recipient
+    /// Periodically claimed via claim_fees instruction
+    pub pending_fee_shares: u128,
+
+    /// Last accepted oracle price (raw token units, as returned to handlers)
+    pub last_oracle_price: u128,
+
+    /// Adaptive curve rate at target utilization (per-second, WAD-scaled)
+    /// 0 = not yet initialized (starts at INITIAL_RATE_AT_TARGET)
+    pub rate_at_target: u128,
+
+    /// Upper bound on the validated price, in the units markets compare
+    /// against (0 = max_oracle_price() only)
+    pub max_price: u128,
+
+    /// Pending bad debt at which it is charged to suppliers
+    /// (loan token units, 0 = charge every write-off immediately)
+    pub bad_debt_threshold: u128,
+
+    /// Bad debt written off the borrow side but not yet charged to suppliers
+    /// (loan token units, included in total_supply_assets until socialized)
+    pub pending_bad_debt: u128,
+
+    /// Ceiling on `total_supply_assets` for new supply, in loan tokens
+    /// (0 = uncapped)
+    pub supply_cap: u128,
+
+    /// Floor on the IRM's borrow rate (per-second, WAD-scaled)
+    pub min_rate: u128,
+
+    /// Ceiling on the IRM's borrow rate (per-second, WAD-scaled;
+    /// 0 = MAX_BORROW_RATE_PER_SECOND only)
+    pub max_rate: u128,
+
+    // === Identity and Oracles (32 bytes) ===
 
     /// Unique market identifier (keccak256 hash of parameters)
     pub market_id: [u8; 32],
 
-    // === Immutable Parameters (set at creation) ===
-
     /// Collateral token mint
     pub collateral_mint: Pubkey,
 
     /// Loan token mint
     pub loan_mint: Pubkey,
 
-    /// Collateral token decimals (cached for gas savings)
-    pub collateral_decimals: u8,
-
-    /// Loan token decimals (cached for gas savings)
-    pub loan_decimals: u8,
-
     /// Oracle program/account for price
     pub oracle: Pubkey,
 
     /// Interest rate model program
     pub irm: Pubkey,
 
-    /// Loan-to-value ratio (basis points, e.g., 8500 = 85%)
-    pub lltv: u64,
+    /// Loan/USD feed for dual-feed markets (`oracle` is then collateral/USD)
+    /// Pubkey::default() = single-feed market
+    pub oracle_secondary: Pubkey,
 
-    // === Mutable State ===
+    /// Oracle consulted when the primary is stale or out of bounds
+    /// Pubkey::default() = no fallback
+    pub oracle_fallback: Pubkey,
 
-    /// Market-specific pause flag
-    pub paused: bool,
+    // === Parameters and Slots (u64) ===
+
+    /// Loan-to-value ratio (basis points, e.g., 8500 = 85%)
+    pub lltv: u64,
 
     /// Protocol fee (basis points, max 2500 = 25%)
     pub fee: u64,
 
-    /// Total loan tokens supplied (increases with interest)
-    pub total_supply_assets: u128,
+    /// Last interest accrual time, in seconds on the market's accrual
+    /// clock (see `accrual_time`)
+    pub last_update: i64,
 
-    /// Total supply shares outstanding
-    pub total_supply_shares: u128,
+    /// Maximum oracle confidence interval as a fraction of price (basis points)
+    /// 0 = use MAX_ORACLE_CONFIDENCE_BPS
+    pub max_oracle_confidence_bps: u64,
 
-    /// Total loan tokens borrowed (increases with interest)
-    pub total_borrow_assets: u128,
+    /// Maximum oracle age in slots, within [1, 300]
+    /// 0 = use MAX_ORACLE_STALENESS (markets created before this field)
+    pub max_staleness_slots: u64,
 
-    /// Total borrow shares outstanding
-    pub total_borrow_shares: u128,
+    /// Slot at which `last_oracle_price` was accepted
+    pub last_oracle_slot: u64,
 
-    /// Last interest accrual timestamp
-    pub last_update: i64,
+    /// Maximum move from `last_oracle_price` within the staleness window
+    /// (basis points, 0 = circuit breaker disabled)
+    pub max_deviation_bps: u64,
 
-    /// Accumulated fee shares owed to fee_recipient
-    /// Periodically claimed via claim_fees instruction
-    pub pending_fee_shares: u128,
+    /// Max share of a position's debt repayable per liquidation (basis points)
+    /// 0 = use DEFAULT_CLOSE_FACTOR_BPS
+    pub close_factor_bps: u64,
+
+    /// Slots over which the auction LIF ramps from LIF_BPS up to the
+    /// market's max LIF
+    /// 0 = auction disabled (fixed `calculate_lif`)
+    pub auction_duration_slots: u64,
+
+    /// Dust floor on debt, in loan tokens (0 = none)
+    pub min_borrow: u64,
+
+    /// Dust floor on collateral value, in loan tokens (0 = none)
+    pub min_collateral: u64,
+
+    /// Slot in which `last_oracle_price` passed full validation; later
+    /// reads in that slot reuse it (0 = no cached price)
+    pub oracle_cache_slot: u64,
+
+    /// Maximum Switchboard standard deviation across responses as a
+    /// fraction of price (basis points, 0 = MAX_ORACLE_STD_BPS)
+    pub max_std_bps: u64,
+
+    /// Highest utilization new borrows may reach (basis points, 0 = no cap)
+    pub max_utilization_bps: u64,
+
+    /// Share of the liquidation bonus routed to the fee recipient
+    /// (basis points of seized collateral above the repaid value, 0 = none)
+    pub liquidation_protocol_fee_bps: u64,
 
-    // === Vault Bumps ===
+    /// Cursor of the LIF curve (basis points, 0 = LIF_CURSOR)
+    pub lif_cursor: u64,
+
+    /// Cap on the LIF, also the end of the auction ramp
+    /// (scaled by LIF_BPS, 0 = MAX_LIF)
+    pub max_lif: u64,
+
+    /// Publication slot of the read behind the cached oracle price
+    pub oracle_cache_published_slot: u64,
+
+    /// Confidence of the read behind the cached oracle price (basis points)
+    pub oracle_cache_confidence_bps: u64,
+
+    /// Slots a position must stay liquidatable before it can be
+    /// liquidated (0 = no grace period)
+    pub liquidation_grace_slots: u64,
+
+    // === u32 ===
+
+    /// Oracle responses a Switchboard read must aggregate
+    /// 0 = MIN_ORACLE_SAMPLES (markets created before the field existed)
+    pub min_oracle_samples: u32,
+
+    // === Bumps and Flags (u8) ===
+
+    /// PDA bump seed
+    pub bump: u8,
+
+    /// Collateral token decimals (cached for gas savings)
+    pub collateral_decimals: u8,
+
+    /// Loan token decimals (cached for gas savings)
+    pub loan_decimals: u8,
+
+    /// Market-specific pause flag (0/1, see `is_paused`)
+    pub paused: u8,
 
     /// Bump for collateral vault PDA
     pub collateral_vault_bump: u8,
@@ -78,13 +261,57 @@ pub struct Market {
     /// Bump for loan vault PDA
     pub loan_vault_bump: u8,
 
-    // === Flash Loan Lock ===
-    
     /// Flash loan lock (non-zero means flash loan in progress)
     pub flash_loan_lock: u8,
 
-    /// Reserved for future use
-    pub reserved: [u8; 127],
+    /// Oracle type (see `OracleKind`, 0 = Switchboard)
+    pub oracle_kind: u8,
+
+    /// Whether `oracle_kind` has been set, at creation or by migration
+    /// (0/1, see `is_oracle_kind_migrated`)
+    pub oracle_kind_migrated: u8,
+
+    /// Whether the feed quotes loan per collateral and must be inverted,
+    /// e.g. a USDC/ETH market reusing an ETH/USDC feed (0/1, see
+    /// `is_oracle_inverted`)
+    pub invert_oracle: u8,
+
+    /// Reentrancy lock (1 while an integrator callback runs)
+    pub callback_lock: u8,
+
+    /// Aligns `reserve_assets` to 16 bytes
+    pub padding: [u8; 1],
+
+    // === Carved from `reserved` ===
+
+    /// Loan tokens deposited through `fund_reserve`, held in the loan vault
+    /// outside `total_supply_assets`; bad debt is charged here before
+    /// suppliers
+    pub reserve_assets: u128,
+
+    /// Clock interest accrues against (see `AccrualBasis`, 0 = Timestamp)
+    pub accrual_basis: u8,
+
+    /// Reserved for future use (also aligns the indices to 16 bytes)
+    pub reserved: [u8; 15],
+
+    /// Growth of one supply share's value from interest since creation
+    /// (WAD-scaled, see `supply_index`)
+    pub supply_index: u128,
+
+    /// Growth of one borrow share's debt from interest since creation
+    /// (WAD-scaled, see `borrow_index`)
+    pub borrow_index: u128,
+
+    // === Appended (see `MARKET_SPACE_BEFORE_FEED_IDS`) ===
+
+    /// Pyth feed id `oracle` and `oracle_fallback` updates must carry
+    /// (zero unless `oracle_kind` is Pyth)
+    pub pyth_feed_id: [u8; 32],
+
+    /// Pyth feed id `oracle_secondary` updates must carry (zero unless a
+    /// Pyth market has a secondary feed)
+    pub pyth_secondary_feed_id: [u8; 32],
 }
 
 impl Market {
@@ -93,28 +320,34 @@ impl Market {
     pub const LOAN_VAULT_SEED: &'static [u8] = b"morpho_loan_vault";
 
     pub fn space() -> usize {
-        8 +     // discriminator
-        1 +     // bump
-        32 +    // market_id
-        32 +    // collateral_mint
-        32 +    // loan_mint
-        1 +     // collateral_decimals
-        1 +     // loan_decimals
-        32 +    // oracle
-        32 +    // irm
-        8 +     // lltv
-        1 +     // paused
-        8 +     // fee
-        16 +    // total_supply_assets
-        16 +    // total_supply_shares
-        16 +    // total_borrow_assets
-        16 +    // total_borrow_shares
-        8 +     // last_update
-        16 +    // pending_fee_shares
-        1 +     // collateral_vault_bump
-        1 +     // loan_vault_bump
-        1 +     // flash_loan_lock
-        127     // reserved
+        8 + std::mem::size_of::<Market>()
+    }
+
+    /// Whether the market is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused != 0
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused as u8;
+    }
+
+    /// Whether `oracle_kind` has been set
+    pub fn is_oracle_kind_migrated(&self) -> bool {
+        self.oracle_kind_migrated != 0
+    }
+
+    pub fn set_oracle_kind_migrated(&mut self, migrated: bool) {
+        self.oracle_kind_migrated = migrated as u8;
+    }
+
+    /// Whether oracle prices are inverted before use
+    pub fn is_oracle_inverted(&self) -> bool {
+        self.invert_oracle != 0
+    }
+
+    pub fn set_oracle_inverted(&mut self, inverted: bool) {
+        self.invert_oracle = inverted as u8;
     }
 
     /// Calculate utilization rate (scaled by WAD = 1e18)
@@ -129,39 +362,392 @@ impl Market {
         ).unwrap_or(0)
     }
 
-    /// Get available liquidity (supply - borrows)
+    /// Get available liquidity (supply - borrows - pending bad debt)
+    ///
+    /// Pending bad debt is still counted in `total_supply_assets` but has no
+    /// tokens behind it in the vault.
     pub fn available_liquidity(&self) -> u128 {
-        checked_sub(self.total_supply_assets, self.total_borrow_assets).unwrap_or(0)
+        checked_sub(self.total_supply_assets, self.total_borrow_assets)
+            .unwrap_or(0)
+            .saturating_sub(self.pending_bad_debt)
+    }
+
+    /// Require `assets` to fit in the free liquidity, so taking them out
+    /// leaves `total_borrow_assets <= total_supply_assets`
+    pub fn ensure_liquidity(&self, assets: u128) -> Result<()> {
+        require!(assets <= self.available_liquidity(), MorphoError::InsufficientLiquidity);
+        Ok(())
+    }
+
+    /// Loan tokens that can still be supplied under `supply_cap`
+    /// (u128::MAX when uncapped, 0 once accrued interest passed the cap)
+    pub fn supply_cap_room(&self) -> u128 {
+        if self.supply_cap == 0 {
+            return u128::MAX;
+        }
+        self.supply_cap.saturating_sub(self.total_supply_assets)
+    }
+
+    /// Require a supply of `assets` to keep `total_supply_assets` within `supply_cap`
+    pub fn ensure_supply_cap(&self, assets: u128) -> Result<()> {
+        require!(assets <= self.supply_cap_room(), MorphoError::SupplyCapReached);
+        Ok(())
+    }
+
+    /// Require a new borrow of `assets` to keep utilization within
+    /// `max_utilization_bps` (rounded up, so the cap is never overshot)
+    pub fn ensure_utilization_cap(&self, assets: u128) -> Result<()> {
+        if self.max_utilization_bps == 0 {
+            return Ok(());
+        }
+        let borrowed = checked_add(self.total_borrow_assets, assets)?;
+        let utilization_bps = mul_div_up(borrowed, BPS as u128, self.total_supply_assets)?;
+        require!(
+            utilization_bps <= self.max_utilization_bps as u128,
+            MorphoError::UtilizationCapReached
+        );
+        Ok(())
+    }
+
+    /// New borrows that keep utilization within `max_utilization_bps`
+    /// (u128::MAX when uncapped), the inverse of `ensure_utilization_cap`
+    pub fn utilization_cap_room(&self) -> Result<u128> {
+        if self.max_utilization_bps == 0 {
+            return Ok(u128::MAX);
+        }
+        let max_borrowed = mul_div_down(
+            self.total_supply_assets,
+            self.max_utilization_bps as u128,
+            BPS as u128,
+        )?;
+        Ok(max_borrowed.saturating_sub(self.total_borrow_assets))
+    }
+
+    /// Whether a position is above the market's dust floor
+    ///
+    /// Positions with no debt are exempt: emptying a position is always
+    /// allowed, and collateral alone can never become bad debt.
+    pub fn above_dust_floor(&self, collateral: u128, borrowed: u128, oracle_price: u128) -> Result<bool> {
+        if borrowed == 0 {
+            return Ok(true);
+        }
+        let collateral_value = mul_div_down(collateral, oracle_price, ORACLE_SCALE)?;
+        Ok(borrowed >= self.min_borrow as u128 && collateral_value >= self.min_collateral as u128)
+    }
+
+    /// Require an indebted position to stay above the market's dust floor
+    pub fn ensure_position_size(&self, collateral: u128, borrowed: u128, oracle_price: u128) -> Result<()> {
+        require!(
+            self.above_dust_floor(collateral, borrowed, oracle_price)?,
+            MorphoError::PositionTooSmall
+        );
+        Ok(())
+    }
+
+    /// Price validated earlier in `slot`, if any
+    ///
+    /// The cache is advisory: it only skips re-reading the feed within a
+    /// slot. The first read of every slot runs the full validation.
+    pub fn cached_oracle_price(&self, slot: u64) -> Option<u128> {
+        (self.oracle_cache_slot != 0 && self.oracle_cache_slot == slot)
+            .then_some(self.last_oracle_price)
+    }
+
+    /// Force the next read to validate the feed again
+    ///
+    /// Call whenever a setting that shapes the price changes.
+    pub fn invalidate_oracle_cache(&mut self) {
+        self.oracle_cache_slot = 0;
+    }
+
+    /// Whether a position first seen liquidatable at `became_unhealthy_slot`
+    /// has outlasted the grace period by `slot`
+    pub fn liquidation_grace_elapsed(&self, became_unhealthy_slot: u64, slot: u64) -> bool {
+        self.liquidation_grace_slots == 0
+            || (became_unhealthy_slot > 0
+                && slot.saturating_sub(became_unhealthy_slot) >= self.liquidation_grace_slots)
     }
 
     /// Check if market is operational (not paused)
     pub fn is_operational(&self) -> bool {
-        !self.paused
+        !self.is_paused()
+    }
+
+    /// Get the configured oracle type
+    pub fn oracle_kind(&self) -> Result<OracleKind> {
+        OracleKind::try_from(self.oracle_kind)
+    }
+
+    /// Supply index, WAD for markets created before the field existed
+    ///
+    /// Multiplied at each accrual by the change in supply share price, net
+    /// of fee dilution. Losses from bad debt are not reflected here, so the
+    /// index measures interest only.
+    pub fn supply_index(&self) -> u128 {
+        if self.supply_index == 0 { WAD } else { self.supply_index }
+    }
+
+    /// Borrow index, WAD for markets created before the field existed
+    pub fn borrow_index(&self) -> u128 {
+        if self.borrow_index == 0 { WAD } else { self.borrow_index }
+    }
+
+    /// Get the configured accrual basis
+    pub fn accrual_basis(&self) -> Result<AccrualBasis> {
+        AccrualBasis::try_from(self.accrual_basis)
+    }
+
+    /// Current time on the market's accrual clock, in seconds
+    ///
+    /// `last_update` is kept on this clock, so `accrual_time - last_update`
+    /// is the elapsed interest period. Slot time is truncated to whole
+    /// seconds; the remainder carries into the next accrual because the
+    /// clock itself never goes backwards.
+    pub fn accrual_time(&self, clock: &Clock) -> Result<i64> {
+        Ok(match self.accrual_basis()? {
+            AccrualBasis::Timestamp => clock.unix_timestamp,
+            AccrualBasis::Slots => {
+                (clock.slot.saturating_mul(SLOT_DURATION_MS) / 1_000).min(i64::MAX as u64) as i64
+            }
+        })
     }
 
     /// Check if flash loan is in progress
     pub fn is_flash_loan_active(&self) -> bool {
         self.flash_loan_lock != 0
     }
+
+    /// Check if an integrator callback is in progress
+    pub fn is_callback_active(&self) -> bool {
+        self.callback_lock != 0
+    }
+
+    /// Take the reentrancy lock before handing control to a callback
+    ///
+    /// The `load_mut` borrow must be dropped before the CPI, both so the
+    /// runtime can hand the account on and so a nested call sees the lock.
+    pub fn enter_callback(&mut self) -> Result<()> {
+        require!(!self.is_callback_active(), MorphoError::ReentrancyDetected);
+        self.callback_lock = 1;
+        Ok(())
+    }
+
+    /// Release the reentrancy lock once the callback has returned
+    pub fn exit_callback(&mut self) {
+        self.callback_lock = 0;
+    }
+}
+
+/// Check a market's accounting invariants
+/// 
+/// - Borrowed assets plus pending bad debt never exceed supplied assets
+///   (`reserve_assets` is held on top, so it never backs borrows)
+/// - Outstanding supply shares are backed by supply assets
+/// 
+/// Neither side requires assets to be zero when shares are: withdrawals
+/// round down and leave dust owned by the virtual shares. Borrow shares are
+/// not checked against borrow assets at all, since a repay rounds up and
+/// may clear the last borrow asset while dust shares remain, and lowers the
+/// borrow share price below issuance in doing so.
+/// 
+/// Called after every operation in tests, and at the end of each handler
+/// when built with the `invariant-checks` feature.
+pub fn assert_invariants(market: &Market) -> Result<()> {
+    let owed = checked_add(market.total_borrow_assets, market.pending_bad_debt)?;
+    require!(
+        owed <= market.total_supply_assets,
+        MorphoError::InvariantBorrowExceedsSupply
+    );
+    require!(
+        market.total_supply_shares == 0 || market.total_supply_assets > 0,
+        MorphoError::InvariantSupplySharesUnbacked
+    );
+    Ok(())
+}
+
+/// Market length before the zero-copy layout, including the discriminator
+pub const LEGACY_MARKET_SPACE: usize = 622;
+
+/// Zero-copy market length before the Pyth feed ids were appended,
+/// including the discriminator
+pub const MARKET_SPACE_BEFORE_FEED_IDS: usize = 680;
+
+/// Borsh layout of `Market` before the zero-copy conversion
+///
+/// Only read by `migrate_market`. Accounts created before later fields
+/// were appended are shorter; they are zero-extended to
+/// `LEGACY_MARKET_SPACE` before decoding, which is how the former
+/// `resize_market` initialized those fields.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct LegacyMarket {
+    pub bump: u8,
+    pub market_id: [u8; 32],
+    pub collateral_mint: Pubkey,
+    pub loan_mint: Pubkey,
+    pub collateral_decimals: u8,
+    pub loan_decimals: u8,
+    pub oracle: Pubkey,
+    pub irm: Pubkey,
+    pub lltv: u64,
+    pub paused: bool,
+    pub fee: u64,
+    pub total_supply_assets: u128,
+    pub total_supply_shares: u128,
+    pub total_borrow_assets: u128,
+    pub total_borrow_shares: u128,
+    pub last_update: i64,
+    pub pending_fee_shares: u128,
+    pub collateral_vault_bump: u8,
+    pub loan_vault_bump: u8,
+    pub flash_loan_lock: u8,
+    pub max_oracle_confidence_bps: u64,
+    pub oracle_kind: u8,
+    pub oracle_kind_migrated: bool,
+    pub oracle_secondary: Pubkey,
+    pub max_staleness_slots: u64,
+    pub invert_oracle: bool,
+    pub oracle_fallback: Pubkey,
+    pub last_oracle_price: u128,
+    pub last_oracle_slot: u64,
+    pub max_deviation_bps: u64,
+    pub close_factor_bps: u64,
+    pub rate_at_target: u128,
+    pub auction_duration_slots: u64,
+    pub min_borrow: u64,
+    pub min_collateral: u64,
+    pub oracle_cache_slot: u64,
+    pub min_oracle_samples: u32,
+    pub max_price: u128,
+    pub max_std_bps: u64,
+    pub callback_lock: u8,
+    pub bad_debt_threshold: u128,
+    pub pending_bad_debt: u128,
+    pub max_utilization_bps: u64,
+    pub liquidation_protocol_fee_bps: u64,
+    pub lif_cursor: u64,
+    pub max_lif: u64,
+    pub oracle_cache_published_slot: u64,
+    pub oracle_cache_confidence_bps: u64,
+    pub liquidation_grace_slots: u64,
+    pub supply_cap: u128,
+    pub min_rate: u128,
+    pub max_rate: u128,
+    pub reserved: [u8; 7],
+}
+
+impl From<LegacyMarket> for Market {
+    fn from(legacy: LegacyMarket) -> Self {
+        Self {
+            bump: legacy.bump,
+            market_id: legacy.market_id,
+            collateral_mint: legacy.collateral_mint,
+            loan_mint: legacy.loan_mint,
+            collateral_decimals: legacy.collateral_decimals,
+            loan_decimals: legacy.loan_decimals,
+            oracle: legacy.oracle,
+            irm: legacy.irm,
+            lltv: legacy.lltv,
+            paused: legacy.paused as u8,
+            fee: legacy.fee,
+            total_supply_assets: legacy.total_supply_assets,
+            total_supply_shares: legacy.total_supply_shares,
+            total_borrow_assets: legacy.total_borrow_assets,
+            total_borrow_shares: legacy.total_borrow_shares,
+            last_update: legacy.last_update,
+            pending_fee_shares: legacy.pending_fee_shares,
+            collateral_vault_bump: legacy.collateral_vault_bump,
+            loan_vault_bump: legacy.loan_vault_bump,
+            flash_loan_lock: legacy.flash_loan_lock,
+            max_oracle_confidence_bps: legacy.max_oracle_confidence_bps,
+            oracle_kind: legacy.oracle_kind,
+            oracle_kind_migrated: legacy.oracle_kind_migrated as u8,
+            oracle_secondary: legacy.oracle_secondary,
+            max_staleness_slots: legacy.max_staleness_slots,
+            invert_oracle: legacy.invert_oracle as u8,
+            oracle_fallback: legacy.oracle_fallback,
+            last_oracle_price: legacy.last_oracle_price,
+            last_oracle_slot: legacy.last_oracle_slot,
+            max_deviation_bps: legacy.max_deviation_bps,
+            close_factor_bps: legacy.close_factor_bps,
+            rate_at_target: legacy.rate_at_target,
+            auction_duration_slots: legacy.auction_duration_slots,
+            min_borrow: legacy.min_borrow,
+            min_collateral: legacy.min_collateral,
+            oracle_cache_slot: legacy.oracle_cache_slot,
+            min_oracle_samples: legacy.min_oracle_samples,
+            max_price: legacy.max_price,
+            max_std_bps: legacy.max_std_bps,
+            callback_lock: legacy.callback_lock,
+            bad_debt_threshold: legacy.bad_debt_threshold,
+            pending_bad_debt: legacy.pending_bad_debt,
+            max_utilization_bps: legacy.max_utilization_bps,
+            liquidation_protocol_fee_bps: legacy.liquidation_protocol_fee_bps,
+            lif_cursor: legacy.lif_cursor,
+            max_lif: legacy.max_lif,
+            oracle_cache_published_slot: legacy.oracle_cache_published_slot,
+            oracle_cache_confidence_bps: legacy.oracle_cache_confidence_bps,
+            liquidation_grace_slots: legacy.liquidation_grace_slots,
+            supply_cap: legacy.supply_cap,
+            min_rate: legacy.min_rate,
+            max_rate: legacy.max_rate,
+            padding: [0; 1],
+            reserve_assets: 0,
+            accrual_basis: 0,
+            reserved: [0; 15],
+            supply_index: 0,
+            borrow_index: 0,
+            pyth_feed_id: [0; 32],
+            pyth_secondary_feed_id: [0; 32],
+        }
+    }
+}
+
+/// Creation-time market configuration beyond the Morpho Blue parameters
+/// 
+/// Passed to `create_market` and hashed into the market id, so anyone can
+/// still create a market for any parameters, but nobody can claim an id
+/// with a configuration other than the one it commits to. Fields set the
+/// `Market` fields of the same name.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MarketConfig {
+    pub oracle_kind: u8,
+    pub oracle_secondary: Pubkey,
+    pub pyth_feed_id: [u8; 32],
+    pub pyth_secondary_feed_id: [u8; 32],
+    pub max_staleness_slots: u64,
+    pub invert_oracle: bool,
+    pub min_borrow: u64,
+    pub min_collateral: u64,
+    pub min_oracle_samples: u32,
+    pub max_price: u128,
+    pub max_utilization_bps: u64,
+    pub lif_cursor: u64,
+    pub max_lif: u64,
+    pub supply_cap: u128,
+    pub min_rate: u128,
+    pub max_rate: u128,
 }
 
 /// Calculate unique market identifier
 /// 
-/// Matches Morpho Blue's Id derivation using keccak256 hash
-/// of the market parameters.
+/// Follows Morpho Blue's Id derivation, a keccak256 hash of the market
+/// parameters, extended with the Borsh encoding of the market's `config`.
 pub fn calculate_market_id(
     collateral_mint: &Pubkey,
     loan_mint: &Pubkey,
     oracle: &Pubkey,
     irm: &Pubkey,
     lltv: u64,
+    config: &MarketConfig,
 ) -> [u8; 32] {
-    let mut data = Vec::with_capacity(32 * 4 + 8);
+    let mut data = Vec::with_capacity(32 * 4 + 8 + std::mem::size_of::<MarketConfig>());
     data.extend_from_slice(collateral_mint.as_ref());
     data.extend_from_slice(loan_mint.as_ref());
     data.extend_from_slice(oracle.as_ref());
     data.extend_from_slice(irm.as_ref());
     data.extend_from_slice(&lltv.to_le_bytes());
+    // Writing into a Vec cannot fail
+    config.serialize(&mut data).unwrap();
 
     keccak::hash(&data).to_bytes()
 }
@@ -174,6 +760,7 @@ pub fn verify_market_id(
     oracle: &Pubkey,
     irm: &Pubkey,
     lltv: u64,
+    config: &MarketConfig,
 ) -> bool {
     let expected = calculate_market_id(
         collateral_mint,
@@ -181,6 +768,7 @@ pub fn verify_market_id(
         oracle,
         irm,
         lltv,
+        config,
     );
     market_id == &expected
 }
@@ -208,3 +796,120 @@ pub fn derive_loan_vault(program_id: &Pubkey, market_id: &[u8; 32]) -> (Pubkey,
         program_id,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{align_of, offset_of, size_of};
+
+    /// Pins the on-chain layout; a failure here means existing accounts
+    /// would be misread, so bump `migrate_market` rather than the offsets.
+    #[test]
+    fn test_market_layout_stable() {
+        assert_eq!(size_of::<Market>(), 736);
+        assert_eq!(align_of::<Market>(), 16);
+        assert_eq!(Market::space(), 744);
+
+        assert_eq!(offset_of!(Market, total_supply_assets), 0);
+        assert_eq!(offset_of!(Market, pending_fee_shares), 64);
+        assert_eq!(offset_of!(Market, max_rate), 192);
+        assert_eq!(offset_of!(Market, market_id), 208);
+        assert_eq!(offset_of!(Market, oracle), 304);
+        assert_eq!(offset_of!(Market, oracle_fallback), 400);
+        assert_eq!(offset_of!(Market, lltv), 432);
+        assert_eq!(offset_of!(Market, last_update), 448);
+        assert_eq!(offset_of!(Market, liquidation_grace_slots), 584);
+        assert_eq!(offset_of!(Market, min_oracle_samples), 592);
+        assert_eq!(offset_of!(Market, bump), 596);
+        assert_eq!(offset_of!(Market, paused), 599);
+        assert_eq!(offset_of!(Market, callback_lock), 606);
+        assert_eq!(offset_of!(Market, reserve_assets), 608);
+        assert_eq!(offset_of!(Market, accrual_basis), 624);
+        assert_eq!(offset_of!(Market, reserved), 625);
+        assert_eq!(offset_of!(Market, supply_index), 640);
+        assert_eq!(offset_of!(Market, borrow_index), 656);
+        assert_eq!(offset_of!(Market, pyth_feed_id), 672);
+        assert_eq!(offset_of!(Market, pyth_secondary_feed_id), 704);
+        assert_eq!(MARKET_SPACE_BEFORE_FEED_IDS, 8 + offset_of!(Market, pyth_feed_id));
+    }
+
+    #[test]
+    fn test_legacy_market_conversion() {
+        let market_id = [7u8; 32];
+        let oracle = Pubkey::new_unique();
+        let legacy = LegacyMarket {
+            bump: 254,
+            market_id,
+            collateral_mint: Pubkey::new_unique(),
+            loan_mint: Pubkey::new_unique(),
+            collateral_decimals: 9,
+            loan_decimals: 6,
+            oracle,
+            irm: Pubkey::new_unique(),
+            lltv: 8_500,
+            paused: true,
+            fee: 1_000,
+            total_supply_assets: 1_000_000,
+            total_supply_shares: 1_000_000_000_000,
+            total_borrow_assets: 400_000,
+            total_borrow_shares: 400_000_000_000,
+            last_update: 1_700_000_000,
+            pending_fee_shares: 12,
+            collateral_vault_bump: 253,
+            loan_vault_bump: 252,
+            flash_loan_lock: 0,
+            max_oracle_confidence_bps: 200,
+            oracle_kind: 1,
+            oracle_kind_migrated: true,
+            oracle_secondary: Pubkey::default(),
+            max_staleness_slots: 25,
+            invert_oracle: true,
+            oracle_fallback: Pubkey::default(),
+            last_oracle_price: 2_000_000_000,
+            last_oracle_slot: 99,
+            max_deviation_bps: 0,
+            close_factor_bps: 5_000,
+            rate_at_target: 1_268_391_679,
+            auction_duration_slots: 0,
+            min_borrow: 0,
+            min_collateral: 0,
+            oracle_cache_slot: 0,
+            min_oracle_samples: 3,
+            max_price: 0,
+            max_std_bps: 0,
+            callback_lock: 0,
+            bad_debt_threshold: 0,
+            pending_bad_debt: 0,
+            max_utilization_bps: 0,
+            liquidation_protocol_fee_bps: 0,
+            lif_cursor: 0,
+            max_lif: 0,
+            oracle_cache_published_slot: 0,
+            oracle_cache_confidence_bps: 0,
+            liquidation_grace_slots: 7,
+            supply_cap: 5_000_000,
+            min_rate: 1,
+            max_rate: 0,
+            reserved: [0; 7],
+        };
+        let bytes = legacy.try_to_vec().unwrap();
+        assert_eq!(8 + bytes.len(), LEGACY_MARKET_SPACE);
+
+        let market = Market::from(LegacyMarket::deserialize(&mut bytes.as_slice()).unwrap());
+        assert_eq!(market.market_id, market_id);
+        assert_eq!(market.oracle, oracle);
+        assert!(market.is_paused());
+        assert!(market.is_oracle_kind_migrated());
+        assert!(market.is_oracle_inverted());
+        assert_eq!(market.total_borrow_shares, 400_000_000_000);
+        assert_eq!(market.last_oracle_price, 2_000_000_000);
+        assert_eq!(market.min_oracle_samples, 3);
+        assert_eq!(market.liquidation_grace_slots, 7);
+        assert_eq!(market.supply_cap, 5_000_000);
+        assert_eq!(market.reserve_assets, 0);
+        assert_eq!(market.accrual_basis().unwrap(), AccrualBasis::Timestamp);
+        assert_eq!(market.supply_index(), WAD);
+        assert_eq!(market.borrow_index(), WAD);
+        assert_eq!(market.reserved, [0; 15]);
+    }
+}
@@ -0,0 +1,140 @@
+//! Median-of-N oracle aggregator
+//!
+//! A `MedianOracle` lists several feeds of one kind. `update_median` reads
+//! them all, drops the unhealthy ones and stores the median, which markets
+//! with `OracleKind::Median` then read like any other feed. A single bad or
+//! stale feed cannot move the price on its own.
+
+use anchor_lang::prelude::*;
+use crate::constants::MAX_MEDIAN_FEEDS;
+use crate::errors::MorphoError;
+
+/// Aggregates the median price of up to MAX_MEDIAN_FEEDS feeds
+///
+/// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_median", aggregator_id]
+#[account]
+pub struct MedianOracle {
+    /// PDA bump seed
+    pub bump: u8,
+
+    /// Unique aggregator identifier (chosen by the owner)
+    pub aggregator_id: [u8; 32],
+
+    /// Kind of every listed feed (see `OracleKind`)
+    pub feed_kind: u8,
+
+    /// Number of listed feeds (active count in `feeds`)
+    pub feed_count: u8,
+
+    /// Listed feeds; `update_median` takes them as remaining accounts in this order
+    pub feeds: [Pubkey; MAX_MEDIAN_FEEDS],
+
+    /// Fewest feeds that must read cleanly for an update to succeed
+    pub min_healthy_feeds: u8,
+
+    /// Staleness limit for each feed read (slots)
+    pub max_staleness_slots: u64,
+
+    /// Median of the last update, scaled by ORACLE_SCALE
+    pub price: u128,
+
+    /// Slot of the last update (0 = never updated)
+    pub last_update_slot: u64,
+
+    /// Feed id every listed Pyth feed must carry (zero for other kinds)
+    pub pyth_feed_id: [u8; 32],
+
+    /// Reserved for future use
+    pub reserved: [u8; 32],
+}
+
+impl MedianOracle {
+    pub const SEED: &'static [u8] = b"morpho_median";
+
+    pub fn space() -> usize {
+        8 +                             // discriminator
+        1 +                             // bump
+        32 +                            // aggregator_id
+        1 +                             // feed_kind
+        1 +                             // feed_count
+        (32 * MAX_MEDIAN_FEEDS) +       // feeds
+        1 +                             // min_healthy_feeds
+        8 +                             // max_staleness_slots
+        16 +                            // price
+        8 +                             // last_update_slot
+        32 +                            // pyth_feed_id
+        32                              // reserved
+    }
+
+    /// Listed feeds in order
+    pub fn feeds(&self) -> &[Pubkey] {
+        &self.feeds[..self.feed_count as usize]
+    }
+}
+
+/// Check a feed list: 1..=MAX_MEDIAN_FEEDS distinct, non-default keys, with
+/// `min_healthy_feeds` in 1..=feeds.len()
+pub fn validate_median_feeds(feeds: &[Pubkey], min_healthy_feeds: u8) -> Result<()> {
+    require!(
+        !feeds.is_empty() && feeds.len() <= MAX_MEDIAN_FEEDS,
+        MorphoError::InvalidInput
+    );
+    require!(
+        min_healthy_feeds >= 1 && min_healthy_feeds as usize <= feeds.len(),
+        MorphoError::InvalidInput
+    );
+    for (i, feed) in feeds.iter().enumerate() {
+        require!(*feed != Pubkey::default(), MorphoError::InvalidOracle);
+        require!(!feeds[..i].contains(feed), MorphoError::InvalidOracle);
+    }
+    Ok(())
+}
+
+/// Median of `prices`, averaging the two middle values for even counts
+/// (rounded down); None when empty
+pub fn median_price(prices: &mut [u128]) -> Option<u128> {
+    if prices.is_empty() {
+        return None;
+    }
+    prices.sort_unstable();
+    let mid = prices.len() / 2;
+    if prices.len() % 2 == 1 {
+        Some(prices[mid])
+    } else {
+        let (a, b) = (prices[mid - 1], prices[mid]);
+        // Midpoint without overflowing near u128::MAX
+        Some(a / 2 + b / 2 + (a % 2 + b % 2) / 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median_price() {
+        assert_eq!(median_price(&mut []), None);
+        assert_eq!(median_price(&mut [7]), Some(7));
+        assert_eq!(median_price(&mut [9, 1, 5]), Some(5));
+        assert_eq!(median_price(&mut [10, 2, 4, 100]), Some(7));
+        // Even count rounds the midpoint down
+        assert_eq!(median_price(&mut [1, 2]), Some(1));
+        assert_eq!(median_price(&mut [u128::MAX, u128::MAX]), Some(u128::MAX));
+        assert_eq!(median_price(&mut [u128::MAX - 1, u128::MAX]), Some(u128::MAX - 1));
+    }
+
+    #[test]
+    fn test_validate_median_feeds() {
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+
+        assert!(validate_median_feeds(&[a, b], 1).is_ok());
+        assert!(validate_median_feeds(&[a, b], 2).is_ok());
+        assert!(validate_median_feeds(&[], 1).is_err());
+        assert!(validate_median_feeds(&[a, b], 0).is_err());
+        assert!(validate_median_feeds(&[a, b], 3).is_err());
+        assert!(validate_median_feeds(&[a, a], 1).is_err());
+        assert!(validate_median_feeds(&[a, Pubkey::default()], 1).is_err());
+        assert!(validate_median_feeds(&[a; MAX_MEDIAN_FEEDS + 1], 1).is_err());
+    }
+}
@@ -4,8 +4,20 @@ pub mod protocol;
 pub mod market;
 pub mod position;
 pub mod authorization;
+pub mod pre_liquidation;
+pub mod param_change;
+pub mod vault;
+pub mod twap;
+pub mod median;
+pub mod registry;
 
 pub use protocol::*;
 pub use market::*;
 pub use position::*;
 pub use authorization::*;
+pub use pre_liquidation::*;
+pub use param_change::*;
+pub use vault::*;
+pub use twap::*;
+pub use median::*;
+pub use registry::*;
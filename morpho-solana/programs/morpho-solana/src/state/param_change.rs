@@ -0,0 +1,127 @@
+//! Timelocked market parameter changes
+//!
+//! Sensitive market parameters are changed in two steps: the owner queues
+//! the new value, and it can only be executed once `execute_after_slot` has
+//! passed, giving users time to react.
+
+use anchor_lang::prelude::*;
+use crate::constants::PROGRAM_SEED_PREFIX;
+use crate::errors::MorphoError;
+
+/// Market parameter a pending change targets
+///
+/// Stored on `PendingParamChange` as a `u8`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamKind {
+    /// Protocol fee (`value`, basis points)
+    Fee = 0,
+    /// Liquidation LTV (`value`, basis points)
+    Lltv = 1,
+    /// Price oracle (`address`)
+    Oracle = 2,
+    /// Interest rate model (`address`)
+    Irm = 3,
+    /// Liquidation close factor (`value`, basis points, 0 = protocol default)
+    CloseFactor = 4,
+    /// Dutch-auction ramp for liquidations (`value`, slots, 0 = fixed LIF)
+    AuctionDuration = 5,
+    /// Share of the liquidation bonus sent to the fee recipient (`value`, basis points)
+    LiquidationProtocolFee = 6,
+    /// Slots a position must stay liquidatable before liquidation (`value`)
+    LiquidationGrace = 7,
+    /// Market supply cap (`value`, loan tokens, 0 = uncapped)
+    SupplyCap = 8,
+    /// Interest accrual clock (`value`, `AccrualBasis` as u8)
+    AccrualBasis = 9,
+    /// Fallback price oracle (`address`, Pubkey::default() = none)
+    OracleFallback = 10,
+}
+
+impl TryFrom<u8> for ParamKind {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ParamKind::Fee),
+            1 => Ok(ParamKind::Lltv),
+            2 => Ok(ParamKind::Oracle),
+            3 => Ok(ParamKind::Irm),
+            4 => Ok(ParamKind::CloseFactor),
+            5 => Ok(ParamKind::AuctionDuration),
+            6 => Ok(ParamKind::LiquidationProtocolFee),
+            7 => Ok(ParamKind::LiquidationGrace),
+            8 => Ok(ParamKind::SupplyCap),
+            9 => Ok(ParamKind::AccrualBasis),
+            10 => Ok(ParamKind::OracleFallback),
+            _ => Err(MorphoError::InvalidParamKind.into()),
+        }
+    }
+}
+
+/// A queued change to one market parameter
+///
+/// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_param_change", market_id, param]
+#[account]
+pub struct PendingParamChange {
+    /// PDA bump seed
+    pub bump: u8,
+
+    /// Market the change applies to
+    pub market_id: [u8; 32],
+
+    /// Target parameter (`ParamKind` as u8)
+    pub param: u8,
+
+    /// New value for numeric parameters (every kind but the oracles and irm)
+    pub value: u64,
+
+    /// New address for account parameters (oracle, fallback oracle, irm)
+    pub address: Pubkey,
+
+    /// Slot the change was queued at
+    pub queued_at_slot: u64,
+
+    /// First slot at which the change can be executed
+    pub execute_after_slot: u64,
+
+    /// Reserved for future use
+    pub reserved: [u8; 32],
+}
+
+impl PendingParamChange {
+    pub const SEED: &'static [u8] = b"morpho_param_change";
+
+    pub fn space() -> usize {
+        8 +     // discriminator
+        1 +     // bump
+        32 +    // market_id
+        1 +     // param
+        8 +     // value
+        32 +    // address
+        8 +     // queued_at_slot
+        8 +     // execute_after_slot
+        32      // reserved
+    }
+
+    /// Check if the delay has elapsed at `slot`
+    pub fn is_executable(&self, slot: u64) -> bool {
+        slot >= self.execute_after_slot
+    }
+}
+
+/// Derive pending parameter change PDA
+pub fn derive_pending_param_change(
+    program_id: &Pubkey,
+    market_id: &[u8; 32],
+    param: ParamKind,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PROGRAM_SEED_PREFIX,
+            PendingParamChange::SEED,
+            market_id,
+            &[param as u8],
+        ],
+        program_id,
+    )
+}
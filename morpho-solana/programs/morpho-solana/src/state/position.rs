@@ -5,11 +5,14 @@
 
 use anchor_lang::prelude::*;
 use crate::constants::PROGRAM_SEED_PREFIX;
+use crate::errors::MorphoError;
+use crate::math::{checked_add, mul_div_down};
 
 /// User position in a specific market
 /// 
 /// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_position", market_id, owner]
 #[account]
+#[derive(Default)]
 pub struct Position {
     /// PDA bump seed
     pub bump: u8,
@@ -30,8 +33,26 @@ pub struct Position {
     /// Collateral does not earn interest in Morpho Blue
     pub collateral: u128,
 
+    /// Slot the liquidation auction started (0 = no auction running)
+    pub auction_start_slot: u64,
+
+    /// Slot of the last change to this position's balances
+    pub last_update: u64,
+
+    /// Slot the position was first seen liquidatable, for the market's
+    /// liquidation grace period (0 = last seen healthy)
+    pub became_unhealthy_slot: u64,
+
+    /// Share-weighted average `Market::supply_index` the supply shares were
+    /// acquired at (0 = not tracked yet)
+    pub supply_entry_index: u128,
+
+    /// Share-weighted average `Market::borrow_index` the borrow shares were
+    /// taken at (0 = not tracked yet)
+    pub borrow_entry_index: u128,
+
     /// Reserved for future use
-    pub reserved: [u8; 64],
+    pub reserved: [u8; 8],
 }
 
 impl Position {
@@ -45,7 +66,12 @@ impl Position {
         16 +    // supply_shares
         16 +    // borrow_shares
         16 +    // collateral
-        64      // reserved
+        8 +     // auction_start_slot
+        8 +     // last_update
+        8 +     // became_unhealthy_slot
+        16 +    // supply_entry_index
+        16 +    // borrow_entry_index
+        8       // reserved
     }
 
     /// Check if position has any activity
@@ -69,6 +95,70 @@ impl Position {
     pub fn has_collateral(&self) -> bool {
         self.collateral > 0
     }
+
+    /// Require that any debt is backed by some collateral
+    ///
+    /// Checked before the oracle-priced health check so a debt-only
+    /// position fails cheaply and with a precise error.
+    pub fn ensure_debt_collateralized(&self) -> Result<()> {
+        require!(
+            !self.has_debt() || self.has_collateral(),
+            MorphoError::InsufficientCollateral
+        );
+        Ok(())
+    }
+
+    /// Track when the position turned liquidatable: stamp the first
+    /// unhealthy reading, clear the stamp on a healthy one
+    pub fn record_health(&mut self, liquidatable: bool, slot: u64) {
+        if !liquidatable {
+            self.became_unhealthy_slot = 0;
+        } else if self.became_unhealthy_slot == 0 {
+            self.became_unhealthy_slot = slot.max(1);
+        }
+    }
+
+    /// Whether a liquidation auction or grace period has been started
+    pub fn has_liquidation_stamp(&self) -> bool {
+        self.auction_start_slot > 0 || self.became_unhealthy_slot > 0
+    }
+
+    /// Add supply shares acquired at the market's current `supply_index`
+    pub fn add_supply_shares(&mut self, shares: u128, supply_index: u128) -> Result<()> {
+        self.supply_entry_index =
+            blend_entry_index(self.supply_entry_index, self.supply_shares, shares, supply_index)?;
+        self.supply_shares = checked_add(self.supply_shares, shares)?;
+        Ok(())
+    }
+
+    /// Add borrow shares taken at the market's current `borrow_index`
+    pub fn add_borrow_shares(&mut self, shares: u128, borrow_index: u128) -> Result<()> {
+        self.borrow_entry_index =
+            blend_entry_index(self.borrow_entry_index, self.borrow_shares, shares, borrow_index)?;
+        self.borrow_shares = checked_add(self.borrow_shares, shares)?;
+        Ok(())
+    }
+
+    /// Stamp `last_update` with the current slot
+    pub fn touch(&mut self) -> Result<()> {
+        self.last_update = Clock::get()?.slot;
+        Ok(())
+    }
+}
+
+/// Share-weighted average of the entry index of `shares` and `added`
+/// shares entering at `index`
+///
+/// An untracked entry (0, positions from before the indices existed) or an
+/// empty position restarts at `index`. Removing shares keeps the average,
+/// so the remaining shares keep their share of the accrued interest.
+fn blend_entry_index(entry: u128, shares: u128, added: u128, index: u128) -> Result<u128> {
+    if entry == 0 || shares == 0 {
+        return Ok(index);
+    }
+    // Indices only grow, so the new entry lies between the old one and `index`
+    let step = mul_div_down(added, index.saturating_sub(entry), checked_add(shares, added)?)?;
+    checked_add(entry, step)
 }
 
 /// Derive position PDA
@@ -87,3 +177,59 @@ pub fn derive_position(
         program_id,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::WAD;
+
+    fn empty_position() -> Position {
+        Position {
+            bump: 255,
+            market_id: [0u8; 32],
+            owner: Pubkey::new_unique(),
+            supply_shares: 0,
+            borrow_shares: 0,
+            collateral: 0,
+            auction_start_slot: 0,
+            last_update: 0,
+            became_unhealthy_slot: 0,
+            supply_entry_index: 0,
+            borrow_entry_index: 0,
+            reserved: [0u8; 8],
+        }
+    }
+
+    #[test]
+    fn test_entry_index_is_share_weighted() {
+        let mut position = empty_position();
+        position.add_supply_shares(300, WAD).unwrap();
+        assert_eq!(position.supply_entry_index, WAD);
+
+        // 100 more shares at 1.4: (300 * 1.0 + 100 * 1.4) / 400 = 1.1
+        position.add_supply_shares(100, WAD * 14 / 10).unwrap();
+        assert_eq!(position.supply_shares, 400);
+        assert_eq!(position.supply_entry_index, WAD * 11 / 10);
+
+        // Removing shares keeps the entry; adding none changes nothing
+        position.supply_shares = 200;
+        position.add_supply_shares(0, WAD * 2).unwrap();
+        assert_eq!(position.supply_entry_index, WAD * 11 / 10);
+
+        // An emptied position restarts at the current index
+        position.supply_shares = 0;
+        position.add_supply_shares(50, WAD * 2).unwrap();
+        assert_eq!(position.supply_entry_index, WAD * 2);
+    }
+
+    #[test]
+    fn test_untracked_entry_starts_at_current_index() {
+        // Shares held from before the indices existed
+        let mut position = empty_position();
+        position.borrow_shares = 1_000;
+        position.add_borrow_shares(10, WAD * 3).unwrap();
+        assert_eq!(position.borrow_entry_index, WAD * 3);
+        assert_eq!(position.borrow_shares, 1_010);
+        assert_eq!(position.supply_entry_index, 0);
+    }
+}
@@ -0,0 +1,72 @@
+//! Pre-liquidation state account
+//!
+//! Lets a borrower opt in to early partial liquidation at a softer
+//! threshold (`pre_lltv`) and a smaller incentive (`pre_lif`) before the
+//! position reaches the market's hard LLTV.
+
+use anchor_lang::prelude::*;
+use crate::constants::PROGRAM_SEED_PREFIX;
+
+/// Pre-liquidation terms for one borrower in one market
+///
+/// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_pre_liquidation", market_id, borrower]
+#[account]
+pub struct PreLiquidation {
+    /// PDA bump seed
+    pub bump: u8,
+
+    /// Market this applies to
+    pub market_id: [u8; 32],
+
+    /// Borrower who opted in
+    pub borrower: Pubkey,
+
+    /// Soft loan-to-value threshold (basis points, below the market's LLTV)
+    pub pre_lltv: u64,
+
+    /// Liquidation incentive factor for pre-liquidations (LIF_BPS scaled)
+    pub pre_lif: u64,
+
+    /// Only account allowed to pre-liquidate (Pubkey::default() = anyone)
+    pub liquidator: Pubkey,
+
+    /// Reserved for future use
+    pub reserved: [u8; 32],
+}
+
+impl PreLiquidation {
+    pub const SEED: &'static [u8] = b"morpho_pre_liquidation";
+
+    pub fn space() -> usize {
+        8 +     // discriminator
+        1 +     // bump
+        32 +    // market_id
+        32 +    // borrower
+        8 +     // pre_lltv
+        8 +     // pre_lif
+        32 +    // liquidator
+        32      // reserved
+    }
+
+    /// Check if `liquidator` may use these terms
+    pub fn is_liquidator_allowed(&self, liquidator: &Pubkey) -> bool {
+        self.liquidator == Pubkey::default() || self.liquidator == *liquidator
+    }
+}
+
+/// Derive pre-liquidation PDA
+pub fn derive_pre_liquidation(
+    program_id: &Pubkey,
+    market_id: &[u8; 32],
+    borrower: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PROGRAM_SEED_PREFIX,
+            PreLiquidation::SEED,
+            market_id,
+            borrower.as_ref(),
+        ],
+        program_id,
+    )
+}
@@ -24,7 +24,8 @@ pub struct ProtocolState {
     /// Receives protocol fees from all markets
     pub fee_recipient: Pubkey,
 
-    /// Global pause flag - stops all user operations across all markets
+    /// Global pause flag - overrides per-market flags; halts withdraw, borrow
+    /// and liquidate everywhere while supply and repay stay open
     pub paused: bool,
 
     /// Number of enabled LLTVs (active count in the array)
@@ -44,8 +45,12 @@ pub struct ProtocolState {
     /// Total markets created (for stats)
     pub market_count: u64,
 
+    /// Can pause/unpause the protocol and markets alongside the owner
+    /// (Pubkey::default() = no guardian)
+    pub guardian: Pubkey,
+
     /// Reserved for future upgrades
-    pub reserved: [u8; 256],
+    pub reserved: [u8; 224],
 }
 
 impl ProtocolState {
@@ -63,7 +68,13 @@ impl ProtocolState {
         1 +                     // irm_count
         (32 * MAX_IRMS) +       // enabled_irms
         8 +                     // market_count
-        256                     // reserved
+        32 +                    // guardian
+        224                     // reserved
+    }
+
+    /// Check if `key` may toggle pause flags (owner or guardian)
+    pub fn is_pause_authority(&self, key: &Pubkey) -> bool {
+        *key == self.owner || (self.guardian != Pubkey::default() && *key == self.guardian)
     }
 
     /// Check if an LLTV value is whitelisted
@@ -0,0 +1,99 @@
+//! Market registry
+//!
+//! Every market created gets a `MarketIndex` entry at the next value of
+//! `ProtocolState::market_count`, so markets can be enumerated by deriving
+//! indices 0..market_count instead of scanning all program accounts.
+//! Markets created before the registry existed have no entry; their
+//! indices are simply never initialized.
+
+use anchor_lang::prelude::*;
+use crate::constants::PROGRAM_SEED_PREFIX;
+
+/// Registry entry for one market
+///
+/// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_market_index", index (le)]
+#[account]
+pub struct MarketIndex {
+    /// PDA bump seed
+    pub bump: u8,
+
+    /// Position of this entry in creation order
+    pub index: u64,
+
+    /// Market account
+    pub market: Pubkey,
+
+    /// Market identifier (keccak256 hash of parameters)
+    pub market_id: [u8; 32],
+
+    /// Collateral token mint
+    pub collateral_mint: Pubkey,
+
+    /// Loan token mint
+    pub loan_mint: Pubkey,
+
+    /// Oracle at creation (may since have been changed by timelock)
+    pub oracle: Pubkey,
+
+    /// IRM at creation (may since have been changed by timelock)
+    pub irm: Pubkey,
+
+    /// LLTV at creation (basis points)
+    pub lltv: u64,
+
+    /// Slot the market was created in
+    pub created_slot: u64,
+}
+
+impl MarketIndex {
+    pub const SEED: &'static [u8] = b"morpho_market_index";
+
+    pub fn space() -> usize {
+        8 +     // discriminator
+        1 +     // bump
+        8 +     // index
+        32 +    // market
+        32 +    // market_id
+        32 +    // collateral_mint
+        32 +    // loan_mint
+        32 +    // oracle
+        32 +    // irm
+        8 +     // lltv
+        8       // created_slot
+    }
+}
+
+/// Market listing returned by `view_markets`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarketSummary {
+    /// Registry index
+    pub index: u64,
+    /// Market account
+    pub market: Pubkey,
+    /// Collateral token mint
+    pub collateral_mint: Pubkey,
+    /// Loan token mint
+    pub loan_mint: Pubkey,
+    /// LLTV at creation (basis points)
+    pub lltv: u64,
+}
+
+impl From<&MarketIndex> for MarketSummary {
+    fn from(entry: &MarketIndex) -> Self {
+        Self {
+            index: entry.index,
+            market: entry.market,
+            collateral_mint: entry.collateral_mint,
+            loan_mint: entry.loan_mint,
+            lltv: entry.lltv,
+        }
+    }
+}
+
+/// Derive market index PDA
+pub fn derive_market_index(program_id: &Pubkey, index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PROGRAM_SEED_PREFIX, MarketIndex::SEED, &index.to_le_bytes()],
+        program_id,
+    )
+}
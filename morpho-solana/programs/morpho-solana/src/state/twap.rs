@@ -0,0 +1,233 @@
+//! Time-weighted average price oracle
+//!
+//! A `TwapOracle` wraps one Switchboard or Pyth feed and keeps a ring buffer
+//! of the prices it has read. Anyone can crank `update_twap` to append a
+//! sample; markets with `OracleKind::Twap` read the average over
+//! `window_slots` instead of the spot price, which makes single-slot
+//! manipulation of the source far more expensive.
+
+use anchor_lang::prelude::*;
+use crate::constants::TWAP_CAPACITY;
+use crate::errors::MorphoError;
+use crate::math::{checked_add, mul_div_down};
+
+/// Cranked TWAP over a single source feed
+///
+/// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_twap", source, window_slots (le)]
+#[account]
+pub struct TwapOracle {
+    /// PDA bump seed
+    pub bump: u8,
+
+    /// Feed sampled by `update_twap`
+    pub source: Pubkey,
+
+    /// Kind of `source` (see `OracleKind`; Switchboard or Pyth)
+    pub source_kind: u8,
+
+    /// Averaging window in slots
+    pub window_slots: u64,
+
+    /// Staleness limit for source reads (slots)
+    pub max_staleness_slots: u64,
+
+    /// Index the next sample is written to
+    pub head: u8,
+
+    /// Number of samples stored (saturates at TWAP_CAPACITY)
+    pub count: u8,
+
+    /// Slot of each sample (ring buffer, parallel to `sample_prices`)
+    pub sample_slots: [u64; TWAP_CAPACITY],
+
+    /// Source price of each sample, scaled by ORACLE_SCALE
+    pub sample_prices: [u128; TWAP_CAPACITY],
+
+    /// Feed id a Pyth `source` must carry (zero for Switchboard sources)
+    pub pyth_feed_id: [u8; 32],
+
+    /// Reserved for future use
+    pub reserved: [u8; 32],
+}
+
+impl TwapOracle {
+    pub const SEED: &'static [u8] = b"morpho_twap";
+
+    pub fn space() -> usize {
+        8 +                         // discriminator
+        1 +                         // bump
+        32 +                        // source
+        1 +                         // source_kind
+        8 +                         // window_slots
+        8 +                         // max_staleness_slots
+        1 +                         // head
+        1 +                         // count
+        (8 * TWAP_CAPACITY) +       // sample_slots
+        (16 * TWAP_CAPACITY) +      // sample_prices
+        32 +                        // pyth_feed_id
+        32                          // reserved
+    }
+
+    /// Fewest slots allowed between two samples
+    ///
+    /// Keeps at least two windows' worth of history in the buffer, so a
+    /// spammed crank cannot evict the samples the average depends on.
+    pub fn min_spacing(&self) -> u64 {
+        self.window_slots.div_ceil((TWAP_CAPACITY / 2) as u64).max(1)
+    }
+
+    /// The `i`-th most recent sample as (slot, price), 0 = newest
+    pub fn sample(&self, i: usize) -> Option<(u64, u128)> {
+        if i >= self.count as usize {
+            return None;
+        }
+        let idx = (self.head as usize + TWAP_CAPACITY - 1 - i) % TWAP_CAPACITY;
+        Some((self.sample_slots[idx], self.sample_prices[idx]))
+    }
+
+    /// Append a sample, overwriting the oldest once the buffer is full
+    pub fn push(&mut self, slot: u64, price: u128) -> Result<()> {
+        if let Some((last_slot, _)) = self.sample(0) {
+            require!(
+                slot >= last_slot.saturating_add(self.min_spacing()),
+                MorphoError::OracleTwapUpdateTooSoon
+            );
+        }
+
+        let idx = self.head as usize;
+        self.sample_slots[idx] = slot;
+        self.sample_prices[idx] = price;
+        self.head = ((idx + 1) % TWAP_CAPACITY) as u8;
+        if (self.count as usize) < TWAP_CAPACITY {
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    /// Time-weighted average over `[current_slot - window_slots, current_slot]`
+    ///
+    /// Each sample's price holds until the next sample (the newest until
+    /// `current_slot`). The newest sample must be no older than
+    /// `max_staleness_slots + min_spacing()`, since cranks cannot land more
+    /// often than the spacing allows. Fails with
+    /// `OracleTwapInsufficientCoverage` when the oldest stored sample is
+    /// younger than the window start.
+    pub fn twap(&self, current_slot: u64) -> Result<u128> {
+        let (latest_slot, _) = self.sample(0).ok_or(MorphoError::OracleTwapInsufficientCoverage)?;
+        require!(
+            current_slot.saturating_sub(latest_slot)
+                <= self.max_staleness_slots.saturating_add(self.min_spacing()),
+            MorphoError::OracleStale
+        );
+
+        let window_start = current_slot.saturating_sub(self.window_slots);
+        let mut end = current_slot;
+        let mut average = 0u128;
+
+        for i in 0..self.count as usize {
+            let (slot, price) = self.sample(i).unwrap();
+            let start = slot.max(window_start);
+            let duration = end.saturating_sub(start);
+            average = checked_add(
+                average,
+                mul_div_down(price, duration as u128, self.window_slots as u128)?,
+            )?;
+            if slot <= window_start {
+                return Ok(average);
+            }
+            end = slot;
+        }
+
+        Err(MorphoError::OracleTwapInsufficientCoverage.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn twap_oracle(window_slots: u64) -> TwapOracle {
+        TwapOracle {
+            bump: 255,
+            source: Pubkey::new_unique(),
+            source_kind: 0,
+            window_slots,
+            max_staleness_slots: 50,
+            head: 0,
+            count: 0,
+            sample_slots: [0; TWAP_CAPACITY],
+            sample_prices: [0; TWAP_CAPACITY],
+            pyth_feed_id: [0; 32],
+            reserved: [0; 32],
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer_wraps() {
+        let mut twap = twap_oracle(16);
+        assert_eq!(twap.min_spacing(), 1);
+
+        for i in 0..(TWAP_CAPACITY as u64 + 5) {
+            twap.push(100 + i, i as u128).unwrap();
+        }
+        assert_eq!(twap.count as usize, TWAP_CAPACITY);
+        assert_eq!(twap.sample(0), Some((100 + TWAP_CAPACITY as u64 + 4, TWAP_CAPACITY as u128 + 4)));
+        // Oldest surviving sample is the sixth one pushed
+        assert_eq!(twap.sample(TWAP_CAPACITY - 1), Some((105, 5)));
+        assert_eq!(twap.sample(TWAP_CAPACITY), None);
+    }
+
+    #[test]
+    fn test_push_enforces_spacing() {
+        let mut twap = twap_oracle(160);
+        assert_eq!(twap.min_spacing(), 10);
+
+        twap.push(1_000, 1).unwrap();
+        assert!(twap.push(1_009, 1).is_err());
+        twap.push(1_010, 1).unwrap();
+        assert_eq!(twap.count, 2);
+    }
+
+    #[test]
+    fn test_twap_weights_by_duration() {
+        let mut twap = twap_oracle(100);
+        twap.push(1_000, 100).unwrap();
+        twap.push(1_050, 200).unwrap();
+        twap.push(1_075, 400).unwrap();
+
+        // Window [1_000, 1_100]: 100 for 50 slots, 200 for 25, 400 for 25
+        assert_eq!(twap.twap(1_100).unwrap(), 50 + 50 + 100);
+
+        // A flat series averages to its price
+        let mut flat = twap_oracle(100);
+        flat.push(1_000, 7_000).unwrap();
+        flat.push(1_050, 7_000).unwrap();
+        assert_eq!(flat.twap(1_100).unwrap(), 7_000);
+    }
+
+    #[test]
+    fn test_twap_rejects_insufficient_coverage() {
+        let mut twap = twap_oracle(100);
+        assert!(twap.twap(1_000).is_err());
+
+        twap.push(1_000, 100).unwrap();
+        // Only 50 of the 100 window slots are covered
+        assert_eq!(
+            twap.twap(1_050).unwrap_err(),
+            MorphoError::OracleTwapInsufficientCoverage.into()
+        );
+        twap.push(1_050, 100).unwrap();
+        assert_eq!(twap.twap(1_100).unwrap(), 100);
+    }
+
+    #[test]
+    fn test_twap_rejects_stale_samples() {
+        let mut twap = twap_oracle(100);
+        twap.push(900, 100).unwrap();
+        twap.push(1_000, 100).unwrap();
+
+        // Tolerance is max_staleness_slots (50) + min_spacing (7)
+        assert_eq!(twap.twap(1_057).unwrap(), 100);
+        assert_eq!(twap.twap(1_058).unwrap_err(), MorphoError::OracleStale.into());
+    }
+}
@@ -0,0 +1,421 @@
+//! Vault state accounts
+//!
+//! A vault takes deposits of one loan token and spreads them across several
+//! markets chosen by a curator. Depositors hold vault shares (tracked on
+//! `VaultPosition`), while the vault itself holds ordinary supply positions
+//! in each listed market.
+
+use anchor_lang::prelude::*;
+use crate::constants::{BPS, MAX_VAULT_MARKETS, PROGRAM_SEED_PREFIX, VAULT_MARKET_REMOVAL_DELAY_SLOTS};
+use crate::errors::MorphoError;
+use crate::math::{checked_add, checked_sub, mul_div_down, to_shares_down};
+
+/// Target supply for one listed market, passed to `reallocate`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VaultAllocation {
+    /// Listed market account
+    pub market: Pubkey,
+    /// Assets the vault should have supplied to it afterwards
+    pub assets: u128,
+}
+
+/// Multi-market allocation vault
+///
+/// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_vault", vault_id]
+#[account]
+pub struct Vault {
+    /// PDA bump seed
+    pub bump: u8,
+
+    /// Unique vault identifier (chosen by the curator)
+    pub vault_id: [u8; 32],
+
+    /// Manages listed markets, caps, queues and reallocations
+    pub curator: Pubkey,
+
+    /// Token deposited into the vault (loan mint of every listed market)
+    pub asset_mint: Pubkey,
+
+    /// Bump for the idle token account PDA
+    pub idle_vault_bump: u8,
+
+    /// Share of total assets kept idle on deposit (basis points)
+    pub idle_buffer_bps: u64,
+
+    /// Assets held in the idle account (not supplied to any market)
+    pub idle_assets: u128,
+
+    /// Total vault shares outstanding
+    pub total_shares: u128,
+
+    /// Number of listed markets (active count in the arrays)
+    pub market_count: u8,
+
+    /// Listed market accounts; remaining accounts are passed in this order
+    pub markets: [Pubkey; MAX_VAULT_MARKETS],
+
+    /// Maximum assets supplied to `markets[i]` (0 = market takes no new supply)
+    pub supply_caps: [u128; MAX_VAULT_MARKETS],
+
+    /// Order in which deposits are allocated (first `market_count` entries;
+    /// default keys are markets left out of the queue)
+    pub supply_queue: [Pubkey; MAX_VAULT_MARKETS],
+
+    /// Order in which withdrawals are sourced (first `market_count` entries;
+    /// default keys are markets left out of the queue)
+    pub withdraw_queue: [Pubkey; MAX_VAULT_MARKETS],
+
+    /// Performance fee on yield (basis points, max MAX_VAULT_FEE)
+    pub fee_bps: u64,
+
+    /// Receives the performance fee as vault shares
+    pub fee_recipient: Pubkey,
+
+    /// Total assets after the last deposit / withdraw (fee high-water mark)
+    pub last_total_assets: u128,
+
+    /// Fee shares minted but not yet claimed by `fee_recipient`
+    pub pending_fee_shares: u128,
+
+    /// Market being removed (default = no removal pending)
+    pub pending_market_removal: Pubkey,
+
+    /// Slot from which `pending_market_removal` can be removed
+    pub removal_after_slot: u64,
+
+    /// Reserved for future use
+    pub reserved: [u8; 24],
+}
+
+impl Vault {
+    pub const SEED: &'static [u8] = b"morpho_vault";
+    pub const IDLE_VAULT_SEED: &'static [u8] = b"morpho_vault_idle";
+
+    pub fn space() -> usize {
+        8 +                             // discriminator
+        1 +                             // bump
+        32 +                            // vault_id
+        32 +                            // curator
+        32 +                            // asset_mint
+        1 +                             // idle_vault_bump
+        8 +                             // idle_buffer_bps
+        16 +                            // idle_assets
+        16 +                            // total_shares
+        1 +                             // market_count
+        (32 * MAX_VAULT_MARKETS) +      // markets
+        (16 * MAX_VAULT_MARKETS) +      // supply_caps
+        (32 * MAX_VAULT_MARKETS) +      // supply_queue
+        (32 * MAX_VAULT_MARKETS) +      // withdraw_queue
+        8 +                             // fee_bps
+        32 +                            // fee_recipient
+        16 +                            // last_total_assets
+        16 +                            // pending_fee_shares
+        32 +                            // pending_market_removal
+        8 +                             // removal_after_slot
+        24                              // reserved
+    }
+
+    /// Listed markets, in listing order
+    pub fn listed_markets(&self) -> &[Pubkey] {
+        &self.markets[..self.market_count as usize]
+    }
+
+    /// Index of `market` in `markets`, if listed
+    pub fn market_index(&self, market: &Pubkey) -> Option<usize> {
+        self.listed_markets().iter().position(|m| m == market)
+    }
+
+    /// List a new market at the end of both queues
+    pub fn list_market(&mut self, market: Pubkey, supply_cap: u128) -> Result<()> {
+        require!(self.market_index(&market).is_none(), MorphoError::InvalidInput);
+        let count = self.market_count as usize;
+        require!(count < MAX_VAULT_MARKETS, MorphoError::VaultTooManyMarkets);
+
+        self.markets[count] = market;
+        self.supply_caps[count] = supply_cap;
+        self.supply_queue[count] = market;
+        self.withdraw_queue[count] = market;
+        self.market_count += 1;
+        Ok(())
+    }
+
+    /// Replace both queues; each must be a permutation of the listed markets
+    pub fn set_queues(&mut self, supply_queue: &[Pubkey], withdraw_queue: &[Pubkey]) -> Result<()> {
+        require!(self.is_permutation(supply_queue), MorphoError::VaultQueueMismatch);
+        require!(self.is_permutation(withdraw_queue), MorphoError::VaultQueueMismatch);
+
+        let count = self.market_count as usize;
+        self.supply_queue[..count].copy_from_slice(supply_queue);
+        self.withdraw_queue[..count].copy_from_slice(withdraw_queue);
+        Ok(())
+    }
+
+    fn is_permutation(&self, queue: &[Pubkey]) -> bool {
+        queue.len() == self.market_count as usize
+            && self.listed_markets().iter().all(|m| queue.contains(m))
+    }
+
+    /// Replace the supply queue with an ordered subset of the withdraw queue
+    ///
+    /// Markets left out take no deposits.
+    pub fn set_supply_queue(&mut self, queue: &[Pubkey]) -> Result<()> {
+        require!(self.is_valid_queue(queue), MorphoError::VaultQueueMismatch);
+        require!(
+            queue.iter().all(|m| self.withdraw_queue.contains(m)),
+            MorphoError::VaultQueueMismatch
+        );
+        Self::write_queue(&mut self.supply_queue, queue);
+        Ok(())
+    }
+
+    /// Replace the withdraw queue with an ordered subset of the listed markets
+    ///
+    /// `supply_shares[i]` is the vault's supply in `markets[i]`. A market can
+    /// only be left out once the vault has nothing supplied there and it is
+    /// off the supply queue, so no deposit can end up where withdrawals
+    /// never look.
+    pub fn set_withdraw_queue(&mut self, queue: &[Pubkey], supply_shares: &[u128]) -> Result<()> {
+        require!(self.is_valid_queue(queue), MorphoError::VaultQueueMismatch);
+        for (index, market) in self.listed_markets().iter().enumerate() {
+            if queue.contains(market) {
+                continue;
+            }
+            require!(supply_shares[index] == 0, MorphoError::VaultMarketHasSupply);
+            require!(!self.supply_queue.contains(market), MorphoError::VaultQueueMismatch);
+        }
+        Self::write_queue(&mut self.withdraw_queue, queue);
+        Ok(())
+    }
+
+    /// Listed markets only, each at most once
+    fn is_valid_queue(&self, queue: &[Pubkey]) -> bool {
+        queue.len() <= self.market_count as usize
+            && queue.iter().enumerate().all(|(i, m)| {
+                self.market_index(m).is_some() && !queue[..i].contains(m)
+            })
+    }
+
+    fn write_queue(target: &mut [Pubkey; MAX_VAULT_MARKETS], queue: &[Pubkey]) {
+        *target = [Pubkey::default(); MAX_VAULT_MARKETS];
+        target[..queue.len()].copy_from_slice(queue);
+    }
+
+    /// Start removing `market`: its cap drops to 0, and it can be removed
+    /// VAULT_MARKET_REMOVAL_DELAY_SLOTS after `slot` once emptied
+    pub fn submit_removal(&mut self, market: Pubkey, slot: u64) -> Result<()> {
+        let index = self.market_index(&market).ok_or(MorphoError::VaultMarketNotListed)?;
+        require!(
+            self.pending_market_removal == Pubkey::default(),
+            MorphoError::VaultRemovalPending
+        );
+
+        self.supply_caps[index] = 0;
+        self.pending_market_removal = market;
+        self.removal_after_slot = slot
+            .checked_add(VAULT_MARKET_REMOVAL_DELAY_SLOTS)
+            .ok_or(MorphoError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Drop the pending removal of `market`; its cap stays at 0
+    pub fn cancel_removal(&mut self, market: Pubkey) -> Result<()> {
+        self.require_pending_removal(market)?;
+        self.pending_market_removal = Pubkey::default();
+        self.removal_after_slot = 0;
+        Ok(())
+    }
+
+    /// Unlist the pending `market` once its delay has elapsed
+    ///
+    /// `supplied` is what the vault has supplied there (rounded down);
+    /// shares worth less than one token are left behind. Later listings
+    /// move up one place, and the market leaves both queues.
+    pub fn remove_market(&mut self, market: Pubkey, slot: u64, supplied: u128) -> Result<()> {
+        self.require_pending_removal(market)?;
+        require!(slot >= self.removal_after_slot, MorphoError::TimelockNotElapsed);
+        require!(supplied == 0, MorphoError::MarketNotEmpty);
+
+        let index = self.market_index(&market).ok_or(MorphoError::VaultMarketNotListed)?;
+        let count = self.market_count as usize;
+        self.markets.copy_within(index + 1..count, index);
+        self.supply_caps.copy_within(index + 1..count, index);
+        self.markets[count - 1] = Pubkey::default();
+        self.supply_caps[count - 1] = 0;
+
+        for queue in [&mut self.supply_queue, &mut self.withdraw_queue] {
+            let kept = queue
+                .iter()
+                .filter(|m| **m != market && **m != Pubkey::default())
+                .copied()
+                .collect::<Vec<_>>();
+            Self::write_queue(queue, &kept);
+        }
+
+        self.market_count -= 1;
+        self.pending_market_removal = Pubkey::default();
+        self.removal_after_slot = 0;
+        Ok(())
+    }
+
+    fn require_pending_removal(&self, market: Pubkey) -> Result<()> {
+        require!(
+            market != Pubkey::default() && self.pending_market_removal == market,
+            MorphoError::VaultRemovalNotPending
+        );
+        Ok(())
+    }
+
+    /// Listing indexes in supply-queue order
+    pub fn supply_order(&self) -> Vec<usize> {
+        self.queue_order(&self.supply_queue)
+    }
+
+    /// Listing indexes in withdraw-queue order
+    pub fn withdraw_order(&self) -> Vec<usize> {
+        self.queue_order(&self.withdraw_queue)
+    }
+
+    fn queue_order(&self, queue: &[Pubkey; MAX_VAULT_MARKETS]) -> Vec<usize> {
+        queue[..self.market_count as usize]
+            .iter()
+            .filter_map(|m| self.market_index(m))
+            .collect()
+    }
+
+    /// Idle assets to hold back when total assets are `total_assets`
+    pub fn idle_target(&self, total_assets: u128) -> Result<u128> {
+        mul_div_down(total_assets, self.idle_buffer_bps as u128, BPS as u128)
+    }
+
+    /// Assets that can still be supplied to `markets[index]`
+    pub fn cap_room(&self, index: usize, supplied: u128) -> u128 {
+        checked_sub(self.supply_caps[index], supplied).unwrap_or(0)
+    }
+
+    /// Mint performance fee shares on yield since `last_total_assets`
+    ///
+    /// Shares are priced so the recipient's claim equals `fee_bps` of the
+    /// interest. Nothing is taken when `total_assets` fell (losses), and the
+    /// new total becomes the reference either way. Returns (interest, fee shares).
+    pub fn accrue_fee(&mut self, total_assets: u128) -> Result<(u128, u128)> {
+        let interest = checked_sub(total_assets, self.last_total_assets).unwrap_or(0);
+        self.last_total_assets = total_assets;
+
+        if interest == 0 || self.fee_bps == 0 {
+            return Ok((interest, 0));
+        }
+
+        let fee_assets = mul_div_down(interest, self.fee_bps as u128, BPS as u128)?;
+        let fee_shares = to_shares_down(
+            fee_assets,
+            checked_sub(total_assets, fee_assets)?,
+            self.total_shares,
+        )?;
+        self.total_shares = checked_add(self.total_shares, fee_shares)?;
+        self.pending_fee_shares = checked_add(self.pending_fee_shares, fee_shares)?;
+        Ok((interest, fee_shares))
+    }
+
+    /// Split `assets` along the supply queue, clamping each market to its cap
+    ///
+    /// `supplied[i]` is the vault's current supply to `markets[i]` and
+    /// `market_room[i]` what that market's own supply cap still admits.
+    /// Returns the (listing index, amount) pairs to supply and the unplaced
+    /// remainder.
+    pub fn plan_supply(&self, assets: u128, supplied: &[u128], market_room: &[u128]) -> (Vec<(usize, u128)>, u128) {
+        let mut remaining = assets;
+        let mut plan = Vec::new();
+        for index in self.supply_order() {
+            if remaining == 0 {
+                break;
+            }
+            let room = std::cmp::min(self.cap_room(index, supplied[index]), market_room[index]);
+            let amount = std::cmp::min(remaining, room);
+            if amount > 0 {
+                plan.push((index, amount));
+                remaining -= amount;
+            }
+        }
+        (plan, remaining)
+    }
+
+    /// Source `assets` along the withdraw queue
+    ///
+    /// `withdrawable[i]` is what `markets[i]` can return right now (the vault's
+    /// supply capped by the market's available liquidity). Returns the
+    /// (listing index, amount) pairs to withdraw and the unmet remainder.
+    pub fn plan_withdraw(&self, assets: u128, withdrawable: &[u128]) -> (Vec<(usize, u128)>, u128) {
+        let mut remaining = assets;
+        let mut plan = Vec::new();
+        for index in self.withdraw_order() {
+            if remaining == 0 {
+                break;
+            }
+            let amount = std::cmp::min(remaining, withdrawable[index]);
+            if amount > 0 {
+                plan.push((index, amount));
+                remaining -= amount;
+            }
+        }
+        (plan, remaining)
+    }
+}
+
+/// A depositor's shares in one vault
+///
+/// PDA Seeds: [PROGRAM_SEED_PREFIX, b"morpho_vault_position", vault_id, owner]
+#[account]
+pub struct VaultPosition {
+    /// PDA bump seed
+    pub bump: u8,
+
+    /// Vault this position belongs to
+    pub vault_id: [u8; 32],
+
+    /// Position owner
+    pub owner: Pubkey,
+
+    /// Vault shares
+    pub shares: u128,
+
+    /// Reserved for future use
+    pub reserved: [u8; 32],
+}
+
+impl VaultPosition {
+    pub const SEED: &'static [u8] = b"morpho_vault_position";
+
+    pub fn space() -> usize {
+        8 +     // discriminator
+        1 +     // bump
+        32 +    // vault_id
+        32 +    // owner
+        16 +    // shares
+        32      // reserved
+    }
+}
+
+/// Derive vault PDA
+pub fn derive_vault(program_id: &Pubkey, vault_id: &[u8; 32]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PROGRAM_SEED_PREFIX, Vault::SEED, vault_id],
+        program_id,
+    )
+}
+
+/// Derive vault position PDA
+pub fn derive_vault_position(
+    program_id: &Pubkey,
+    vault_id: &[u8; 32],
+    owner: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            PROGRAM_SEED_PREFIX,
+            VaultPosition::SEED,
+            vault_id,
+            owner.as_ref(),
+        ],
+        program_id,
+    )
+}
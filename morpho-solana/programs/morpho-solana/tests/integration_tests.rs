@@ -4,6 +4,7 @@
 //! Uses LiteSVM for fast, in-process Solana runtime execution.
 
 use litesvm::LiteSVM;
+use anchor_lang::Discriminator;
 use anchor_lang::solana_program::{
     clock::Clock,
     pubkey::Pubkey,
@@ -13,14 +14,25 @@ use anchor_lang::solana_program::{
 use morpho_solana::constants::{
     PROGRAM_SEED_PREFIX, BPS, WAD, ORACLE_SCALE, MAX_FEE, FLASH_LOAN_FEE_BPS,
     VIRTUAL_SHARES, VIRTUAL_ASSETS, MAX_LIF, LIF_BPS, MAX_LLTVS, MAX_IRMS, LIF_CURSOR,
+    MAX_LIF_CURSOR, MAX_LIF_CAP,
+    PARAM_CHANGE_DELAY_SLOTS, MAX_VAULT_MARKETS, VAULT_MARKET_REMOVAL_DELAY_SLOTS, MAX_LIQUIDATION_PROTOCOL_FEE_BPS,
 };
 use morpho_solana::state::{
-    ProtocolState, Market, Position, Authorization,
-    calculate_market_id, derive_protocol_state, derive_market,
-    derive_position,
+    ProtocolState, Market, Position, Authorization, OracleKind, PreLiquidation,
+    ParamKind, PendingParamChange, Vault,
+    MarketConfig, calculate_market_id, assert_invariants, derive_protocol_state, derive_market,
+    derive_position, derive_market_index, derive_loan_vault, derive_collateral_vault,
+    MarketSummary,
 };
+use morpho_solana::errors::MorphoError;
 use morpho_solana::math::*;
-use morpho_solana::interfaces::calculate_lif;
+use morpho_solana::interfaces::{
+    calculate_lif, check_price_deviation, socialize_bad_debt, is_liquidatable, ensure_liquidatable,
+    health_factor,
+    calculate_seized_collateral, calculate_repaid_assets, liquidation_protocol_fee, StaticOracle,
+    max_market_price, max_oracle_price, write_off_bad_debt, socialize_pending_bad_debt,
+    supply_share_price, repay_backstops_position,
+};
 
 use solana_sdk::signature::{Keypair, Signer as SolanaSigner};
 use solana_sdk::transaction::Transaction;
@@ -42,10 +54,12 @@ const LOAN_DECIMALS: u8 = 6;        // e.g., USDC
 // Test amounts
 const SUPPLY_AMOUNT: u64 = 10_000_000_000;     // 10,000 USDC (6 decimals)
 const COLLATERAL_AMOUNT: u64 = 5_000_000_000;  // 5 ETH (9 decimals)
+#[allow(dead_code)]
 const BORROW_AMOUNT: u64 = 5_000_000_000;      // 5,000 USDC (6 decimals)
 
 // Market parameters
 const LLTV_85_PERCENT: u64 = 8500;
+#[allow(dead_code)]
 const LLTV_80_PERCENT: u64 = 8000;
 
 // ============================================================================
@@ -58,6 +72,17 @@ fn program_id() -> Pubkey {
     "MorphoXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX".parse().unwrap()
 }
 
+/// Zeroed market with mainnet-like decimals and an 85% LLTV; tests
+/// override only the fields they exercise
+fn test_market() -> Market {
+    Market {
+        collateral_decimals: 9,
+        loan_decimals: 6,
+        lltv: LLTV_85_PERCENT,
+        ..bytemuck::Zeroable::zeroed()
+    }
+}
+
 /// Test environment containing LiteSVM and test accounts
 pub struct TestEnv {
     pub svm: LiteSVM,
@@ -79,6 +104,12 @@ pub struct TestEnv {
     pub irm: Keypair,
 }
 
+impl Default for TestEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TestEnv {
     /// Create a new test environment with deployed program
     pub fn new() -> Self {
@@ -158,6 +189,7 @@ impl TestEnv {
             &self.oracle.pubkey(),
             &self.irm.pubkey(),
             lltv,
+            &MarketConfig::default(),
         )
     }
 
@@ -236,14 +268,14 @@ impl TestEnv {
 
     /// Create mock oracle account with a fixed price
     pub fn create_static_oracle(&mut self, price: u128) {
-        let rent = self.svm.minimum_balance_for_rent_exemption(57); // StaticOracle size
+        let rent = self.svm.minimum_balance_for_rent_exemption(StaticOracle::space());
 
         // Create account
         let create_ix = system_instruction::create_account(
             &self.owner.pubkey(),
             &self.oracle.pubkey(),
             rent,
-            57,
+            StaticOracle::space() as u64,
             &self.program_id,
         );
 
@@ -257,9 +289,9 @@ impl TestEnv {
         self.svm.send_transaction(tx).unwrap();
 
         // Write oracle data directly (mock)
-        // Format: discriminator(8) + bump(1) + price(16) + admin(32)
-        let mut data = vec![0u8; 57];
-        // Skip discriminator bytes (anchor discriminator for StaticOracle)
+        // Format: discriminator(8) + bump(1) + price(16) + admin(32) + last_update_slot(8)
+        let mut data = vec![0u8; StaticOracle::space()];
+        data[..8].copy_from_slice(StaticOracle::DISCRIMINATOR);
         data[8] = 1; // bump
         data[9..25].copy_from_slice(&price.to_le_bytes());
         data[25..57].copy_from_slice(self.owner.pubkey().as_ref());
@@ -330,7 +362,7 @@ pub fn derive_market_id(
     irm: &Pubkey,
     lltv: u64,
 ) -> [u8; 32] {
-    calculate_market_id(collateral_mint, loan_mint, oracle, irm, lltv)
+    calculate_market_id(collateral_mint, loan_mint, oracle, irm, lltv, &MarketConfig::default())
 }
 
 // ============================================================================
@@ -376,6 +408,19 @@ mod unit_tests {
         // Different LLTV should give different ID
         let id3 = derive_market_id(&collateral, &loan, &oracle, &irm, 8000);
         assert_ne!(id1, id3, "Different LLTV should produce different market ID");
+
+        // Any config difference should too, so a hostile config cannot
+        // claim the id of the canonical one
+        let canonical = MarketConfig { max_staleness_slots: 25, max_lif: MAX_LIF, ..MarketConfig::default() };
+        let hostile = MarketConfig { max_staleness_slots: 300, ..canonical };
+        assert_ne!(
+            calculate_market_id(&collateral, &loan, &oracle, &irm, lltv, &canonical),
+            calculate_market_id(&collateral, &loan, &oracle, &irm, lltv, &hostile),
+        );
+        assert_ne!(
+            calculate_market_id(&collateral, &loan, &oracle, &irm, lltv, &canonical),
+            calculate_market_id(&collateral, &loan, &oracle, &irm, lltv, &MarketConfig { invert_oracle: true, ..canonical }),
+        );
     }
 
     #[test]
@@ -413,6 +458,36 @@ mod unit_tests {
 
         assert_ne!(pos1, pos2, "Different owners should have different position PDAs");
     }
+
+    #[test]
+    fn test_vault_pdas_isolated_per_market() {
+        let program_id = morpho_solana::ID;
+        let market_a = [1u8; 32];
+        let market_b = [2u8; 32];
+
+        let (loan_a, loan_a_bump) = derive_loan_vault(&program_id, &market_a);
+        let (collateral_a, _) = derive_collateral_vault(&program_id, &market_a);
+        let (loan_b, _) = derive_loan_vault(&program_id, &market_b);
+        let (collateral_b, _) = derive_collateral_vault(&program_id, &market_b);
+
+        let vaults = [loan_a, collateral_a, loan_b, collateral_b];
+        for (i, a) in vaults.iter().enumerate() {
+            for b in &vaults[i + 1..] {
+                assert_ne!(a, b, "Every market vault must be its own account");
+            }
+        }
+
+        // Handlers re-derive the vault from the instruction's market_id and
+        // the market's stored bump, so market A's vault never satisfies
+        // market B's seeds, whatever bump is supplied
+        for bump in [loan_a_bump, 255, 254] {
+            let crafted = Pubkey::create_program_address(
+                &[PROGRAM_SEED_PREFIX, Market::LOAN_VAULT_SEED, &market_b, &[bump]],
+                &program_id,
+            );
+            assert_ne!(crafted.ok(), Some(loan_a));
+        }
+    }
 }
 
 // ============================================================================
@@ -474,27 +549,12 @@ mod math_validation_tests {
     fn test_interest_accrual_over_time() {
         // Create a mock market state
         let mut market = Market {
-            bump: 0,
-            market_id: [0u8; 32],
-            collateral_mint: Pubkey::default(),
-            loan_mint: Pubkey::default(),
-            collateral_decimals: 9,
-            loan_decimals: 6,
-            oracle: Pubkey::default(),
-            irm: Pubkey::default(),
-            lltv: 8500,
-            paused: false,
             fee: 1000, // 10% fee
             total_supply_assets: 10_000_000_000_000, // 10M
             total_supply_shares: 10_000_000_000_000_000_000, // 10e18
             total_borrow_assets: 5_000_000_000_000, // 5M borrowed
             total_borrow_shares: 5_000_000_000_000_000_000, // 5e18
-            last_update: 0,
-            pending_fee_shares: 0,
-            collateral_vault_bump: 0,
-            loan_vault_bump: 0,
-            flash_loan_lock: 0,
-            reserved: [0u8; 127],
+            ..test_market()
         };
 
         let initial_supply = market.total_supply_assets;
@@ -513,32 +573,16 @@ mod math_validation_tests {
 
         // With 10% fee and ~10% interest on 5M borrowed, fee_shares should be meaningful
         assert!(result.fee_shares > 0 || market.fee == 0, "Fee shares should accrue if fee > 0");
+        assert_invariants(&market).unwrap();
     }
 
     #[test]
     fn test_no_interest_when_no_borrows() {
         let mut market = Market {
-            bump: 0,
-            market_id: [0u8; 32],
-            collateral_mint: Pubkey::default(),
-            loan_mint: Pubkey::default(),
-            collateral_decimals: 9,
-            loan_decimals: 6,
-            oracle: Pubkey::default(),
-            irm: Pubkey::default(),
-            lltv: 8500,
-            paused: false,
             fee: 1000,
             total_supply_assets: 10_000_000_000_000,
             total_supply_shares: 10_000_000_000_000_000_000,
-            total_borrow_assets: 0, // No borrows
-            total_borrow_shares: 0,
-            last_update: 0,
-            pending_fee_shares: 0,
-            collateral_vault_bump: 0,
-            loan_vault_bump: 0,
-            flash_loan_lock: 0,
-            reserved: [0u8; 127],
+            ..test_market()
         };
 
         let rate = WAD / 10 / 31_536_000;
@@ -548,49 +592,188 @@ mod math_validation_tests {
         assert_eq!(result.fee_shares, 0, "No fee shares when no borrows");
     }
 
+    #[test]
+    fn test_lif_at_extreme_lltvs() {
+        // LLTV 0: the cursor term is at its largest and the result caps at MAX_LIF
+        assert_eq!(calculate_lif(0, LIF_CURSOR, MAX_LIF).unwrap(), MAX_LIF);
+        // LLTV 100%: no incentive beyond par
+        assert_eq!(calculate_lif(BPS, LIF_CURSOR, MAX_LIF).unwrap(), LIF_BPS);
+        // Past 100% is an error, not a silently clamped incentive
+        assert_eq!(calculate_lif(BPS + 1, LIF_CURSOR, MAX_LIF).unwrap_err(), MorphoError::MathUnderflow.into());
+        assert_eq!(calculate_lif(u64::MAX, LIF_CURSOR, MAX_LIF).unwrap_err(), MorphoError::MathUnderflow.into());
+    }
+
+    #[test]
+    fn test_lif_at_enabled_lltvs() {
+        // Morpho Blue's governance-enabled LLTVs and their incentive
+        let expected = [
+            (3850, 11500), // capped at MAX_LIF
+            (6250, 11267),
+            (7700, 10741),
+            (8600, 10438),
+            (9150, 10261),
+            (9450, 10167),
+            (9650, 10106),
+            (9800, 10060),
+        ];
+        for (lltv, lif) in expected {
+            assert_eq!(calculate_lif(lltv, LIF_CURSOR, MAX_LIF).unwrap(), lif, "LIF at LLTV {lltv}");
+            // Seizing LIF * debt stays within the collateral at the threshold
+            assert!(lltv * lif < BPS * BPS, "LLTV {lltv} leaves no margin");
+        }
+    }
+
+    #[test]
+    fn test_lif_cursor_changes_incentive() {
+        // Same 80% LLTV, three curves: 1 / (1 - cursor * 20%)
+        assert_eq!(calculate_lif(8000, 1_000, MAX_LIF).unwrap(), 10204);
+        assert_eq!(calculate_lif(8000, LIF_CURSOR, MAX_LIF).unwrap(), 10638);
+        assert_eq!(calculate_lif(8000, MAX_LIF_CURSOR, MAX_LIF_CAP).unwrap(), 11111);
+        // A lower max LIF cuts the steeper curve off
+        assert_eq!(calculate_lif(8000, MAX_LIF_CURSOR, 11_000).unwrap(), 11_000);
+    }
+
+    #[test]
+    fn test_withdraw_collateral_boundary() {
+        // 1 collateral = 2 loan tokens, 80% LLTV, 1_600 owed:
+        // at least 1_000 collateral must stay behind
+        let price = 2 * ORACLE_SCALE;
+        let lltv = 8000;
+        let total_borrow_assets = 1_600u128;
+        let borrow_shares = total_borrow_assets * VIRTUAL_SHARES;
+        let collateral = 5_000u128;
+
+        let liquidatable_after = |withdrawn: u128| {
+            is_liquidatable(
+                collateral - withdrawn,
+                borrow_shares,
+                total_borrow_assets,
+                borrow_shares,
+                price,
+                lltv,
+            )
+            .unwrap()
+        };
+
+        assert!(!liquidatable_after(4_000), "Maximum safe withdrawal must pass");
+        assert!(liquidatable_after(4_001), "One wei more must be rejected");
+    }
+
+    #[test]
+    fn test_borrow_lltv_boundary() {
+        // 1_000 collateral worth 2_000 loan tokens at 80% LLTV: 1_600 borrowable
+        let price = 2 * ORACLE_SCALE;
+        let lltv = 8000;
+        let collateral = 1_000u128;
+
+        // Fresh market: the borrow's shares are the whole borrow side
+        let liquidatable_after = |assets: u128| {
+            let shares = to_shares_up(assets, 0, 0).unwrap();
+            is_liquidatable(collateral, shares, assets, shares, price, lltv).unwrap()
+        };
+
+        assert!(!liquidatable_after(1_600), "Borrowing up to the LLTV must pass");
+        assert!(liquidatable_after(1_601), "One unit past the LLTV must be rejected");
+    }
+
+    #[test]
+    fn test_health_factor_agrees_with_is_liquidatable_at_boundary() {
+        // 1_000 collateral worth 2_000 loan tokens at 80% LLTV: 1_600 borrowable
+        let price = 2 * ORACLE_SCALE;
+        let lltv = 8000;
+        let collateral = 1_000u128;
+
+        let check = |assets: u128, price: u128| {
+            let shares = to_shares_up(assets, 0, 0).unwrap();
+            let borrowed = to_assets_up(shares, assets, shares).unwrap();
+            let health = health_factor(collateral, borrowed, price, lltv).unwrap();
+            let liquidatable = is_liquidatable(collateral, shares, assets, shares, price, lltv).unwrap();
+            assert_eq!(health < WAD, liquidatable, "Disagreement at {} borrowed", assets);
+            health
+        };
+
+        // Exactly at the boundary both call the position healthy
+        assert_eq!(check(1_600, price), WAD);
+        // One unit past it both call it liquidatable
+        assert!(check(1_601, price) < WAD);
+        assert!(check(1_599, price) > WAD);
+
+        // Prices whose collateral value floors must not split them either
+        for price in [ORACLE_SCALE / 3, ORACLE_SCALE * 7 / 9, ORACLE_SCALE + 1] {
+            let collateral_value = mul_div_down(collateral, price, ORACLE_SCALE).unwrap();
+            let max_borrow = collateral_value * lltv as u128 / 10_000;
+            for assets in max_borrow - 2..=max_borrow + 2 {
+                check(assets, price);
+            }
+        }
+    }
+
     #[test]
     fn test_liquidation_math() {
         // Test LIF calculation
         let lltv_85 = 8500u64; // 85% LTV
-        let lif_85 = calculate_lif(lltv_85);
+        let lif_85 = calculate_lif(lltv_85, LIF_CURSOR, MAX_LIF).unwrap();
 
         // LIF should be > 10000 (> 100%) to incentivize liquidators
         assert!(lif_85 > 10000, "LIF should be above 100% (10000 bps)");
         assert!(lif_85 <= 11500, "LIF should not exceed max (115%)");
 
         // Test different LLTV values
-        let lif_80 = calculate_lif(8000); // 80% LTV
-        let lif_90 = calculate_lif(9000); // 90% LTV
+        let lif_80 = calculate_lif(8000, LIF_CURSOR, MAX_LIF).unwrap(); // 80% LTV
+        let lif_90 = calculate_lif(9000, LIF_CURSOR, MAX_LIF).unwrap(); // 90% LTV
 
         // Higher LLTV = lower LIF (less buffer for liquidation bonus)
         assert!(lif_80 > lif_85, "Lower LLTV should have higher LIF");
         assert!(lif_85 > lif_90, "Higher LLTV should have lower LIF");
     }
 
+    #[test]
+    fn test_seize_exact_collateral_math() {
+        // Mirrors the repay-driven mode: 1_000_000 repaid 1:1 at a 5% LIF seizes 1_050_000
+        let lif = 10_500;
+        assert_eq!(calculate_repaid_assets(1_050_000, ORACLE_SCALE, lif).unwrap(), 1_000_000);
+
+        // Round trips through the repay-driven formula never underpay
+        for price in [ORACLE_SCALE, ORACLE_SCALE * 3 / 7, ORACLE_SCALE * 5 / 2] {
+            for seized in [1u128, 999, 1_000_003, 123_456_789] {
+                let repaid = calculate_repaid_assets(seized, price, lif).unwrap();
+                assert!(
+                    calculate_seized_collateral(repaid, price, lif).unwrap() >= seized,
+                    "Repay {} must cover {} collateral at price {}", repaid, seized, price
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_liquidation_protocol_fee_split() {
+        // 1_000_000 repaid 1:1 at a 5% LIF: 50_000 collateral of bonus
+        let repaid = 1_000_000u128;
+        let seized = calculate_seized_collateral(repaid, ORACLE_SCALE, 10_500).unwrap();
+        assert_eq!(seized, 1_050_000);
+
+        assert_eq!(liquidation_protocol_fee(seized, repaid, ORACLE_SCALE, 0).unwrap(), 0);
+        // 20% of the bonus to the protocol, never any of the repaid value
+        let fee = liquidation_protocol_fee(seized, repaid, ORACLE_SCALE, 2_000).unwrap();
+        assert_eq!(fee, 10_000);
+        assert_eq!(seized - fee, 1_040_000);
+
+        // At the maximum the liquidator still keeps half the bonus
+        let fee = liquidation_protocol_fee(seized, repaid, ORACLE_SCALE, MAX_LIQUIDATION_PROTOCOL_FEE_BPS).unwrap();
+        assert_eq!(seized - fee, repaid + 25_000);
+
+        // Seizure capped below the repaid value (collateral ran out): no bonus
+        assert_eq!(liquidation_protocol_fee(900_000, repaid, ORACLE_SCALE, 2_000).unwrap(), 0);
+    }
+
     #[test]
     fn test_utilization_calculation() {
         let market = Market {
-            bump: 0,
-            market_id: [0u8; 32],
-            collateral_mint: Pubkey::default(),
-            loan_mint: Pubkey::default(),
-            collateral_decimals: 9,
-            loan_decimals: 6,
-            oracle: Pubkey::default(),
-            irm: Pubkey::default(),
-            lltv: 8500,
-            paused: false,
-            fee: 0,
             total_supply_assets: 1_000_000_000_000, // 1M
             total_supply_shares: 1_000_000_000_000_000_000,
             total_borrow_assets: 500_000_000_000, // 500K borrowed = 50% utilization
             total_borrow_shares: 500_000_000_000_000_000,
-            last_update: 0,
-            pending_fee_shares: 0,
-            collateral_vault_bump: 0,
-            loan_vault_bump: 0,
-            flash_loan_lock: 0,
-            reserved: [0u8; 127],
+            ..test_market()
         };
 
         let utilization = market.utilization();
@@ -603,33 +786,91 @@ mod math_validation_tests {
     #[test]
     fn test_available_liquidity() {
         let market = Market {
-            bump: 0,
-            market_id: [0u8; 32],
-            collateral_mint: Pubkey::default(),
-            loan_mint: Pubkey::default(),
-            collateral_decimals: 9,
-            loan_decimals: 6,
-            oracle: Pubkey::default(),
-            irm: Pubkey::default(),
-            lltv: 8500,
-            paused: false,
-            fee: 0,
             total_supply_assets: 1_000_000,
             total_supply_shares: 1_000_000_000_000,
             total_borrow_assets: 400_000,
             total_borrow_shares: 400_000_000_000,
-            last_update: 0,
-            pending_fee_shares: 0,
-            collateral_vault_bump: 0,
-            loan_vault_bump: 0,
-            flash_loan_lock: 0,
-            reserved: [0u8; 127],
+            ..test_market()
         };
 
         let liquidity = market.available_liquidity();
         assert_eq!(liquidity, 600_000, "Available = Supply - Borrow");
     }
 
+    #[test]
+    fn test_borrow_beyond_liquidity_rejected() {
+        let market = Market {
+            total_supply_assets: 1_000_000,
+            total_supply_shares: 1_000_000_000_000,
+            total_borrow_assets: 400_000,
+            total_borrow_shares: 400_000_000_000,
+            ..test_market()
+        };
+
+        // 600_000 free: borrowing it all is fine, one more unit is not
+        assert!(market.ensure_liquidity(600_000).is_ok());
+        let err = market.ensure_liquidity(600_001).unwrap_err();
+        assert_eq!(err, MorphoError::InsufficientLiquidity.into(), "Dedicated error, not an underflow");
+
+        // A market already past full utilization has no liquidity at all
+        let mut over = market;
+        over.total_borrow_assets = over.total_supply_assets + 1;
+        assert_eq!(over.available_liquidity(), 0);
+        assert!(over.ensure_liquidity(1).is_err());
+    }
+
+    /// New borrows stop at the utilization cap; 0 means uncapped
+    #[test]
+    fn test_borrow_utilization_cap() {
+        let mut market = Market {
+            total_supply_assets: 1_000_000,
+            total_supply_shares: 1_000_000_000_000,
+            total_borrow_assets: 400_000,
+            total_borrow_shares: 400_000_000_000,
+            max_utilization_bps: 5_000,
+            ..test_market()
+        };
+
+        // 40% utilized, capped at 50%: 100_000 more is exactly at the cap
+        assert!(market.ensure_utilization_cap(100_000).is_ok());
+        let err = market.ensure_utilization_cap(100_001).unwrap_err();
+        assert_eq!(err, MorphoError::UtilizationCapReached.into());
+        // Liquidity alone would still allow it
+        assert!(market.ensure_liquidity(100_001).is_ok());
+        assert_eq!(market.utilization_cap_room().unwrap(), 100_000);
+
+        market.max_utilization_bps = 0;
+        assert!(market.ensure_utilization_cap(600_000).is_ok());
+        assert_eq!(market.utilization_cap_room().unwrap(), u128::MAX);
+    }
+
+    #[test]
+    fn test_position_size_dust_floor() {
+        let market = Market {
+            total_supply_assets: 1_000_000,
+            total_supply_shares: 1_000_000_000_000,
+            total_borrow_assets: 400_000,
+            total_borrow_shares: 400_000_000_000,
+            min_borrow: 100,
+            min_collateral: 500,
+            ..test_market()
+        };
+        // 1 collateral = 2 loan tokens
+        let price = 2 * ORACLE_SCALE;
+
+        assert!(market.ensure_position_size(250, 100, price).is_ok());
+        let err = market.ensure_position_size(250, 99, price).unwrap_err();
+        assert_eq!(err, MorphoError::PositionTooSmall.into());
+        // 249 collateral is worth 498 < 500
+        assert!(market.ensure_position_size(249, 100, price).is_err());
+
+        // Debt-free positions are never dust, whatever is left
+        assert!(market.ensure_position_size(1, 0, price).is_ok());
+        assert!(market.above_dust_floor(250, 100, price).unwrap());
+        assert!(!market.above_dust_floor(249, 100, price).unwrap());
+        assert!(market.above_dust_floor(1, 0, price).unwrap());
+    }
+
     #[test]
     fn test_flash_loan_fee_calculation() {
         let borrowed = 1_000_000_000u128; // 1000 USDC
@@ -695,6 +936,33 @@ mod state_tests {
         assert!(space < 2000, "Protocol state shouldn't be too large");
     }
 
+    #[test]
+    fn test_pause_authority() {
+        let owner = Pubkey::new_unique();
+        let mut state = ProtocolState {
+            bump: 1,
+            owner,
+            pending_owner: Pubkey::default(),
+            fee_recipient: Pubkey::new_unique(),
+            paused: false,
+            lltv_count: 0,
+            enabled_lltvs: [0u64; MAX_LLTVS],
+            irm_count: 0,
+            enabled_irms: [Pubkey::default(); MAX_IRMS],
+            market_count: 0,
+            guardian: Pubkey::default(),
+            reserved: [0u8; 224],
+        };
+
+        assert!(state.is_pause_authority(&owner), "Owner can always pause");
+        assert!(!state.is_pause_authority(&Pubkey::default()), "Unset guardian grants nothing");
+
+        let guardian = Pubkey::new_unique();
+        state.guardian = guardian;
+        assert!(state.is_pause_authority(&guardian), "Guardian can pause");
+        assert!(!state.is_pause_authority(&Pubkey::new_unique()), "Others cannot pause");
+    }
+
     #[test]
     fn test_market_space() {
         let space = Market::space();
@@ -716,6 +984,358 @@ mod state_tests {
         assert!(space < 200, "Authorization shouldn't be too large");
     }
 
+    #[test]
+    fn test_pending_param_change_timelock() {
+        let pending = PendingParamChange {
+            bump: 1,
+            market_id: [1u8; 32],
+            param: ParamKind::Fee as u8,
+            value: 1000,
+            address: Pubkey::default(),
+            queued_at_slot: 100,
+            execute_after_slot: 100 + PARAM_CHANGE_DELAY_SLOTS,
+            reserved: [0u8; 32],
+        };
+
+        assert!(!pending.is_executable(100), "Not executable when queued");
+        assert!(!pending.is_executable(99 + PARAM_CHANGE_DELAY_SLOTS), "Not executable before delay");
+        assert!(pending.is_executable(100 + PARAM_CHANGE_DELAY_SLOTS), "Executable once delay elapsed");
+
+        assert_eq!(ParamKind::try_from(pending.param).unwrap(), ParamKind::Fee);
+        assert_eq!(ParamKind::try_from(3).unwrap(), ParamKind::Irm);
+        assert_eq!(ParamKind::try_from(4).unwrap(), ParamKind::CloseFactor);
+        assert_eq!(ParamKind::try_from(5).unwrap(), ParamKind::AuctionDuration);
+        assert_eq!(ParamKind::try_from(6).unwrap(), ParamKind::LiquidationProtocolFee);
+        assert_eq!(ParamKind::try_from(7).unwrap(), ParamKind::LiquidationGrace);
+        assert_eq!(ParamKind::try_from(8).unwrap(), ParamKind::SupplyCap);
+        assert_eq!(ParamKind::try_from(9).unwrap(), ParamKind::AccrualBasis);
+        assert_eq!(ParamKind::try_from(10).unwrap(), ParamKind::OracleFallback);
+        assert!(ParamKind::try_from(11).is_err(), "Unknown param kinds are rejected");
+    }
+
+    fn empty_vault(idle_buffer_bps: u64) -> Vault {
+        Vault {
+            bump: 1,
+            vault_id: [2u8; 32],
+            curator: Pubkey::new_unique(),
+            asset_mint: Pubkey::new_unique(),
+            idle_vault_bump: 1,
+            idle_buffer_bps,
+            idle_assets: 0,
+            total_shares: 0,
+            market_count: 0,
+            markets: [Pubkey::default(); MAX_VAULT_MARKETS],
+            supply_caps: [0; MAX_VAULT_MARKETS],
+            supply_queue: [Pubkey::default(); MAX_VAULT_MARKETS],
+            withdraw_queue: [Pubkey::default(); MAX_VAULT_MARKETS],
+            fee_bps: 0,
+            fee_recipient: Pubkey::default(),
+            last_total_assets: 0,
+            pending_fee_shares: 0,
+            pending_market_removal: Pubkey::default(),
+            removal_after_slot: 0,
+            reserved: [0u8; 24],
+        }
+    }
+
+    #[test]
+    fn test_vault_market_listing() {
+        let mut vault = empty_vault(0);
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+
+        vault.list_market(a, 1_000).unwrap();
+        vault.list_market(b, 2_000).unwrap();
+        assert_eq!(vault.listed_markets(), &[a, b]);
+        assert_eq!(vault.market_index(&b), Some(1));
+        assert!(vault.list_market(a, 5).is_err(), "Markets are listed once");
+
+        // New markets go to the end of both queues
+        assert_eq!(vault.supply_order(), vec![0, 1]);
+        assert_eq!(vault.withdraw_order(), vec![0, 1]);
+
+        for _ in 2..MAX_VAULT_MARKETS {
+            vault.list_market(Pubkey::new_unique(), 0).unwrap();
+        }
+        assert!(vault.list_market(Pubkey::new_unique(), 0).is_err(), "Listing is capped");
+    }
+
+    #[test]
+    fn test_vault_full_listing_rejects_next_market() {
+        let mut vault = empty_vault(0);
+        for _ in 0..MAX_VAULT_MARKETS {
+            vault.list_market(Pubkey::new_unique(), u128::MAX).unwrap();
+        }
+        assert_eq!(vault.market_count as usize, MAX_VAULT_MARKETS);
+        assert_eq!(
+            vault.list_market(Pubkey::new_unique(), u128::MAX).unwrap_err(),
+            MorphoError::VaultTooManyMarkets.into()
+        );
+        assert_eq!(vault.market_count as usize, MAX_VAULT_MARKETS);
+
+        // Plans over a full vault stop at the first market that covers the amount
+        let (plan, rest) = vault.plan_supply(500, &[0; MAX_VAULT_MARKETS], &[u128::MAX; MAX_VAULT_MARKETS]);
+        assert_eq!((plan, rest), (vec![(0, 500)], 0));
+        let (plan, rest) = vault.plan_withdraw(500, &[1_000; MAX_VAULT_MARKETS]);
+        assert_eq!((plan, rest), (vec![(0, 500)], 0));
+        let (plan, _) = vault.plan_withdraw(1_500, &[1_000; MAX_VAULT_MARKETS]);
+        assert_eq!(plan, vec![(0, 1_000), (1, 500)]);
+    }
+
+    #[test]
+    fn test_vault_queues() {
+        let mut vault = empty_vault(0);
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        vault.list_market(a, 1_000).unwrap();
+        vault.list_market(b, 1_000).unwrap();
+
+        vault.set_queues(&[b, a], &[a, b]).unwrap();
+        assert_eq!(vault.supply_order(), vec![1, 0]);
+        assert_eq!(vault.withdraw_order(), vec![0, 1]);
+
+        assert!(vault.set_queues(&[a], &[a, b]).is_err(), "Queue missing a market");
+        assert!(vault.set_queues(&[a, a], &[a, b]).is_err(), "Queue with a duplicate");
+        assert!(vault.set_queues(&[a, b], &[a, Pubkey::new_unique()]).is_err(), "Unlisted market");
+    }
+
+    #[test]
+    fn test_vault_supply_queue_subset() {
+        let mut vault = empty_vault(0);
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let c = Pubkey::new_unique();
+        vault.list_market(a, 1_000).unwrap();
+        vault.list_market(b, 1_000).unwrap();
+        vault.list_market(c, 1_000).unwrap();
+
+        // Dropping markets from the supply queue is always allowed
+        vault.set_supply_queue(&[c, a]).unwrap();
+        assert_eq!(vault.supply_order(), vec![2, 0]);
+        let (plan, _) = vault.plan_supply(3_000, &[0; 3], &[u128::MAX; 3]);
+        assert_eq!(plan, vec![(2, 1_000), (0, 1_000)]);
+
+        assert!(vault.set_supply_queue(&[a, a]).is_err(), "Queue with a duplicate");
+        assert!(vault.set_supply_queue(&[Pubkey::new_unique()]).is_err(), "Unlisted market");
+
+        // Newly listed markets are appended past the gaps
+        let d = Pubkey::new_unique();
+        vault.list_market(d, 1_000).unwrap();
+        assert_eq!(vault.supply_order(), vec![2, 0, 3]);
+    }
+
+    #[test]
+    fn test_vault_withdraw_queue_rejects_stranded_funds() {
+        let mut vault = empty_vault(0);
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        vault.list_market(a, 1_000).unwrap();
+        vault.list_market(b, 1_000).unwrap();
+
+        // `b` still holds supply: it cannot leave the withdraw queue
+        assert_eq!(
+            vault.set_withdraw_queue(&[a], &[0, 5]).unwrap_err(),
+            MorphoError::VaultMarketHasSupply.into()
+        );
+        assert_eq!(vault.withdraw_order(), vec![0, 1]);
+
+        // Empty but still taking deposits: it cannot leave either
+        assert_eq!(
+            vault.set_withdraw_queue(&[a], &[0, 0]).unwrap_err(),
+            MorphoError::VaultQueueMismatch.into()
+        );
+
+        // Off the supply queue and empty: dropped
+        vault.set_supply_queue(&[a]).unwrap();
+        vault.set_withdraw_queue(&[a], &[0, 0]).unwrap();
+        assert_eq!(vault.withdraw_order(), vec![0]);
+
+        // The supply queue must stay within the withdraw queue
+        assert!(vault.set_supply_queue(&[a, b]).is_err());
+
+        // Reordering the markets that remain needs no balances to be zero
+        vault.set_withdraw_queue(&[b, a], &[7, 7]).unwrap();
+        assert_eq!(vault.withdraw_order(), vec![1, 0]);
+    }
+
+    #[test]
+    fn test_vault_market_removal_lifecycle() {
+        let mut vault = empty_vault(0);
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let c = Pubkey::new_unique();
+        vault.list_market(a, 1_000).unwrap();
+        vault.list_market(b, 2_000).unwrap();
+        vault.list_market(c, 3_000).unwrap();
+        vault.set_queues(&[c, b, a], &[b, a, c]).unwrap();
+
+        assert_eq!(
+            vault.remove_market(b, 0, 0).unwrap_err(),
+            MorphoError::VaultRemovalNotPending.into()
+        );
+        assert!(vault.submit_removal(Pubkey::new_unique(), 100).is_err(), "Unlisted market");
+
+        // Submitting stops new supply at once
+        vault.submit_removal(b, 100).unwrap();
+        assert_eq!(vault.supply_caps[1], 0);
+        assert_eq!(vault.removal_after_slot, 100 + VAULT_MARKET_REMOVAL_DELAY_SLOTS);
+        assert_eq!(
+            vault.submit_removal(a, 100).unwrap_err(),
+            MorphoError::VaultRemovalPending.into()
+        );
+
+        // Cancelling keeps the cap at 0; resubmitting restarts the clock
+        vault.cancel_removal(b).unwrap();
+        assert_eq!(vault.pending_market_removal, Pubkey::default());
+        assert!(vault.cancel_removal(b).is_err());
+        vault.submit_removal(b, 200).unwrap();
+        let ready = 200 + VAULT_MARKET_REMOVAL_DELAY_SLOTS;
+
+        assert_eq!(
+            vault.remove_market(b, ready - 1, 0).unwrap_err(),
+            MorphoError::TimelockNotElapsed.into()
+        );
+        assert_eq!(
+            vault.remove_market(b, ready, 1).unwrap_err(),
+            MorphoError::MarketNotEmpty.into()
+        );
+        assert_eq!(vault.market_count, 3);
+
+        // Emptied and past the delay: `c` moves up, both queues close the gap
+        vault.remove_market(b, ready, 0).unwrap();
+        assert_eq!(vault.listed_markets(), &[a, c]);
+        assert_eq!(&vault.supply_caps[..2], &[1_000, 3_000]);
+        assert_eq!(vault.supply_order(), vec![1, 0]);
+        assert_eq!(vault.withdraw_order(), vec![0, 1]);
+        assert_eq!(vault.pending_market_removal, Pubkey::default());
+
+        // The freed slot can be listed again
+        vault.list_market(b, 500).unwrap();
+        assert_eq!(vault.listed_markets(), &[a, c, b]);
+        assert_eq!(vault.supply_order(), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_vault_caps_and_idle_buffer() {
+        let mut vault = empty_vault(1_000); // 10% idle
+        vault.list_market(Pubkey::new_unique(), 5_000).unwrap();
+
+        assert_eq!(vault.cap_room(0, 0), 5_000);
+        assert_eq!(vault.cap_room(0, 4_000), 1_000);
+        assert_eq!(vault.cap_room(0, 6_000), 0, "Over-cap markets take nothing");
+
+        assert_eq!(vault.idle_target(10_000).unwrap(), 1_000);
+        assert_eq!(empty_vault(0).idle_target(10_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_vault_fee_only_on_yield() {
+        let mut vault = empty_vault(0);
+        vault.fee_bps = 1_000; // 10%
+        vault.fee_recipient = Pubkey::new_unique();
+        vault.total_shares = 1_000_000 * VIRTUAL_SHARES;
+        vault.last_total_assets = 1_000_000;
+
+        // 10_000 of yield: fee worth 1_000 assets
+        let (interest, fee_shares) = vault.accrue_fee(1_010_000).unwrap();
+        assert_eq!(interest, 10_000);
+        assert!(fee_shares > 0);
+        assert_eq!(vault.pending_fee_shares, fee_shares);
+        assert_eq!(vault.total_shares, 1_000_000 * VIRTUAL_SHARES + fee_shares);
+        assert_eq!(vault.last_total_assets, 1_010_000);
+
+        let fee_value = to_assets_down(fee_shares, 1_010_000, vault.total_shares).unwrap();
+        assert!((999..=1_000).contains(&fee_value), "Fee claim is worth fee_bps of the yield");
+
+        // A loss takes nothing and lowers the high-water mark
+        let shares_before = vault.total_shares;
+        let (interest, fee_shares) = vault.accrue_fee(900_000).unwrap();
+        assert_eq!((interest, fee_shares), (0, 0));
+        assert_eq!(vault.total_shares, shares_before);
+        assert_eq!(vault.last_total_assets, 900_000);
+
+        // Recovering from the loss counts as yield again
+        let (interest, _) = vault.accrue_fee(950_000).unwrap();
+        assert_eq!(interest, 50_000);
+    }
+
+    #[test]
+    fn test_vault_withdraw_cascades_past_utilized_market() {
+        let mut vault = empty_vault(0);
+        let utilized = Pubkey::new_unique();
+        let liquid = Pubkey::new_unique();
+        vault.list_market(utilized, 10_000).unwrap();
+        vault.list_market(liquid, 10_000).unwrap();
+
+        // Vault supplies 5_000 to each; the first market is fully borrowed
+        // (withdrawable = min(vault supply, total_supply_assets - total_borrow_assets))
+        let withdrawable = [
+            std::cmp::min(5_000u128, 5_000 - 5_000),
+            std::cmp::min(5_000u128, 8_000 - 2_000),
+        ];
+        assert_eq!(withdrawable, [0, 5_000]);
+
+        let (plan, shortfall) = vault.plan_withdraw(3_000, &withdrawable);
+        assert_eq!(plan, vec![(1, 3_000)], "Skips the utilized market");
+        assert_eq!(shortfall, 0);
+
+        // Only reverts once the whole queue is exhausted
+        let (_, shortfall) = vault.plan_withdraw(6_000, &withdrawable);
+        assert_eq!(shortfall, 1_000);
+    }
+
+    #[test]
+    fn test_vault_supply_plan_clamps_to_caps() {
+        let mut vault = empty_vault(0);
+        let a = Pubkey::new_unique();
+        let b = Pubkey::new_unique();
+        let c = Pubkey::new_unique();
+        vault.list_market(a, 1_000).unwrap();
+        vault.list_market(b, 0).unwrap();
+        vault.list_market(c, 5_000).unwrap();
+        vault.set_queues(&[a, b, c], &[c, b, a]).unwrap();
+
+        // `a` is 600 in: it takes 400, `b` is skipped, `c` gets the rest
+        let (plan, remaining) = vault.plan_supply(2_000, &[600, 0, 0], &[u128::MAX; 3]);
+        assert_eq!(plan, vec![(0, 400), (2, 1_600)]);
+        assert_eq!(remaining, 0);
+
+        // Beyond every cap the remainder is reported
+        let (plan, remaining) = vault.plan_supply(10_000, &[600, 0, 0], &[u128::MAX; 3]);
+        assert_eq!(plan, vec![(0, 400), (2, 5_000)]);
+        assert_eq!(remaining, 4_600);
+
+        // A market's own supply cap binds before the vault's
+        let (plan, remaining) = vault.plan_supply(2_000, &[600, 0, 0], &[u128::MAX, u128::MAX, 1_000]);
+        assert_eq!(plan, vec![(0, 400), (2, 1_000)]);
+        assert_eq!(remaining, 600);
+    }
+
+    #[test]
+    fn test_pre_liquidation_liquidator_restriction() {
+        let liquidator = Pubkey::new_unique();
+        let mut terms = PreLiquidation {
+            bump: 1,
+            market_id: [0u8; 32],
+            borrower: Pubkey::new_unique(),
+            pre_lltv: 7500,
+            pre_lif: 10_200,
+            liquidator: Pubkey::default(),
+            reserved: [0u8; 32],
+        };
+
+        // Open to anyone by default
+        assert!(terms.is_liquidator_allowed(&liquidator));
+        assert!(terms.is_liquidator_allowed(&Pubkey::new_unique()));
+
+        // Restricted to a single liquidator
+        terms.liquidator = liquidator;
+        assert!(terms.is_liquidator_allowed(&liquidator));
+        assert!(!terms.is_liquidator_allowed(&Pubkey::new_unique()));
+
+        assert!(PreLiquidation::space() < 200, "PreLiquidation shouldn't be too large");
+    }
+
     #[test]
     fn test_position_is_empty() {
         let empty_position = Position {
@@ -725,7 +1345,12 @@ mod state_tests {
             supply_shares: 0,
             borrow_shares: 0,
             collateral: 0,
-            reserved: [0u8; 64],
+            auction_start_slot: 0,
+            last_update: 0,
+            became_unhealthy_slot: 0,
+            supply_entry_index: 0,
+            borrow_entry_index: 0,
+            reserved: [0u8; 8],
         };
 
         assert!(empty_position.is_empty(), "Position with all zeros should be empty");
@@ -737,7 +1362,12 @@ mod state_tests {
             supply_shares: 100,
             borrow_shares: 0,
             collateral: 0,
-            reserved: [0u8; 64],
+            auction_start_slot: 0,
+            last_update: 0,
+            became_unhealthy_slot: 0,
+            supply_entry_index: 0,
+            borrow_entry_index: 0,
+            reserved: [0u8; 8],
         };
 
         assert!(!non_empty_position.is_empty(), "Position with supply shares should not be empty");
@@ -752,11 +1382,123 @@ mod state_tests {
             supply_shares: 0,
             borrow_shares: 1000,
             collateral: 5000,
-            reserved: [0u8; 64],
+            auction_start_slot: 0,
+            last_update: 0,
+            became_unhealthy_slot: 0,
+            supply_entry_index: 0,
+            borrow_entry_index: 0,
+            reserved: [0u8; 8],
         };
 
         assert!(position_with_debt.has_debt(), "Position with borrow shares should have debt");
         assert!(position_with_debt.has_collateral(), "Position should have collateral");
+        assert!(position_with_debt.ensure_debt_collateralized().is_ok());
+
+        // Debt with nothing behind it is rejected before any oracle read
+        let mut bare_debt = position_with_debt.clone();
+        bare_debt.collateral = 0;
+        let err = bare_debt.ensure_debt_collateralized().unwrap_err();
+        assert_eq!(err, MorphoError::InsufficientCollateral.into());
+
+        // Collateral alone is fine
+        let mut collateral_only = position_with_debt.clone();
+        collateral_only.borrow_shares = 0;
+        assert!(collateral_only.ensure_debt_collateralized().is_ok());
+    }
+
+    #[test]
+    fn test_liquidation_grace_period() {
+        let market = Market {
+            liquidation_grace_slots: 10,
+            ..test_market()
+        };
+        let mut position = Position {
+            bump: 1,
+            market_id: [0u8; 32],
+            owner: Pubkey::new_unique(),
+            supply_shares: 0,
+            borrow_shares: 1000,
+            collateral: 5000,
+            auction_start_slot: 0,
+            last_update: 0,
+            became_unhealthy_slot: 0,
+            supply_entry_index: 0,
+            borrow_entry_index: 0,
+            reserved: [0u8; 8],
+        };
+
+        // An unstamped position is never past the grace period
+        assert!(!market.liquidation_grace_elapsed(position.became_unhealthy_slot, 1_000));
+
+        // Blip then recover: the stamp is cleared before the grace runs out,
+        // and the next breach starts a fresh grace period
+        position.record_health(true, 100);
+        assert_eq!(position.became_unhealthy_slot, 100);
+        assert!(!market.liquidation_grace_elapsed(position.became_unhealthy_slot, 105));
+        position.record_health(false, 105);
+        assert_eq!(position.became_unhealthy_slot, 0);
+        assert!(!position.has_liquidation_stamp());
+        position.record_health(true, 108);
+        assert!(!market.liquidation_grace_elapsed(position.became_unhealthy_slot, 110));
+
+        // Sustained breach: further unhealthy readings keep the first stamp
+        position.record_health(true, 115);
+        assert_eq!(position.became_unhealthy_slot, 108);
+        assert!(position.has_liquidation_stamp());
+        assert!(!market.liquidation_grace_elapsed(position.became_unhealthy_slot, 117));
+        assert!(market.liquidation_grace_elapsed(position.became_unhealthy_slot, 118));
+
+        // Slot 0 still stamps
+        let mut fresh = position.clone();
+        fresh.became_unhealthy_slot = 0;
+        fresh.record_health(true, 0);
+        assert_eq!(fresh.became_unhealthy_slot, 1);
+
+        // Without a grace period liquidation is immediate
+        let mut no_grace = market;
+        no_grace.liquidation_grace_slots = 0;
+        assert!(no_grace.liquidation_grace_elapsed(0, 0));
+    }
+
+    #[test]
+    fn test_supply_cap() {
+        let mut market = Market {
+            total_supply_assets: 9_000,
+            ..test_market()
+        };
+        // 0 = uncapped
+        assert_eq!(market.supply_cap_room(), u128::MAX);
+        assert!(market.ensure_supply_cap(u128::MAX).is_ok());
+
+        market.supply_cap = 10_000;
+        assert_eq!(market.supply_cap_room(), 1_000);
+        assert!(market.ensure_supply_cap(1_000).is_ok());
+        assert_eq!(market.ensure_supply_cap(1_001).unwrap_err(), MorphoError::SupplyCapReached.into());
+
+        // Accrued interest past the cap leaves no room, but does not underflow
+        market.total_supply_assets = 10_500;
+        assert_eq!(market.supply_cap_room(), 0);
+        assert!(market.ensure_supply_cap(1).is_err());
+    }
+
+    #[test]
+    fn test_authorization_permits_only_matching_pair() {
+        let owner = Pubkey::new_unique();
+        let manager = Pubkey::new_unique();
+        let auth = Authorization {
+            bump: 1,
+            authorizer: owner,
+            authorized: manager,
+            is_authorized: true,
+            is_revoked: false,
+            expires_at: 0,
+            reserved: [0u8; 32],
+        };
+
+        assert!(auth.permits(&owner, &manager, 1000), "Manager may act for owner");
+        assert!(!auth.permits(&owner, &Pubkey::new_unique(), 1000), "Other callers are rejected");
+        assert!(!auth.permits(&Pubkey::new_unique(), &manager, 1000), "Manager cannot act for other owners");
+        assert!(!auth.permits(&manager, &owner, 1000), "Authorization is not symmetric");
     }
 
     #[test]
@@ -826,67 +1568,185 @@ mod state_tests {
 
     #[test]
     fn test_market_operational_check() {
-        let mut market = Market {
-            bump: 0,
-            market_id: [0u8; 32],
-            collateral_mint: Pubkey::default(),
-            loan_mint: Pubkey::default(),
-            collateral_decimals: 9,
-            loan_decimals: 6,
-            oracle: Pubkey::default(),
-            irm: Pubkey::default(),
-            lltv: 8500,
-            paused: false,
-            fee: 0,
-            total_supply_assets: 0,
-            total_supply_shares: 0,
-            total_borrow_assets: 0,
-            total_borrow_shares: 0,
-            last_update: 0,
-            pending_fee_shares: 0,
-            collateral_vault_bump: 0,
-            loan_vault_bump: 0,
-            flash_loan_lock: 0,
-            reserved: [0u8; 127],
-        };
+        let mut market = test_market();
 
         assert!(market.is_operational(), "Market should be operational when not paused");
 
-        market.paused = true;
+        market.set_paused(true);
         assert!(!market.is_operational(), "Market should not be operational when paused");
     }
 
     #[test]
-    fn test_flash_loan_lock() {
-        let mut market = Market {
-            bump: 0,
-            market_id: [0u8; 32],
-            collateral_mint: Pubkey::default(),
-            loan_mint: Pubkey::default(),
-            collateral_decimals: 9,
-            loan_decimals: 6,
-            oracle: Pubkey::default(),
-            irm: Pubkey::default(),
-            lltv: 8500,
-            paused: false,
-            fee: 0,
-            total_supply_assets: 0,
-            total_supply_shares: 0,
-            total_borrow_assets: 0,
-            total_borrow_shares: 0,
-            last_update: 0,
-            pending_fee_shares: 0,
-            collateral_vault_bump: 0,
-            loan_vault_bump: 0,
-            flash_loan_lock: 0,
-            reserved: [0u8; 127],
+    fn test_market_registry_page_fits_return_data() {
+        use anchor_lang::AnchorSerialize;
+        use morpho_solana::constants::MAX_MARKETS_PER_PAGE;
+        use morpho_solana::instructions::MarketPage;
+
+        // Each index derives its own registry entry
+        let (first, _) = derive_market_index(&morpho_solana::ID, 0);
+        let (second, _) = derive_market_index(&morpho_solana::ID, 1);
+        assert_ne!(first, second);
+
+        let full_page = MarketPage {
+            total: u64::MAX,
+            markets: (0..MAX_MARKETS_PER_PAGE as u64)
+                .map(|index| MarketSummary {
+                    index,
+                    market: Pubkey::new_unique(),
+                    collateral_mint: Pubkey::new_unique(),
+                    loan_mint: Pubkey::new_unique(),
+                    lltv: 8600,
+                })
+                .collect(),
         };
+        let bytes = full_page.try_to_vec().unwrap();
+        assert!(bytes.len() <= 1024, "A full page must fit in return data");
+    }
+
+    #[test]
+    fn test_oracle_kind_from_u8() {
+        // Zero must stay Switchboard for markets created before the field existed
+        assert_eq!(OracleKind::try_from(0u8).unwrap(), OracleKind::Switchboard);
+        assert_eq!(OracleKind::try_from(1u8).unwrap(), OracleKind::Static);
+        assert_eq!(OracleKind::try_from(2u8).unwrap(), OracleKind::Pyth);
+        assert_eq!(OracleKind::try_from(3u8).unwrap(), OracleKind::Twap);
+        assert_eq!(OracleKind::try_from(4u8).unwrap(), OracleKind::Median);
+        assert!(OracleKind::try_from(5u8).is_err(), "Unknown oracle kinds should be rejected");
+    }
+
+    #[test]
+    fn test_flash_loan_lock() {
+        let mut market = test_market();
 
         assert!(!market.is_flash_loan_active(), "Flash loan should not be active initially");
 
         market.flash_loan_lock = 1;
         assert!(market.is_flash_loan_active(), "Flash loan should be active when lock is set");
     }
+
+    #[test]
+    fn test_callback_reentrancy_lock() {
+        let mut market = test_market();
+
+        assert!(!market.is_callback_active());
+        market.enter_callback().unwrap();
+        assert!(market.is_callback_active());
+
+        // A nested entry while the callback runs is rejected
+        assert_eq!(
+            market.enter_callback().unwrap_err(),
+            MorphoError::ReentrancyDetected.into()
+        );
+
+        market.exit_callback();
+        assert!(!market.is_callback_active());
+        market.enter_callback().unwrap();
+    }
+
+    #[test]
+    fn test_max_market_price() {
+        let mut market = test_market();
+
+        // Unset falls back to the global bound
+        assert_eq!(max_market_price(&market), max_oracle_price());
+
+        // A stablecoin pair can pin the ratio near 1:1
+        market.max_price = ORACLE_SCALE * 101 / 100;
+        assert_eq!(max_market_price(&market), ORACLE_SCALE * 101 / 100);
+
+        // The override can only tighten the global bound
+        market.max_price = u128::MAX;
+        assert_eq!(max_market_price(&market), max_oracle_price());
+    }
+
+    #[test]
+    fn test_price_deviation_circuit_breaker() {
+        let mut market = Market {
+            max_staleness_slots: 10,
+            max_deviation_bps: 1_000, // 10%
+            ..test_market()
+        };
+
+        // First read has no reference and is always accepted
+        check_price_deviation(&mut market, ORACLE_SCALE, 100).unwrap();
+        assert_eq!(market.last_oracle_price, ORACLE_SCALE);
+        assert_eq!(market.last_oracle_slot, 100);
+
+        // A 5% move is accepted and becomes the new reference
+        let up_5 = ORACLE_SCALE + ORACLE_SCALE / 20;
+        check_price_deviation(&mut market, up_5, 101).unwrap();
+        assert_eq!(market.last_oracle_price, up_5);
+
+        // A 50% drop within the window trips the breaker and is not recorded
+        let crash = up_5 / 2;
+        assert!(check_price_deviation(&mut market, crash, 105).is_err());
+        assert_eq!(market.last_oracle_price, up_5);
+
+        // Once the reference is older than max_staleness_slots the move is accepted
+        check_price_deviation(&mut market, crash, 112).unwrap();
+        assert_eq!(market.last_oracle_price, crash);
+
+        // Disabled breaker accepts anything
+        market.max_deviation_bps = 0;
+        check_price_deviation(&mut market, crash * 3, 113).unwrap();
+    }
+
+    #[test]
+    fn test_oracle_cache_is_per_slot() {
+        let mut market = Market {
+            max_staleness_slots: 10,
+            max_deviation_bps: 1_000, // 10%
+            ..test_market()
+        };
+
+        assert_eq!(market.cached_oracle_price(100), None, "Nothing cached yet");
+
+        // A validated read in slot 100 is reused for the rest of that slot
+        check_price_deviation(&mut market, ORACLE_SCALE, 100).unwrap();
+        market.oracle_cache_slot = 100;
+        assert_eq!(market.cached_oracle_price(100), Some(ORACLE_SCALE));
+        assert_eq!(market.cached_oracle_price(101), None, "A new slot re-validates");
+
+        // Oracle config changes drop the cache but keep the breaker reference
+        market.invalidate_oracle_cache();
+        assert_eq!(market.cached_oracle_price(100), None);
+        assert_eq!(market.last_oracle_price, ORACLE_SCALE);
+    }
+
+    #[test]
+    fn test_assert_invariants_flags_each_violation() {
+        let market = Market {
+            total_supply_assets: 10_000_000_000_000,
+            total_supply_shares: 10_000_000_000_000_000_000,
+            total_borrow_assets: 5_000_000_000_000,
+            total_borrow_shares: 5_000_000_000_000_000_000,
+            ..test_market()
+        };
+        assert!(assert_invariants(&market).is_ok());
+
+        // Rounding dust with no shares left is not drift
+        let mut drained = market;
+        drained.total_supply_shares = 0;
+        drained.total_borrow_assets = 0;
+        drained.total_borrow_shares = 0;
+        assert!(assert_invariants(&drained).is_ok());
+
+        let mut broken = market;
+        broken.pending_bad_debt = market.total_supply_assets - market.total_borrow_assets + 1;
+        assert_eq!(assert_invariants(&broken).unwrap_err(), MorphoError::InvariantBorrowExceedsSupply.into());
+
+        let mut broken = market;
+        broken.total_supply_assets = 0;
+        broken.total_borrow_assets = 0;
+        assert_eq!(assert_invariants(&broken).unwrap_err(), MorphoError::InvariantSupplySharesUnbacked.into());
+
+        // A repay's round-up can clear the last borrow asset before the
+        // last dust shares
+        let mut dust = market;
+        dust.total_borrow_assets = 0;
+        dust.total_borrow_shares = 10_000;
+        assert!(assert_invariants(&dust).is_ok());
+    }
 }
 
 // ============================================================================
@@ -896,7 +1756,6 @@ mod state_tests {
 #[cfg(test)]
 mod constants_tests {
     use super::*;
-    use morpho_solana::constants::*;
 
     #[test]
     fn test_wad_constant() {
@@ -958,6 +1817,7 @@ mod integration_tests {
     use anchor_lang::system_program;
 
     /// Helper to create an Anchor instruction
+    #[allow(dead_code)]
     fn create_instruction(
         program_id: Pubkey,
         accounts: Vec<AccountMeta>,
@@ -1177,7 +2037,7 @@ mod integration_tests {
         assert!(oracle_account.is_some(), "Oracle should exist");
         
         // Test LIF calculation for liquidation incentive
-        let lif = calculate_lif(LLTV_85_PERCENT);
+        let lif = calculate_lif(LLTV_85_PERCENT, LIF_CURSOR, MAX_LIF).unwrap();
         assert!(lif > BPS, "LIF should be > 100%");
         assert!(lif <= MAX_LIF, "LIF should be <= MAX_LIF");
         
@@ -1369,6 +2229,24 @@ mod error_tests {
         assert!(zero_shares.is_ok());
         assert_eq!(zero_shares.unwrap(), 0);
     }
+
+    #[test]
+    fn test_liquidating_healthy_position_fails_with_position_healthy() {
+        // 1_000 collateral worth 2_000 loan tokens at 80% LLTV, 1_000 borrowed
+        let price = 2 * ORACLE_SCALE;
+        let shares = to_shares_up(1_000, 0, 0).unwrap();
+
+        let err = ensure_liquidatable(1_000, shares, 1_000, shares, price, 8000).unwrap_err();
+        assert_eq!(err, MorphoError::PositionHealthy.into());
+
+        // No debt at all is never liquidatable
+        let err = ensure_liquidatable(1_000, 0, 1_000, shares, price, 8000).unwrap_err();
+        assert_eq!(err, MorphoError::PositionHealthy.into());
+
+        // Past the LLTV the gate opens
+        let shares = to_shares_up(1_601, 0, 0).unwrap();
+        assert!(ensure_liquidatable(1_000, shares, 1_601, shares, price, 8000).is_ok());
+    }
 }
 
 // ============================================================================
@@ -1423,6 +2301,8 @@ mod scenario_tests {
 
         total_borrow_assets -= bob_repay_assets;
         total_borrow_shares -= bob_borrow_shares;
+        assert_eq!(total_borrow_shares, 0, "Bob's repay should clear all borrow shares");
+        assert!(total_borrow_assets < bob_borrow, "Only rounding dust should remain borrowed");
 
         // Alice withdraws
         let alice_withdraw_assets = to_assets_down(alice_shares, total_supply_assets, total_supply_shares).unwrap();
@@ -1465,7 +2345,7 @@ mod scenario_tests {
         assert!(borrowed > new_max_borrow, "Position should be liquidatable after price drop");
 
         // Calculate liquidation incentive
-        let lif = calculate_lif(lltv);
+        let lif = calculate_lif(lltv, LIF_CURSOR, MAX_LIF).unwrap();
         assert!(lif > BPS, "LIF should be > 100%");
 
         // Liquidator repays half the debt
@@ -1484,27 +2364,11 @@ mod scenario_tests {
     #[test]
     fn test_bad_debt_simulation() {
         let mut market = Market {
-            bump: 0,
-            market_id: [0u8; 32],
-            collateral_mint: Pubkey::default(),
-            loan_mint: Pubkey::default(),
-            collateral_decimals: 9,
-            loan_decimals: 6,
-            oracle: Pubkey::default(),
-            irm: Pubkey::default(),
-            lltv: 8500,
-            paused: false,
-            fee: 0,
             total_supply_assets: 10_000_000_000_000, // 10M supplied
             total_supply_shares: 10_000_000_000_000_000_000,
             total_borrow_assets: 1_000_000_000_000, // 1M borrowed
             total_borrow_shares: 1_000_000_000_000_000_000,
-            last_update: 0,
-            pending_fee_shares: 0,
-            collateral_vault_bump: 0,
-            loan_vault_bump: 0,
-            flash_loan_lock: 0,
-            reserved: [0u8; 127],
+            ..test_market()
         };
 
         let initial_supply = market.total_supply_assets;
@@ -1540,6 +2404,283 @@ mod scenario_tests {
 
         assert!(new_value_per_share < old_value_per_share, "Share value should decrease");
     }
+
+    /// Bad debt is only written off once the position has no collateral left
+    #[test]
+    fn test_bad_debt_socialized_only_when_collateral_exhausted() {
+        let mut market = Market {
+            total_supply_assets: 10_000_000_000_000, // 10M supplied
+            total_supply_shares: 10_000_000_000_000_000_000,
+            total_borrow_assets: 5_000_000_000_000, // 5M borrowed
+            total_borrow_shares: 5_000_000_000_000_000_000,
+            ..test_market()
+        };
+        let mut position = Position {
+            bump: 1,
+            market_id: [0u8; 32],
+            owner: Pubkey::new_unique(),
+            supply_shares: 0,
+            borrow_shares: 1_000_000_000_000_000,
+            collateral: 1,
+            auction_start_slot: 0,
+            last_update: 0,
+            became_unhealthy_slot: 0,
+            supply_entry_index: 0,
+            borrow_entry_index: 0,
+            reserved: [0u8; 8],
+        };
+
+        // Collateral remains: nothing is written off
+        assert!(write_off_bad_debt(&mut market, &mut position).unwrap().is_none());
+        assert_eq!(position.borrow_shares, 1_000_000_000_000_000);
+        assert_eq!(market.total_supply_assets, 10_000_000_000_000);
+
+        // Collateral fully seized with debt left: suppliers absorb it
+        position.collateral = 0;
+        let written_off = write_off_bad_debt(&mut market, &mut position).unwrap();
+        assert_eq!(written_off, Some((1_000_000_000, 1_000_000_000_000_000)));
+        assert_eq!(position.borrow_shares, 0);
+        assert_invariants(&market).unwrap();
+        let event = socialize_pending_bad_debt(&mut market, false).unwrap()
+            .expect("Bad debt should be socialized with no threshold");
+        assert_eq!(event.bad_debt_assets, 1_000_000_000);
+        assert_eq!(market.pending_bad_debt, 0);
+        assert_eq!(market.total_supply_assets, 10_000_000_000_000 - 1_000_000_000);
+        assert!(event.share_price_after < event.share_price_before);
+        assert_eq!(event.share_price_after, supply_share_price(&market).unwrap());
+        assert_invariants(&market).unwrap();
+
+        // No debt left: nothing more to socialize
+        assert!(write_off_bad_debt(&mut market, &mut position).unwrap().is_none());
+        assert!(socialize_pending_bad_debt(&mut market, false).unwrap().is_none());
+    }
+
+    /// A backstop repaying a zero-collateral position's residual debt leaves
+    /// nothing to write off, so suppliers keep their assets
+    #[test]
+    fn test_backstop_repay_prevents_bad_debt() {
+        let mut market = Market {
+            total_supply_assets: 10_000_000_000_000, // 10M supplied
+            total_supply_shares: 10_000_000_000_000_000_000,
+            total_borrow_assets: 5_000_000_000_000, // 5M borrowed
+            total_borrow_shares: 5_000_000_000_000_000_000,
+            ..test_market()
+        };
+        let mut position = Position {
+            bump: 1,
+            market_id: [0u8; 32],
+            owner: Pubkey::new_unique(),
+            supply_shares: 0,
+            borrow_shares: 1_000_000_000_000_000,
+            collateral: 0,
+            auction_start_slot: 0,
+            last_update: 0,
+            became_unhealthy_slot: 0,
+            supply_entry_index: 0,
+            borrow_entry_index: 0,
+            reserved: [0u8; 8],
+        };
+
+        // A partial repay leaves residual debt to be written off
+        assert!(!repay_backstops_position(&position, position.borrow_shares / 2));
+
+        // Repaying by the full share balance clears the debt exactly
+        let (repay_assets, burn_shares) = repay_amounts(
+            0,
+            position.borrow_shares,
+            position.borrow_shares,
+            market.total_borrow_assets,
+            market.total_borrow_shares,
+        ).unwrap();
+        assert_eq!(repay_assets, 1_000_000_000);
+        assert!(repay_backstops_position(&position, burn_shares));
+
+        position.borrow_shares -= burn_shares;
+        market.total_borrow_assets -= repay_assets;
+        market.total_borrow_shares -= burn_shares;
+        assert_invariants(&market).unwrap();
+
+        // Nothing left to write off or socialize
+        assert!(write_off_bad_debt(&mut market, &mut position).unwrap().is_none());
+        assert!(socialize_pending_bad_debt(&mut market, true).unwrap().is_none());
+        assert_eq!(market.pending_bad_debt, 0);
+        assert_eq!(market.total_supply_assets, 10_000_000_000_000);
+
+        // An empty position is not a backstop
+        assert!(!repay_backstops_position(&position, 0));
+    }
+
+    /// The reserve absorbs bad debt before suppliers: fully, partially, or
+    /// not at all when empty
+    #[test]
+    fn test_reserve_absorbs_bad_debt_first() {
+        let bad_debt = 1_000_000_000;
+        for (reserve, from_reserve) in [(2_500_000_000, bad_debt), (400_000_000, 400_000_000), (0, 0)] {
+            let mut market = Market {
+                total_supply_assets: 10_000_000_000_000, // 10M supplied
+                total_supply_shares: 10_000_000_000_000_000_000,
+                total_borrow_assets: 5_000_000_000_000, // 5M borrowed
+                total_borrow_shares: 5_000_000_000_000_000_000,
+                reserve_assets: reserve,
+                ..test_market()
+            };
+            let mut position = Position {
+                bump: 1,
+                market_id: [0u8; 32],
+                owner: Pubkey::new_unique(),
+                supply_shares: 0,
+                borrow_shares: 1_000_000_000_000_000,
+                collateral: 0,
+                auction_start_slot: 0,
+                last_update: 0,
+                became_unhealthy_slot: 0,
+                supply_entry_index: 0,
+                borrow_entry_index: 0,
+                reserved: [0u8; 8],
+            };
+
+            assert_eq!(write_off_bad_debt(&mut market, &mut position).unwrap(), Some((bad_debt, 1_000_000_000_000_000)));
+            let event = socialize_pending_bad_debt(&mut market, false).unwrap().unwrap();
+            assert_eq!(event.bad_debt_assets, bad_debt);
+            assert_eq!(event.reserve_assets_used, from_reserve);
+            assert_eq!(market.reserve_assets, reserve - from_reserve);
+            assert_eq!(market.total_supply_assets, 10_000_000_000_000 - (bad_debt - from_reserve));
+            assert_eq!(event.share_price_after == event.share_price_before, from_reserve == bad_debt);
+            assert_eq!(market.pending_bad_debt, 0);
+            assert_invariants(&market).unwrap();
+        }
+    }
+
+    /// The immediate write-off path draws on the reserve the same way
+    #[test]
+    fn test_socialize_bad_debt_uses_reserve() {
+        let mut market = Market {
+            total_supply_assets: 10_000_000_000_000, // 10M supplied
+            total_supply_shares: 10_000_000_000_000_000_000,
+            total_borrow_assets: 5_000_000_000_000, // 5M borrowed
+            total_borrow_shares: 5_000_000_000_000_000_000,
+            reserve_assets: 300_000_000,
+            ..test_market()
+        };
+
+        let bad_debt = socialize_bad_debt(&mut market, 1_000_000_000_000_000).unwrap();
+        assert_eq!(bad_debt, 1_000_000_000);
+        assert_eq!(market.reserve_assets, 0);
+        assert_eq!(market.total_supply_assets, 10_000_000_000_000 - 700_000_000);
+        assert_eq!(market.total_borrow_assets, 5_000_000_000_000 - 1_000_000_000);
+        assert_invariants(&market).unwrap();
+    }
+
+    /// Write-offs below the threshold batch up in pending_bad_debt and the
+    /// pending plus socialized amounts always add up to the total shortfall
+    #[test]
+    fn test_bad_debt_threshold_batches_socialization() {
+        let mut market = Market {
+            total_supply_assets: 10_000_000_000_000, // 10M supplied
+            total_supply_shares: 10_000_000_000_000_000_000,
+            total_borrow_assets: 5_000_000_000_000, // 5M borrowed
+            total_borrow_shares: 5_000_000_000_000_000_000,
+            bad_debt_threshold: 2_500_000_000,
+            ..test_market()
+        };
+        let mut position = Position {
+            bump: 1,
+            market_id: [0u8; 32],
+            owner: Pubkey::new_unique(),
+            supply_shares: 0,
+            borrow_shares: 1_000_000_000_000_000,
+            collateral: 0,
+            auction_start_slot: 0,
+            last_update: 0,
+            became_unhealthy_slot: 0,
+            supply_entry_index: 0,
+            borrow_entry_index: 0,
+            reserved: [0u8; 8],
+        };
+
+        // 1_000_000_000 written off: below the 2_500_000_000 threshold
+        assert!(write_off_bad_debt(&mut market, &mut position).unwrap().is_some());
+        assert!(socialize_pending_bad_debt(&mut market, false).unwrap().is_none());
+        assert_eq!(market.pending_bad_debt, 1_000_000_000);
+        assert_eq!(market.total_supply_assets, 10_000_000_000_000);
+        assert_eq!(market.total_borrow_assets, 5_000_000_000_000 - 1_000_000_000);
+        assert_invariants(&market).unwrap();
+
+        // Two more write-offs cross the threshold and flush the whole batch
+        let mut total_shortfall = 1_000_000_000;
+        for _ in 0..2 {
+            position.borrow_shares = 1_000_000_000_000_000;
+            market.total_borrow_shares += 1_000_000_000_000_000;
+            market.total_borrow_assets += 1_000_000_000;
+            let (assets, _) = write_off_bad_debt(&mut market, &mut position).unwrap().unwrap();
+            total_shortfall += assets;
+        }
+        let event = socialize_pending_bad_debt(&mut market, false).unwrap()
+            .expect("Batch should be socialized once over the threshold");
+        assert_eq!(event.bad_debt_assets, total_shortfall);
+        assert_eq!(market.pending_bad_debt, 0);
+        assert_eq!(market.total_supply_assets, 10_000_000_000_000 - total_shortfall);
+        assert_invariants(&market).unwrap();
+
+        // The crank flushes a sub-threshold batch on demand
+        position.borrow_shares = 1_000_000_000_000_000;
+        market.total_borrow_shares += 1_000_000_000_000_000;
+        market.total_borrow_assets += 1_000_000_000;
+        write_off_bad_debt(&mut market, &mut position).unwrap();
+        assert!(socialize_pending_bad_debt(&mut market, false).unwrap().is_none());
+        let event = socialize_pending_bad_debt(&mut market, true).unwrap().unwrap();
+        assert_eq!(event.bad_debt_assets, 1_000_000_000);
+        assert_eq!(
+            market.total_supply_assets,
+            10_000_000_000_000 - total_shortfall - 1_000_000_000
+        );
+        assert_invariants(&market).unwrap();
+    }
+
+    /// Bad debt must hit protocol fee shares as hard as plain supply shares
+    #[test]
+    fn test_bad_debt_shared_with_fee_recipient() {
+        let mut market = Market {
+            fee: 2000, // 20%
+            total_supply_assets: 10_000_000_000_000, // 10M supplied
+            total_supply_shares: 10_000_000_000_000_000_000,
+            total_borrow_assets: 5_000_000_000_000, // 5M borrowed
+            total_borrow_shares: 5_000_000_000_000_000_000,
+            ..test_market()
+        };
+
+        // One year of 10% APR mints fee shares to the recipient
+        let rate = WAD / 10 / 31_536_000;
+        accrue_interest_on_market(&mut market, 31_536_000, rate).unwrap();
+        let fee_shares = market.pending_fee_shares;
+        assert!(fee_shares > 0, "Fee shares should have accrued");
+        assert_invariants(&market).unwrap();
+
+        // A plain supplier holding 10% of the original shares
+        let supplier_shares = 1_000_000_000_000_000_000u128;
+
+        let fee_before = to_assets_down(fee_shares, market.total_supply_assets, market.total_supply_shares).unwrap();
+        let supplier_before = to_assets_down(supplier_shares, market.total_supply_assets, market.total_supply_shares).unwrap();
+
+        // 20% of borrows go bad
+        let bad_shares = market.total_borrow_shares / 5;
+        let bad_debt = socialize_bad_debt(&mut market, bad_shares).unwrap();
+        assert!(bad_debt > 0);
+        assert_invariants(&market).unwrap();
+
+        let fee_after = to_assets_down(fee_shares, market.total_supply_assets, market.total_supply_shares).unwrap();
+        let supplier_after = to_assets_down(supplier_shares, market.total_supply_assets, market.total_supply_shares).unwrap();
+
+        assert!(fee_after < fee_before, "Fee recipient must absorb part of the loss");
+
+        // Same relative loss for both (cross-multiplied, allowing 1 unit of rounding per side)
+        let fee_ratio = fee_after * supplier_before;
+        let supplier_ratio = supplier_after * fee_before;
+        assert!(
+            fee_ratio.abs_diff(supplier_ratio) <= fee_before + supplier_before,
+            "Fee shares and supply shares should lose value proportionally"
+        );
+    }
 }
 
 // ============================================================================
@@ -1600,9 +2741,54 @@ mod property_tests {
     #[test]
     fn test_lif_bounds() {
         for lltv in [1000, 5000, 7500, 8000, 8500, 9000, 9500, 9900] {
-            let lif = calculate_lif(lltv);
+            let lif = calculate_lif(lltv, LIF_CURSOR, MAX_LIF).unwrap();
             assert!(lif >= BPS, "LIF should be >= 100%");
             assert!(lif <= MAX_LIF, "LIF should be <= 115%");
         }
     }
+
+    proptest::proptest! {
+        /// Property: assets -> shares -> assets never returns more than went in
+        #[test]
+        fn prop_share_roundtrip_never_profits(
+            assets in 0u128..1_000_000_000_000_000,
+            total_assets in 0u128..1_000_000_000_000_000,
+            total_shares in 0u128..1_000_000_000_000_000_000_000,
+        ) {
+            let shares = to_shares_down(assets, total_assets, total_shares).unwrap();
+            let back = to_assets_down(shares, total_assets, total_shares).unwrap();
+            proptest::prop_assert!(back <= assets);
+
+            let debt_shares = to_shares_up(assets, total_assets, total_shares).unwrap();
+            let owed = to_assets_up(debt_shares, total_assets, total_shares).unwrap();
+            proptest::prop_assert!(owed >= assets);
+        }
+
+        /// Property: conversions are monotonic in the input amount
+        #[test]
+        fn prop_share_conversions_monotonic(
+            a in 0u128..1_000_000_000_000_000,
+            b in 0u128..1_000_000_000_000_000,
+            total_assets in 0u128..1_000_000_000_000_000,
+            total_shares in 0u128..1_000_000_000_000_000_000_000,
+        ) {
+            let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+            proptest::prop_assert!(
+                to_shares_down(lo, total_assets, total_shares).unwrap()
+                    <= to_shares_down(hi, total_assets, total_shares).unwrap()
+            );
+            proptest::prop_assert!(
+                to_shares_up(lo, total_assets, total_shares).unwrap()
+                    <= to_shares_up(hi, total_assets, total_shares).unwrap()
+            );
+            proptest::prop_assert!(
+                to_assets_down(lo, total_assets, total_shares).unwrap()
+                    <= to_assets_down(hi, total_assets, total_shares).unwrap()
+            );
+            proptest::prop_assert!(
+                to_assets_up(lo, total_assets, total_shares).unwrap()
+                    <= to_assets_up(hi, total_assets, total_shares).unwrap()
+            );
+        }
+    }
 }
@@ -26,6 +26,7 @@ const MARKET_SEED = Buffer.from("morpho_market");
 const POSITION_SEED = Buffer.from("morpho_position");
 const COLLATERAL_VAULT_SEED = Buffer.from("morpho_collateral_vault");
 const LOAN_VAULT_SEED = Buffer.from("morpho_loan_vault");
+const PARAM_CHANGE_SEED = Buffer.from("morpho_param_change");
 
 // Market parameters
 const LLTV_85_PERCENT = 8500;
@@ -91,6 +92,13 @@ function deriveLoanVaultPda(programId: PublicKey, marketId: Buffer): [PublicKey,
   );
 }
 
+function deriveParamChangePda(programId: PublicKey, marketId: Buffer, param: number): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [PROGRAM_SEED_PREFIX, PARAM_CHANGE_SEED, marketId, Buffer.from([param])],
+    programId
+  );
+}
+
 async function airdrop(
   connection: anchor.web3.Connection,
   pubkey: PublicKey,
@@ -280,7 +288,7 @@ describe("morpho-solana comprehensive e2e tests", () => {
         await program.methods
           .setProtocolPaused(true)
           .accountsStrict({
-            owner: owner.publicKey,
+            authority: owner.publicKey,
             protocolState: protocolStatePda,
           })
           .signers([owner])
@@ -294,7 +302,7 @@ describe("morpho-solana comprehensive e2e tests", () => {
         await program.methods
           .setProtocolPaused(false)
           .accountsStrict({
-            owner: owner.publicKey,
+            authority: owner.publicKey,
             protocolState: protocolStatePda,
           })
           .signers([owner])
@@ -474,6 +482,7 @@ describe("morpho-solana comprehensive e2e tests", () => {
             oracle: oracle.publicKey,
             irm: irm.publicKey,
             tokenProgram: TOKEN_PROGRAM_ID,
+            collateralTokenProgram: TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId,
           })
           .rpc();
@@ -509,25 +518,29 @@ describe("morpho-solana comprehensive e2e tests", () => {
       }
     });
 
-    it("3.3 Sets market fee", async () => {
+    it("3.3 Queues a market fee increase", async () => {
       const marketIdArray = marketIdToArray(marketId);
+      const [pendingChangePda] = deriveParamChangePda(program.programId, marketId, 0);
 
       try {
+        // Raising the fee is timelocked, so it is queued rather than applied
         await program.methods
-          .setFee(marketIdArray, new BN(1000))
+          .queueParamChange(marketIdArray, 0, new BN(1000), PublicKey.default)
           .accountsStrict({
             owner: owner.publicKey,
             protocolState: protocolStatePda,
             market: marketPda,
+            pendingChange: pendingChangePda,
+            systemProgram: SystemProgram.programId,
           })
           .signers([owner])
           .rpc();
 
-        const market = await program.account.market.fetch(marketPda);
-        if (market.fee.toNumber() !== 1000) throw new Error("Fee should be 1000");
-        console.log("    Fee set to 10%");
+        const pending = await program.account.pendingParamChange.fetch(pendingChangePda);
+        if (pending.value.toNumber() !== 1000) throw new Error("Pending fee should be 1000");
+        console.log("    Fee change to 10% queued until slot", pending.executeAfterSlot.toString());
       } catch (e: any) {
-        console.log("    Set fee error:", e.message?.substring(0, 100));
+        console.log("    Queue fee error:", e.message?.substring(0, 100));
       }
     });
   });